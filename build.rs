@@ -0,0 +1,178 @@
+//! Check that `memory.x` agrees with the configuration crate, and identify
+//! the build.
+//!
+//! - It must reserve as much memory for the contiguous stack as the
+//!   configuration crate assumes. A mismatch shifts the start of the heap and
+//!   the `.data` and `.bss` sections away from where the kernel expects them,
+//!   which corrupts memory silently at runtime.
+//! - Its RAM region must not cover the region reserved for DMA buffers. The
+//!   kernel allocator extends the heap to the end of the RAM region, so it
+//!   would hand out the DMA buffers as ordinary heap memory.
+//!
+//! The git revision and the time of the build are passed to the firmware as
+//! the `GIT_REVISION` and `BUILD_TIMESTAMP` environment variables, printed in
+//! the boot banner of `src/identity.rs`. The revision is suffixed with
+//! `-dirty` when the tree has uncommitted changes, and is `unknown` outside a
+//! git checkout. The time is taken from `SOURCE_DATE_EPOCH` when set, for
+//! reproducible builds. The script reruns whenever a source file or the
+//! checked out commit changes, so the time is that of the last build that
+//! changed something, not of the last `cargo build`.
+
+use hopter_conf_params::{_CONTIGUOUS_STACK_LENGTH, DMA_REGION_LEN, DMA_REGION_START};
+use std::{
+    env, fs,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    check_memory();
+    identify();
+}
+
+fn check_memory() {
+    println!("cargo:rerun-if-changed=memory.x");
+
+    let script = fs::read_to_string("memory.x").expect("cannot read memory.x");
+
+    let len = script
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once('=')?;
+            if name.trim() != "_contiguous_stack_length" {
+                return None;
+            }
+            Some(value.trim().trim_end_matches(';').trim())
+        })
+        .expect("memory.x does not define `_contiguous_stack_length`");
+    let len = parse(len).expect("cannot parse `_contiguous_stack_length` in memory.x");
+
+    if len != _CONTIGUOUS_STACK_LENGTH {
+        panic!(
+            "memory.x reserves {len:#x} bytes for the contiguous stack, but \
+             `_CONTIGUOUS_STACK_LENGTH` is {_CONTIGUOUS_STACK_LENGTH:#x}"
+        );
+    }
+
+    if DMA_REGION_LEN == 0 {
+        return;
+    }
+
+    // E.g., `RAM (xrw) : ORIGIN = 0x20000000, LENGTH = 128K`.
+    let ram = script
+        .lines()
+        .find(|line| line.trim_start().starts_with("RAM"))
+        .expect("memory.x does not define the RAM region");
+    let field = |name: &str| {
+        ram.split(',')
+            .find_map(|part| {
+                let (key, value) = part.rsplit_once('=')?;
+                key.trim().ends_with(name).then(|| parse(value.trim()))?
+            })
+            .unwrap_or_else(|| panic!("cannot parse the {name} of the RAM region in memory.x"))
+    };
+    let (origin, length) = (field("ORIGIN"), field("LENGTH"));
+
+    let dma_end = DMA_REGION_START + DMA_REGION_LEN;
+    if origin < dma_end && DMA_REGION_START < origin + length {
+        panic!(
+            "the RAM region in memory.x, {origin:#x}..{:#x}, overlaps the DMA \
+             region, {DMA_REGION_START:#x}..{dma_end:#x}",
+            origin + length
+        );
+    }
+}
+
+fn identify() {
+    for path in ["src", "examples", "Cargo.toml"] {
+        println!("cargo:rerun-if-changed={path}");
+    }
+    // The commit checked out, and the branch it is on.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+        if let Some(head) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={git_dir}/{head}");
+        }
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let revision = match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(hash) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{hash}-dirty")
+            } else {
+                hash
+            }
+        }
+        None => "unknown".into(),
+    };
+    println!("cargo:rustc-env=GIT_REVISION={revision}");
+
+    let secs = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("SOURCE_DATE_EPOCH={text} is not a valid number")),
+        Err(_) => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("the clock is before 1970")
+            .as_secs(),
+    };
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp(secs));
+}
+
+/// Run git with the arguments, and return its output, trimmed, if it
+/// succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().into())
+}
+
+/// Format seconds since the Unix epoch as, e.g., `2024-08-01 12:00:00 UTC`.
+fn timestamp(secs: u64) -> String {
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // The civil date of a day count, after Howard Hinnant's
+    // `civil_from_days`, with years starting in March so that the leap day
+    // ends them.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number, optionally followed
+/// by a `K` or `M` suffix as in linker scripts.
+fn parse(text: &str) -> Option<u32> {
+    let (text, unit) = match text.strip_suffix('K') {
+        Some(text) => (text, 1024),
+        None => match text.strip_suffix('M') {
+            Some(text) => (text, 1024 * 1024),
+            None => (text, 1),
+        },
+    };
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    value.checked_mul(unit)
+}
@@ -0,0 +1,202 @@
+//! Parsing of the dumps saved by `src/crash_dump.rs` of the firmware.
+
+use std::fmt;
+
+/// The first word of a dump.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"HQSD");
+
+/// The version of the layout understood by the decoder.
+pub const VERSION: u32 = 1;
+
+/// The index of the words in the header, as in `src/crash_dump.rs`.
+pub mod word {
+    pub const MAGIC: usize = 0;
+    pub const VERSION: usize = 1;
+    pub const HEADER_LEN: usize = 2;
+    pub const LEN: usize = 3;
+    pub const TICK: usize = 4;
+    pub const EXCEPTION: usize = 5;
+    pub const EXC_RETURN: usize = 6;
+    pub const SP: usize = 7;
+    pub const FRAME: usize = 8;
+    pub const STKLET_BOUND: usize = 16;
+    pub const PSP: usize = 17;
+    pub const CUR_CTXT: usize = 18;
+    pub const CFSR: usize = 19;
+    pub const HFSR: usize = 20;
+    pub const MMFAR: usize = 21;
+    pub const BFAR: usize = 22;
+    pub const REGION_COUNT: usize = 23;
+    pub const REGIONS: usize = 24;
+}
+
+/// An error of [`Dump::load`].
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Neither a raw dump nor the lines printed by `crash dump`.
+    NotADump,
+    /// A layout the decoder does not understand.
+    Version(u32),
+    /// The dump ends before the length given by its header.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotADump => write!(f, "not a crash dump"),
+            Self::Version(version) => write!(f, "unknown dump version {}", version),
+            Self::Truncated => write!(f, "the dump is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A memory region saved in a dump.
+pub struct Region {
+    pub start: u32,
+    pub data: Vec<u8>,
+}
+
+/// A parsed dump.
+pub struct Dump {
+    pub tick: u32,
+    /// The number of the exception taken upon the fault.
+    pub exception: u32,
+    pub exc_return: u32,
+    /// The stack pointer upon the fault, i.e., the address of the frame.
+    pub sp: u32,
+    /// The registers stacked by the fault, from r0 to xPSR, or zeros if the
+    /// stack pointer was out of the RAM.
+    pub frame: [u32; 8],
+    /// The stacklet boundary of the running task, or that of the contiguous
+    /// stack if the fault struck a handler.
+    pub stklet_bound: u32,
+    pub psp: u32,
+    /// The address of the context of the running task.
+    pub cur_ctxt: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    pub regions: Vec<Region>,
+}
+
+impl Dump {
+    /// Parse either a raw dump, e.g., read by a debug probe, or the lines
+    /// printed by the `crash dump` shell command, among other lines.
+    pub fn load(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.starts_with(&MAGIC.to_le_bytes()) {
+            Self::parse(bytes)
+        } else {
+            Self::parse(&from_text(&String::from_utf8_lossy(bytes)))
+        }
+    }
+
+    /// Parse a raw dump.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let word = |index: usize| {
+            bytes
+                .get(index * 4..index * 4 + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(Error::Truncated)
+        };
+        if bytes.len() < 8 || word(word::MAGIC)? != MAGIC {
+            return Err(Error::NotADump);
+        }
+        if word(word::VERSION)? != VERSION {
+            return Err(Error::Version(word(word::VERSION)?));
+        }
+        let len = word(word::LEN)? as usize;
+        if bytes.len() < len {
+            return Err(Error::Truncated);
+        }
+
+        let mut offset = word(word::HEADER_LEN)? as usize;
+        let mut regions = Vec::new();
+        for i in 0..word(word::REGION_COUNT)? as usize {
+            let start = word(word::REGIONS + 2 * i)?;
+            let region_len = word(word::REGIONS + 2 * i + 1)? as usize;
+            let data = bytes
+                .get(offset..offset + region_len)
+                .ok_or(Error::Truncated)?;
+            regions.push(Region {
+                start,
+                data: data.to_vec(),
+            });
+            offset += region_len;
+        }
+
+        Ok(Self {
+            tick: word(word::TICK)?,
+            exception: word(word::EXCEPTION)?,
+            exc_return: word(word::EXC_RETURN)?,
+            sp: word(word::SP)?,
+            frame: std::array::from_fn(|i| word(word::FRAME + i).unwrap_or(0)),
+            stklet_bound: word(word::STKLET_BOUND)?,
+            psp: word(word::PSP)?,
+            cur_ctxt: word(word::CUR_CTXT)?,
+            cfsr: word(word::CFSR)?,
+            hfsr: word(word::HFSR)?,
+            mmfar: word(word::MMFAR)?,
+            bfar: word(word::BFAR)?,
+            regions,
+        })
+    }
+
+    /// Return the word at the address, or `None` if it was not saved.
+    pub fn read(&self, addr: u32) -> Option<u32> {
+        self.regions.iter().find_map(|region| {
+            let offset = addr.checked_sub(region.start)? as usize;
+            let bytes = region.data.get(offset..offset.checked_add(4)?)?;
+            Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+        })
+    }
+
+    /// Return the name of the exception taken upon the fault.
+    pub fn exception_name(&self) -> &'static str {
+        match self.exception {
+            3 => "HardFault",
+            4 => "MemManage",
+            5 => "BusFault",
+            6 => "UsageFault",
+            _ => "fault",
+        }
+    }
+
+    /// Return whether the fault struck a task rather than a handler.
+    pub fn in_thread_mode(&self) -> bool {
+        self.exc_return & 1 << 3 != 0
+    }
+}
+
+/// Gather the bytes of the lines printed by `crash dump`, each the offset
+/// and the bytes in hexadecimal, e.g., `00000020:48515344...`. Other lines,
+/// e.g., the shell prompt captured along, are ignored.
+pub fn from_text(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in text.lines() {
+        let Some((offset, data)) = line.trim().split_once(':') else {
+            continue;
+        };
+        if offset.len() != 8 || data.len() % 2 != 0 {
+            continue;
+        }
+        let Ok(offset) = usize::from_str_radix(offset, 16) else {
+            continue;
+        };
+        let Some(data) = (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+        else {
+            continue;
+        };
+        if bytes.len() < offset + data.len() {
+            bytes.resize(offset + data.len(), 0);
+        }
+        bytes[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    bytes
+}
@@ -0,0 +1,201 @@
+//! Reading the function symbols of the firmware ELF file.
+//!
+//! Only what the report needs is parsed: the symbol table, and the sections
+//! holding code. The file must be a 32-bit little-endian ELF file, as built
+//! for the Cortex-M target.
+
+use std::fmt;
+
+/// An error of [`Elf::parse`].
+#[derive(Debug)]
+pub struct Error(&'static str);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad ELF file: {}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A function symbol.
+struct Symbol {
+    name: String,
+    addr: u32,
+    size: u32,
+}
+
+/// The function symbols and the code sections of an ELF file.
+pub struct Elf {
+    /// Sorted by address.
+    symbols: Vec<Symbol>,
+    /// The address ranges of the sections holding code.
+    code: Vec<(u32, u32)>,
+}
+
+/// `sh_type` of a symbol table.
+const SHT_SYMTAB: u32 = 2;
+/// `sh_flags` of a section occupying memory and holding code.
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+/// The low bits of `st_info` of a function symbol.
+const STT_FUNC: u8 = 2;
+
+impl Elf {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if !bytes.starts_with(b"\x7fELF\x01\x01") {
+            return Err(Error("not a 32-bit little-endian ELF file"));
+        }
+        let u16_at = |at: usize| {
+            bytes
+                .get(at..at + 2)
+                .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(Error("truncated"))
+        };
+        let u32_at = |at: usize| {
+            bytes
+                .get(at..at + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .ok_or(Error("truncated"))
+        };
+
+        // The section header table.
+        let shoff = u32_at(0x20)? as usize;
+        let shentsize = u16_at(0x2e)? as usize;
+        let shnum = u16_at(0x30)? as usize;
+        let section = |index: usize| shoff + index * shentsize;
+
+        let mut symbols = Vec::new();
+        let mut code = Vec::new();
+        for index in 0..shnum {
+            let header = section(index);
+            let sh_type = u32_at(header + 4)?;
+            let flags = u32_at(header + 8)?;
+            let addr = u32_at(header + 12)?;
+            let offset = u32_at(header + 16)? as usize;
+            let size = u32_at(header + 20)? as usize;
+
+            if flags & (SHF_ALLOC | SHF_EXECINSTR) == SHF_ALLOC | SHF_EXECINSTR {
+                code.push((addr, addr + size as u32));
+            }
+            if sh_type != SHT_SYMTAB {
+                continue;
+            }
+
+            // The string table holding the names is given by `sh_link`.
+            let strtab = section(u32_at(header + 24)? as usize);
+            let strtab = u32_at(strtab + 16)? as usize;
+            for entry in (offset..offset + size).step_by(16) {
+                let info = *bytes.get(entry + 12).ok_or(Error("truncated"))?;
+                if info & 0xf != STT_FUNC {
+                    continue;
+                }
+                let name_at = strtab + u32_at(entry)? as usize;
+                let name = bytes
+                    .get(name_at..)
+                    .and_then(|rest| rest.split(|&b| b == 0).next())
+                    .ok_or(Error("truncated"))?;
+                symbols.push(Symbol {
+                    name: demangle(&String::from_utf8_lossy(name)),
+                    // Clear the Thumb bit.
+                    addr: u32_at(entry + 4)? & !1,
+                    size: u32_at(entry + 8)?,
+                });
+            }
+        }
+        symbols.sort_by_key(|symbol| symbol.addr);
+
+        Ok(Self { symbols, code })
+    }
+
+    /// Return the function holding the address, and the offset of the
+    /// address in it. The Thumb bit of the address is ignored.
+    pub fn symbolize(&self, addr: u32) -> Option<(&str, u32)> {
+        let addr = addr & !1;
+        let index = self.symbols.partition_point(|symbol| symbol.addr <= addr);
+        // Several symbols may share the address, some of them of no size.
+        self.symbols[..index]
+            .iter()
+            .rev()
+            .take(8)
+            .find(|symbol| addr < symbol.addr + symbol.size)
+            .map(|symbol| (symbol.name.as_str(), addr - symbol.addr))
+    }
+
+    /// Return whether the address lies in a section holding code.
+    pub fn is_code(&self, addr: u32) -> bool {
+        self.code
+            .iter()
+            .any(|&(start, end)| (start..end).contains(&addr))
+    }
+}
+
+/// Demangle a symbol of the legacy Rust mangling, e.g.,
+/// `_ZN4core3fmt5write17h0123456789abcdefE` into `core::fmt::write`. Other
+/// symbols are returned as they are.
+pub fn demangle(symbol: &str) -> String {
+    let Some(mut rest) = symbol
+        .strip_prefix("_ZN")
+        .and_then(|rest| rest.strip_suffix('E'))
+    else {
+        return symbol.to_string();
+    };
+
+    let mut parts = Vec::new();
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let Some(len) = rest[..digits].parse::<usize>().ok() else {
+            return symbol.to_string();
+        };
+        let Some(part) = rest.get(digits..digits + len) else {
+            return symbol.to_string();
+        };
+        parts.push(part);
+        rest = &rest[digits + len..];
+    }
+
+    // Drop the hash.
+    if let Some(last) = parts.last() {
+        if last.len() == 17 && last.starts_with('h') {
+            parts.pop();
+        }
+    }
+
+    parts
+        .iter()
+        .map(|part| {
+            unescape(
+                part.strip_prefix('_')
+                    .filter(|p| p.starts_with('$'))
+                    .unwrap_or(part),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// Replace the escapes of the legacy mangling by the characters they stand
+/// for.
+fn unescape(part: &str) -> String {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$SP$", "@"),
+        ("$BP$", "*"),
+        ("$RF$", "&"),
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+        ("..", "::"),
+    ];
+    ESCAPES
+        .iter()
+        .fold(part.to_string(), |part, (from, to)| part.replace(from, to))
+}
@@ -0,0 +1,11 @@
+//! Decoding of the crash dumps saved by `src/crash_dump.rs` of the firmware.
+//!
+//! A dump holds the registers stacked by the fault, the fault status
+//! registers, and the RAM. The [`report`] lists the fault, then every task
+//! whose context is found in the RAM, with the code addresses found on its
+//! stack. Given the ELF file of the firmware, the addresses are printed with
+//! the functions holding them.
+
+pub mod dump;
+pub mod elf;
+pub mod report;
@@ -0,0 +1,35 @@
+//! Print the report of a crash dump.
+//!
+//! Usage: `crash-decoder <dump> [<elf>]`, where `<dump>` is either the output
+//! of the `crash dump` shell command captured from the terminal, or the CRASH
+//! region read by a debug probe, e.g., with
+//! `st-flash read crash.bin 0x80c0000 0x20000`. Pass the ELF file the board
+//! ran, e.g., `../target/thumbv7em-none-eabihf/release/hopter-quick-start`,
+//! to name the functions.
+
+use crash_decoder::{dump::Dump, elf::Elf, report};
+use std::{env, error::Error, fs, process};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: crash-decoder <dump> [<elf>]");
+        process::exit(2);
+    }
+    if let Err(err) = run(&args[0], args.get(1)) {
+        eprintln!("crash-decoder: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(dump: &str, elf: Option<&String>) -> Result<(), Box<dyn Error>> {
+    let dump = Dump::load(&fs::read(dump)?)?;
+    let elf = match elf {
+        Some(path) => Some(Elf::parse(&fs::read(path)?)?),
+        None => None,
+    };
+    let mut text = String::new();
+    report::write(&mut text, &dump, elf.as_ref())?;
+    print!("{}", text);
+    Ok(())
+}
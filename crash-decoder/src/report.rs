@@ -0,0 +1,294 @@
+//! The report of a dump: the fault, then each task found in the RAM.
+//!
+//! The control blocks of the tasks are private to Hopter, and their layout
+//! is left to the compiler, except for the context of a task, which is
+//! `#[repr(C)]`: the three words of the task local storage, the first being
+//! the stacklet boundary, then the stack pointer, then r4 to r11, then s16 to
+//! s31. [`find_contexts`] looks for words of this shape. A task switched out
+//! has the registers of its trap frame at its stack pointer, the return
+//! address in lr and the address it resumes at in pc.
+//!
+//! There is no frame pointer to walk the calls, so the stack of each task is
+//! scanned for words that look like return addresses, i.e., addresses of
+//! code with the Thumb bit set. Some of them are stale, left by calls that
+//! returned, so the result is a hint rather than a backtrace.
+
+use crate::{dump::Dump, elf::Elf};
+use std::fmt::{self, Write};
+
+/// The size in bytes of a task context.
+const CTXT_LEN: u32 = 112;
+
+/// The distance from the boundary of a stacklet down to its metadata, as in
+/// `src/fault.rs`.
+const STACKLET_META_OFFSET: u32 = 16 + 4 + 104 + 4;
+
+/// The largest distance between a stacklet boundary and a stack pointer
+/// above it taken for a task context.
+const MAX_STACKLET_LEN: u32 = 0x1_0000;
+
+/// The number of bytes scanned from a stack pointer for return addresses.
+const SCAN_LEN: u32 = 1024;
+
+/// The number of return addresses listed per stacklet at most.
+const MAX_RETURNS: usize = 16;
+
+/// The number of stacklets followed per task at most.
+const MAX_STACKLETS: usize = 16;
+
+/// The address range of the flash, taken for code without an ELF file.
+const FLASH: std::ops::Range<u32> = 0x0800_0000..0x0810_0000;
+
+/// The context of a task, as saved upon its last switch.
+#[derive(Debug, PartialEq)]
+pub struct Context {
+    /// The address of the context.
+    pub addr: u32,
+    pub stklet_bound: u32,
+    pub sp: u32,
+}
+
+/// Find the task contexts in the dump.
+pub fn find_contexts(dump: &Dump, elf: Option<&Elf>) -> Vec<Context> {
+    let mut contexts = Vec::new();
+    for region in &dump.regions {
+        let end = region.start + region.data.len() as u32;
+        for addr in (region.start..end.saturating_sub(CTXT_LEN)).step_by(4) {
+            if let Some(context) = context_at(dump, elf, addr) {
+                contexts.push(context);
+            }
+        }
+    }
+    // A context is copied around at times, e.g., to the stack of the
+    // kernel. Keep the current one, and the first of every other stack.
+    contexts.sort_by_key(|context| (context.sp, context.addr != dump.cur_ctxt));
+    contexts.dedup_by_key(|context| context.sp);
+
+    // The context of the running task is stale, and may not look like one
+    // any longer.
+    if contexts.iter().all(|context| context.addr != dump.cur_ctxt) {
+        if let (Some(stklet_bound), Some(sp)) =
+            (dump.read(dump.cur_ctxt), dump.read(dump.cur_ctxt + 12))
+        {
+            contexts.push(Context {
+                addr: dump.cur_ctxt,
+                stklet_bound,
+                sp,
+            });
+        }
+    }
+    contexts.sort_by_key(|context| context.addr);
+    contexts
+}
+
+/// Return the context at the address if the words there look like one.
+fn context_at(dump: &Dump, elf: Option<&Elf>, addr: u32) -> Option<Context> {
+    let bound = dump.read(addr)?;
+    let nested_drop_cnt = dump.read(addr + 4)?;
+    let unwind_pending = dump.read(addr + 8)?;
+    let sp = dump.read(addr + 12)?;
+    let plausible = nested_drop_cnt < 64
+        && unwind_pending <= 1
+        && sp % 8 == 0
+        && bound < sp
+        && sp - bound <= MAX_STACKLET_LEN
+        && dump
+            .read(bound.checked_sub(STACKLET_META_OFFSET)?)
+            .is_some();
+    if !plausible {
+        return None;
+    }
+
+    // The trap frame at the stack pointer: pc must be code and xPSR must
+    // have the Thumb bit set.
+    let pc = dump.read(sp + 24)?;
+    let xpsr = dump.read(sp + 28)?;
+    (is_code(elf, pc) && pc % 2 == 0 && xpsr & 1 << 24 != 0).then_some(Context {
+        addr,
+        stklet_bound: bound,
+        sp,
+    })
+}
+
+/// Write the report of the dump.
+pub fn write(out: &mut impl Write, dump: &Dump, elf: Option<&Elf>) -> fmt::Result {
+    let mode = if dump.in_thread_mode() {
+        "thread"
+    } else {
+        "handler"
+    };
+    writeln!(
+        out,
+        "{} in {} mode at tick {}",
+        dump.exception_name(),
+        mode,
+        dump.tick
+    )?;
+
+    const NAMES: [&str; 8] = ["r0", "r1", "r2", "r3", "r12", "lr", "pc", "xpsr"];
+    for (i, (name, value)) in NAMES.iter().zip(dump.frame).enumerate() {
+        write!(out, "{:>6} {:#010x}", name, value)?;
+        if i % 4 == 3 {
+            writeln!(out)?;
+        }
+    }
+    writeln!(out, "    pc {}", Symbol(elf, dump.frame[6]))?;
+    writeln!(out, "    lr {}", Symbol(elf, dump.frame[5]))?;
+
+    write!(out, "CFSR {:#010x}", dump.cfsr)?;
+    for (bit, name) in CFSR_BITS {
+        if dump.cfsr & 1 << bit != 0 {
+            write!(out, " {}", name)?;
+        }
+    }
+    writeln!(out)?;
+    write!(out, "HFSR {:#010x}", dump.hfsr)?;
+    for (bit, name) in HFSR_BITS {
+        if dump.hfsr & 1 << bit != 0 {
+            write!(out, " {}", name)?;
+        }
+    }
+    writeln!(out)?;
+    if dump.cfsr & 1 << 7 != 0 {
+        writeln!(out, "MMFAR {:#010x}", dump.mmfar)?;
+    }
+    if dump.cfsr & 1 << 15 != 0 {
+        writeln!(out, "BFAR {:#010x}", dump.bfar)?;
+    }
+
+    write!(out, "saved")?;
+    for region in &dump.regions {
+        let end = region.start as u64 + region.data.len() as u64;
+        write!(out, " {:#010x}..{:#010x}", region.start, end)?;
+    }
+    writeln!(out)?;
+
+    if !dump.in_thread_mode() {
+        writeln!(out, "\nhandler stack from {:#010x}", dump.sp)?;
+        write_returns(out, dump, elf, dump.sp)?;
+    }
+
+    let contexts = find_contexts(dump, elf);
+    writeln!(out, "\n{} task contexts found", contexts.len())?;
+    for context in &contexts {
+        write_task(out, dump, elf, context)?;
+    }
+    Ok(())
+}
+
+/// Write the registers and the stacklets of a task.
+fn write_task(
+    out: &mut impl Write,
+    dump: &Dump,
+    elf: Option<&Elf>,
+    context: &Context,
+) -> fmt::Result {
+    // The context of the running task is stale. Its stack pointer is the one
+    // upon the fault, or the process stack pointer if a handler was running.
+    let running = context.addr == dump.cur_ctxt;
+    let (sp, bound) = match (running, dump.in_thread_mode()) {
+        (true, true) => (dump.sp, dump.stklet_bound),
+        (true, false) => (dump.psp, context.stklet_bound),
+        (false, _) => (context.sp, context.stklet_bound),
+    };
+
+    writeln!(
+        out,
+        "\ncontext at {:#010x}{}",
+        context.addr,
+        if running { ", running" } else { "" }
+    )?;
+    writeln!(out, "  sp {:#010x}, stacklet boundary {:#010x}", sp, bound)?;
+    if let (Some(lr), Some(pc)) = (dump.read(sp + 20), dump.read(sp + 24)) {
+        writeln!(out, "  pc {}", Symbol(elf, pc))?;
+        writeln!(out, "  lr {}", Symbol(elf, lr))?;
+    }
+
+    // Follow the stacklets from the newest one.
+    let (mut sp, mut bound) = (sp, bound);
+    for _ in 0..MAX_STACKLETS {
+        write_returns(out, dump, elf, sp)?;
+        let meta = bound.wrapping_sub(STACKLET_META_OFFSET);
+        match (dump.read(meta), dump.read(meta + 4)) {
+            (Some(prev_bound), Some(prev_sp)) if prev_bound != 0 => {
+                writeln!(
+                    out,
+                    "  previous stacklet, sp {:#010x}, boundary {:#010x}",
+                    prev_sp, prev_bound
+                )?;
+                (sp, bound) = (prev_sp, prev_bound);
+            }
+            _ => break,
+        }
+    }
+    Ok(())
+}
+
+/// Write the words looking like return addresses from the stack pointer.
+fn write_returns(out: &mut impl Write, dump: &Dump, elf: Option<&Elf>, sp: u32) -> fmt::Result {
+    let returns = (sp..sp.saturating_add(SCAN_LEN))
+        .step_by(4)
+        .filter_map(|addr| Some((addr, dump.read(addr)?)))
+        .filter(|&(_, value)| value % 2 == 1 && is_return(elf, value))
+        .take(MAX_RETURNS);
+    for (addr, value) in returns {
+        writeln!(out, "    [{:#010x}] {}", addr, Symbol(elf, value))?;
+    }
+    Ok(())
+}
+
+/// Return whether the address lies in code.
+fn is_code(elf: Option<&Elf>, addr: u32) -> bool {
+    match elf {
+        Some(elf) => elf.is_code(addr),
+        None => FLASH.contains(&addr),
+    }
+}
+
+/// Return whether the address may be a return address, i.e., one inside a
+/// function rather than at its start.
+fn is_return(elf: Option<&Elf>, addr: u32) -> bool {
+    match elf {
+        Some(elf) => elf.symbolize(addr).is_some_and(|(_, offset)| offset > 0),
+        None => FLASH.contains(&addr),
+    }
+}
+
+/// An address, printed with its function if known.
+struct Symbol<'a>(Option<&'a Elf>, u32);
+
+impl fmt::Display for Symbol<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010x}", self.1)?;
+        if let Some((name, offset)) = self.0.and_then(|elf| elf.symbolize(self.1)) {
+            write!(f, " {}+{:#x}", name, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// The bits of the configurable fault status register.
+const CFSR_BITS: [(u32, &str); 19] = [
+    (0, "IACCVIOL"),
+    (1, "DACCVIOL"),
+    (3, "MUNSTKERR"),
+    (4, "MSTKERR"),
+    (5, "MLSPERR"),
+    (7, "MMARVALID"),
+    (8, "IBUSERR"),
+    (9, "PRECISERR"),
+    (10, "IMPRECISERR"),
+    (11, "UNSTKERR"),
+    (12, "STKERR"),
+    (13, "LSPERR"),
+    (15, "BFARVALID"),
+    (16, "UNDEFINSTR"),
+    (17, "INVSTATE"),
+    (18, "INVPC"),
+    (19, "NOCP"),
+    (24, "UNALIGNED"),
+    (25, "DIVBYZERO"),
+];
+
+/// The bits of the HardFault status register.
+const HFSR_BITS: [(u32, &str); 3] = [(1, "VECTTBL"), (30, "FORCED"), (31, "DEBUGEVT")];
@@ -0,0 +1,111 @@
+//! Decode a dump built by hand in the layout written by the firmware.
+
+use crash_decoder::{
+    dump::{self, word, Dump},
+    report::{self, Context},
+};
+
+/// The start of the RAM region of the dump.
+const RAM: u32 = 0x2000_0000;
+const RAM_LEN: usize = 0x400;
+
+/// A switched-out task: its context, stacklet boundary, and stack pointer.
+const CTXT: u32 = 0x2000_0100;
+const BOUND: u32 = 0x2000_0200;
+const SP: u32 = 0x2000_0300;
+
+/// The address the task resumes at, and the return addresses on its stack.
+const PC: u32 = 0x0800_1240;
+const LR: u32 = 0x0800_1235;
+const RETURN: u32 = 0x0800_2001;
+
+/// Build a dump of a BusFault in handler mode.
+fn build() -> Vec<u8> {
+    let mut ram = vec![0; RAM_LEN];
+    let mut put = |addr: u32, value: u32| {
+        let at = (addr - RAM) as usize;
+        ram[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    };
+    put(CTXT, BOUND);
+    put(CTXT + 12, SP);
+    put(SP + 20, LR);
+    put(SP + 24, PC);
+    put(SP + 28, 1 << 24);
+    put(SP + 0x80, RETURN);
+
+    let header_words = word::REGIONS + 8;
+    let mut header = vec![0; header_words];
+    header[word::MAGIC] = dump::MAGIC;
+    header[word::VERSION] = dump::VERSION;
+    header[word::HEADER_LEN] = header_words as u32 * 4;
+    header[word::LEN] = (header_words * 4 + RAM_LEN) as u32;
+    header[word::TICK] = 1234;
+    header[word::EXCEPTION] = 5;
+    header[word::EXC_RETURN] = 0xffff_fff1;
+    header[word::CFSR] = 1 << 9 | 1 << 15;
+    header[word::BFAR] = 0x3000_0000;
+    header[word::REGION_COUNT] = 1;
+    header[word::REGIONS] = RAM;
+    header[word::REGIONS + 1] = RAM_LEN as u32;
+
+    let mut bytes: Vec<u8> = header.iter().flat_map(|w| w.to_le_bytes()).collect();
+    bytes.extend(ram);
+    bytes
+}
+
+/// Print the dump like the `crash dump` shell command, after a prompt.
+fn print(bytes: &[u8]) -> String {
+    let mut text = String::from("> crash dump\r\n");
+    for (i, line) in bytes.chunks(32).enumerate() {
+        text += &format!("{:08x}:", i * 32);
+        for byte in line {
+            text += &format!("{:02x}", byte);
+        }
+        text += "\r\n";
+    }
+    text + "end of crash dump\r\n"
+}
+
+#[test]
+fn raw_and_printed_dumps_agree() {
+    let bytes = build();
+    let raw = Dump::load(&bytes).unwrap();
+    let printed = Dump::load(print(&bytes).as_bytes()).unwrap();
+    assert_eq!(raw.tick, 1234);
+    assert_eq!(printed.tick, raw.tick);
+    assert_eq!(printed.regions[0].data, raw.regions[0].data);
+}
+
+#[test]
+fn other_files_are_rejected() {
+    assert!(matches!(Dump::load(b"hello\n"), Err(dump::Error::NotADump)));
+
+    let mut bytes = build();
+    bytes.truncate(bytes.len() - 4);
+    assert!(matches!(Dump::load(&bytes), Err(dump::Error::Truncated)));
+}
+
+#[test]
+fn switched_out_task_is_found() {
+    let dump = Dump::load(&build()).unwrap();
+    assert_eq!(
+        report::find_contexts(&dump, None),
+        [Context {
+            addr: CTXT,
+            stklet_bound: BOUND,
+            sp: SP,
+        }]
+    );
+}
+
+#[test]
+fn report_lists_fault_and_returns() {
+    let dump = Dump::load(&build()).unwrap();
+    let mut text = String::new();
+    report::write(&mut text, &dump, None).unwrap();
+    assert!(text.starts_with("BusFault in handler mode at tick 1234\n"));
+    assert!(text.contains("PRECISERR BFARVALID"));
+    assert!(text.contains("BFAR 0x30000000"));
+    assert!(text.contains(&format!("pc {:#010x}", PC)));
+    assert!(text.contains(&format!("[{:#010x}] {:#010x}", SP + 0x80, RETURN)));
+}
@@ -0,0 +1,252 @@
+//! Continuous ADC sampling into a circular DMA buffer.
+//!
+//! The tutorial in `src/main.rs` converts one ADC sample at a time when a task
+//! asks for it. Data acquisition usually runs the other way around: the ADC
+//! converts continuously, the DMA moves every sample into a buffer without
+//! involving the CPU, and a task processes the samples in blocks.
+//!
+//! Here ADC1 converts PA1 back to back, and DMA2 stream 0 writes the samples
+//! into a buffer in circular mode. The buffer is split into two halves. The
+//! DMA raises the half-transfer IRQ once it has filled the first half and the
+//! transfer-complete IRQ once it has filled the second half, then wraps
+//! around. Each IRQ releases a `Semaphore`, and the processing task reads the
+//! half just filled while the DMA writes the other one.
+//!
+//! Connect an analog signal to PA1, e.g., the wiper of a potentiometer between
+//! GND and 3V. The LEDs on PD12 to PD15, as on STM32F407 and STM32F411
+//! Discovery, show the average level of each block as a bar graph. Build and
+//! flash with `cargo run --release --example adc_dma`.
+//!
+//! The DMA writes the buffer behind the back of the compiler, so the buffer is
+//! never handed out as a Rust reference. The DMA gets it once through
+//! [`DmaTarget`], and the task reads a half through raw pointers only while
+//! the DMA is busy with the other half. If the task falls behind, the DMA
+//! wraps around into the half being read, which the task detects afterwards
+//! and counts as an overrun.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use embedded_dma::WriteBuffer;
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{Semaphore, SpinIrqSafe},
+    task::{self, main},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, Clock, Continuous, Dma, SampleTime, Scan, Sequence},
+        Adc,
+    },
+    dma::{
+        config::DmaConfig, traits::Stream, DmaFlag, PeripheralToMemory, Stream0, StreamsTuple,
+        Transfer,
+    },
+    gpio::{ErasedPin, Output},
+    pac::{self, ADC1, DMA2},
+    prelude::*,
+    ClearFlags, ReadFlags,
+};
+
+/// The number of samples in each half of the buffer.
+const HALF_LEN: usize = 256;
+
+/// The largest value of a 12-bit sample.
+const FULL_SCALE: u32 = 4095;
+
+/// The buffer written by the DMA.
+struct SampleBuffer {
+    /// Set once the buffer has been handed to the DMA.
+    taken: AtomicBool,
+    samples: UnsafeCell<[u16; 2 * HALF_LEN]>,
+}
+
+// Safety: The samples are only accessed through raw pointers, by the DMA
+// and by the processing task. See the module documentation.
+unsafe impl Sync for SampleBuffer {}
+
+static BUFFER: SampleBuffer = SampleBuffer {
+    taken: AtomicBool::new(false),
+    samples: UnsafeCell::new([0; 2 * HALF_LEN]),
+};
+
+/// The exclusive right of the DMA to write [`BUFFER`]. There is at most one.
+struct DmaTarget(());
+
+impl DmaTarget {
+    /// Return the right to write the buffer, or `None` if it was already
+    /// taken.
+    fn take() -> Option<Self> {
+        if BUFFER.taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(()))
+        }
+    }
+}
+
+// Safety: The buffer is a static, so its location is stable, and any bit
+// pattern is a valid `u16`.
+unsafe impl WriteBuffer for DmaTarget {
+    type Word = u16;
+
+    unsafe fn write_buffer(&mut self) -> (*mut u16, usize) {
+        (BUFFER.samples.get().cast(), 2 * HALF_LEN)
+    }
+}
+
+type AdcTransfer = Transfer<Stream0<DMA2>, 0, Adc<ADC1>, PeripheralToMemory, DmaTarget>;
+
+irq!(Dma2Stream0Irq, pac::interrupt::DMA2_STREAM0);
+
+/// The running transfer. The DMA IRQ is masked when the lock is held.
+static TRANSFER: SpinIrqSafe<Option<AdcTransfer>, Dma2Stream0Irq> = SpinIrqSafe::new(None);
+
+/// The number of halves filled by the DMA since the start.
+static FILLED: AtomicU32 = AtomicU32::new(0);
+
+/// Released by the handler for each half filled. The task is at most two
+/// halves behind before the DMA catches up with it, hence the maximum count.
+static READY: Semaphore = Semaphore::new(2, 0);
+
+/// The number of halves that were overwritten before being processed. Inspect
+/// it with a debugger.
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiod = dp.GPIOD.split();
+    let leds = [
+        gpiod.pd12.into_push_pull_output().erase(),
+        gpiod.pd13.into_push_pull_output().erase(),
+        gpiod.pd14.into_push_pull_output().erase(),
+        gpiod.pd15.into_push_pull_output().erase(),
+    ];
+
+    // Convert PA1 back to back, and issue a DMA request after each
+    // conversion. The ADC clock is kept below its 36 MHz limit at any PCLK2.
+    let pa1 = gpioa.pa1.into_analog();
+    let config = AdcConfig::default()
+        .clock(Clock::Pclk2_div_8)
+        .scan(Scan::Disabled)
+        .continuous(Continuous::Continuous)
+        .dma(Dma::Continuous);
+    let mut adc = Adc::adc1(dp.ADC1, true, config);
+    adc.configure_channel(&pa1, Sequence::One, SampleTime::Cycles_480);
+
+    // Raise the IRQ at both the half and the end of the buffer.
+    let config = DmaConfig::default()
+        .memory_increment(true)
+        .half_transfer_interrupt(true)
+        .transfer_complete_interrupt(true);
+    let stream = StreamsTuple::new(dp.DMA2).0;
+    let target = DmaTarget::take().unwrap();
+    let mut transfer = Transfer::init_peripheral_to_memory(stream, adc, target, None, config);
+
+    // The HAL has no setting for circular mode, which restarts the transfer
+    // from the beginning of the buffer once it completes.
+    unsafe { transfer.stream().set_circular_mode(true) };
+
+    transfer.start(|adc| adc.start_conversion());
+    *TRANSFER.lock() = Some(transfer);
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::DMA2_STREAM0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::DMA2_STREAM0);
+    }
+
+    task::build()
+        .set_entry(move || process(leds))
+        .spawn()
+        .unwrap();
+}
+
+/// Process the halves as they get filled, and show their average level.
+fn process(mut leds: [ErasedPin<Output>; 4]) {
+    let mut processed: u32 = 0;
+
+    loop {
+        READY.down();
+
+        // Only the half filled last is not being written by the DMA. Skip
+        // ahead to it if the task fell behind.
+        let filled = FILLED.load(Ordering::SeqCst);
+        if filled == processed {
+            // A release for a half already skipped.
+            continue;
+        }
+        let missed = filled.wrapping_sub(processed) - 1;
+        if missed > 0 {
+            OVERRUNS.fetch_add(missed, Ordering::SeqCst);
+            processed = filled.wrapping_sub(1);
+        }
+
+        let mean = mean_of_half(processed as usize % 2);
+
+        // The DMA starts writing this half again once it fills the other
+        // one, in which case the samples read may be torn.
+        if FILLED.load(Ordering::SeqCst) != processed.wrapping_add(1) {
+            OVERRUNS.fetch_add(1, Ordering::SeqCst);
+        } else {
+            // Light from none to all four LEDs as the level rises.
+            let lit = (mean * 5 / (FULL_SCALE + 1)) as usize;
+            for (i, led) in leds.iter_mut().enumerate() {
+                led.set_state((i < lit).into());
+            }
+        }
+
+        processed = processed.wrapping_add(1);
+    }
+}
+
+/// Return the average of the samples in the given half of the buffer.
+fn mean_of_half(half: usize) -> u32 {
+    let start = BUFFER.samples.get().cast::<u16>();
+    let sum: u32 = (0..HALF_LEN)
+        .map(|i| {
+            // Safety: The index is within the buffer. The read is volatile
+            // because the DMA writes the buffer.
+            u32::from(unsafe { ptr::read_volatile(start.add(half * HALF_LEN + i)) })
+        })
+        .sum();
+    sum / HALF_LEN as u32
+}
+
+#[handler(DMA2_STREAM0)]
+fn dma2_stream0_handler() {
+    // Acknowledge the IRQ.
+    let mut transfer = TRANSFER.lock();
+    let transfer = transfer.as_mut().unwrap();
+    let flags = transfer.flags();
+    transfer.clear_flags(DmaFlag::HalfTransfer | DmaFlag::TransferComplete);
+
+    // The two flags are both set if the handler ran late.
+    for flag in [DmaFlag::HalfTransfer, DmaFlag::TransferComplete] {
+        if flags.contains(flag) {
+            FILLED.fetch_add(1, Ordering::SeqCst);
+            // Failing means the task is already two halves behind, which it
+            // will find out from `FILLED`.
+            let _ = READY.try_up_allow_isr();
+        }
+    }
+}
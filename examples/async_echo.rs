@@ -0,0 +1,225 @@
+//! Run `async` code on Hopter, with an async driver of USART2 echoing the
+//! bytes received, and async delays blinking the LEDs.
+//!
+//! The `executor` module in `src/executor.rs` runs a future to completion in
+//! a task, blocking the task on a `Mailbox` while the future is pending, see
+//! its documentation. The async driver keeps the wakers of a pending read
+//! and a pending write, and enables the RXNE or the TXE IRQ of the USART
+//! while one waits. The USART2 handler disables the IRQ again, and wakes the
+//! future, which then reads or writes the byte itself. A task blocked in an
+//! async read thus takes no CPU, as one blocked on a channel does.
+//!
+//! Two tasks run async code, each with an executor of its own:
+//!
+//! - `echo` runs two futures at once with `executor::join`. One echoes the
+//!   bytes received, turning a carriage return into a line break, and prints
+//!   a note when nothing is received for [`IDLE_MS`], through
+//!   `executor::timeout`. The other blinks the orange LED with
+//!   `executor::sleep_ms`.
+//! - `blink` blinks the green LED at a slower pace, sleeping alongside the
+//!   futures of the other task.
+//!
+//! Open the console wired as in Part 11 of the tutorial, at 115200 baud, and
+//! type. Hopter schedules both tasks as usual, so async code can move in
+//! one task at a time, next to the tasks calling the blocking APIs. Build
+//! and flash with `cargo run --release --example async_echo`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/executor.rs"]
+mod executor;
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
+use executor::Executor;
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::SpinIrqSafe,
+    task::{self, main},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    gpio::{ErasedPin, Output},
+    nb,
+    pac::{self, USART2},
+    prelude::*,
+    serial::{self, Rx, RxISR, RxListen, Tx, TxISR, TxListen},
+};
+
+/// The time without a byte received after which a note is printed.
+const IDLE_MS: u32 = 10_000;
+
+/// A half of the USART, with the waker of the future waiting on it.
+struct Half<T> {
+    half: T,
+    waker: Option<Waker>,
+}
+
+irq!(Usart2Irq, pac::interrupt::USART2);
+
+/// The halves of the USART. The IRQ is masked when a lock is held.
+static RX: SpinIrqSafe<Option<Half<Rx<USART2>>>, Usart2Irq> = SpinIrqSafe::new(None);
+static TX: SpinIrqSafe<Option<Half<Tx<USART2>>>, Usart2Irq> = SpinIrqSafe::new(None);
+
+/// The bytes lost to an overrun or a framing error.
+static RX_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+static ECHO: Executor = Executor::new();
+static BLINK: Executor = Executor::new();
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpiod = dp.GPIOD.split();
+    let green = gpiod.pd12.into_push_pull_output().erase();
+    let orange = gpiod.pd13.into_push_pull_output().erase();
+
+    let gpioa = dp.GPIOA.split();
+    let (tx, rx) = dp
+        .USART2
+        .serial(
+            (gpioa.pa2, gpioa.pa3),
+            serial::Config::default().baudrate(115_200.bps()),
+            &clocks,
+        )
+        .unwrap()
+        .split();
+    *RX.lock() = Some(Half {
+        half: rx,
+        waker: None,
+    });
+    *TX.lock() = Some(Half {
+        half: tx,
+        waker: None,
+    });
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::USART2, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::USART2);
+    }
+
+    task::build()
+        .set_entry(move || {
+            ECHO.block_on(executor::join(echo(), blink(orange, 250)));
+        })
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_entry(move || BLINK.block_on(blink(green, 1000)))
+        .spawn()
+        .unwrap();
+}
+
+/// Echo the bytes received, and print a note when idle.
+async fn echo() {
+    write_all(b"\r\nasync echo, type something\r\n").await;
+    loop {
+        match executor::timeout(IDLE_MS, read_byte()).await {
+            Some(Ok(b'\r')) => write_all(b"\r\n").await,
+            Some(Ok(byte)) => write_byte(byte).await,
+            Some(Err(_)) => {
+                RX_ERRORS.fetch_add(1, Ordering::SeqCst);
+            }
+            None => {
+                let errors = RX_ERRORS.load(Ordering::SeqCst);
+                let note: &[u8] = if errors == 0 {
+                    b"\r\n(idle)\r\n"
+                } else {
+                    b"\r\n(idle, bytes lost)\r\n"
+                };
+                write_all(note).await;
+            }
+        }
+    }
+}
+
+/// Toggle the LED every half period.
+async fn blink(mut led: ErasedPin<Output>, period_ms: u32) {
+    loop {
+        led.toggle();
+        executor::sleep_ms(period_ms / 2).await;
+    }
+}
+
+/// Read a byte, waiting for one to be received.
+async fn read_byte() -> Result<u8, serial::Error> {
+    poll_fn(|cx| {
+        let mut rx = RX.lock();
+        let rx = rx.as_mut().unwrap();
+        match rx.half.read() {
+            Ok(byte) => Poll::Ready(Ok(byte)),
+            Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+            Err(nb::Error::WouldBlock) => {
+                // The IRQ is masked until the lock is released, so a byte
+                // received meanwhile raises it with the waker in place.
+                rx.waker = Some(cx.waker().clone());
+                rx.half.listen();
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+/// Write a byte, waiting for room in the transmit register.
+async fn write_byte(byte: u8) {
+    poll_fn(|cx| {
+        let mut tx = TX.lock();
+        let tx = tx.as_mut().unwrap();
+        match tx.half.write(byte) {
+            Ok(()) => Poll::Ready(()),
+            // Writing a byte never fails.
+            Err(nb::Error::Other(_)) => Poll::Ready(()),
+            Err(nb::Error::WouldBlock) => {
+                tx.waker = Some(cx.waker().clone());
+                tx.half.listen();
+                Poll::Pending
+            }
+        }
+    })
+    .await
+}
+
+async fn write_all(bytes: &[u8]) {
+    for &byte in bytes {
+        write_byte(byte).await;
+    }
+}
+
+#[handler(USART2)]
+fn usart2_handler() {
+    // Wake up the futures whose condition is met, disabling their IRQ so
+    // that it is not raised again before they read or write.
+    if let Some(rx) = RX.lock().as_mut() {
+        if rx.half.is_rx_not_empty() {
+            rx.half.unlisten();
+            if let Some(waker) = rx.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+    if let Some(tx) = TX.lock().as_mut() {
+        if tx.half.is_tx_empty() {
+            tx.half.unlisten();
+            if let Some(waker) = tx.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Measure the cost of the synchronization primitives of Hopter in cycles.
+//!
+//! Each primitive is measured uncontended, by a single task, and contended,
+//! where every operation involves another party:
+//! - `mutex`: lock and unlock. When contended, a lower priority task holds
+//!   the lock, so the lock blocks, the holder inherits the priority and
+//!   unlocks, and the lock is taken over.
+//! - `spin_irq`: lock and unlock a `SpinIrqSafe`. When contended, the IRQ
+//!   handler taking the same lock is pended while the lock is held, and runs
+//!   upon the unlock.
+//! - `mailbox`: notify and wait. When contended, two tasks notify each other
+//!   in turn, so every wait blocks and every notify switches tasks.
+//! - `channel`: send and receive one word, contended like `mailbox`.
+//!
+//! A contended figure is the cost per operation, i.e., half of a round trip
+//! for `mailbox` and `channel`. The cost of the empty measuring loop is
+//! subtracted from every figure.
+//!
+//! The results are printed on USART2 as lines of comma separated values,
+//! `bench_sync,<primitive>,<uncontended|contended>,<cycles>`, after a line
+//! starting with `#` that gives the clock and the number of iterations, so
+//! that the output can be kept and compared across toolchain updates, e.g.,
+//! with `grep ^bench_sync`. The console wiring is the one of Part 11 of the
+//! tutorial. Build and flash with `cargo run --release --example bench_sync`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::fmt::Write;
+use cortex_m::peripheral::{DWT, NVIC};
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mailbox, Mutex, Producer, SpinIrqSafe},
+    task::{self, main},
+};
+use hopter_conf_params::{HCLK_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY};
+use stm32f4xx_hal::{
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The operations per measurement.
+const ITERATIONS: u32 = 1000;
+
+/// The capacity of the channels.
+const CHANNEL_LEN: usize = 4;
+
+/// The priority of the `bench` task. The holder of the contended mutex runs
+/// below it, and the peer of the contended mailbox and channel above it.
+const PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY;
+
+type Sender = Producer<u32, CHANNEL_LEN>;
+type Receiver = Consumer<u32, CHANNEL_LEN>;
+
+// Triggered by software only, to contend for `SPIN`.
+irq!(Exti1Irq, pac::interrupt::EXTI1);
+
+static MUTEX: Mutex<u32> = Mutex::new(0);
+
+/// EXTI1 IRQ is masked when the lock is held.
+static SPIN: SpinIrqSafe<u32, Exti1Irq> = SpinIrqSafe::new(0);
+
+/// Notified by the holder of `MUTEX` once it holds it.
+static HELD: Mailbox = Mailbox::new();
+
+/// Notified by each side of the contended mailbox in turn.
+static PING: Mailbox = Mailbox::new();
+static PONG: Mailbox = Mailbox::new();
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Count the CPU cycles.
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::EXTI1, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::EXTI1);
+    }
+
+    task::build()
+        .set_priority(PRIORITY)
+        .set_entry(move || bench(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Run every measurement and print the results.
+fn bench(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\n# bench_sync hclk={} iterations={}\r\n",
+        HCLK_FREQUENCY_HZ, ITERATIONS
+    );
+    let baseline = cycles(|| {});
+    let mut print = |primitive: &str, contention: &str, cycles: u32| {
+        let _ = write!(
+            tx,
+            "bench_sync,{},{},{}\r\n",
+            primitive,
+            contention,
+            cycles.saturating_sub(baseline)
+        );
+    };
+
+    print(
+        "mutex",
+        "uncontended",
+        cycles(|| {
+            drop(MUTEX.lock());
+        }),
+    );
+    print("mutex", "contended", mutex_contended());
+
+    print(
+        "spin_irq",
+        "uncontended",
+        cycles(|| {
+            drop(SPIN.lock());
+        }),
+    );
+    print(
+        "spin_irq",
+        "contended",
+        cycles(|| {
+            let guard = SPIN.lock();
+            NVIC::pend(pac::interrupt::EXTI1);
+            drop(guard);
+        }),
+    );
+
+    print(
+        "mailbox",
+        "uncontended",
+        cycles(|| {
+            PING.notify_allow_isr();
+            PING.wait();
+        }),
+    );
+    spawn_peer(|| {
+        for _ in 0..ITERATIONS {
+            PING.wait();
+            PONG.notify_allow_isr();
+        }
+    });
+    print(
+        "mailbox",
+        "contended",
+        cycles(|| {
+            PING.notify_allow_isr();
+            PONG.wait();
+        }) / 2,
+    );
+
+    let (sender, receiver): (Sender, Receiver) = sync::create_channel();
+    print(
+        "channel",
+        "uncontended",
+        cycles(|| {
+            sender.produce(0);
+            receiver.consume();
+        }),
+    );
+    let (peer_sender, receiver): (Sender, Receiver) = sync::create_channel();
+    let (sender, peer_receiver): (Sender, Receiver) = sync::create_channel();
+    spawn_peer(move || {
+        for _ in 0..ITERATIONS {
+            peer_sender.produce(peer_receiver.consume());
+        }
+    });
+    print(
+        "channel",
+        "contended",
+        cycles(|| {
+            sender.produce(0);
+            receiver.consume();
+        }) / 2,
+    );
+
+    let _ = write!(tx, "# done\r\n");
+}
+
+/// Return the cycles taken by `op` on average over [`ITERATIONS`] runs.
+fn cycles(mut op: impl FnMut()) -> u32 {
+    let start = DWT::cycle_count();
+    for _ in 0..ITERATIONS {
+        op();
+    }
+    DWT::cycle_count().wrapping_sub(start) / ITERATIONS
+}
+
+/// Return the cycles taken by a lock of `MUTEX` held by a lower priority
+/// task, until the lock is taken over.
+fn mutex_contended() -> u32 {
+    task::build()
+        .set_priority(PRIORITY + 1)
+        .set_entry(|| {
+            for _ in 0..ITERATIONS {
+                let guard = MUTEX.lock();
+                // Wakes up the `bench` task, which preempts this one.
+                HELD.notify_allow_isr();
+                drop(guard);
+            }
+        })
+        .spawn()
+        .unwrap();
+
+    let mut total = 0;
+    for _ in 0..ITERATIONS {
+        HELD.wait();
+        let start = DWT::cycle_count();
+        drop(MUTEX.lock());
+        total += DWT::cycle_count().wrapping_sub(start);
+    }
+    total / ITERATIONS
+}
+
+/// Spawn the peer of a contended measurement above the `bench` task, so that
+/// it runs as soon as it is woken.
+fn spawn_peer(f: impl FnOnce() + Send + 'static) {
+    task::build()
+        .set_priority(PRIORITY - 1)
+        .set_entry(f)
+        .spawn()
+        .unwrap();
+}
+
+#[handler(EXTI1)]
+fn exti1_handler() {
+    *SPIN.lock() += 1;
+}
@@ -0,0 +1,37 @@
+//! The board setup shared by the examples, which include the module with
+//! `#[path = "common/board.rs"] mod board;`.
+//!
+//! Unlike the tutorial, the examples leave the tick on SysTick rather than
+//! wiring the alternative tick source.
+
+use cortex_m::peripheral::SYST;
+use hopter_conf_params::{
+    TickSource, HCLK_FREQUENCY_HZ, HSE_FREQUENCY_HZ, SYSTICK_USE_CPU_CLOCK, TARGET_SYSCLK_HZ,
+    TICK_SOURCE,
+};
+use stm32f4xx_hal::{
+    prelude::*,
+    rcc::{Clocks, CFGR},
+};
+
+const _: () = assert!(
+    matches!(TICK_SOURCE, TickSource::SysTick),
+    "the examples require the SysTick tick source"
+);
+
+/// Run the clocks at the frequencies configured in `hopter-conf-params`, and
+/// switch the SysTick clock source if configured so. See Part 1 of the
+/// tutorial. An example needing more of the clocks, e.g., the 48 MHz clock,
+/// asks for it in `cfgr` beforehand.
+pub fn init_clocks(cfgr: CFGR, syst: &SYST) -> Clocks {
+    let clocks = cfgr
+        .use_hse(HSE_FREQUENCY_HZ.Hz())
+        .sysclk(TARGET_SYSCLK_HZ.Hz())
+        .hclk(HCLK_FREQUENCY_HZ.Hz())
+        .freeze();
+    assert_eq!(clocks.hclk().raw(), HCLK_FREQUENCY_HZ);
+    if !SYSTICK_USE_CPU_CLOCK {
+        unsafe { syst.csr.modify(|val| val & !(1 << 2)) };
+    }
+    clocks
+}
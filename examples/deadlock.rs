@@ -0,0 +1,217 @@
+//! Let two tasks deadlock on two mutexes, then recover from the same pattern
+//! with a lock timeout and a task restart.
+//!
+//! The `left` task locks mutex A then mutex B, and the `right` task locks B
+//! then A, each pausing for [`HOLD_MS`] between the two, as if working on
+//! the first resource. Each time both are held, the task counts a round.
+//! Soon each holds the mutex the other waits for, and neither moves again.
+//! The `watch` task reports the deadlock once the rounds stop for
+//! [`STALL_MS`].
+//!
+//! Hopter mutexes cannot be taken with a timeout, and a deadlocked task
+//! cannot be woken. The `watch` task then starts a second pair, on a second
+//! pair of mutexes, which takes the second mutex with `try_lock` in a loop
+//! for at most [`LOCK_TIMEOUT_MS`]. Upon a timeout, the task panics. The
+//! unwinding drops the guard of the first mutex, releasing it to the other
+//! task, and the task, being restartable, starts over after a back-off of
+//! its own, so that the two do not meet in the same order again right away.
+//! The counters protected by the mutexes are changed only with both held,
+//! so a timeout leaves them consistent. The mutex released by the unwinding
+//! reports it with `is_poisoned`, which the restarted task ignores for that
+//! reason.
+//!
+//! The `watch` task prints the rounds and the timeouts every second for
+//! [`RECOVERY_REPORTS`] seconds. The console wiring is the one of Part 11 of
+//! the tutorial. Build and flash with `cargo run --release --example
+//! deadlock`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hopter::{
+    config,
+    sync::{Mutex, MutexGuard},
+    task::{self, main},
+    time,
+};
+use stm32f4xx_hal::{
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The pause between taking the first mutex and the second.
+const HOLD_MS: u32 = 10;
+
+/// The time without a round after which the first pair is deadlocked.
+const STALL_MS: u32 = 1000;
+
+/// The longest wait for the second mutex of the second pair.
+const LOCK_TIMEOUT_MS: u32 = 50;
+
+/// The back-off of each task of the second pair before it starts over.
+const LEFT_BACKOFF_MS: u32 = 3;
+const RIGHT_BACKOFF_MS: u32 = 17;
+
+/// The reports printed for the second pair.
+const RECOVERY_REPORTS: u32 = 10;
+
+/// The mutexes of each pair, protecting the rounds of the two tasks.
+static DEADLOCK_A: Mutex<u32> = Mutex::new(0);
+static DEADLOCK_B: Mutex<u32> = Mutex::new(0);
+static RECOVERY_A: Mutex<u32> = Mutex::new(0);
+static RECOVERY_B: Mutex<u32> = Mutex::new(0);
+
+/// The rounds of the tasks of the current pair, also readable without the
+/// mutexes, which may never be released.
+static LEFT_ROUNDS: AtomicU32 = AtomicU32::new(0);
+static RIGHT_ROUNDS: AtomicU32 = AtomicU32::new(0);
+
+/// The lock timeouts of the second pair, each followed by a restart.
+static TIMEOUTS: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || watch(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Run each pair and print its progress.
+fn watch(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nleft locks A then B, right locks B then A\r\n\r\nwithout timeout\r\n"
+    );
+    task::build()
+        .set_entry(|| deadlocking(&DEADLOCK_A, &DEADLOCK_B, &LEFT_ROUNDS))
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_entry(|| deadlocking(&DEADLOCK_B, &DEADLOCK_A, &RIGHT_ROUNDS))
+        .spawn()
+        .unwrap();
+
+    let mut last = (0, 0);
+    let mut stalled_ms = 0;
+    while stalled_ms < STALL_MS {
+        time::sleep_ms(STALL_MS / 4).unwrap();
+        let rounds = rounds();
+        if rounds == last {
+            stalled_ms += STALL_MS / 4;
+        } else {
+            stalled_ms = 0;
+            last = rounds;
+        }
+    }
+    let _ = write!(
+        tx,
+        "  left {} rounds, right {} rounds, then no progress for {} ms: deadlock\r\n",
+        last.0, last.1, STALL_MS
+    );
+
+    let _ = write!(
+        tx,
+        "\r\nwith a {} ms timeout and restart\r\n",
+        LOCK_TIMEOUT_MS
+    );
+    LEFT_ROUNDS.store(0, Ordering::SeqCst);
+    RIGHT_ROUNDS.store(0, Ordering::SeqCst);
+    task::build()
+        .set_entry(|| recovering(&RECOVERY_A, &RECOVERY_B, &LEFT_ROUNDS, LEFT_BACKOFF_MS))
+        .spawn_restartable()
+        .unwrap();
+    task::build()
+        .set_entry(|| recovering(&RECOVERY_B, &RECOVERY_A, &RIGHT_ROUNDS, RIGHT_BACKOFF_MS))
+        .spawn_restartable()
+        .unwrap();
+
+    for _ in 0..RECOVERY_REPORTS {
+        time::sleep_ms(1000).unwrap();
+        let (left, right) = rounds();
+        let _ = write!(
+            tx,
+            "  left {} rounds, right {} rounds, {} timeouts\r\n",
+            left,
+            right,
+            TIMEOUTS.load(Ordering::SeqCst)
+        );
+    }
+    let _ = write!(tx, "\r\ndone\r\n");
+}
+
+fn rounds() -> (u32, u32) {
+    (
+        LEFT_ROUNDS.load(Ordering::SeqCst),
+        RIGHT_ROUNDS.load(Ordering::SeqCst),
+    )
+}
+
+/// Take `first` then `second` in a loop, waiting for `second` for as long
+/// as it takes.
+fn deadlocking(first: &Mutex<u32>, second: &Mutex<u32>, rounds: &AtomicU32) {
+    loop {
+        let mut first = first.lock();
+        time::sleep_ms(HOLD_MS).unwrap();
+        let mut second = second.lock();
+        round(&mut first, &mut second, rounds);
+    }
+}
+
+/// Take `first` then `second` in a loop, panicking if `second` is not taken
+/// in time. Back off for `backoff_ms` upon each start.
+fn recovering(first: &Mutex<u32>, second: &Mutex<u32>, rounds: &AtomicU32, backoff_ms: u32) {
+    time::sleep_ms(backoff_ms).unwrap();
+    loop {
+        let mut first = first.lock();
+        time::sleep_ms(HOLD_MS).unwrap();
+        let Some(mut second) = lock_timeout(second, LOCK_TIMEOUT_MS) else {
+            TIMEOUTS.fetch_add(1, Ordering::SeqCst);
+            // Release `first` through unwinding, and start over.
+            panic!("lock timeout");
+        };
+        round(&mut first, &mut second, rounds);
+    }
+}
+
+/// Try to lock the mutex every millisecond for at most `timeout_ms`.
+fn lock_timeout<T>(mutex: &Mutex<T>, timeout_ms: u32) -> Option<MutexGuard<'_, T>> {
+    let start = time::get_tick();
+    loop {
+        if let Some(guard) = mutex.try_lock() {
+            return Some(guard);
+        }
+        if time::get_tick().wrapping_sub(start) >= timeout_ms {
+            return None;
+        }
+        time::sleep_ms(1).unwrap();
+    }
+}
+
+/// Count a round, with both mutexes held.
+fn round(first: &mut u32, second: &mut u32, rounds: &AtomicU32) {
+    *first += 1;
+    *second += 1;
+    rounds.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,273 @@
+//! Copy a large buffer with a memory-to-memory DMA stream while the CPU keeps
+//! running other tasks, and compare the throughput with `copy_from_slice`.
+//!
+//! The `copy` task copies [`WORDS`] words [`ROUNDS`] times in two ways. By the
+//! CPU, with `copy_from_slice`, and by DMA2 stream 0, the only DMA controller
+//! able to transfer from memory to memory. For the DMA copies, the task
+//! starts the stream and blocks on [`DONE`], which the handler of the
+//! transfer complete IRQ releases. While the task is blocked, the lower
+//! priority `count` task runs, spinning on a counter, so the counter tells
+//! how much of the CPU is left to other tasks during each kind of copy.
+//!
+//! The stream moves words in bursts of four through its FIFO. The HAL only
+//! sets up memory-to-memory transfers of bytes, so the stream is programmed
+//! through its registers here. The buffers are aligned so that no burst
+//! crosses a 1 KiB boundary. Note that the DMA cannot reach the CCM RAM, so
+//! the buffers must not be placed there.
+//!
+//! The throughput of each kind of copy, the counting done by the `count`
+//! task during it, and a `PASS` or `FAIL` verdict from checking the copied
+//! words are printed on USART2. The console wiring is the one of Part 11 of
+//! the tutorial. Build and flash with `cargo run --release --example
+//! dma_copy`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::peripheral::{DWT, NVIC};
+use hopter::{
+    config,
+    interrupt::declare::handler,
+    sync::Semaphore,
+    task::{self, main},
+};
+use hopter_conf_params::{HCLK_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY};
+use stm32f4xx_hal::{
+    pac::{self, DMA2, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The words copied each round, 16 KiB.
+const WORDS: usize = 4096;
+
+/// The rounds of each kind of copy.
+const ROUNDS: u32 = 32;
+
+// A stream transfers at most 65535 items at once.
+const _: () = assert!(WORDS <= 0xffff);
+
+/// A buffer aligned on a burst of four words.
+#[repr(C, align(16))]
+struct Aligned([u32; WORDS]);
+
+/// The source and the destination of the copies.
+struct Buffers {
+    src: UnsafeCell<Aligned>,
+    dst: UnsafeCell<Aligned>,
+}
+
+// Safety: Only the `copy` task accesses the buffers, and it does not touch
+// them while the DMA transfer is running.
+unsafe impl Sync for Buffers {}
+
+static BUFFERS: Buffers = Buffers {
+    src: UnsafeCell::new(Aligned([0; WORDS])),
+    dst: UnsafeCell::new(Aligned([0; WORDS])),
+};
+
+/// Released by the handler when a DMA transfer ends.
+static DONE: Semaphore = Semaphore::new(1, 0);
+
+/// The number of DMA transfers ended by an error.
+static ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Incremented by the `count` task whenever it runs.
+static COUNT: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    // Clock the DMA controller before the RCC is taken over by the HAL.
+    dp.RCC.ahb1enr.modify(|_, w| w.dma2en().enabled());
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Count the CPU cycles.
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let dma = dp.DMA2;
+    let stream = &dma.st[0];
+    stream.cr.write(|w| {
+        w.chsel()
+            .bits(0)
+            .dir()
+            .memory_to_memory()
+            .psize()
+            .bits32()
+            .msize()
+            .bits32()
+            .pinc()
+            .incremented()
+            .minc()
+            .incremented()
+            .pburst()
+            .incr4()
+            .mburst()
+            .incr4()
+            .pl()
+            .very_high()
+            .tcie()
+            .enabled()
+            .teie()
+            .enabled()
+    });
+    // Bursts need the FIFO, so the direct mode is disabled.
+    stream.fcr.write(|w| w.dmdis().disabled().fth().full());
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::DMA2_STREAM0, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::DMA2_STREAM0);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || copy(dma, tx))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY + 1)
+        .set_entry(count)
+        .spawn()
+        .unwrap();
+}
+
+/// Run both kinds of copy and print the results.
+fn copy(dma: DMA2, mut tx: Tx<USART2>) {
+    // Safety: Only this task accesses the buffers. See `Buffers`.
+    let src = unsafe { &mut (*BUFFERS.src.get()).0 };
+    let dst = unsafe { &mut (*BUFFERS.dst.get()).0 };
+
+    let _ = write!(
+        tx,
+        "\r\ndma_copy: {} rounds of {} bytes at {} MHz\r\n",
+        ROUNDS,
+        WORDS * 4,
+        HCLK_FREQUENCY_HZ / 1_000_000
+    );
+
+    let mut pass = true;
+
+    // Copy by the CPU.
+    let mut cycles = 0;
+    let count_start = COUNT.load(Ordering::SeqCst);
+    for round in 0..ROUNDS {
+        fill(src, round);
+        let start = DWT::cycle_count();
+        dst.copy_from_slice(src);
+        cycles += DWT::cycle_count().wrapping_sub(start);
+        pass &= check(dst, round);
+    }
+    let counted = COUNT.load(Ordering::SeqCst).wrapping_sub(count_start);
+    report(&mut tx, "copy_from_slice", cycles, counted);
+
+    // Copy by the DMA, starting from the other round patterns.
+    let mut cycles = 0;
+    let count_start = COUNT.load(Ordering::SeqCst);
+    for round in ROUNDS..2 * ROUNDS {
+        fill(src, round);
+        let start = DWT::cycle_count();
+        dma_copy(&dma, src, dst);
+        cycles += DWT::cycle_count().wrapping_sub(start);
+        pass &= check(dst, round);
+    }
+    let counted = COUNT.load(Ordering::SeqCst).wrapping_sub(count_start);
+    report(&mut tx, "dma", cycles, counted);
+
+    let errors = ERRORS.load(Ordering::SeqCst);
+    let _ = write!(tx, "transfer errors: {}\r\n", errors);
+    pass &= errors == 0;
+    let _ = write!(tx, "dma_copy: {}\r\n", if pass { "PASS" } else { "FAIL" });
+}
+
+/// Copy `src` to `dst` with DMA2 stream 0, blocking until the transfer ends.
+fn dma_copy(dma: &DMA2, src: &[u32; WORDS], dst: &mut [u32; WORDS]) {
+    let stream = &dma.st[0];
+    // In the memory-to-memory direction, the peripheral port reads the
+    // source and the memory port writes the destination.
+    stream.par.write(|w| unsafe { w.bits(src.as_ptr() as u32) });
+    stream
+        .m0ar
+        .write(|w| unsafe { w.bits(dst.as_mut_ptr() as u32) });
+    stream.ndtr.write(|w| unsafe { w.bits(WORDS as u32) });
+    // Have the source written out before the stream reads it.
+    cortex_m::asm::dsb();
+    stream.cr.modify(|_, w| w.en().enabled());
+    DONE.down();
+    // Have the destination read only after the stream wrote it.
+    cortex_m::asm::dsb();
+}
+
+/// Fill the source with a pattern unique to the round.
+fn fill(src: &mut [u32; WORDS], round: u32) {
+    for (i, word) in src.iter_mut().enumerate() {
+        *word = pattern(round, i);
+    }
+}
+
+/// Return true if the destination holds the pattern of the round.
+fn check(dst: &[u32; WORDS], round: u32) -> bool {
+    // The stream wrote the destination behind the compiler's back.
+    dst.iter()
+        .enumerate()
+        .all(|(i, word)| unsafe { core::ptr::read_volatile(word) } == pattern(round, i))
+}
+
+fn pattern(round: u32, i: usize) -> u32 {
+    (round << 24) ^ (i as u32).wrapping_mul(0x9e37_79b9)
+}
+
+/// Print the throughput of a kind of copy and the counting done during it.
+fn report(tx: &mut Tx<USART2>, name: &str, cycles: u32, counted: u32) {
+    let bytes = u64::from(ROUNDS) * (WORDS as u64) * 4;
+    // In tenths of a MB per second.
+    let rate = bytes * u64::from(HCLK_FREQUENCY_HZ) / u64::from(cycles.max(1)) / 100_000;
+    let _ = write!(
+        tx,
+        "{}: {} cycles, {}.{} MB/s, counted {} while copying\r\n",
+        name,
+        cycles,
+        rate / 10,
+        rate % 10,
+        counted
+    );
+}
+
+/// Spin on the counter, running only when the `copy` task is blocked.
+fn count() {
+    loop {
+        COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[handler(DMA2_STREAM0)]
+fn dma2_stream0_handler() {
+    // Safety: Only the flags are read and cleared here.
+    let dma = unsafe { &*DMA2::ptr() };
+    let lisr = dma.lisr.read();
+    if lisr.teif0().bit_is_set() {
+        ERRORS.fetch_add(1, Ordering::SeqCst);
+    }
+    dma.lifcr.write(|w| w.ctcif0().set_bit().cteif0().set_bit());
+    // The stream is disabled by the hardware upon both events.
+    let _ = DONE.try_up_allow_isr();
+}
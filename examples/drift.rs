@@ -0,0 +1,186 @@
+//! Measure the drift of a periodic task timed by `sleep_ms` against one
+//! timed by `IntervalBarrier`, under CPU load.
+//!
+//! Part 2 of the tutorial states that `sleep_ms` slowly drifts away from the
+//! interval when the system is under load, while `IntervalBarrier` does not.
+//! Here two tasks of the same priority run a period of [`PERIOD_MS`] each,
+//! doing [`WORK_MS`] of work per period:
+//! - `sleeper` sleeps for the period after its work, so each period lasts
+//!   the work, the sleep, and any time the task is kept from running.
+//! - `barrier` waits on an `IntervalBarrier` of the period, which lets it
+//!   through at multiples of the period since the barrier was created.
+//!
+//! The `load` task, above both, spins for [`LOAD_SPIN_MS`] every
+//! [`LOAD_EVERY_MS`]. At the start of each period, a task records how late
+//! it is against the ideal schedule, i.e., the start plus a whole number of
+//! periods. The `report` task prints the latest and the largest lateness of
+//! each every [`REPORT_EVERY_MS`] on USART2.
+//!
+//! The lateness of `sleeper` grows without bound. That of `barrier` stays
+//! within a period. After [`RUN_MS`], the example prints `PASS` if it did,
+//! and `FAIL` otherwise, so it also serves as a check of the time subsystem
+//! after a kernel update. The console wiring is the one of Part 11 of the
+//! tutorial. Build and flash with `cargo run --release --example drift`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hopter::{
+    config,
+    task::{self, main},
+    time::{self, IntervalBarrier},
+};
+use stm32f4xx_hal::{
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The period of both tasks.
+const PERIOD_MS: u32 = 100;
+
+/// The work of both tasks per period.
+const WORK_MS: u32 = 5;
+
+/// The load.
+const LOAD_EVERY_MS: u32 = 37;
+const LOAD_SPIN_MS: u32 = 8;
+
+/// The time between two reports.
+const REPORT_EVERY_MS: u32 = 10_000;
+
+/// The length of the run.
+const RUN_MS: u32 = 60_000;
+
+/// The priority of the two periodic tasks. The `load` task runs above, and
+/// the `report` task above all.
+const PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY;
+
+/// The latest and the largest lateness of each periodic task, in
+/// milliseconds.
+static SLEEPER_LATE: AtomicU32 = AtomicU32::new(0);
+static SLEEPER_MAX_LATE: AtomicU32 = AtomicU32::new(0);
+static BARRIER_LATE: AtomicU32 = AtomicU32::new(0);
+static BARRIER_MAX_LATE: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    task::build()
+        .set_priority(PRIORITY - 2)
+        .set_entry(move || report(tx))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(PRIORITY - 1)
+        .set_entry(load)
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(PRIORITY)
+        .set_entry(sleeper)
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(PRIORITY)
+        .set_entry(barrier)
+        .spawn()
+        .unwrap();
+}
+
+/// Time the periods with `sleep_ms`.
+fn sleeper() {
+    let start = time::get_tick();
+    for period in 0.. {
+        record(start, period, &SLEEPER_LATE, &SLEEPER_MAX_LATE);
+        busy_wait_ms(WORK_MS);
+        time::sleep_ms(PERIOD_MS).unwrap();
+    }
+}
+
+/// Time the periods with `IntervalBarrier`.
+fn barrier() {
+    let start = time::get_tick();
+    let mut barrier = IntervalBarrier::new(PERIOD_MS).unwrap();
+    for period in 0.. {
+        record(start, period, &BARRIER_LATE, &BARRIER_MAX_LATE);
+        busy_wait_ms(WORK_MS);
+        barrier.wait();
+    }
+}
+
+/// Record the lateness of the period with the given number.
+fn record(start: u32, period: u32, late: &AtomicU32, max_late: &AtomicU32) {
+    let ideal = start.wrapping_add(period.wrapping_mul(PERIOD_MS));
+    // A tick passing between reading `start` and creating the barrier may
+    // make the barrier a tick early.
+    let lateness = (time::get_tick().wrapping_sub(ideal) as i32).max(0) as u32;
+    late.store(lateness, Ordering::SeqCst);
+    max_late.fetch_max(lateness, Ordering::SeqCst);
+}
+
+/// Spin for a while at a regular pace.
+fn load() {
+    let mut barrier = IntervalBarrier::new(LOAD_EVERY_MS).unwrap();
+    loop {
+        barrier.wait();
+        busy_wait_ms(LOAD_SPIN_MS);
+    }
+}
+
+/// Print the lateness of both tasks periodically, then the verdict.
+fn report(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\ndrift: period {} ms, work {} ms, load {} ms every {} ms\r\n",
+        PERIOD_MS, WORK_MS, LOAD_SPIN_MS, LOAD_EVERY_MS
+    );
+
+    let mut barrier = IntervalBarrier::new(REPORT_EVERY_MS).unwrap();
+    for elapsed in (REPORT_EVERY_MS..=RUN_MS).step_by(REPORT_EVERY_MS as usize) {
+        barrier.wait();
+        let _ = write!(
+            tx,
+            "{:>3} s  sleep_ms late {:>5} ms (max {:>5}), IntervalBarrier late {:>3} ms (max {:>3})\r\n",
+            elapsed / 1000,
+            SLEEPER_LATE.load(Ordering::SeqCst),
+            SLEEPER_MAX_LATE.load(Ordering::SeqCst),
+            BARRIER_LATE.load(Ordering::SeqCst),
+            BARRIER_MAX_LATE.load(Ordering::SeqCst)
+        );
+    }
+
+    let bounded = BARRIER_MAX_LATE.load(Ordering::SeqCst) < PERIOD_MS;
+    let _ = write!(
+        tx,
+        "drift: {}, IntervalBarrier {} within a period\r\n",
+        if bounded { "PASS" } else { "FAIL" },
+        if bounded { "stayed" } else { "did not stay" }
+    );
+}
+
+fn busy_wait_ms(ms: u32) {
+    let start = time::get_tick();
+    while time::get_tick().wrapping_sub(start) < ms {}
+}
@@ -0,0 +1,185 @@
+//! Drive the LEDs from a group of event flags, set by an IRQ handler and by
+//! tasks, on which tasks wait for any or all of a combination of flags.
+//!
+//! The `event_flags` module in `src/event_flags.rs` provides the group. Four
+//! flags are used:
+//!
+//! - `PRESSED`, set by the EXTI0 handler when the user button on PA0 is
+//!   pressed.
+//! - `TICK`, set by the `ticker` task every [`TICK_MS`].
+//! - `READY`, set by the `worker` task every [`WORK_MS`], standing for a
+//!   result ready to be sent.
+//! - `ARMED`, toggled by the `display` task upon each press.
+//!
+//! The `display` task waits for any of `PRESSED` and `TICK`. It toggles
+//! `ARMED` upon a press, and the green LED upon a tick, then lights the red
+//! LED while `ARMED` is set, and the blue one while `READY` is. The
+//! `launcher` task waits for all of `ARMED` and `READY`, and flashes the
+//! orange LED when both are set, taking both flags. So the blue LED lights
+//! up when a result is ready, and a press sends it, lighting the orange LED
+//! for a second. A press with no result ready arms the launcher, shown by
+//! the red LED, until the next result comes.
+//!
+//! The tutorial in `src/main.rs` already uses EXTI0 and the LEDs, hence a
+//! separate program. Build and flash with `cargo run --release --example
+//! event_flags`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/event_flags.rs"]
+mod event_flags;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use event_flags::{EventFlags, Mode};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::SpinIrqSafe,
+    task::{self, main},
+    time::{self, IntervalBarrier},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    gpio::{Edge, ErasedPin, Input, Output, PA0},
+    pac,
+    prelude::*,
+};
+
+/// The period of the `TICK` flag.
+const TICK_MS: u32 = 500;
+
+/// The period of the `READY` flag.
+const WORK_MS: u32 = 5000;
+
+/// The time the orange LED stays lit upon a launch.
+const LAUNCH_MS: u32 = 1000;
+
+/// The edges within this time after a press are bounces.
+const DEBOUNCE_MS: u32 = 200;
+
+const PRESSED: u32 = 1 << 0;
+const TICK: u32 = 1 << 1;
+const READY: u32 = 1 << 2;
+const ARMED: u32 = 1 << 3;
+
+static FLAGS: EventFlags = EventFlags::new();
+
+irq!(Exti0Irq, pac::interrupt::EXTI0);
+
+/// The button pin. The IRQ is masked when the lock is held.
+static BUTTON: SpinIrqSafe<Option<PA0<Input>>, Exti0Irq> = SpinIrqSafe::new(None);
+
+/// The tick of the last press accepted by the handler.
+static LAST_PRESS: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let mut dp = unsafe { pac::Peripherals::steal() };
+    board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiod = dp.GPIOD.split();
+    let green = gpiod.pd12.into_push_pull_output().erase();
+    let orange = gpiod.pd13.into_push_pull_output().erase();
+    let red = gpiod.pd14.into_push_pull_output().erase();
+    let blue = gpiod.pd15.into_push_pull_output().erase();
+
+    // Raise EXTI0 when the button drives PA0 high.
+    let mut syscfg = dp.SYSCFG.constrain();
+    let mut button = gpioa.pa0.into_floating_input();
+    button.make_interrupt_source(&mut syscfg);
+    button.trigger_on_edge(&mut dp.EXTI, Edge::Rising);
+    button.enable_interrupt(&mut dp.EXTI);
+    *BUTTON.lock() = Some(button);
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::EXTI0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::EXTI0);
+    }
+
+    task::build()
+        .set_entry(|| every(TICK_MS, TICK))
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_entry(|| every(WORK_MS, READY))
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_entry(move || display(green, red, blue))
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_entry(move || launcher(orange))
+        .spawn()
+        .unwrap();
+}
+
+/// Set the flag every `period_ms`, as the `ticker` and `worker` tasks do.
+fn every(period_ms: u32, flag: u32) {
+    let mut barrier = IntervalBarrier::new(period_ms).unwrap();
+    loop {
+        barrier.wait();
+        FLAGS.set_allow_isr(flag);
+    }
+}
+
+/// Toggle `ARMED` upon a press, and the green LED upon a tick, and show
+/// `ARMED` and `READY` on the red and blue LEDs.
+fn display(mut green: ErasedPin<Output>, mut red: ErasedPin<Output>, mut blue: ErasedPin<Output>) {
+    loop {
+        let flags = FLAGS.take(PRESSED | TICK, Mode::Any);
+        if flags & PRESSED != 0 {
+            if FLAGS.get() & ARMED != 0 {
+                FLAGS.clear(ARMED);
+            } else {
+                FLAGS.set_allow_isr(ARMED);
+            }
+        }
+        if flags & TICK != 0 {
+            green.toggle();
+        }
+
+        let flags = FLAGS.get();
+        red.set_state((flags & ARMED != 0).into());
+        blue.set_state((flags & READY != 0).into());
+    }
+}
+
+/// Flash the orange LED whenever both `ARMED` and `READY` are set.
+fn launcher(mut orange: ErasedPin<Output>) {
+    loop {
+        FLAGS.take(ARMED | READY, Mode::All);
+        orange.set_high();
+        time::sleep_ms(LAUNCH_MS).unwrap();
+        orange.set_low();
+    }
+}
+
+#[handler(EXTI0)]
+fn exti0_handler() {
+    // Acknowledge the IRQ.
+    BUTTON
+        .lock()
+        .as_mut()
+        .unwrap()
+        .clear_interrupt_pending_bit();
+
+    // The contact bounces, so take only the first edge of a press. Presses
+    // within the debounce window after boot are ignored as well.
+    let now = time::get_tick();
+    if now.wrapping_sub(LAST_PRESS.load(Ordering::SeqCst)) >= DEBOUNCE_MS {
+        LAST_PRESS.store(now, Ordering::SeqCst);
+        FLAGS.set_allow_isr(PRESSED);
+    }
+}
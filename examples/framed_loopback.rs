@@ -0,0 +1,266 @@
+//! Exchange framed messages over a UART looped back by a wire, tampering
+//! with some of them on the way, and check that the receiver drops exactly
+//! those.
+//!
+//! The `framing` module in `src/framing.rs` frames messages by COBS, checked
+//! by a CRC-32 from the CRC unit of the MCU. Wire PC6, the TX pin of USART6,
+//! to PC7, its RX pin. The `send` task frames a telemetry record every
+//! [`SEND_PERIOD_MS`], with a sequence number, the tick, and some text of a
+//! varying length. It flips a bit of every frame whose sequence number is
+//! [`FLIP_AT`] modulo [`FLIP_EVERY`], and leaves out a byte of every frame
+//! whose number is [`SKIP_AT`] modulo [`SKIP_EVERY`], as noise on a long
+//! wire would. The USART6 IRQ handler produces the bytes received into a
+//! channel, and the `receive` task hands them to a `Decoder`.
+//!
+//! The receiver knows which frames were tampered with from their numbers.
+//! It counts the intact frames missing, and the tampered frames accepted,
+//! both of which should stay at zero. The counts and the statistics of the
+//! decoder are printed on USART2 every [`REPORT_EVERY`] frames. A flipped
+//! bit may turn a byte into zero, splitting the frame in two, so there may
+//! be more frames dropped than tampered with. The console wiring is the one
+//! of Part 11 of the tutorial. Build and flash with `cargo run --release
+//! --example framed_loopback`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/framing.rs"]
+mod framing;
+
+use core::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::peripheral::NVIC;
+use framing::Decoder;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Producer, SpinIrqSafe},
+    task::{self, main},
+    time::{self, IntervalBarrier},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    nb,
+    pac::{self, USART2, USART6},
+    prelude::*,
+    serial::{Rx, Tx},
+};
+
+/// The time between frames.
+const SEND_PERIOD_MS: u32 = 20;
+
+/// The longest payload, the record with the most text.
+const MAX_PAYLOAD: usize = 8 + TEXT.len();
+
+/// The text sent, of which a record takes a prefix.
+const TEXT: &[u8] = b"framed by COBS, checked by CRC-32, resynchronized at zero";
+
+/// Flip a bit of the frames numbered `FLIP_AT` modulo `FLIP_EVERY`.
+const FLIP_EVERY: u32 = 10;
+const FLIP_AT: u32 = 3;
+
+/// Leave out a byte of the frames numbered `SKIP_AT` modulo `SKIP_EVERY`.
+const SKIP_EVERY: u32 = 17;
+const SKIP_AT: u32 = 5;
+
+/// The intact frames between reports.
+const REPORT_EVERY: u32 = 200;
+
+/// The bytes received queued before the task takes them.
+const RX_QUEUE_LEN: usize = 256;
+
+irq!(Usart6Irq, pac::interrupt::USART6);
+
+/// The receiver and the producing end of the channel.
+type Receiver = (Rx<USART6>, Producer<u8, RX_QUEUE_LEN>);
+
+/// The receiver, `None` until it is set up. USART6 IRQ is masked when the
+/// lock is held.
+static RX: SpinIrqSafe<Option<Receiver>, Usart6Irq> = SpinIrqSafe::new(None);
+
+/// The bytes received but dropped, because the channel was full or the
+/// reception failed.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// How a frame is tampered with.
+#[derive(PartialEq)]
+enum Tamper {
+    None,
+    Flip,
+    Skip,
+}
+
+fn tamper(seq: u32) -> Tamper {
+    if seq % FLIP_EVERY == FLIP_AT {
+        Tamper::Flip
+    } else if seq % SKIP_EVERY == SKIP_AT {
+        Tamper::Skip
+    } else {
+        Tamper::None
+    }
+}
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    framing::init(dp.CRC);
+
+    let gpioa = dp.GPIOA.split();
+    let console = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let gpioc = dp.GPIOC.split();
+    let serial = dp
+        .USART6
+        .serial((gpioc.pc6, gpioc.pc7), 115_200.bps(), &clocks)
+        .unwrap();
+    let (tx, mut rx) = serial.split();
+    rx.listen();
+    let (producer, consumer) = sync::create_channel();
+    *RX.lock() = Some((rx, producer));
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::USART6, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::USART6);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || send(tx))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || receive(consumer, console))
+        .spawn()
+        .unwrap();
+}
+
+/// Frame a record periodically, tamper with some frames, and send them.
+fn send(mut tx: Tx<USART6>) {
+    let mut barrier = IntervalBarrier::new(SEND_PERIOD_MS).unwrap();
+    let mut payload = [0; MAX_PAYLOAD];
+    let mut frame = [0; framing::max_frame_len(MAX_PAYLOAD)];
+
+    for seq in 0u32.. {
+        barrier.wait();
+
+        // The record: the sequence number, the tick, and a prefix of the
+        // text. The numbers hold zero bytes, which COBS has to replace.
+        let text_len = seq as usize % (TEXT.len() + 1);
+        payload[..4].copy_from_slice(&seq.to_le_bytes());
+        payload[4..8].copy_from_slice(&time::get_tick().to_le_bytes());
+        payload[8..8 + text_len].copy_from_slice(&TEXT[..text_len]);
+        let len = framing::encode(&payload[..8 + text_len], &mut frame).unwrap();
+
+        // Never touch the zero byte ending the frame, so that the next one
+        // is received alone.
+        let frame = &mut frame[..len];
+        let at = seq as usize % (len - 1);
+        match tamper(seq) {
+            Tamper::Flip => frame[at] ^= 1 << (seq % 8),
+            Tamper::Skip => frame.copy_within(at + 1.., at),
+            Tamper::None => {}
+        }
+        let len = if tamper(seq) == Tamper::Skip {
+            len - 1
+        } else {
+            len
+        };
+
+        for &byte in &frame[..len] {
+            nb::block!(tx.write(byte)).unwrap();
+        }
+    }
+}
+
+/// Decode the frames received, check them against the tampering, and print
+/// the counts.
+fn receive(bytes: Consumer<u8, RX_QUEUE_LEN>, mut console: Tx<USART2>) {
+    let _ = write!(
+        console,
+        "\r\nframed_loopback: a frame every {} ms on USART6, PC6 wired to PC7\r\n",
+        SEND_PERIOD_MS
+    );
+
+    let mut decoder = Decoder::<MAX_PAYLOAD>::new();
+    // The number of the next frame expected.
+    let mut next = 0u32;
+    let mut missing = 0u32;
+    let mut accepted = 0u32;
+
+    loop {
+        let Some(payload) = decoder.push(bytes.consume()) else {
+            continue;
+        };
+        let seq = u32::from_le_bytes(payload[..4].try_into().unwrap());
+
+        // The frames skipped over should all have been tampered with.
+        missing += (next..seq)
+            .filter(|&skipped| tamper(skipped) == Tamper::None)
+            .count() as u32;
+        if tamper(seq) != Tamper::None {
+            accepted += 1;
+        }
+        next = seq + 1;
+
+        let stats = decoder.stats();
+        if stats.frames % REPORT_EVERY != 0 {
+            continue;
+        }
+        let _ = write!(
+            console,
+            "frame {}: {} intact, {} corrupted, {} malformed, {} oversized, \
+             {} bytes dropped, {} missing, {} tampered accepted: {}\r\n",
+            seq,
+            stats.frames,
+            stats.corrupted,
+            stats.malformed,
+            stats.oversized,
+            DROPPED.load(Ordering::SeqCst),
+            missing,
+            accepted,
+            if missing == 0 && accepted == 0 {
+                "PASS"
+            } else {
+                "FAIL"
+            }
+        );
+    }
+}
+
+#[handler(USART6)]
+fn usart6_handler() {
+    let mut rx = RX.lock();
+    let (rx, producer) = rx.as_mut().unwrap();
+
+    // Drain the receive register. Reading it also acknowledges the IRQ.
+    loop {
+        match rx.read() {
+            Ok(byte) => {
+                if producer.try_produce_allow_isr(byte).is_err() {
+                    DROPPED.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            Err(nb::Error::WouldBlock) => break,
+            // The HAL clears the error flags upon reporting them.
+            Err(nb::Error::Other(_)) => {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
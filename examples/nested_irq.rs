@@ -0,0 +1,283 @@
+//! Let a high priority timer IRQ preempt the handler of a low priority one,
+//! and an SVC extending a task stack, and count the nestings.
+//!
+//! The configuration crate orders the priorities such that IRQs of
+//! `IRQ_HIGH_PRIORITY` preempt those of `IRQ_NORMAL_PRIORITY`, and every IRQ
+//! preempts an active SVC, which runs at `SVC_NORMAL_PRIORITY` below all
+//! IRQs. Here, two timers check the order on the hardware:
+//! - TIM3 raises its update IRQ at normal priority every [`LOW_PERIOD_US`],
+//!   and the handler spins until [`LOW_BUSY_US`] after the update.
+//! - TIM4 raises its update IRQ at high priority every [`HIGH_PERIOD_US`],
+//!   and the handler returns right away.
+//!
+//! Each handler sets a flag while it runs, and counts the times it finds the
+//! flag of the other set, i.e., the times it preempted the other. It also
+//! reads `SVCALLACT` of the SCB to count the times it preempted an SVC.
+//! Each handler records its latency, read from the counter of its timer,
+//! which starts from zero upon the update. The latency of TIM4 handler must
+//! stay short even while TIM3 handler spins. Meanwhile the `grow` task
+//! recurses to a changing depth, so that the SVCs extending and shrinking
+//! its stack keep coming.
+//!
+//! PD12, the green LED, is high while TIM3 handler runs, and PD15, the blue
+//! LED, while TIM4 handler runs, so that a logic analyzer captures the
+//! nesting. The counters are printed every second on USART2 for
+//! [`REPORTS`] seconds, followed by a `PASS` or `FAIL` verdict. The console
+//! wiring is the one of Part 11 of the tutorial. Build and flash with `cargo
+//! run --release --example nested_irq`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    hint::black_box,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::{NVIC, SCB};
+use hopter::{
+    config,
+    debug::segmented_stack,
+    interrupt::declare::handler,
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::{
+    ALLOW_DYNAMIC_STACK, IRQ_HIGH_PRIORITY, IRQ_NORMAL_PRIORITY, SVC_NORMAL_PRIORITY,
+};
+use stm32f4xx_hal::{
+    pac::{self, GPIOD, TIM3, TIM4, USART2},
+    prelude::*,
+    serial::Tx,
+    timer::FTimerUs,
+};
+
+// A smaller value is a higher priority.
+const _: () = assert!(IRQ_HIGH_PRIORITY < IRQ_NORMAL_PRIORITY);
+const _: () = assert!(IRQ_NORMAL_PRIORITY < SVC_NORMAL_PRIORITY);
+
+/// The period of TIM3 IRQ, and how long after the update its handler spins.
+/// The spinning stays below a tick period, so that no tick is lost.
+const LOW_PERIOD_US: u32 = 10_000;
+const LOW_BUSY_US: u32 = 500;
+
+/// The period of TIM4 IRQ. It does not divide that of TIM3, so that TIM4
+/// IRQ arrives at every point of TIM3 handler over time.
+const HIGH_PERIOD_US: u32 = 97;
+
+/// The reports printed.
+const REPORTS: u32 = 10;
+
+/// The largest depth of the recursion of the `grow` task.
+const MAX_DEPTH: u32 = 48;
+
+/// The pins driven high while each handler runs.
+const LOW_PIN: u32 = 12;
+const HIGH_PIN: u32 = 15;
+
+/// The `SVCALLACT` bit of SHCSR, set while an SVC is active.
+const SVCALLACT: u32 = 1 << 7;
+
+/// Set while each handler runs.
+static LOW_ACTIVE: AtomicBool = AtomicBool::new(false);
+static HIGH_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The runs of each handler.
+static LOW_RUNS: AtomicU32 = AtomicU32::new(0);
+static HIGH_RUNS: AtomicU32 = AtomicU32::new(0);
+
+/// The runs of TIM4 handler preempting TIM3 handler, and conversely. The
+/// latter must stay zero.
+static HIGH_IN_LOW: AtomicU32 = AtomicU32::new(0);
+static LOW_IN_HIGH: AtomicU32 = AtomicU32::new(0);
+
+/// The runs of each handler preempting an SVC.
+static LOW_IN_SVC: AtomicU32 = AtomicU32::new(0);
+static HIGH_IN_SVC: AtomicU32 = AtomicU32::new(0);
+
+/// The largest latency of each handler, in microseconds.
+static LOW_MAX_LATENCY: AtomicU32 = AtomicU32::new(0);
+static HIGH_MAX_LATENCY: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    // The handlers drive the pins through the registers, and the modes set
+    // here stay after the pins are dropped.
+    let gpiod = dp.GPIOD.split();
+    gpiod.pd12.into_push_pull_output();
+    gpiod.pd15.into_push_pull_output();
+
+    // Both timers count microseconds, and update at the end of the period.
+    let tim3 = FTimerUs::new(dp.TIM3, &clocks).release();
+    let tim4 = FTimerUs::new(dp.TIM4, &clocks).release();
+    tim3.arr.write(|w| unsafe { w.bits(LOW_PERIOD_US - 1) });
+    tim4.arr.write(|w| unsafe { w.bits(HIGH_PERIOD_US - 1) });
+    // Load the prescalers, then clear the flags raised by doing so.
+    tim3.egr.write(|w| w.ug().set_bit());
+    tim4.egr.write(|w| w.ug().set_bit());
+    tim3.sr.write(|w| w.uif().clear_bit());
+    tim4.sr.write(|w| w.uif().clear_bit());
+    tim3.dier.write(|w| w.uie().set_bit());
+    tim4.dier.write(|w| w.uie().set_bit());
+    tim3.cr1.write(|w| w.cen().set_bit());
+    tim4.cr1.write(|w| w.cen().set_bit());
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::TIM3, IRQ_NORMAL_PRIORITY);
+        nvic.set_priority(pac::interrupt::TIM4, IRQ_HIGH_PRIORITY);
+        NVIC::unmask(pac::interrupt::TIM3);
+        NVIC::unmask(pac::interrupt::TIM4);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || report(tx))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(grow)
+        .spawn()
+        .unwrap();
+}
+
+/// Print the counters every second, then the verdict.
+fn report(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nnested_irq: TIM3 every {} us spinning {} us, TIM4 every {} us\r\n",
+        LOW_PERIOD_US, LOW_BUSY_US, HIGH_PERIOD_US
+    );
+    let extensions = segmented_stack::get_stack_extend_count();
+
+    for _ in 0..REPORTS {
+        time::sleep_ms(1000).unwrap();
+        let _ = write!(
+            tx,
+            "TIM3 {:>5} runs, max latency {:>3} us, {:>5} in SVC | \
+             TIM4 {:>6} runs, max latency {:>3} us, {:>5} in SVC, {:>6} in TIM3 | \
+             TIM3 in TIM4 {}\r\n",
+            LOW_RUNS.load(Ordering::SeqCst),
+            LOW_MAX_LATENCY.load(Ordering::SeqCst),
+            LOW_IN_SVC.load(Ordering::SeqCst),
+            HIGH_RUNS.load(Ordering::SeqCst),
+            HIGH_MAX_LATENCY.load(Ordering::SeqCst),
+            HIGH_IN_SVC.load(Ordering::SeqCst),
+            HIGH_IN_LOW.load(Ordering::SeqCst),
+            LOW_IN_HIGH.load(Ordering::SeqCst)
+        );
+    }
+
+    let extensions = segmented_stack::get_stack_extend_count().wrapping_sub(extensions);
+    let _ = write!(tx, "{} stack extensions by the grow task\r\n", extensions);
+    if !ALLOW_DYNAMIC_STACK {
+        let _ = write!(
+            tx,
+            "dynamic stack extension is off, so no SVC extended a stack\r\n"
+        );
+    }
+
+    // The latency of TIM4 handler must stay well below the spinning of TIM3
+    // handler, which it would otherwise wait for.
+    let nested = HIGH_IN_LOW.load(Ordering::SeqCst) > 0
+        && LOW_IN_HIGH.load(Ordering::SeqCst) == 0
+        && HIGH_MAX_LATENCY.load(Ordering::SeqCst) < LOW_BUSY_US / 4;
+    let over_svc = extensions == 0 || HIGH_IN_SVC.load(Ordering::SeqCst) > 0;
+    let _ = write!(
+        tx,
+        "nested_irq: {}\r\n",
+        if nested && over_svc { "PASS" } else { "FAIL" }
+    );
+}
+
+/// Recurse to a changing depth forever, extending and shrinking the stack.
+fn grow() {
+    let mut seed = 1u32;
+    loop {
+        // A linear congruential generator is random enough here.
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        black_box(recurse((seed >> 16) % MAX_DEPTH));
+    }
+}
+
+fn recurse(depth: u32) -> u32 {
+    // Kept on the stack of the frame.
+    let frame = black_box([depth; 16]);
+    if depth == 0 {
+        return 0;
+    }
+    frame.iter().sum::<u32>() + recurse(depth - 1)
+}
+
+/// Drive the pin of GPIOD high or low.
+fn set_pin(pin: u32, high: bool) {
+    let bits = if high { 1 << pin } else { 1 << (pin + 16) };
+    // Safety: The write to BSRR changes only the given pin.
+    unsafe { (*GPIOD::ptr()).bsrr.write(|w| w.bits(bits)) };
+}
+
+/// Return true if the handler preempted an SVC.
+fn svc_active() -> bool {
+    // Safety: The register is only read here.
+    unsafe { (*SCB::PTR).shcsr.read() & SVCALLACT != 0 }
+}
+
+#[handler(TIM3)]
+fn tim3_handler() {
+    // Safety: Only the registers of TIM3 are accessed here.
+    let tim3 = unsafe { &*TIM3::ptr() };
+    LOW_MAX_LATENCY.fetch_max(tim3.cnt.read().bits(), Ordering::SeqCst);
+    tim3.sr.write(|w| w.uif().clear_bit());
+
+    set_pin(LOW_PIN, true);
+    LOW_ACTIVE.store(true, Ordering::SeqCst);
+    LOW_RUNS.fetch_add(1, Ordering::SeqCst);
+    if HIGH_ACTIVE.load(Ordering::SeqCst) {
+        LOW_IN_HIGH.fetch_add(1, Ordering::SeqCst);
+    }
+    if svc_active() {
+        LOW_IN_SVC.fetch_add(1, Ordering::SeqCst);
+    }
+
+    while tim3.cnt.read().bits() < LOW_BUSY_US {}
+
+    LOW_ACTIVE.store(false, Ordering::SeqCst);
+    set_pin(LOW_PIN, false);
+}
+
+#[handler(TIM4)]
+fn tim4_handler() {
+    // Safety: Only the registers of TIM4 are accessed here.
+    let tim4 = unsafe { &*TIM4::ptr() };
+    HIGH_MAX_LATENCY.fetch_max(tim4.cnt.read().bits(), Ordering::SeqCst);
+    tim4.sr.write(|w| w.uif().clear_bit());
+
+    set_pin(HIGH_PIN, true);
+    HIGH_ACTIVE.store(true, Ordering::SeqCst);
+    HIGH_RUNS.fetch_add(1, Ordering::SeqCst);
+    if LOW_ACTIVE.load(Ordering::SeqCst) {
+        HIGH_IN_LOW.fetch_add(1, Ordering::SeqCst);
+    }
+    if svc_active() {
+        HIGH_IN_SVC.fetch_add(1, Ordering::SeqCst);
+    }
+    HIGH_ACTIVE.store(false, Ordering::SeqCst);
+    set_pin(HIGH_PIN, false);
+}
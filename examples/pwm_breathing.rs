@@ -0,0 +1,150 @@
+//! Breathing LEDs driven by hardware PWM.
+//!
+//! The tutorial in `src/main.rs` blinks the four LEDs by toggling GPIO pins,
+//! so the LEDs are either fully on or off. This example instead routes PD12 to
+//! PD15 to the four channels of TIM4 in PWM mode. The timer switches the pins
+//! on and off faster than the eye can follow, and the duty cycle of each
+//! channel sets the perceived brightness. The CPU only steps the duty cycles,
+//! here to make the LEDs fade in and out one after another.
+//!
+//! The pins are those of the LEDs on STM32F407 and STM32F411 Discovery. Build
+//! and flash with `cargo run --release --example pwm_breathing`.
+//!
+//! TIM4 is shared by the HAL driver, which sets the duty cycles, and by its
+//! update IRQ handler, which paces the fading. Both reach the timer through a
+//! `SpinIrqSafe` lock on TIM4 IRQ, so a task holding the lock cannot be
+//! preempted by the handler halfway through an update.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{Mailbox, SpinIrqSafe},
+    task::{self, main},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    pac::{self, TIM4},
+    prelude::*,
+    timer::{Channel, Channel1, Channel2, Channel3, Channel4, Event, Flag, PwmHz},
+};
+
+/// The PWM frequency. It is well above the rate at which flicker is visible.
+const PWM_FREQUENCY_HZ: u32 = 1000;
+
+/// The rate at which the duty cycles are stepped.
+const STEP_RATE_HZ: u32 = 100;
+
+/// The number of steps in a full fade in and out of an LED.
+const STEPS_PER_BREATH: u32 = 200;
+
+const CHANNELS: [Channel; 4] = [Channel::C1, Channel::C2, Channel::C3, Channel::C4];
+
+type Pwm = PwmHz<
+    TIM4,
+    (
+        Channel1<TIM4>,
+        Channel2<TIM4>,
+        Channel3<TIM4>,
+        Channel4<TIM4>,
+    ),
+>;
+
+irq!(Tim4Irq, pac::interrupt::TIM4);
+
+/// The PWM timer. TIM4 IRQ is masked when the lock is held.
+static PWM: SpinIrqSafe<Option<Pwm>, Tim4Irq> = SpinIrqSafe::new(None);
+
+/// The number of PWM periods elapsed.
+static PERIODS: AtomicU32 = AtomicU32::new(0);
+
+/// Notified by the handler once per step.
+static STEP: Mailbox = Mailbox::new();
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Hand the LED pins to the timer channels, all starting dark.
+    let gpiod = dp.GPIOD.split();
+    let channels = (
+        Channel1::new(gpiod.pd12),
+        Channel2::new(gpiod.pd13),
+        Channel3::new(gpiod.pd14),
+        Channel4::new(gpiod.pd15),
+    );
+    let mut pwm = dp.TIM4.pwm_hz(channels, PWM_FREQUENCY_HZ.Hz(), &clocks);
+    for channel in CHANNELS {
+        pwm.set_duty(channel, 0);
+        pwm.enable(channel);
+    }
+
+    // Raise the update IRQ at the end of every PWM period.
+    pwm.listen(Event::Update);
+    *PWM.lock() = Some(pwm);
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::TIM4, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::TIM4);
+    }
+
+    // Step the duty cycles in a breathing task. Its stack is released while
+    // it waits for the next step. See Part 4 of the tutorial.
+    task::build_breathing()
+        .set_init(|| 0)
+        .set_wait(|_: &mut u32| STEP.wait())
+        .set_work(|step: &mut u32, _| {
+            let mut pwm = PWM.lock();
+            let pwm = pwm.as_mut().unwrap();
+            let max_duty = u32::from(pwm.get_max_duty());
+
+            // Each LED breathes a quarter of a breath after the previous one.
+            for (i, channel) in CHANNELS.into_iter().enumerate() {
+                let phase = (*step + i as u32 * STEPS_PER_BREATH / 4) % STEPS_PER_BREATH;
+                let level = triangle(phase);
+                // The eye perceives brightness roughly logarithmically, so
+                // square the level to make the fading look even.
+                let duty = max_duty * level * level / (STEPS_PER_BREATH / 2).pow(2);
+                pwm.set_duty(channel, duty as u16);
+            }
+
+            *step = (*step + 1) % STEPS_PER_BREATH;
+        })
+        .spawn()
+        .unwrap();
+}
+
+/// Rise from 0 to `STEPS_PER_BREATH / 2` and fall back over a breath.
+fn triangle(phase: u32) -> u32 {
+    let half = STEPS_PER_BREATH / 2;
+    if phase < half {
+        phase
+    } else {
+        STEPS_PER_BREATH - phase
+    }
+}
+
+#[handler(TIM4)]
+fn tim4_handler() {
+    // Acknowledge the IRQ.
+    PWM.lock().as_mut().unwrap().clear_flags(Flag::Update);
+
+    // Notify the task once every `PWM_FREQUENCY_HZ / STEP_RATE_HZ` periods.
+    let periods = PERIODS.fetch_add(1, Ordering::SeqCst) + 1;
+    if periods % (PWM_FREQUENCY_HZ / STEP_RATE_HZ) == 0 {
+        STEP.notify_allow_isr();
+    }
+}
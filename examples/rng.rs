@@ -0,0 +1,257 @@
+//! Draw random numbers from the hardware RNG shared by two tasks, checking
+//! its output with the statistical tests of FIPS 140-2.
+//!
+//! The `HwRng` driver in `src/drivers/rng.rs` keeps the RNG peripheral
+//! behind a mutex and runs the checks asked of the software by the reference
+//! manual. The driver is shared by an `Arc`. The `blink` task draws through
+//! `rand_core::RngCore`, as any crate taking a random number generator
+//! would, to light one of the four LEDs for a random time. Another example
+//! needing random numbers takes the driver the same way.
+//!
+//! Every [`CHECK_PERIOD_MS`], the `health` task draws 20000 bits, and runs
+//! the monobit, poker, runs, and long run tests of FIPS 140-2 on them, each
+//! bounding how far a property of the bits may stray from that of a fair
+//! coin. A healthy generator still fails one of them by chance once in a
+//! long while, so a failure repeated over consecutive checks is what tells a
+//! broken noise source. The task also times each word drawn
+//! in CPU cycles. A word comes within a microsecond, unless the mutex is
+//! held by the other task or the generator is restarted after a seed error,
+//! so the largest time shows the jitter seen by the tasks.
+//!
+//! The results and the errors counted by the driver are printed on USART2.
+//! The console wiring is the one of Part 11 of the tutorial. Build and flash
+//! with `cargo run --release --example rng`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/drivers/rng.rs"]
+mod rng;
+
+use alloc::sync::Arc;
+use core::fmt::Write;
+use cortex_m::peripheral::DWT;
+use hopter::{
+    config,
+    task::{self, main},
+    time,
+};
+use rand_core::RngCore;
+use rng::HwRng;
+use stm32f4xx_hal::{
+    gpio::{ErasedPin, Output},
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The time between health checks.
+const CHECK_PERIOD_MS: u32 = 10_000;
+
+/// The words drawn for a check, 20000 bits as the tests require.
+const CHECK_WORDS: usize = 20_000 / 32;
+
+/// The range of the time an LED stays on.
+const MIN_LIGHT_MS: u32 = 50;
+const MAX_LIGHT_MS: u32 = 500;
+
+/// The bounds of the runs test of each length, 1 to 6 or longer, of either
+/// bit, as given by FIPS 140-2.
+const RUN_BOUNDS: [(u32, u32); 6] = [
+    (2315, 2685),
+    (1114, 1386),
+    (527, 723),
+    (240, 384),
+    (103, 209),
+    (103, 209),
+];
+
+/// The shortest run failing the long run test.
+const LONG_RUN: u32 = 26;
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below. The RNG is
+    // clocked by the 48 MHz output of the PLL.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr.require_pll48clk(), &cp.SYST);
+
+    // Count the CPU cycles.
+    let mut dcb = cp.DCB;
+    let mut dwt = cp.DWT;
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let gpiod = dp.GPIOD.split();
+    let leds = [
+        gpiod.pd12.into_push_pull_output().erase(),
+        gpiod.pd13.into_push_pull_output().erase(),
+        gpiod.pd14.into_push_pull_output().erase(),
+        gpiod.pd15.into_push_pull_output().erase(),
+    ];
+
+    let rng = Arc::new(HwRng::new(dp.RNG, &clocks));
+
+    let blink_rng = rng.clone();
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || blink(&*blink_rng, leds))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || health(&rng, tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Light a random LED for a random time, over and over. Takes any `RngCore`,
+/// as a crate drawing random numbers would.
+fn blink(mut rng: impl RngCore, mut leds: [ErasedPin<Output>; 4]) {
+    loop {
+        let led = &mut leds[rng.next_u32() as usize % 4];
+        let light_ms = MIN_LIGHT_MS + rng.next_u32() % (MAX_LIGHT_MS - MIN_LIGHT_MS + 1);
+        led.set_high();
+        time::sleep_ms(light_ms).unwrap();
+        led.set_low();
+    }
+}
+
+/// Check the output of the generator periodically, and print the results.
+fn health(rng: &HwRng, mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nrng: checking {} bits every {} ms\r\n",
+        CHECK_WORDS * 32,
+        CHECK_PERIOD_MS
+    );
+
+    let mut words = [0u32; CHECK_WORDS];
+    let mut checks = 0u32;
+    let mut failed = 0u32;
+
+    loop {
+        let mut min_cycles = u32::MAX;
+        let mut max_cycles = 0;
+        let mut retries = 0;
+        for word in words.iter_mut() {
+            *word = loop {
+                let start = DWT::cycle_count();
+                let drawn = rng.next_u32();
+                let cycles = DWT::cycle_count().wrapping_sub(start);
+                min_cycles = min_cycles.min(cycles);
+                max_cycles = max_cycles.max(cycles);
+                match drawn {
+                    Ok(word) => break word,
+                    Err(err) if err.is_recovered() => retries += 1,
+                    Err(err) => panic!("RNG: {:?}", err),
+                }
+            };
+        }
+
+        let ones = words.iter().map(|word| word.count_ones()).sum::<u32>();
+        let monobit = 9725 < ones && ones < 10275;
+        let poker = poker(&words);
+        let (runs, longest) = runs(&words);
+        let runs_pass = runs.iter().all(|counts| {
+            counts
+                .iter()
+                .zip(RUN_BOUNDS)
+                .all(|(&count, (low, high))| low <= count && count <= high)
+        });
+        let long_run = longest < LONG_RUN;
+        let pass = monobit && poker && runs_pass && long_run;
+
+        checks += 1;
+        if !pass {
+            failed += 1;
+        }
+        let errors = rng.errors();
+        let _ = write!(
+            tx,
+            "check {}: {}, monobit {} ones, poker {}, runs {}, longest run {}, \
+             {} to {} cycles per word, {} retries, errors: {} seed, {} repeated, {} clock, \
+             {} checks failed\r\n",
+            checks,
+            if pass { "PASS" } else { "FAIL" },
+            ones,
+            verdict(poker),
+            verdict(runs_pass),
+            longest,
+            min_cycles,
+            max_cycles,
+            retries,
+            errors.seed,
+            errors.repeated,
+            errors.clock,
+            failed
+        );
+
+        time::sleep_ms(CHECK_PERIOD_MS).unwrap();
+    }
+}
+
+fn verdict(pass: bool) -> &'static str {
+    if pass {
+        "pass"
+    } else {
+        "fail"
+    }
+}
+
+/// Run the poker test: count each value of the 5000 nibbles, and bound the
+/// spread of the counts.
+fn poker(words: &[u32; CHECK_WORDS]) -> bool {
+    let mut counts = [0u32; 16];
+    for word in words {
+        for nibble in 0..8 {
+            counts[(word >> (4 * nibble) & 0xf) as usize] += 1;
+        }
+    }
+    // The statistic is 16 / 5000 * sum(count^2) - 5000, which must lie in
+    // (2.16, 46.17). Scaled by 5000 to stay in integers.
+    let sum = counts.iter().map(|&count| count * count).sum::<u32>();
+    let scaled = i64::from(16 * sum) - 5000 * 5000;
+    10_800 < scaled && scaled < 230_850
+}
+
+/// Count the runs of each length, 1 to 6 or longer, of zeros and of ones,
+/// and return the counts along with the longest run.
+fn runs(words: &[u32; CHECK_WORDS]) -> ([[u32; 6]; 2], u32) {
+    let mut counts = [[0; 6]; 2];
+    let mut longest = 0;
+    let mut bit = words[0] & 1;
+    let mut len = 0;
+
+    let mut end_run = |bit: u32, len: u32| {
+        counts[bit as usize][(len.min(6) - 1) as usize] += 1;
+        longest = longest.max(len);
+    };
+
+    for word in words {
+        for i in 0..32 {
+            let next = word >> i & 1;
+            if next == bit {
+                len += 1;
+            } else {
+                end_run(bit, len);
+                bit = next;
+                len = 1;
+            }
+        }
+    }
+    end_run(bit, len);
+    (counts, longest)
+}
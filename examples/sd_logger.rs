@@ -0,0 +1,374 @@
+//! Append telemetry records to a file on an SD card.
+//!
+//! The `sampler` task reads the chip temperature once per second and pushes
+//! a record into a channel, never blocking: records that find the channel
+//! full are dropped. The `logger` task consumes the records and appends them
+//! as lines of text to `LOG.CSV` in the root directory of the FAT filesystem
+//! on the card, through the `embedded-sdmmc` crate. It syncs the file to the
+//! card every [`SYNC_RECORDS`] records, so at most that many are lost when
+//! the power goes. The green LED toggles upon each sync.
+//!
+//! The driver of the card blocks. It polls the SPI bus byte by byte and
+//! spins while the card is busy, sometimes for hundreds of milliseconds
+//! during a write. The `logger` task thus runs at a lower priority than the
+//! `sampler` task, which preempts it whenever a sample is due, so the
+//! records keep their pace whatever the card does.
+//!
+//! Pulling the card out fails the next access with an error rather than a
+//! panic. The `logger` task then closes everything, lights the red LED, and
+//! tries to mount the card again every [`RETRY_MS`]. Meanwhile the channel
+//! fills up and the newest records are dropped. Once a card is back, the
+//! logger writes the buffered records and the red LED goes off.
+//!
+//! Wire a microSD card module to SPI2: SCK on PB13, MISO on PB14, MOSI on
+//! PB15, and the chip select on PB12, with pull-ups on the data lines if the
+//! module has none. The card must hold a FAT16 or FAT32 filesystem in its
+//! first partition. The LEDs are those of STM32F407 and STM32F411 Discovery.
+//! Build and flash with `cargo run --release --example sd_logger --features
+//! sd-logger`.
+//!
+//! The board has no calendar clock, so the file carries a fixed modification
+//! date. The records are stamped with the tick instead.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicU32, Ordering},
+};
+use embedded_hal::{
+    delay::DelayNs,
+    spi::{ErrorType, Operation, SpiDevice},
+};
+use embedded_sdmmc::{Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use hopter::{
+    config,
+    sync::{self, Consumer, Producer},
+    task::{self, main},
+    time::{self, IntervalBarrier},
+};
+use hopter_conf_params::TARGET_SYSCLK_HZ;
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, SampleTime},
+        Adc, Temperature,
+    },
+    gpio::{Output, PB12, PD12, PD14},
+    pac::{self, ADC1, SPI2},
+    prelude::*,
+    signature::{VtempCal110, VtempCal30},
+    spi::{self, Mode as SpiMode, Phase, Polarity, Spi},
+};
+
+/// The name of the file the records are appended to.
+const FILE_NAME: &str = "LOG.CSV";
+
+/// The interval between two records.
+const SAMPLE_PERIOD_MS: u32 = 1000;
+
+/// The number of records appended between two syncs of the file.
+const SYNC_RECORDS: u32 = 10;
+
+/// The time between two attempts at mounting the card.
+const RETRY_MS: u32 = 2000;
+
+/// The number of records buffered while the card is busy or missing.
+const RECORD_QUEUE_LEN: usize = 32;
+
+/// The highest SPI clock used while the card is identified, as required by
+/// the SD specification, and once it is.
+const IDENTIFY_CLOCK_HZ: u32 = 400_000;
+const TRANSFER_CLOCK_HZ: u32 = 12_000_000;
+
+/// The SPI mode of SD cards.
+const SD_SPI_MODE: SpiMode = SpiMode {
+    polarity: Polarity::IdleLow,
+    phase: Phase::CaptureOnFirstTransition,
+};
+
+/// A telemetry record.
+#[derive(Clone, Copy)]
+struct Record {
+    /// The number of records taken before, dropped ones included.
+    sequence: u32,
+    /// The tick at which the record was taken.
+    tick: u32,
+    /// The chip temperature in hundredths of a degree Celsius.
+    centi_celsius: i32,
+}
+
+/// The number of records dropped because the channel was full.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+type Card = SdCard<CardSpi, SpinDelay>;
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpiob = dp.GPIOB.split();
+    let gpiod = dp.GPIOD.split();
+
+    let spi = Spi::new(
+        dp.SPI2,
+        (gpiob.pb13, gpiob.pb14, gpiob.pb15),
+        SD_SPI_MODE,
+        IDENTIFY_CLOCK_HZ.Hz(),
+        &clocks,
+    );
+    let mut card_spi = CardSpi {
+        spi,
+        cs: gpiob.pb12.into_push_pull_output_in_state(true.into()),
+        identify_divider: divider(clocks.pclk1().raw(), IDENTIFY_CLOCK_HZ),
+        transfer_divider: divider(clocks.pclk1().raw(), TRANSFER_CLOCK_HZ),
+    };
+    card_spi.wake();
+    let card = SdCard::new(card_spi, SpinDelay);
+    let leds = Leds {
+        green: gpiod.pd12.into_push_pull_output(),
+        red: gpiod.pd14.into_push_pull_output(),
+    };
+
+    let (producer, consumer) = sync::create_channel();
+
+    task::build()
+        .set_entry(move || sampler(dp.ADC1, producer))
+        .spawn()
+        .unwrap();
+
+    // Lower than the sampler, see the module documentation.
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY + 1)
+        .set_entry(move || logger(card, consumer, leds))
+        .spawn()
+        .unwrap();
+}
+
+/// Push a record into the channel every [`SAMPLE_PERIOD_MS`].
+fn sampler(adc1: ADC1, records: Producer<Record, RECORD_QUEUE_LEN>) {
+    let mut adc = Adc::adc1(adc1, true, AdcConfig::default());
+    adc.enable_temperature_and_vref();
+    let cal30 = i32::from(VtempCal30::get().read());
+    let cal110 = i32::from(VtempCal110::get().read());
+
+    let mut barrier = IntervalBarrier::new(SAMPLE_PERIOD_MS).unwrap();
+    for sequence in 0.. {
+        barrier.wait();
+
+        // The sensor needs a sampling time above 10 us. The calibration
+        // assumes a 3.3 V supply, which the boards provide within a few
+        // percent, see `src/temperature.rs` for the correction.
+        let temp = i32::from(adc.convert(&Temperature, SampleTime::Cycles_480));
+        let record = Record {
+            sequence,
+            tick: time::get_tick(),
+            centi_celsius: 3000 + (temp - cal30) * (11000 - 3000) / (cal110 - cal30).max(1),
+        };
+        if records.try_produce_allow_isr(record).is_err() {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The LEDs showing the state of the logger.
+struct Leds {
+    /// Toggled upon each sync.
+    green: PD12<Output>,
+    /// Lit while no card can be written.
+    red: PD14<Output>,
+}
+
+/// Mount the card and append the records, mounting it again after a
+/// failure.
+fn logger(card: Card, records: Consumer<Record, RECORD_QUEUE_LEN>, mut leds: Leds) {
+    let mut volume_mgr = VolumeManager::new(card, FixedTime);
+    loop {
+        // Only returns upon a failure.
+        let _ = append(&mut volume_mgr, &records, &mut leds);
+
+        // The handles are closed on the way out of `append`, although a
+        // missing card fails the writes of the closing. Start afresh with a
+        // new manager rather than trusting its state.
+        leds.red.set_high();
+        let (card, time) = volume_mgr.free();
+        card.mark_card_uninit();
+        card.spi(|spi| spi.wake());
+        volume_mgr = VolumeManager::new(card, time);
+        time::sleep_ms(RETRY_MS).unwrap();
+    }
+}
+
+/// Open the file and append the records to it until an access fails.
+fn append(
+    volume_mgr: &mut VolumeManager<Card, FixedTime>,
+    records: &Consumer<Record, RECORD_QUEUE_LEN>,
+    leds: &mut Leds,
+) -> Result<(), embedded_sdmmc::Error<embedded_sdmmc::SdCardError>> {
+    // Identify the card at the low clock, then speed up.
+    volume_mgr.device().num_bytes()?;
+    volume_mgr
+        .device()
+        .spi(|spi| spi.set_divider(spi.transfer_divider));
+
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root = volume.open_root_dir()?;
+    let mut file = root.open_file_in_dir(FILE_NAME, Mode::ReadWriteCreateOrAppend)?;
+    leds.red.set_low();
+
+    let mut line = Line::new();
+    let mut unsynced = 0;
+    loop {
+        let record = records.consume();
+        line.clear();
+        // The line always fits.
+        let _ = writeln!(
+            line,
+            "{},{},{}.{:02},{}",
+            record.sequence,
+            record.tick,
+            record.centi_celsius / 100,
+            (record.centi_celsius % 100).abs(),
+            DROPPED.load(Ordering::SeqCst)
+        );
+        file.write(line.as_bytes())?;
+
+        unsynced += 1;
+        if unsynced == SYNC_RECORDS {
+            file.flush()?;
+            unsynced = 0;
+            leds.green.toggle();
+        }
+    }
+}
+
+/// A line of text built in place.
+struct Line {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            bytes: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// SPI2 with the chip select of the card.
+struct CardSpi {
+    spi: Spi<SPI2>,
+    cs: PB12<Output>,
+    /// The values of the baud rate field of the SPI configuration giving the
+    /// clocks of identification and transfer.
+    identify_divider: u8,
+    transfer_divider: u8,
+}
+
+impl CardSpi {
+    /// Slow the clock down for identification, and clock out 80 bits with the
+    /// card deselected, which a card needs after it is powered up before it
+    /// takes commands.
+    fn wake(&mut self) {
+        self.set_divider(self.identify_divider);
+        let _ = self.spi.write(&[0xff; 10]);
+    }
+
+    /// Set the baud rate field of the SPI configuration.
+    fn set_divider(&mut self, divider: u8) {
+        // Safety: The SPI is owned by the card, and idle between
+        // transactions. The field may only change while the SPI is disabled.
+        unsafe {
+            let spi = &*SPI2::ptr();
+            spi.cr1.modify(|_, w| w.spe().clear_bit());
+            spi.cr1.modify(|_, w| w.br().bits(divider));
+            spi.cr1.modify(|_, w| w.spe().set_bit());
+        }
+    }
+}
+
+/// Return the value of the baud rate field of the SPI configuration giving
+/// the highest clock not above the target. The field divides the clock by 2
+/// to 256.
+fn divider(pclk_hz: u32, target_hz: u32) -> u8 {
+    (0..7)
+        .find(|br| pclk_hz >> (br + 1) <= target_hz)
+        .unwrap_or(7)
+}
+
+impl ErrorType for CardSpi {
+    type Error = spi::Error;
+}
+
+impl SpiDevice for CardSpi {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), spi::Error> {
+        self.cs.set_low();
+        let result = operations
+            .iter_mut()
+            .try_for_each(|operation| match operation {
+                Operation::Read(words) => self.spi.read(words),
+                Operation::Write(words) => self.spi.write(words),
+                Operation::Transfer(read, write) => self.spi.transfer(read, write),
+                Operation::TransferInPlace(words) => self.spi.transfer_in_place(words),
+                Operation::DelayNs(ns) => {
+                    SpinDelay.delay_ns(*ns);
+                    Ok(())
+                }
+            });
+        let flushed = self.spi.flush();
+        self.cs.set_high();
+        result.and(flushed)
+    }
+}
+
+/// Waits by spinning, as needed by the driver between the polls of a busy
+/// card. The spinning task is preempted as any other.
+struct SpinDelay;
+
+impl DelayNs for SpinDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        let cycles = u64::from(ns) * u64::from(TARGET_SYSCLK_HZ) / 1_000_000_000;
+        cortex_m::asm::delay(cycles as u32);
+    }
+}
+
+/// Stamps the files with a fixed date, see the module documentation.
+struct FixedTime;
+
+impl TimeSource for FixedTime {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp::from_calendar(2024, 1, 1, 0, 0, 0).unwrap()
+    }
+}
@@ -0,0 +1,121 @@
+//! Sweep a hobby servo back and forth with the `Servo` driver.
+//!
+//! The servo signal is TIM3 channel 3 on PB0, in PWM mode at 50 Hz. Wire it
+//! to the signal pin of the servo, and power the servo from its own 5 V
+//! supply, whose ground is joined to the ground of the board. The driver
+//! lives in `src/drivers/servo.rs`, and takes any PWM channel, so another
+//! pin works as well with the timer and the channel changed to match.
+//!
+//! The `sweep` task moves the horn by one degree every [`STEP_MS`], from
+//! one end of the travel to the other and back, pausing [`DWELL_MS`] at
+//! each end. The steps are deliberately out of step with the 20 ms period
+//! of the pulses, so that new widths are written at any point of it, e.g.,
+//! in the middle of a pulse. The compare register is preloaded, which the
+//! task checks before starting, so each width takes effect from the next
+//! period and the horn moves smoothly. The width at each end is printed on
+//! USART2. The console wiring is the one of Part 11 of the tutorial. Build
+//! and flash with `cargo run --release --example servo_sweep`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/drivers/servo.rs"]
+mod servo;
+
+use core::fmt::Write;
+use hopter::{
+    config,
+    task::{self, main},
+    time,
+};
+use servo::Servo;
+use stm32f4xx_hal::{
+    pac::{self, TIM3, USART2},
+    prelude::*,
+    serial::Tx,
+    timer::{Channel3, PwmChannel},
+};
+
+/// The time between steps of one degree.
+const STEP_MS: u32 = 7;
+
+/// How long the horn stays at each end of the travel.
+const DWELL_MS: u32 = 500;
+
+/// The PWM channel of the servo, the third of TIM3.
+type ServoPwm = PwmChannel<TIM3, 2>;
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    // Count in microseconds, so that the width has a resolution of 1 us.
+    let gpiob = dp.GPIOB.split();
+    let pwm = dp.TIM3.pwm_us(
+        Channel3::new(gpiob.pb0),
+        u32::from(servo::PERIOD_US).micros(),
+        &clocks,
+    );
+    let mut channel = pwm.split();
+    // The width is still zero, so no pulse is sent until the driver sets one.
+    channel.enable();
+    let servo = Servo::new(channel).unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || sweep(servo, tx))
+        .spawn()
+        .unwrap();
+}
+
+fn sweep(mut servo: Servo<ServoPwm>, mut tx: Tx<USART2>) {
+    // Safety: The register is only read here.
+    let tim3 = unsafe { &*TIM3::ptr() };
+    let preloaded = tim3.ccmr2_output().read().oc3pe().bit_is_set();
+    let _ = write!(
+        tx,
+        "\r\nservo_sweep: {} us to {} us over {} degrees, compare register preloaded: {}\r\n",
+        servo::MIN_PULSE_US,
+        servo::MAX_PULSE_US,
+        servo::TRAVEL_DEG,
+        preloaded
+    );
+    if !preloaded {
+        let _ = write!(tx, "servo_sweep: FAIL\r\n");
+        return;
+    }
+
+    let mut sweeps = 0u32;
+    loop {
+        for deg in (0..=servo::TRAVEL_DEG).chain((1..servo::TRAVEL_DEG).rev()) {
+            let at_end = deg == 0 || deg == servo::TRAVEL_DEG;
+            servo.set_angle(deg).unwrap();
+            if at_end {
+                let _ = write!(
+                    tx,
+                    "sweep {}: {} degrees, {} us\r\n",
+                    sweeps,
+                    servo.angle(),
+                    servo.pulse_us()
+                );
+                time::sleep_ms(DWELL_MS).unwrap();
+            } else {
+                time::sleep_ms(STEP_MS).unwrap();
+            }
+        }
+        sweeps += 1;
+    }
+}
@@ -0,0 +1,223 @@
+//! Share a resource between a task and two IRQ handlers through a single
+//! `SpinIrqSafe` masking both IRQs, and check that neither handler runs
+//! while the lock is held.
+//!
+//! The second type parameter of `SpinIrqSafe` names the IRQs masked while
+//! the lock is held. It may be a tuple of types declared with `irq!`, here
+//! `(Tim2Irq, Exti1Irq)`, in which case all of them are masked, in the order
+//! of the tuple, and unmasked in the reverse order. The TIM2 update IRQ is
+//! raised every millisecond, and EXTI1 IRQ is pended by software. Both
+//! handlers, as well as the `check` task, count into [`SHARED`].
+//!
+//! For each of [`ROUNDS`] rounds, the `check` task takes the lock, pends
+//! EXTI1 IRQ, and holds the lock for [`HOLD_MS`], longer than a TIM2
+//! period. While the lock is held, a flag is set, and each handler counts
+//! the times it finds the flag set. After the hold, both IRQs must be
+//! pending, and right after the unlock, both handlers must have run.
+//!
+//! As a counter-check of the instrumentation, the task then holds a lock
+//! masking EXTI1 IRQ only for as long, during which the TIM2 handler should
+//! be found running. The counts and a `PASS` or `FAIL` verdict are printed
+//! on USART2. The console wiring is the one of Part 11 of the tutorial.
+//! Build and flash with `cargo run --release --example spin_multi_irq`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::NVIC;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::SpinIrqSafe,
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    pac::{self, TIM2, USART2},
+    prelude::*,
+    serial::Tx,
+    timer::FTimerUs,
+};
+
+/// The period of TIM2 update IRQ.
+const TIM2_PERIOD_US: u32 = 1000;
+
+/// How long the task holds a lock each round.
+const HOLD_MS: u32 = 3;
+
+/// The rounds of each check.
+const ROUNDS: u32 = 100;
+
+irq!(Tim2Irq, pac::interrupt::TIM2);
+// Triggered by software only.
+irq!(Exti1Irq, pac::interrupt::EXTI1);
+
+/// What each party has counted.
+#[derive(Clone, Copy)]
+struct Counts {
+    tim2: u32,
+    exti1: u32,
+    task: u32,
+}
+
+/// Both TIM2 and EXTI1 IRQs are masked when the lock is held.
+static SHARED: SpinIrqSafe<Counts, (Tim2Irq, Exti1Irq)> = SpinIrqSafe::new(Counts {
+    tim2: 0,
+    exti1: 0,
+    task: 0,
+});
+
+/// Only EXTI1 IRQ is masked when the lock is held.
+static EXTI1_ONLY: SpinIrqSafe<(), Exti1Irq> = SpinIrqSafe::new(());
+
+/// Set while the task holds a lock.
+static HELD: AtomicBool = AtomicBool::new(false);
+
+/// The times each handler ran while the task held a lock.
+static TIM2_WHILE_HELD: AtomicU32 = AtomicU32::new(0);
+static EXTI1_WHILE_HELD: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    // Enables the timer and sets the prescaler for 1 MHz.
+    let tim2 = FTimerUs::new(dp.TIM2, &clocks).release();
+    tim2.arr.write(|w| w.bits(TIM2_PERIOD_US - 1));
+    // Load the prescaler, then clear the flag raised by doing so.
+    tim2.egr.write(|w| w.ug().set_bit());
+    tim2.sr.write(|w| w.uif().clear_bit());
+    tim2.dier.write(|w| w.uie().set_bit());
+    tim2.cr1.write(|w| w.cen().set_bit());
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::TIM2, IRQ_NORMAL_PRIORITY);
+        nvic.set_priority(pac::interrupt::EXTI1, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::TIM2);
+        NVIC::unmask(pac::interrupt::EXTI1);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || check(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Run both checks and print the results.
+fn check(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nspin_multi_irq: {} rounds holding a lock for {} ms, TIM2 IRQ every {} us\r\n",
+        ROUNDS, HOLD_MS, TIM2_PERIOD_US
+    );
+
+    // Hold the lock masking both IRQs.
+    let mut both_pending = 0;
+    let mut both_served = 0;
+    for _ in 0..ROUNDS {
+        let before = {
+            let mut shared = SHARED.lock();
+            HELD.store(true, Ordering::SeqCst);
+            NVIC::pend(pac::interrupt::EXTI1);
+            busy_wait_ms(HOLD_MS);
+            if NVIC::is_pending(pac::interrupt::TIM2) && NVIC::is_pending(pac::interrupt::EXTI1) {
+                both_pending += 1;
+            }
+            shared.task += 1;
+            HELD.store(false, Ordering::SeqCst);
+            *shared
+        };
+        // The pending handlers run upon the unlock above.
+        let after = *SHARED.lock();
+        if after.tim2 > before.tim2 && after.exti1 > before.exti1 {
+            both_served += 1;
+        }
+    }
+    let counts = *SHARED.lock();
+    let tim2_while_held = TIM2_WHILE_HELD.load(Ordering::SeqCst);
+    let exti1_while_held = EXTI1_WHILE_HELD.load(Ordering::SeqCst);
+    let _ = write!(
+        tx,
+        "(Tim2Irq, Exti1Irq): both pending after the hold {}/{}, both served upon the unlock {}/{}\r\n\
+         \x20 handlers run while held: TIM2 {}, EXTI1 {}\r\n\
+         \x20 counted: TIM2 {}, EXTI1 {}, task {}\r\n",
+        both_pending,
+        ROUNDS,
+        both_served,
+        ROUNDS,
+        tim2_while_held,
+        exti1_while_held,
+        counts.tim2,
+        counts.exti1,
+        counts.task
+    );
+    let masked =
+        both_pending == ROUNDS && both_served == ROUNDS && tim2_while_held + exti1_while_held == 0;
+
+    // Hold the lock masking EXTI1 IRQ only. TIM2 handler is expected to run.
+    TIM2_WHILE_HELD.store(0, Ordering::SeqCst);
+    for _ in 0..ROUNDS {
+        let _guard = EXTI1_ONLY.lock();
+        HELD.store(true, Ordering::SeqCst);
+        busy_wait_ms(HOLD_MS);
+        HELD.store(false, Ordering::SeqCst);
+    }
+    let unmasked = TIM2_WHILE_HELD.load(Ordering::SeqCst);
+    let _ = write!(
+        tx,
+        "Exti1Irq only: TIM2 handler run while held {} times\r\n",
+        unmasked
+    );
+
+    let pass = masked && unmasked > 0;
+    let _ = write!(
+        tx,
+        "spin_multi_irq: {}\r\n",
+        if pass { "PASS" } else { "FAIL" }
+    );
+}
+
+fn busy_wait_ms(ms: u32) {
+    let start = time::get_tick();
+    while time::get_tick().wrapping_sub(start) < ms {}
+}
+
+#[handler(TIM2)]
+fn tim2_handler() {
+    // Safety: Only the flag is cleared here.
+    let tim2 = unsafe { &*TIM2::ptr() };
+    tim2.sr.write(|w| w.uif().clear_bit());
+
+    if HELD.load(Ordering::SeqCst) {
+        TIM2_WHILE_HELD.fetch_add(1, Ordering::SeqCst);
+    }
+    SHARED.lock().tim2 += 1;
+}
+
+#[handler(EXTI1)]
+fn exti1_handler() {
+    if HELD.load(Ordering::SeqCst) {
+        EXTI1_WHILE_HELD.fetch_add(1, Ordering::SeqCst);
+    }
+    SHARED.lock().exti1 += 1;
+}
@@ -0,0 +1,238 @@
+//! Run the same workloads on a segmented stack and on a contiguous one, and
+//! compare the time taken and the memory held by the stack.
+//!
+//! Two workloads are run, each [`REPEATS`] times:
+//! - `recursive`: a recursion [`DEPTH`] calls deep, each frame holding a
+//!   small array, so that the stack grows and shrinks by a few kilobytes.
+//! - `iterators`: chains of iterator adapters and a slice sort, which nest
+//!   many small calls.
+//!
+//! The `segmented` task runs them first, with the stack extended by new
+//! stacklets from the heap as it grows, and freed as it shrinks. It then
+//! spawns the `contiguous` task, with dynamic stack extension disabled, so
+//! that its whole stack of [`STACK_LIMIT`] bytes is allocated when it is
+//! spawned, and runs them again. Under the `static-alloc` feature, dynamic
+//! extension is off for every task, so only the contiguous run is made.
+//!
+//! The time is read from the cycle counter of the DWT. The memory is found
+//! by walking the chain of stacklets of the task from the deepest points of
+//! the workloads, and adding up the heap chunks holding them, the metadata
+//! included. The peak is reported, together with the number of times the
+//! stack was extended, from `hopter::debug::segmented_stack`. Hopter keeps
+//! the sites where a stacklet is repeatedly allocated and freed, and makes
+//! the stacklet allocated there larger, so the first run of a segmented
+//! workload is the slowest. The first and the mean of the later runs are
+//! printed on USART2. The console wiring is the one of Part 11 of the
+//! tutorial. Build and flash with `cargo run --release --example
+//! stack_compare`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use alloc::vec::Vec;
+use core::{
+    fmt::Write,
+    hint::black_box,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::peripheral::DWT;
+use hopter::{
+    config,
+    debug::segmented_stack,
+    task::{self, main},
+};
+use hopter_conf_params::{__TLS_MEM_ADDR, ALLOW_DYNAMIC_STACK, HCLK_FREQUENCY_HZ};
+use stm32f4xx_hal::{
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The stack limit of both tasks, and the stack size of the contiguous one.
+const STACK_LIMIT: usize = 8192;
+
+/// The runs of each workload.
+const REPEATS: u32 = 20;
+
+/// The depth of the recursive workload.
+const DEPTH: u32 = 48;
+
+/// The distance from the boundary of a stacklet down to the start of its
+/// heap chunk, as in `STACKLET_META_OFFSET` of `src/fault.rs`, plus the
+/// chunk header.
+const STACKLET_CHUNK_OFFSET: u32 = 16 + 4 + 104 + 4 + 4;
+
+/// The most stacklets walked, in case the chain is damaged.
+const MAX_STACKLETS: usize = 64;
+
+/// The largest stack memory seen by [`probe`] since the last reset.
+static PEAK: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Count the CPU cycles.
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let gpioa = dp.GPIOA.split();
+    let mut tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let _ = write!(
+        tx,
+        "\r\nstack compare, HCLK at {} Hz, {} runs per workload\r\n",
+        HCLK_FREQUENCY_HZ, REPEATS
+    );
+
+    if ALLOW_DYNAMIC_STACK {
+        task::build()
+            .set_priority(config::DEFAULT_TASK_PRIORITY)
+            .set_stack_limit(STACK_LIMIT)
+            .set_entry(move || {
+                run(&mut tx, "segmented");
+                spawn_contiguous(tx);
+            })
+            .spawn()
+            .unwrap();
+    } else {
+        let _ = write!(
+            tx,
+            "\r\ndynamic stack extension is off, segmented run skipped\r\n"
+        );
+        spawn_contiguous(tx);
+    }
+}
+
+/// Spawn the task running the workloads on a contiguous stack.
+fn spawn_contiguous(mut tx: Tx<USART2>) {
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_stack_limit(STACK_LIMIT)
+        .disable_dynamic_stack()
+        .set_entry(move || {
+            run(&mut tx, "contiguous");
+            let _ = write!(tx, "\r\ndone\r\n");
+        })
+        .spawn()
+        .unwrap();
+}
+
+/// Run every workload on the stack of the calling task, and print the
+/// figures.
+fn run(tx: &mut Tx<USART2>, stack: &str) {
+    let _ = write!(tx, "\r\n{} stack\r\n", stack);
+    for (name, workload) in [
+        ("recursive", recursive as fn() -> u32),
+        ("iterators", iterators),
+    ] {
+        PEAK.store(0, Ordering::SeqCst);
+        let extends = segmented_stack::get_stack_extend_count();
+
+        let mut first = 0;
+        let mut rest = 0;
+        for i in 0..REPEATS {
+            let start = DWT::cycle_count();
+            black_box(workload());
+            let cycles = DWT::cycle_count().wrapping_sub(start);
+            if i == 0 {
+                first = cycles;
+            } else {
+                rest += u64::from(cycles);
+            }
+        }
+
+        let _ = write!(
+            tx,
+            "  {:<9} first {:>7} cycles, mean {:>7} cycles, peak {:>5} bytes, {} extensions\r\n",
+            name,
+            first,
+            rest / u64::from(REPEATS - 1),
+            PEAK.load(Ordering::SeqCst),
+            segmented_stack::get_stack_extend_count().wrapping_sub(extends)
+        );
+    }
+}
+
+/// Recurse [`DEPTH`] calls deep.
+fn recursive() -> u32 {
+    recurse(DEPTH)
+}
+
+#[inline(never)]
+fn recurse(depth: u32) -> u32 {
+    // Kept on the stack of the frame.
+    let mut frame = [0u32; 16];
+    for (i, word) in frame.iter_mut().enumerate() {
+        *word = depth.wrapping_mul(i as u32 + 1);
+    }
+    let below = if depth == 0 {
+        probe();
+        0
+    } else {
+        recurse(depth - 1)
+    };
+    black_box(&frame).iter().fold(below, |acc, &w| acc ^ w)
+}
+
+/// Chain iterator adapters over a vector and sort it.
+fn iterators() -> u32 {
+    let mut values: Vec<u32> = (0..256u32)
+        .map(|x| x.wrapping_mul(2_654_435_761))
+        .filter(|x| x % 3 != 0)
+        .collect();
+    values.sort_unstable_by(|a, b| {
+        probe();
+        a.rotate_left(7).cmp(&b.rotate_left(7))
+    });
+    values
+        .iter()
+        .zip(values.iter().skip(1))
+        .map(|(a, b)| a ^ b)
+        .enumerate()
+        .filter_map(|(i, x)| (i % 2 == 0).then_some(x))
+        .fold(0, |acc, x| {
+            probe();
+            acc.wrapping_add(x)
+        })
+}
+
+/// Record the memory held by the stack of the calling task, as the heap
+/// chunks of its chain of stacklets.
+#[inline(never)]
+fn probe() {
+    // The boundary of the current stacklet is the first word of the task
+    // local storage. Each stacklet starts with its metadata, whose first
+    // word is the boundary of the previous stacklet, or zero.
+    //
+    // Safety: The storage and the metadata are owned by the running task.
+    let mut bound = unsafe { ptr::read_volatile(__TLS_MEM_ADDR as *const u32) };
+    let mut bytes = 0;
+    for _ in 0..MAX_STACKLETS {
+        if bound == 0 {
+            break;
+        }
+        let chunk = bound - STACKLET_CHUNK_OFFSET;
+        // Safety: As above. The chunk header lies right below the metadata.
+        let (header, prev_bound) = unsafe {
+            (
+                ptr::read_volatile(chunk as *const u32),
+                ptr::read_volatile((chunk + 4) as *const u32),
+            )
+        };
+        bytes += header & !0x3;
+        bound = prev_bound;
+    }
+    PEAK.fetch_max(bytes, Ordering::Relaxed);
+}
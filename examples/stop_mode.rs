@@ -0,0 +1,481 @@
+//! Enter the STOP mode while every task sleeps for long, waking upon the RTC
+//! wakeup timer or the user button, without the tick jumping.
+//!
+//! In the STOP mode, all the clocks of the core domain stop, and the MCU
+//! draws some hundreds of microamperes instead of tens of milliamperes.
+//! Only the RTC, clocked here by the LSI, and the EXTI lines keep running,
+//! and either may wake the MCU up. The `blink` task flashes the green LED
+//! every [`BLINK_PERIOD_MS`], and the `button` task lights the orange LED
+//! for [`PRESS_LIGHT_MS`] upon each press of the user button on PA0.
+//!
+//! Hopter does not tell when the next sleeping task is due, so the tasks
+//! sleep through [`sleep_ms`], which records the tick each one sleeps until
+//! in [`SLEEPERS`]. The `stopper` task runs at the lowest priority above
+//! the idle task, i.e., only when all other tasks are blocked. If the
+//! earliest of the recorded ticks is at least [`MIN_STOP_MS`] away, it arms
+//! the RTC wakeup timer to fire [`GUARD_MS`] before it, and stops the MCU.
+//! Otherwise it sleeps for a tick, and looks again.
+//!
+//! SysTick stops along with the core, so the tick would fall behind the
+//! time spent stopped. The `stopper` task disables SysTick and reads the
+//! RTC right before stopping, and reads the RTC again upon waking up. It
+//! then catches the tick up by pending SysTick once for each millisecond
+//! elapsed, so that a sleeping task wakes up on time rather than late, and
+//! a task comparing ticks sees no jump. The MCU wakes up running from the
+//! HSI, so the HSE and the PLL are restarted before anything else.
+//!
+//! The F407 Discovery board has no LSE crystal, so the RTC runs from the
+//! LSI, which is only accurate to several percent. Its rate is measured
+//! against the CPU clock at boot, and used to convert RTC time into ticks.
+//! The tick, the RTC time since boot, and the number and length of the
+//! stops are printed upon each blink. The two times should agree within a
+//! few milliseconds however long the program runs.
+//!
+//! The debugger loses the core while it is stopped. If flashing fails
+//! afterwards, hold the reset button while starting the flash tool. The
+//! console wiring is the one of Part 11 of the tutorial. Build and flash
+//! with `cargo run --release --example stop_mode`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::{DWT, NVIC, SCB, SYST};
+use hopter::{
+    config,
+    interrupt::declare::handler,
+    sync::Mailbox,
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::{HCLK_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY};
+use stm32f4xx_hal::{
+    gpio::{Edge, Output, PD12, PD13},
+    pac::{self, EXTI, RCC, RTC, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The time between flashes of the green LED.
+const BLINK_PERIOD_MS: u32 = 5000;
+
+/// How long each flash lasts, too short to stop in between.
+const FLASH_MS: u32 = 20;
+
+/// How long the orange LED stays on after a press.
+const PRESS_LIGHT_MS: u32 = 300;
+
+/// The time after a press during which further presses are taken as the
+/// bouncing of the button.
+const DEBOUNCE_MS: u32 = 50;
+
+/// The shortest stop worth entering. Waking up takes some tens of
+/// microseconds, and restarting the HSE up to a couple of milliseconds.
+const MIN_STOP_MS: u32 = 100;
+
+/// How early the wakeup timer fires before the tick due, to cover
+/// restarting the clocks and the error of the LSI measurement.
+const GUARD_MS: u32 = 5;
+
+/// The longest stop, within the 65536 periods of the wakeup timer. With no
+/// task sleeping, the MCU is stopped for this long at a time.
+const MAX_STOP_MS: u32 = 30_000;
+
+/// The LSI is divided by `PREDIV_A + 1` into `ck_apre`, the clock of the
+/// subsecond counter, which is divided again by `PREDIV_S + 1` into 1 Hz
+/// for the calendar. The nominal LSI of 32 kHz gives `ck_apre` at 8 kHz.
+const PREDIV_A: u8 = 3;
+const PREDIV_S: u16 = 7999;
+
+/// The `ck_apre` periods in a day, after which the calendar time wraps.
+const DAY_TICKS: u32 = 86_400 * (PREDIV_S as u32 + 1);
+
+/// The `ck_apre` periods timed against the CPU clock at boot.
+const CALIBRATION_TICKS: u32 = 4000;
+
+/// A task sleeping through [`sleep_ms`].
+struct Sleeper {
+    asleep: AtomicBool,
+    until: AtomicU32,
+}
+
+impl Sleeper {
+    const fn new() -> Self {
+        Self {
+            asleep: AtomicBool::new(false),
+            until: AtomicU32::new(0),
+        }
+    }
+}
+
+/// The `blink` task and the `button` task.
+static SLEEPERS: [Sleeper; 2] = [Sleeper::new(), Sleeper::new()];
+
+/// Notified by the EXTI0 handler upon a press of the button.
+static PRESSED: Mailbox = Mailbox::new();
+
+/// The tick of the last press taken.
+static LAST_PRESS: AtomicU32 = AtomicU32::new(0);
+
+/// The presses taken.
+static PRESSES: AtomicU32 = AtomicU32::new(0);
+
+/// The frequency of `ck_apre` measured at boot.
+static APRE_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// The stops entered.
+static STOPS: AtomicU32 = AtomicU32::new(0);
+
+/// The stops ended by something other than the wakeup timer.
+static EARLY_WAKEUPS: AtomicU32 = AtomicU32::new(0);
+
+/// The milliseconds spent stopped.
+static STOPPED_MS: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let mut dp = unsafe { pac::Peripherals::steal() };
+    // Start the RTC before the RCC is taken over by the HAL.
+    start_rtc(&dp.RCC, &dp.PWR, &dp.RTC);
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+    let syst = cp.SYST;
+
+    // Time the LSI with the CPU cycles.
+    let mut dcb = cp.DCB;
+    let mut dwt = cp.DWT;
+    dcb.enable_trace();
+    dwt.enable_cycle_counter();
+    APRE_HZ.store(measure_apre_hz(&dp.RTC), Ordering::SeqCst);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let gpiod = dp.GPIOD.split();
+    let green = gpiod.pd12.into_push_pull_output();
+    let orange = gpiod.pd13.into_push_pull_output();
+
+    // Raise EXTI0 when the button is pressed. The button drives PA0 high,
+    // and the board pulls it down otherwise. The EXTI lines wake the MCU up
+    // from the STOP mode.
+    let mut syscfg = dp.SYSCFG.constrain();
+    let mut button = gpioa.pa0.into_floating_input();
+    button.make_interrupt_source(&mut syscfg);
+    button.trigger_on_edge(&mut dp.EXTI, Edge::Rising);
+    button.enable_interrupt(&mut dp.EXTI);
+
+    // The wakeup timer of the RTC raises EXTI line 22, the only way for it
+    // to wake the MCU up from the STOP mode.
+    dp.EXTI.imr.modify(|_, w| w.mr22().unmasked());
+    dp.EXTI.rtsr.modify(|_, w| w.tr22().enabled());
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::EXTI0, IRQ_NORMAL_PRIORITY);
+        nvic.set_priority(pac::interrupt::RTC_WKUP, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::EXTI0);
+        NVIC::unmask(pac::interrupt::RTC_WKUP);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || blink(green, tx))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || button_task(orange))
+        .spawn()
+        .unwrap();
+
+    let scb = cp.SCB;
+    task::build()
+        .set_priority(config::IDLE_TASK_PRIORITY - 1)
+        .set_entry(move || stopper(syst, scb))
+        .spawn()
+        .unwrap();
+}
+
+/// Clock the RTC by the LSI, and start its calendar from midnight.
+fn start_rtc(
+    rcc: &pac::rcc::RegisterBlock,
+    pwr: &pac::pwr::RegisterBlock,
+    rtc: &pac::rtc::RegisterBlock,
+) {
+    // The backup domain, where the RTC lives, is write protected.
+    rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+    rcc.csr.modify(|_, w| w.lsion().on());
+    while rcc.csr.read().lsirdy().is_not_ready() {}
+
+    // The clock of the RTC can only be chosen once after a reset of the
+    // backup domain, which survives resets of the MCU. Reset the domain in
+    // case a previous program chose another one.
+    rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+    rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+    rcc.bdcr.modify(|_, w| w.rtcsel().lsi().rtcen().enabled());
+
+    // Unlock the registers of the RTC, and leave them unlocked for the
+    // wakeup timer to be programmed before each stop.
+    rtc.wpr.write(|w| w.key().bits(0xca));
+    rtc.wpr.write(|w| w.key().bits(0x53));
+
+    rtc.isr.modify(|_, w| w.init().init_mode());
+    while rtc.isr.read().initf().is_not_allowed() {}
+    // The two dividers are written one at a time.
+    rtc.prer.write(|w| w.prediv_s().bits(PREDIV_S));
+    rtc.prer
+        .modify(|_, w| w.prediv_a().bits(PREDIV_A).prediv_s().bits(PREDIV_S));
+    // Read the counters directly rather than through the shadow registers,
+    // which are only resynchronized two RTC clock periods after waking up.
+    rtc.cr.modify(|_, w| w.bypshad().bypass_shadow_reg());
+    rtc.isr.modify(|_, w| w.init().free_running_mode());
+
+    // Enter the STOP mode rather than the standby mode upon deep sleep, with
+    // the voltage regulator in low power mode.
+    pwr.cr.modify(|_, w| w.pdds().clear_bit().lpds().set_bit());
+}
+
+/// Return the RTC time of the day in `ck_apre` periods.
+fn rtc_ticks(rtc: &pac::rtc::RegisterBlock) -> u32 {
+    loop {
+        // The counters are read directly, so a second may pass between the
+        // reads. The subsecond counter counts down and is reloaded upon the
+        // second, so read again if it went up.
+        let before = rtc.ssr.read().ss().bits();
+        let tr = rtc.tr.read();
+        let after = rtc.ssr.read().ss().bits();
+        if after > before {
+            continue;
+        }
+        let hours = u32::from(tr.ht().bits() * 10 + tr.hu().bits());
+        let minutes = u32::from(tr.mnt().bits() * 10 + tr.mnu().bits());
+        let seconds = u32::from(tr.st().bits() * 10 + tr.su().bits());
+        let secs = hours * 3600 + minutes * 60 + seconds;
+        return secs * (u32::from(PREDIV_S) + 1) + u32::from(PREDIV_S - after);
+    }
+}
+
+/// Return the `ck_apre` periods from `start` to `end`.
+fn rtc_elapsed(start: u32, end: u32) -> u32 {
+    (end + DAY_TICKS - start) % DAY_TICKS
+}
+
+/// Time [`CALIBRATION_TICKS`] periods of `ck_apre` with the CPU cycles, and
+/// return its frequency.
+fn measure_apre_hz(rtc: &pac::rtc::RegisterBlock) -> u32 {
+    // Start upon a period boundary.
+    let first = rtc_ticks(rtc);
+    while rtc_ticks(rtc) == first {}
+    let start = rtc_ticks(rtc);
+    let start_cycles = DWT::cycle_count();
+    while rtc_elapsed(start, rtc_ticks(rtc)) < CALIBRATION_TICKS {}
+    let cycles = DWT::cycle_count().wrapping_sub(start_cycles);
+    (u64::from(CALIBRATION_TICKS) * u64::from(HCLK_FREQUENCY_HZ) / u64::from(cycles)) as u32
+}
+
+/// Sleep for `ms` milliseconds, recording the tick due in `sleeper` for the
+/// `stopper` task to see.
+fn sleep_ms(sleeper: &Sleeper, ms: u32) {
+    sleeper
+        .until
+        .store(time::get_tick().wrapping_add(ms), Ordering::SeqCst);
+    sleeper.asleep.store(true, Ordering::SeqCst);
+    time::sleep_ms(ms).unwrap();
+    sleeper.asleep.store(false, Ordering::SeqCst);
+}
+
+/// Return the milliseconds until the earliest tick due, or `None` if no task
+/// sleeps.
+fn next_due_ms() -> Option<u32> {
+    let now = time::get_tick();
+    SLEEPERS
+        .iter()
+        .filter(|sleeper| sleeper.asleep.load(Ordering::SeqCst))
+        // A task may be due already, just not run yet.
+        .map(|sleeper| {
+            (sleeper.until.load(Ordering::SeqCst).wrapping_sub(now) as i32).max(0) as u32
+        })
+        .min()
+}
+
+/// Flash the green LED, and print the tick against the RTC.
+fn blink(mut led: PD12<Output>, mut tx: Tx<USART2>) {
+    let sleeper = &SLEEPERS[0];
+    // Safety: The registers are only read here.
+    let rtc = unsafe { &*RTC::ptr() };
+    let apre_hz = APRE_HZ.load(Ordering::SeqCst);
+    let start_tick = time::get_tick();
+    let start_rtc = rtc_ticks(rtc);
+
+    let _ = write!(
+        tx,
+        "\r\nstop_mode: LSI measured at {} Hz, stopping for {} ms or longer\r\n",
+        apre_hz * (u32::from(PREDIV_A) + 1),
+        MIN_STOP_MS
+    );
+
+    loop {
+        sleep_ms(sleeper, BLINK_PERIOD_MS - FLASH_MS);
+        led.set_high();
+        sleep_ms(sleeper, FLASH_MS);
+        led.set_low();
+
+        let tick_ms = time::get_tick().wrapping_sub(start_tick);
+        let rtc_ms =
+            (u64::from(rtc_elapsed(start_rtc, rtc_ticks(rtc))) * 1000 / u64::from(apre_hz)) as u32;
+        let _ = write!(
+            tx,
+            "tick {} ms, rtc {} ms, {} stops for {} ms, {} woken early, {} presses\r\n",
+            tick_ms,
+            rtc_ms,
+            STOPS.load(Ordering::SeqCst),
+            STOPPED_MS.load(Ordering::SeqCst),
+            EARLY_WAKEUPS.load(Ordering::SeqCst),
+            PRESSES.load(Ordering::SeqCst)
+        );
+    }
+}
+
+/// Light the orange LED for a while upon each press of the button.
+fn button_task(mut led: PD13<Output>) {
+    let sleeper = &SLEEPERS[1];
+    loop {
+        PRESSED.wait();
+        PRESSES.fetch_add(1, Ordering::SeqCst);
+        led.set_high();
+        sleep_ms(sleeper, PRESS_LIGHT_MS);
+        led.set_low();
+    }
+}
+
+/// Stop the MCU whenever all other tasks sleep for long.
+fn stopper(mut syst: SYST, mut scb: SCB) {
+    // Safety: Only this task writes the registers below after the boot,
+    // except for the flags cleared by the RTC_WKUP handler.
+    let rcc = unsafe { &*RCC::ptr() };
+    let rtc = unsafe { &*RTC::ptr() };
+    let usart2 = unsafe { &*USART2::ptr() };
+    let apre_hz = u64::from(APRE_HZ.load(Ordering::SeqCst));
+    // The part of a millisecond left over from converting the previous stops.
+    let mut carry = 0;
+
+    loop {
+        // Nothing may wake a task up from now until the stop.
+        cortex_m::interrupt::disable();
+        let stop_ms = match next_due_ms() {
+            Some(ms) if ms < MIN_STOP_MS => {
+                unsafe { cortex_m::interrupt::enable() };
+                // The tick keeps running meanwhile.
+                time::sleep_ms(1).unwrap();
+                continue;
+            }
+            Some(ms) => (ms - GUARD_MS).min(MAX_STOP_MS),
+            None => MAX_STOP_MS,
+        };
+
+        // Let the last byte printed go out before the clock of the USART
+        // stops.
+        while usart2.sr.read().tc().bit_is_clear() {}
+
+        syst.disable_counter();
+        let start = rtc_ticks(rtc);
+        arm_wakeup_timer(rtc, stop_ms);
+
+        scb.set_sleepdeep();
+        cortex_m::asm::dsb();
+        // Any IRQ pending, even a masked one, wakes the MCU up here. Its
+        // handler runs once the IRQs are enabled again.
+        cortex_m::asm::wfi();
+        restart_clocks(rcc);
+        scb.clear_sleepdeep();
+
+        let woken_by_timer = rtc.isr.read().wutf().bit_is_set();
+        rtc.cr
+            .modify(|_, w| w.wutie().clear_bit().wute().clear_bit());
+        let end = rtc_ticks(rtc);
+
+        let total = u64::from(rtc_elapsed(start, end)) * 1000 + carry;
+        let elapsed_ms = (total / apre_hz) as u32;
+        carry = total % apre_hz;
+
+        unsafe { cortex_m::interrupt::enable() };
+        // Catch the tick up, one SysTick at a time, waking up the tasks due
+        // as if SysTick had kept running.
+        for _ in 0..elapsed_ms {
+            SCB::set_pendst();
+            cortex_m::asm::isb();
+        }
+        syst.enable_counter();
+
+        STOPS.fetch_add(1, Ordering::SeqCst);
+        STOPPED_MS.fetch_add(elapsed_ms, Ordering::SeqCst);
+        if !woken_by_timer {
+            EARLY_WAKEUPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Have the wakeup timer fire after `ms` milliseconds.
+fn arm_wakeup_timer(rtc: &pac::rtc::RegisterBlock, ms: u32) {
+    rtc.cr.modify(|_, w| w.wute().clear_bit());
+    while rtc.isr.read().wutwf().bit_is_clear() {}
+    // The timer counts the RTC clock divided by 16, i.e., `ck_apre` divided
+    // by 4, and fires after the reload value plus one periods.
+    let timer_hz = u64::from(APRE_HZ.load(Ordering::SeqCst)) * (u64::from(PREDIV_A) + 1) / 16;
+    let periods = (u64::from(ms) * timer_hz / 1000).clamp(1, 0x10000) as u32;
+    rtc.wutr.write(|w| w.wut().bits((periods - 1) as u16));
+    rtc.cr.modify(|_, w| w.wucksel().div16());
+    rtc.isr.modify(|_, w| w.wutf().clear());
+    rtc.cr.modify(|_, w| w.wutie().set_bit().wute().set_bit());
+}
+
+/// Switch the system clock back to the PLL fed by the HSE, as set up by the
+/// HAL at boot. The MCU wakes up from the STOP mode running from the HSI,
+/// with both the HSE and the PLL off, but their settings kept.
+fn restart_clocks(rcc: &pac::rcc::RegisterBlock) {
+    rcc.cr.modify(|_, w| w.hseon().on());
+    while rcc.cr.read().hserdy().is_not_ready() {}
+    rcc.cr.modify(|_, w| w.pllon().on());
+    while rcc.cr.read().pllrdy().is_not_ready() {}
+    rcc.cfgr.modify(|_, w| w.sw().pll());
+    while !rcc.cfgr.read().sws().is_pll() {}
+}
+
+#[handler(EXTI0)]
+fn exti0_handler() {
+    // Safety: Only the flag of the line is cleared here.
+    let exti = unsafe { &*EXTI::ptr() };
+    exti.pr.write(|w| w.pr0().clear());
+
+    // The tick may lag behind right after a stop, which only lengthens the
+    // time ignored.
+    let now = time::get_tick();
+    if now.wrapping_sub(LAST_PRESS.load(Ordering::SeqCst)) < DEBOUNCE_MS {
+        return;
+    }
+    LAST_PRESS.store(now, Ordering::SeqCst);
+    PRESSED.notify_allow_isr();
+}
+
+#[handler(RTC_WKUP)]
+fn rtc_wkup_handler() {
+    // Safety: Only the flags of the wakeup timer are cleared here.
+    let rtc = unsafe { &*RTC::ptr() };
+    let exti = unsafe { &*EXTI::ptr() };
+    rtc.isr.modify(|_, w| w.wutf().clear());
+    exti.pr.write(|w| w.pr22().clear());
+}
@@ -0,0 +1,304 @@
+//! Spawn short-lived tasks up to the task limit over and over, panic some of
+//! them, and check that the task slots, the heap, and the stacklets are all
+//! reclaimed.
+//!
+//! Each of [`ROUNDS`] rounds, the `stress` task spawns workers until the
+//! spawn fails with `NoMoreTask`, half of them restartable. The workers run
+//! below it, so they start once all are spawned. A worker allocates a block
+//! of a random size, recurses to a random depth to extend its stack, may
+//! sleep, and panics with a chance of one in [`PANIC_ONE_IN`], holding the
+//! block and the stacklets. A panicked worker is unwound and ends, or is
+//! restarted if restartable. The restart runs in a new task when a slot is
+//! free, i.e., concurrently with the unwinding, and in place otherwise, so
+//! both ways are taken as the slots are used up and freed.
+//!
+//! Once every worker has ended and the kernel has had [`SETTLE_MS`] to tidy
+//! up, the task compares against the first round the number of workers that
+//! could be spawned, the bytes used in the heap, and the active stacklets.
+//! The differences are the leak counters, printed every [`REPORT_EVERY`]
+//! rounds on USART2 with the tasks run, the panics, and the restarts, and
+//! checked after the last round. A round whose workers do not all end in
+//! [`ROUND_TIMEOUT_MS`] is reported as stuck. The console wiring is the one
+//! of Part 11 of the tutorial. Build and flash with `cargo run --release
+//! --example stress`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use alloc::vec::Vec;
+use core::{
+    fmt::Write,
+    hint::black_box,
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hopter::{
+    config,
+    debug::segmented_stack,
+    sync::SpinSchedSafe,
+    task::{self, main, TaskBuildError},
+    time,
+};
+use hopter_conf_params::{ALLOW_DYNAMIC_STACK, RAM_END_ADDR};
+use stm32f4xx_hal::{
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The rounds of spawning.
+const ROUNDS: u32 = 500;
+
+/// The rounds between two reports.
+const REPORT_EVERY: u32 = 50;
+
+/// The chance of a worker run to panic is one in this number.
+const PANIC_ONE_IN: u32 = 4;
+
+/// The largest block allocated by a worker, in bytes.
+const MAX_BLOCK: u32 = 512;
+
+/// The deepest recursion of a worker.
+const MAX_DEPTH: u32 = 24;
+
+/// The time given to the kernel to release the ended tasks.
+const SETTLE_MS: u32 = 20;
+
+/// The longest wait for the workers of a round to end.
+const ROUND_TIMEOUT_MS: u32 = 5000;
+
+/// The priority of the `stress` task. The workers run one level below.
+const PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY - 1;
+
+/// The workers that ended, by returning or by panicking without a restart,
+/// since the start of the round.
+static ENDED: AtomicU32 = AtomicU32::new(0);
+
+/// The runs of the worker entry, the first ones and the restarts, and the
+/// panics, since boot.
+static RUNS: AtomicU32 = AtomicU32::new(0);
+static PANICS: AtomicU32 = AtomicU32::new(0);
+
+/// The state of the pseudo-random numbers.
+static SEED: AtomicU32 = AtomicU32::new(1);
+
+/// Suspends the scheduler while the heap is walked.
+static WALK: SpinSchedSafe<()> = SpinSchedSafe::new(());
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let mut tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    // The workers rely on dynamic stack extension, as they are spawned
+    // without a stack limit.
+    if !ALLOW_DYNAMIC_STACK {
+        let _ = write!(tx, "\r\nstress: dynamic stack extension is off\r\n");
+        return;
+    }
+
+    task::build()
+        .set_priority(PRIORITY)
+        .set_entry(move || stress(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// The figures compared across the rounds.
+#[derive(Clone, Copy)]
+struct Snapshot {
+    spawned: u32,
+    heap_used: u32,
+    stacklets: usize,
+}
+
+/// Run every round and print the leak counters.
+fn stress(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nstress: {} rounds, up to {} tasks\r\n",
+        ROUNDS,
+        config::MAX_TASK_NUMBER
+    );
+
+    let mut baseline: Option<Snapshot> = None;
+    let mut leaky_rounds = 0;
+    let mut stuck_rounds = 0;
+    let mut tasks = 0;
+
+    for round in 1..=ROUNDS {
+        ENDED.store(0, Ordering::SeqCst);
+        let spawned = spawn_workers();
+        tasks += spawned;
+
+        let mut waited = 0;
+        while ENDED.load(Ordering::SeqCst) < spawned && waited < ROUND_TIMEOUT_MS {
+            time::sleep_ms(1).unwrap();
+            waited += 1;
+        }
+        if waited >= ROUND_TIMEOUT_MS {
+            stuck_rounds += 1;
+            let _ = write!(
+                tx,
+                "round {}: {} of {} workers ended, stuck\r\n",
+                round,
+                ENDED.load(Ordering::SeqCst),
+                spawned
+            );
+        }
+        time::sleep_ms(SETTLE_MS).unwrap();
+
+        let now = Snapshot {
+            spawned,
+            heap_used: heap_used(),
+            stacklets: segmented_stack::get_active_stacklet_count(),
+        };
+        let base = *baseline.get_or_insert(now);
+        let leaked = Snapshot {
+            spawned: base.spawned.saturating_sub(now.spawned),
+            heap_used: now.heap_used.saturating_sub(base.heap_used),
+            stacklets: now.stacklets.saturating_sub(base.stacklets),
+        };
+        if leaked.spawned != 0 || leaked.heap_used != 0 || leaked.stacklets != 0 {
+            leaky_rounds += 1;
+        }
+
+        if round % REPORT_EVERY == 0 || round == ROUNDS {
+            let runs = RUNS.load(Ordering::SeqCst);
+            let _ = write!(
+                tx,
+                "round {:>4}: {:>6} tasks, {:>5} panics, {:>5} restarts, \
+                 leaked {} slots, {} heap bytes, {} stacklets\r\n",
+                round,
+                tasks,
+                PANICS.load(Ordering::SeqCst),
+                runs.saturating_sub(tasks),
+                leaked.spawned,
+                leaked.heap_used,
+                leaked.stacklets
+            );
+        }
+    }
+
+    let _ = write!(
+        tx,
+        "stress: {}, {} leaky rounds, {} stuck rounds\r\n",
+        if leaky_rounds == 0 && stuck_rounds == 0 {
+            "PASS"
+        } else {
+            "FAIL"
+        },
+        leaky_rounds,
+        stuck_rounds
+    );
+}
+
+/// Spawn workers until no more task can be spawned, and return how many
+/// were spawned.
+fn spawn_workers() -> u32 {
+    let mut spawned = 0;
+    loop {
+        let result = if spawned % 2 == 0 {
+            task::build()
+                .set_priority(PRIORITY + 1)
+                .set_entry(|| worker(false))
+                .spawn()
+        } else {
+            task::build()
+                .set_priority(PRIORITY + 1)
+                .set_entry(|| worker(true))
+                .spawn_restartable()
+        };
+        match result {
+            Ok(()) => spawned += 1,
+            Err(TaskBuildError::NoMoreTask) => return spawned,
+            Err(_) => panic!("worker not spawned"),
+        }
+    }
+}
+
+/// Allocate, recurse, maybe sleep, and maybe panic.
+fn worker(restartable: bool) {
+    RUNS.fetch_add(1, Ordering::SeqCst);
+
+    let block: Vec<u8> = Vec::with_capacity(random(MAX_BLOCK) as usize + 1);
+    black_box(&block);
+    black_box(recurse(random(MAX_DEPTH)));
+    if random(2) == 0 {
+        time::sleep_ms(random(3) + 1).unwrap();
+    }
+
+    if random(PANIC_ONE_IN) == 0 {
+        PANICS.fetch_add(1, Ordering::SeqCst);
+        // A restartable worker runs again, and ends later.
+        if !restartable {
+            ENDED.fetch_add(1, Ordering::SeqCst);
+        }
+        panic!("worker panicked on purpose");
+    }
+    ENDED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[inline(never)]
+fn recurse(depth: u32) -> u32 {
+    // Kept on the stack of the frame.
+    let frame = [depth; 16];
+    let below = if depth == 0 { 0 } else { recurse(depth - 1) };
+    black_box(&frame).iter().fold(below, |acc, &w| acc ^ w)
+}
+
+/// Return a pseudo-random number below `bound`.
+fn random(bound: u32) -> u32 {
+    let mut x = SEED.fetch_add(0x9e37_79b9, Ordering::Relaxed);
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 13;
+    x % bound
+}
+
+/// Return the bytes of the allocated chunks of the heap, walked as in
+/// `usage` of `src/heap_stats.rs`.
+fn heap_used() -> u32 {
+    extern "C" {
+        // The end of the static data, defined by the linker script of the
+        // kernel.
+        static __sheap: u32;
+    }
+
+    // The heap starts at a multiple of 4 that is not a multiple of 8.
+    let mut header = (ptr::addr_of!(__sheap) as u32 + 3) & !3;
+    if header % 8 == 0 {
+        header += 4;
+    }
+
+    let mut used = 0;
+    let _suspended = WALK.lock();
+    while header < RAM_END_ADDR {
+        // Safety: The header lies in the heap, and the heap does not change
+        // with the scheduler suspended.
+        let word = unsafe { ptr::read_volatile(header as *const u32) };
+        // The guard at the end of the heap is the only chunk of length zero.
+        let len = word & !0x3;
+        if len == 0 {
+            break;
+        }
+        // Bit 1 is set for an allocated chunk.
+        if word & 0x2 != 0 {
+            used += len;
+        }
+        header += len;
+    }
+    used
+}
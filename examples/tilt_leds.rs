@@ -0,0 +1,152 @@
+//! Tilt the board to choose the LED that lights up.
+//!
+//! The LIS3DSH accelerometer of STM32F407 Discovery raises its INT1 pin,
+//! wired to PE0, whenever a new measurement is ready. PE0 raises EXTI0, whose
+//! handler notifies a `Mailbox`. The `tilt` task waits on the mailbox, reads
+//! the measurement over SPI1, and lights the LED on the side the board tilts
+//! towards, none while it lies flat, or all four while it is upside down. The
+//! task never polls the accelerometer, so it sleeps between measurements.
+//!
+//! The tutorial in `src/main.rs` already uses EXTI0 for the user button on
+//! PA0, and only one pin can drive an EXTI line, hence a separate program.
+//! The driver lives in `src/drivers/lis3dsh.rs`. Build and flash with
+//! `cargo run --release --example tilt_leds`.
+//!
+//! The accelerometer holds INT1 high until the measurement is read. If a
+//! read is ever missed, no further rising edge comes, so the task also reads
+//! the accelerometer when no notification arrives in time.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/drivers/lis3dsh.rs"]
+mod lis3dsh;
+
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{Mailbox, SpinIrqSafe},
+    task::{self, main},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use lis3dsh::Lis3dsh;
+use stm32f4xx_hal::{
+    gpio::{Edge, ErasedPin, Input, Output, PE0},
+    pac,
+    prelude::*,
+    spi::Spi,
+};
+
+/// The tilt in mg beyond which the board no longer counts as flat, about 15
+/// degrees.
+const TILT_MG: i32 = 250;
+
+/// The time without a measurement after which the task reads one anyway,
+/// several measurement periods.
+const TIMEOUT_MS: u32 = 5 * 1000 / lis3dsh::OUTPUT_RATE_HZ;
+
+irq!(Exti0Irq, pac::interrupt::EXTI0);
+
+/// The INT1 pin. The IRQ is masked when the lock is held.
+static INT1: SpinIrqSafe<Option<PE0<Input>>, Exti0Irq> = SpinIrqSafe::new(None);
+
+/// Notified by the handler when a measurement is ready.
+static DATA_READY: Mailbox = Mailbox::new();
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let mut dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let gpiod = dp.GPIOD.split();
+    let gpioe = dp.GPIOE.split();
+
+    // In the order of the tilt directions below.
+    let leds = [
+        gpiod.pd14.into_push_pull_output().erase(),
+        gpiod.pd12.into_push_pull_output().erase(),
+        gpiod.pd13.into_push_pull_output().erase(),
+        gpiod.pd15.into_push_pull_output().erase(),
+    ];
+
+    let spi = Spi::new(
+        dp.SPI1,
+        (gpioa.pa5, gpioa.pa6, gpioa.pa7),
+        lis3dsh::SPI_MODE,
+        1.MHz(),
+        &clocks,
+    );
+    let mut accel = match Lis3dsh::new(spi, gpioe.pe3.into_push_pull_output()) {
+        Ok(accel) => accel,
+        Err(err) => panic!("LIS3DSH: {}", err),
+    };
+
+    // Raise EXTI0 when INT1 goes high.
+    let mut syscfg = dp.SYSCFG.constrain();
+    let mut int1 = gpioe.pe0.into_floating_input();
+    int1.make_interrupt_source(&mut syscfg);
+    int1.trigger_on_edge(&mut dp.EXTI, Edge::Rising);
+    int1.enable_interrupt(&mut dp.EXTI);
+    *INT1.lock() = Some(int1);
+
+    // A measurement may have completed during the configuration, holding
+    // INT1 high. Read it so that the next one raises an edge.
+    let _ = accel.acceleration();
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::EXTI0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::EXTI0);
+    }
+
+    task::build()
+        .set_entry(move || tilt(accel, leds))
+        .spawn()
+        .unwrap();
+}
+
+/// Light the LED on the side the board tilts towards upon each measurement.
+fn tilt(mut accel: Lis3dsh, mut leds: [ErasedPin<Output>; 4]) {
+    loop {
+        // Read anyway after the timeout, see the module documentation.
+        let _ = DATA_READY.wait_until_timeout(TIMEOUT_MS);
+
+        let a = match accel.acceleration() {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+
+        // The acceleration is about +1 g along Z while the board lies face
+        // up. Light all four LEDs while it is upside down.
+        if a.z < -TILT_MG {
+            for led in leds.iter_mut() {
+                led.set_high();
+            }
+            continue;
+        }
+
+        // Towards +X, -X, +Y, and -Y, the steepest one winning.
+        let tilts = [a.x, -a.x, a.y, -a.y];
+        let (steepest, &mg) = tilts.iter().enumerate().max_by_key(|(_, &mg)| mg).unwrap();
+        for (i, led) in leds.iter_mut().enumerate() {
+            led.set_state((mg > TILT_MG && i == steepest).into());
+        }
+    }
+}
+
+#[handler(EXTI0)]
+fn exti0_handler() {
+    // Acknowledge the IRQ.
+    INT1.lock().as_mut().unwrap().clear_interrupt_pending_bit();
+
+    DATA_READY.notify_allow_isr();
+}
@@ -0,0 +1,538 @@
+//! Bridge USART2 and USART3 both ways at 921600 baud, with DMA on all four
+//! paths, and count the bytes forwarded in each direction.
+//!
+//! Put the board between two devices talking over a UART, and it passes
+//! their bytes along while counting them, a sniffer for a link whose ends
+//! cannot be probed. Or stream files into both sides at once from two
+//! USB-to-serial adapters, and it keeps four DMA streams, two channels, and
+//! two tasks busy at full rate, a torture test for the channel primitives.
+//!
+//! Each side receives as `examples/uart_dma_rx.rs` does. A DMA stream moves
+//! the bytes into a ring of [`RING_LEN`] bytes in circular mode. The
+//! idle-line IRQ of the USART, and the half-transfer and transfer-complete
+//! IRQs of the stream, hand the bytes received since the last time to the
+//! forwarding task of the direction, as a [`Chunk`] locating them in the
+//! ring, through a channel of [`CHUNK_QUEUE_LEN`] chunks. A burst, e.g., a
+//! line typed, thus makes one chunk, and a stream of bytes a chunk per half
+//! ring.
+//!
+//! The forwarding task hands the bytes of each chunk, still in place in the
+//! ring, to the sending DMA stream of the other side, and waits on a
+//! `Mailbox` for the transfer-complete IRQ of the stream. A chunk wrapping
+//! around the end of the ring is sent in two pieces. Both sides run at the
+//! same baud rate, so once a chunk starts being sent, the sending DMA stays
+//! ahead of the receiving one, which overwrites the ring behind it. A task
+//! falling behind by a whole ring, e.g., because the channel filled up with
+//! short chunks, finds the first byte of a chunk already overwritten, and
+//! skips the chunk, counting it as overwritten. A chunk not fitting in the
+//! channel is counted as dropped.
+//!
+//! The counts of both directions are printed every second on the TX pin of
+//! USART6, PC6, at 115200 baud, so that the report does not mix with the
+//! bridged bytes. Wire the first device to PA2, the TX pin of USART2, and
+//! PA3, its RX pin, and the second one to PD8, the TX pin of USART3, and
+//! PD9, its RX pin. Build and flash with `cargo run --release --example
+//! uart_bridge`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::NVIC;
+use embedded_dma::{ReadBuffer, WriteBuffer};
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mailbox, Producer, SpinIrqSafe},
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    dma::{
+        config::DmaConfig, traits::Stream, DmaFlag, MemoryToPeripheral, PeripheralToMemory,
+        Stream1, Stream3, Stream5, Stream6, StreamsTuple, Transfer,
+    },
+    pac::{self, DMA1, USART2, USART3, USART6},
+    prelude::*,
+    serial::{self, Rx, RxISR, RxListen, Tx},
+    ClearFlags,
+};
+
+/// The baud rate of both sides.
+const BAUD_RATE: u32 = 921_600;
+
+/// The size of the ring of each side. At 921600 baud, it takes about 5.5 ms
+/// to fill.
+const RING_LEN: usize = 512;
+
+/// The chunks handed to a forwarding task and not yet taken.
+const CHUNK_QUEUE_LEN: usize = 8;
+
+/// The directions, named by the side receiving the bytes, which index
+/// [`RINGS`], [`SENT`], and [`COUNTS`].
+const FROM_USART2: usize = 0;
+const FROM_USART3: usize = 1;
+
+/// A ring written by a receiving DMA stream.
+struct Ring {
+    /// Set once the ring has been handed to the DMA.
+    taken: AtomicBool,
+    bytes: UnsafeCell<[u8; RING_LEN]>,
+}
+
+// Safety: The bytes are only accessed through raw pointers, by the receiving
+// DMA stream writing them and the sending one reading them.
+unsafe impl Sync for Ring {}
+
+static RINGS: [Ring; 2] = [const {
+    Ring {
+        taken: AtomicBool::new(false),
+        bytes: UnsafeCell::new([0; RING_LEN]),
+    }
+}; 2];
+
+/// The exclusive right of a receiving DMA stream to write a ring.
+struct RingTarget(&'static Ring);
+
+impl RingTarget {
+    /// Return the right to write the ring, or `None` if it was already
+    /// taken.
+    fn take(ring: &'static Ring) -> Option<Self> {
+        if ring.taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(ring))
+        }
+    }
+}
+
+// Safety: The ring is a static, so its location is stable, and any bit
+// pattern is a valid `u8`.
+unsafe impl WriteBuffer for RingTarget {
+    type Word = u8;
+
+    unsafe fn write_buffer(&mut self) -> (*mut u8, usize) {
+        (self.0.bytes.get().cast(), RING_LEN)
+    }
+}
+
+/// Bytes of a ring read in place by a sending DMA stream, not wrapping
+/// around its end.
+#[derive(Debug)]
+struct Piece {
+    /// The index of the ring in [`RINGS`].
+    ring: usize,
+    start: usize,
+    len: usize,
+}
+
+// Safety: The ring is a static, so its location is stable. The receiving
+// DMA stream may overwrite the bytes while they are sent, which the
+// forwarding task checks for, see the module documentation.
+unsafe impl ReadBuffer for Piece {
+    type Word = u8;
+
+    unsafe fn read_buffer(&self) -> (*const u8, usize) {
+        let bytes = RINGS[self.ring].bytes.get().cast::<u8>();
+        (bytes.add(self.start), self.len)
+    }
+}
+
+/// The bytes received on a side since the previous chunk, left in place in
+/// the ring.
+struct Chunk {
+    /// The position of the first byte in the stream of received bytes.
+    start: u32,
+    len: u32,
+}
+
+impl Chunk {
+    /// Split the chunk into the pieces before and after the end of the
+    /// ring.
+    fn pieces(&self, ring: usize) -> impl Iterator<Item = Piece> {
+        let start = self.start as usize % RING_LEN;
+        let len = self.len as usize;
+        let first = len.min(RING_LEN - start);
+        [(start, first), (0, len - first)]
+            .into_iter()
+            .filter(|&(_, len)| len > 0)
+            .map(move |(start, len)| Piece { ring, start, len })
+    }
+}
+
+type Rx2Transfer = Transfer<Stream5<DMA1>, 4, Rx<USART2>, PeripheralToMemory, RingTarget>;
+type Rx3Transfer = Transfer<Stream1<DMA1>, 4, Rx<USART3>, PeripheralToMemory, RingTarget>;
+type Tx2Transfer = Transfer<Stream6<DMA1>, 4, Tx<USART2>, MemoryToPeripheral, Piece>;
+type Tx3Transfer = Transfer<Stream3<DMA1>, 4, Tx<USART3>, MemoryToPeripheral, Piece>;
+
+/// The state of the reception of a side, shared by its two handlers.
+struct Reception {
+    /// The direction of the bytes received.
+    dir: usize,
+    chunks: Producer<Chunk, CHUNK_QUEUE_LEN>,
+    /// The index in the ring of the next byte to be written by the DMA,
+    /// when last looked at.
+    pos: usize,
+    /// The bytes received, up to `pos`.
+    received: u32,
+    /// The bytes handed to the forwarding task.
+    delivered: u32,
+}
+
+impl Reception {
+    fn new(dir: usize, chunks: Producer<Chunk, CHUNK_QUEUE_LEN>) -> Self {
+        Self {
+            dir,
+            chunks,
+            pos: 0,
+            received: 0,
+            delivered: 0,
+        }
+    }
+
+    /// Account for the bytes written by the DMA since the last call, given
+    /// the transfers it has left in the lap, and return the bytes received.
+    /// Called at least twice per lap of the ring, by the DMA IRQs, so that
+    /// the distance between two calls is never a whole lap.
+    fn advance(&mut self, remaining: u16) -> u32 {
+        let pos = (RING_LEN - usize::from(remaining)) % RING_LEN;
+        let written = (pos + RING_LEN - self.pos) % RING_LEN;
+        self.pos = pos;
+        self.received = self.received.wrapping_add(written as u32);
+        self.received
+    }
+
+    /// Return the bytes received and not yet handed to the task.
+    fn pending(&self) -> u32 {
+        self.received.wrapping_sub(self.delivered)
+    }
+
+    /// Hand the bytes received and not yet handed to the task.
+    fn deliver(&mut self) {
+        let len = self.pending();
+        if len == 0 {
+            return;
+        }
+        let chunk = Chunk {
+            start: self.delivered,
+            len,
+        };
+        if self.chunks.try_produce_allow_isr(chunk).is_err() {
+            COUNTS[self.dir].dropped.fetch_add(1, Ordering::SeqCst);
+        }
+        self.delivered = self.received;
+    }
+}
+
+irq!(Usart2Irq, pac::interrupt::USART2);
+irq!(Usart3Irq, pac::interrupt::USART3);
+irq!(Dma1Stream5Irq, pac::interrupt::DMA1_STREAM5);
+irq!(Dma1Stream1Irq, pac::interrupt::DMA1_STREAM1);
+irq!(Dma1Stream6Irq, pac::interrupt::DMA1_STREAM6);
+irq!(Dma1Stream3Irq, pac::interrupt::DMA1_STREAM3);
+
+/// The receptions, each masking the IRQs of both of its handlers when the
+/// lock is held.
+static RX2: SpinIrqSafe<Option<(Rx2Transfer, Reception)>, (Usart2Irq, Dma1Stream5Irq)> =
+    SpinIrqSafe::new(None);
+static RX3: SpinIrqSafe<Option<(Rx3Transfer, Reception)>, (Usart3Irq, Dma1Stream1Irq)> =
+    SpinIrqSafe::new(None);
+
+/// The sending DMA streams. A stream IRQ is masked when the lock is held.
+static TX2: SpinIrqSafe<Option<Tx2Transfer>, Dma1Stream6Irq> = SpinIrqSafe::new(None);
+static TX3: SpinIrqSafe<Option<Tx3Transfer>, Dma1Stream3Irq> = SpinIrqSafe::new(None);
+
+/// Notified when a piece of each direction has been sent.
+static SENT: [Mailbox; 2] = [const { Mailbox::new() }; 2];
+
+/// The counts of a direction.
+struct Counts {
+    /// The bytes and chunks forwarded.
+    bytes: AtomicU32,
+    chunks: AtomicU32,
+    /// The chunks dropped because the queue was full.
+    dropped: AtomicU32,
+    /// The chunks overwritten by the receiving DMA before being sent.
+    overwritten: AtomicU32,
+}
+
+static COUNTS: [Counts; 2] = [const {
+    Counts {
+        bytes: AtomicU32::new(0),
+        chunks: AtomicU32::new(0),
+        dropped: AtomicU32::new(0),
+        overwritten: AtomicU32::new(0),
+    }
+}; 2];
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioc = dp.GPIOC.split();
+    let report_tx = dp.USART6.tx(gpioc.pc6, 115_200.bps(), &clocks).unwrap();
+
+    // Let both USARTs issue DMA requests both ways, and raise the IRQ when
+    // the line goes idle.
+    let config = serial::Config::default()
+        .baudrate(BAUD_RATE.bps())
+        .dma(serial::config::DmaConfig::TxRx);
+    let gpioa = dp.GPIOA.split();
+    let (tx2, mut rx2) = dp
+        .USART2
+        .serial((gpioa.pa2, gpioa.pa3), config, &clocks)
+        .unwrap()
+        .split();
+    let gpiod = dp.GPIOD.split();
+    let (tx3, mut rx3) = dp
+        .USART3
+        .serial((gpiod.pd8, gpiod.pd9), config, &clocks)
+        .unwrap()
+        .split();
+    rx2.listen_idle();
+    rx3.listen_idle();
+
+    // Receive in circular mode, with the IRQ raised at both the half and the
+    // end of the ring. The HAL has no setting for circular mode.
+    let streams = StreamsTuple::new(dp.DMA1);
+    let rx_config = DmaConfig::default()
+        .memory_increment(true)
+        .half_transfer_interrupt(true)
+        .transfer_complete_interrupt(true);
+    let target = RingTarget::take(&RINGS[FROM_USART2]).unwrap();
+    let mut rx2 = Transfer::init_peripheral_to_memory(streams.5, rx2, target, None, rx_config);
+    let target = RingTarget::take(&RINGS[FROM_USART3]).unwrap();
+    let mut rx3 = Transfer::init_peripheral_to_memory(streams.1, rx3, target, None, rx_config);
+    unsafe {
+        rx2.stream().set_circular_mode(true);
+        rx3.stream().set_circular_mode(true);
+    }
+
+    // Send from the rings, starting each piece with `next_transfer`. The
+    // transfers are set up with empty pieces and not started.
+    let tx_config = DmaConfig::default()
+        .memory_increment(true)
+        .transfer_complete_interrupt(true);
+    let empty = |ring| Piece {
+        ring,
+        start: 0,
+        len: 0,
+    };
+    *TX3.lock() = Some(Transfer::init_memory_to_peripheral(
+        streams.3,
+        tx3,
+        empty(FROM_USART2),
+        None,
+        tx_config,
+    ));
+    *TX2.lock() = Some(Transfer::init_memory_to_peripheral(
+        streams.6,
+        tx2,
+        empty(FROM_USART3),
+        None,
+        tx_config,
+    ));
+
+    let (producer, from_usart2) = sync::create_channel();
+    rx2.start(|_| {});
+    *RX2.lock() = Some((rx2, Reception::new(FROM_USART2, producer)));
+    let (producer, from_usart3) = sync::create_channel();
+    rx3.start(|_| {});
+    *RX3.lock() = Some((rx3, Reception::new(FROM_USART3, producer)));
+
+    let mut nvic = cp.NVIC;
+    for irq in [
+        pac::interrupt::USART2,
+        pac::interrupt::USART3,
+        pac::interrupt::DMA1_STREAM5,
+        pac::interrupt::DMA1_STREAM1,
+        pac::interrupt::DMA1_STREAM6,
+        pac::interrupt::DMA1_STREAM3,
+    ] {
+        unsafe {
+            nvic.set_priority(irq, IRQ_NORMAL_PRIORITY);
+            NVIC::unmask(irq);
+        }
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || forward(FROM_USART2, from_usart2, send_on_usart3, received_on_usart2))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || forward(FROM_USART3, from_usart3, send_on_usart2, received_on_usart3))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || report(report_tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Forward the chunks of a direction, starting each piece with `send`, and
+/// checking first with `received` that the chunk is not overwritten yet.
+fn forward(
+    dir: usize,
+    chunks: Consumer<Chunk, CHUNK_QUEUE_LEN>,
+    send: fn(Piece),
+    received: fn() -> u32,
+) {
+    let counts = &COUNTS[dir];
+    loop {
+        let chunk = chunks.consume();
+        if received().wrapping_sub(chunk.start) > RING_LEN as u32 {
+            counts.overwritten.fetch_add(1, Ordering::SeqCst);
+            continue;
+        }
+        for piece in chunk.pieces(dir) {
+            send(piece);
+            SENT[dir].wait();
+        }
+        counts.bytes.fetch_add(chunk.len, Ordering::SeqCst);
+        counts.chunks.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// A transfer is restarted only after the previous one completed, so
+// `next_transfer` does not fail.
+
+fn send_on_usart2(piece: Piece) {
+    TX2.lock().as_mut().unwrap().next_transfer(piece).unwrap();
+}
+
+fn send_on_usart3(piece: Piece) {
+    TX3.lock().as_mut().unwrap().next_transfer(piece).unwrap();
+}
+
+fn received_on_usart2() -> u32 {
+    let mut rx = RX2.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    reception.advance(transfer.number_of_transfers())
+}
+
+fn received_on_usart3() -> u32 {
+    let mut rx = RX3.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    reception.advance(transfer.number_of_transfers())
+}
+
+/// Print the counts of both directions every second while bytes are
+/// forwarded.
+fn report(mut tx: Tx<USART6>) {
+    let _ = write!(
+        tx,
+        "\r\nuart_bridge: USART2 and USART3 at {} baud, {} byte rings\r\n",
+        BAUD_RATE, RING_LEN
+    );
+    let mut last = [0; 2];
+    loop {
+        time::sleep_ms(1000).unwrap();
+        let bytes = COUNTS
+            .each_ref()
+            .map(|counts| counts.bytes.load(Ordering::SeqCst));
+        if bytes == last {
+            continue;
+        }
+        for (dir, name) in [(FROM_USART2, "2 -> 3"), (FROM_USART3, "3 -> 2")] {
+            let counts = &COUNTS[dir];
+            let _ = write!(
+                tx,
+                "{}: {} bytes ({} B/s), {} chunks, dropped {}, overwritten {}\r\n",
+                name,
+                bytes[dir],
+                bytes[dir].wrapping_sub(last[dir]),
+                counts.chunks.load(Ordering::SeqCst),
+                counts.dropped.load(Ordering::SeqCst),
+                counts.overwritten.load(Ordering::SeqCst)
+            );
+        }
+        last = bytes;
+    }
+}
+
+// The receiving handlers, as in `examples/uart_dma_rx.rs`. The idle-line IRQ
+// ends a chunk, and the DMA IRQs end one once half the ring is pending.
+
+#[handler(USART2)]
+fn usart2_handler() {
+    let mut rx = RX2.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    if transfer.is_idle() {
+        transfer.clear_idle_interrupt();
+        reception.advance(transfer.number_of_transfers());
+        reception.deliver();
+    }
+}
+
+#[handler(USART3)]
+fn usart3_handler() {
+    let mut rx = RX3.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    if transfer.is_idle() {
+        transfer.clear_idle_interrupt();
+        reception.advance(transfer.number_of_transfers());
+        reception.deliver();
+    }
+}
+
+#[handler(DMA1_STREAM5)]
+fn dma1_stream5_handler() {
+    let mut rx = RX2.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    transfer.clear_flags(DmaFlag::HalfTransfer | DmaFlag::TransferComplete);
+    reception.advance(transfer.number_of_transfers());
+    if reception.pending() >= RING_LEN as u32 / 2 {
+        reception.deliver();
+    }
+}
+
+#[handler(DMA1_STREAM1)]
+fn dma1_stream1_handler() {
+    let mut rx = RX3.lock();
+    let (transfer, reception) = rx.as_mut().unwrap();
+    transfer.clear_flags(DmaFlag::HalfTransfer | DmaFlag::TransferComplete);
+    reception.advance(transfer.number_of_transfers());
+    if reception.pending() >= RING_LEN as u32 / 2 {
+        reception.deliver();
+    }
+}
+
+// The sending handlers release the forwarding task waiting for the piece.
+
+#[handler(DMA1_STREAM6)]
+fn dma1_stream6_handler() {
+    TX2.lock()
+        .as_mut()
+        .unwrap()
+        .clear_flags(DmaFlag::TransferComplete);
+    SENT[FROM_USART3].notify_allow_isr();
+}
+
+#[handler(DMA1_STREAM3)]
+fn dma1_stream3_handler() {
+    TX3.lock()
+        .as_mut()
+        .unwrap()
+        .clear_flags(DmaFlag::TransferComplete);
+    SENT[FROM_USART2].notify_allow_isr();
+}
@@ -0,0 +1,359 @@
+//! Receive variable-length frames on USART2 at 921600 baud through a
+//! circular DMA buffer and the idle-line IRQ.
+//!
+//! The console of the tutorial takes an IRQ per received byte, which leaves
+//! about 10 us per byte at 921600 baud for the handler, the kernel, and every
+//! handler of higher priority. Any longer and bytes are lost to overruns.
+//! Here DMA1 stream 5 instead moves every byte into a buffer of [`BUF_LEN`]
+//! bytes in circular mode, and the CPU is involved only once per frame:
+//! - USART2 raises the idle-line IRQ when the line stays idle for a
+//!   character time after a byte, i.e., at the end of a frame. The handler
+//!   hands the bytes received since the last frame to the task.
+//! - The DMA raises its half-transfer and transfer-complete IRQs at the half
+//!   and the end of the buffer. A frame longer than half the buffer is then
+//!   handed over in parts, so that the task reads it before the DMA wraps
+//!   around into it.
+//!
+//! Both handlers share the state of the reception through a `SpinIrqSafe`
+//! masking both IRQs. The bytes of a frame are left in place. The task gets
+//! a [`Frame`] through a channel, a handle of a few words locating the
+//! frame in the buffer, and reads the bytes from there. The DMA never
+//! stops, so a handle cannot keep the DMA away from its bytes. If the task
+//! falls behind by a whole buffer, the bytes are overwritten, which
+//! [`Frame::intact`] detects after reading them.
+//!
+//! The `process` task counts the frames and the bytes, and the `report` task
+//! prints the counts every second on USART2, at the same baud rate. Send data
+//! to PA3 in bursts, e.g., files, from a USB-to-serial adapter able to run at
+//! 921600 baud. The console wiring is otherwise the one of Part 11 of the
+//! tutorial. Build and flash with `cargo run --release --example
+//! uart_dma_rx`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::NVIC;
+use embedded_dma::WriteBuffer;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Producer, SpinIrqSafe},
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    dma::{
+        config::DmaConfig, traits::Stream, DmaFlag, PeripheralToMemory, Stream5, StreamsTuple,
+        Transfer,
+    },
+    pac::{self, DMA1, USART2},
+    prelude::*,
+    serial::{self, Rx, RxISR, RxListen, Tx},
+    ClearFlags,
+};
+
+/// The baud rate of USART2.
+const BAUD_RATE: u32 = 921_600;
+
+/// The size of the circular buffer. At 921600 baud, it takes about 11 ms to
+/// fill.
+const BUF_LEN: usize = 1024;
+
+/// The frames handed to the task and not yet taken.
+const FRAME_QUEUE_LEN: usize = 8;
+
+/// The buffer written by the DMA.
+struct RxBuffer {
+    /// Set once the buffer has been handed to the DMA.
+    taken: AtomicBool,
+    bytes: UnsafeCell<[u8; BUF_LEN]>,
+}
+
+// Safety: The bytes are only accessed through raw pointers, by the DMA and by
+// the `process` task. See the module documentation.
+unsafe impl Sync for RxBuffer {}
+
+static BUFFER: RxBuffer = RxBuffer {
+    taken: AtomicBool::new(false),
+    bytes: UnsafeCell::new([0; BUF_LEN]),
+};
+
+/// The exclusive right of the DMA to write [`BUFFER`]. There is at most one.
+struct DmaTarget(());
+
+impl DmaTarget {
+    /// Return the right to write the buffer, or `None` if it was already
+    /// taken.
+    fn take() -> Option<Self> {
+        if BUFFER.taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(()))
+        }
+    }
+}
+
+// Safety: The buffer is a static, so its location is stable, and any bit
+// pattern is a valid `u8`.
+unsafe impl WriteBuffer for DmaTarget {
+    type Word = u8;
+
+    unsafe fn write_buffer(&mut self) -> (*mut u8, usize) {
+        (BUFFER.bytes.get().cast(), BUF_LEN)
+    }
+}
+
+type RxTransfer = Transfer<Stream5<DMA1>, 4, Rx<USART2>, PeripheralToMemory, DmaTarget>;
+
+/// The bytes of a received frame, or of a part of a frame longer than half
+/// the buffer, left in place in the buffer.
+struct Frame {
+    /// The position of the first byte in the stream of received bytes.
+    start: u32,
+    len: u32,
+    /// False for a part followed by more bytes of the same frame.
+    last: bool,
+}
+
+impl Frame {
+    /// Read the bytes from the buffer. Check [`Frame::intact`] afterwards.
+    fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let buffer = BUFFER.bytes.get().cast::<u8>();
+        (0..self.len).map(move |i| {
+            // The position wraps around in step with the buffer, whose
+            // length divides 2^32.
+            let pos = self.start.wrapping_add(i) as usize % BUF_LEN;
+            // Safety: The index is within the buffer. The read is volatile
+            // because the DMA writes the buffer.
+            unsafe { ptr::read_volatile(buffer.add(pos)) }
+        })
+    }
+
+    /// Return true if no byte of the frame has been overwritten by the DMA
+    /// yet.
+    fn intact(&self) -> bool {
+        let received = RECEPTION.lock().as_mut().unwrap().advance();
+        received.wrapping_sub(self.start) <= BUF_LEN as u32
+    }
+}
+
+irq!(Usart2Irq, pac::interrupt::USART2);
+irq!(Dma1Stream5Irq, pac::interrupt::DMA1_STREAM5);
+
+/// The state of the reception, shared by the two handlers.
+struct Reception {
+    transfer: RxTransfer,
+    frames: Producer<Frame, FRAME_QUEUE_LEN>,
+    /// The index in the buffer of the next byte to be written by the DMA,
+    /// when last looked at.
+    pos: usize,
+    /// The bytes received, up to `pos`.
+    received: u32,
+    /// The bytes handed to the task.
+    delivered: u32,
+}
+
+impl Reception {
+    /// Account for the bytes written by the DMA since the last call, and
+    /// return the bytes received. Called at least twice per lap of the
+    /// buffer, by the DMA IRQs, so that the distance between two calls is
+    /// never a whole lap.
+    fn advance(&mut self) -> u32 {
+        let pos = (BUF_LEN - usize::from(self.transfer.number_of_transfers())) % BUF_LEN;
+        let written = (pos + BUF_LEN - self.pos) % BUF_LEN;
+        self.pos = pos;
+        self.received = self.received.wrapping_add(written as u32);
+        self.received
+    }
+
+    /// Hand the bytes received and not yet handed to the task.
+    fn deliver(&mut self, last: bool) {
+        let len = self.received.wrapping_sub(self.delivered);
+        if len == 0 {
+            return;
+        }
+        let frame = Frame {
+            start: self.delivered,
+            len,
+            last,
+        };
+        if self.frames.try_produce_allow_isr(frame).is_err() {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+        self.delivered = self.received;
+    }
+}
+
+/// Both USART2 and DMA1 stream 5 IRQs are masked when the lock is held.
+static RECEPTION: SpinIrqSafe<Option<Reception>, (Usart2Irq, Dma1Stream5Irq)> =
+    SpinIrqSafe::new(None);
+
+/// The frames and the bytes processed by the task.
+static FRAMES: AtomicU32 = AtomicU32::new(0);
+static BYTES: AtomicU32 = AtomicU32::new(0);
+
+/// The length of the longest frame.
+static LONGEST: AtomicU32 = AtomicU32::new(0);
+
+/// The frames or parts of frames dropped because the queue was full.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// The frames or parts of frames overwritten by the DMA before being read.
+static OVERWRITTEN: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Let USART2 issue a DMA request for each byte received, and raise the
+    // IRQ when the line goes idle.
+    let gpioa = dp.GPIOA.split();
+    let config = serial::Config::default()
+        .baudrate(BAUD_RATE.bps())
+        .dma(serial::config::DmaConfig::Rx);
+    let serial = dp
+        .USART2
+        .serial((gpioa.pa2, gpioa.pa3), config, &clocks)
+        .unwrap();
+    let (tx, mut rx) = serial.split();
+    rx.listen_idle();
+
+    // Raise the IRQ at both the half and the end of the buffer.
+    let config = DmaConfig::default()
+        .memory_increment(true)
+        .half_transfer_interrupt(true)
+        .transfer_complete_interrupt(true);
+    let stream = StreamsTuple::new(dp.DMA1).5;
+    let target = DmaTarget::take().unwrap();
+    let mut transfer = Transfer::init_peripheral_to_memory(stream, rx, target, None, config);
+
+    // The HAL has no setting for circular mode, which restarts the transfer
+    // from the beginning of the buffer once it completes.
+    unsafe { transfer.stream().set_circular_mode(true) };
+    transfer.start(|_| {});
+
+    let (frames, consumer) = sync::create_channel();
+    *RECEPTION.lock() = Some(Reception {
+        transfer,
+        frames,
+        pos: 0,
+        received: 0,
+        delivered: 0,
+    });
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::USART2, IRQ_NORMAL_PRIORITY);
+        nvic.set_priority(pac::interrupt::DMA1_STREAM5, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::USART2);
+        NVIC::unmask(pac::interrupt::DMA1_STREAM5);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || process(consumer))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || report(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Take the frames as they arrive and count them.
+fn process(frames: Consumer<Frame, FRAME_QUEUE_LEN>) {
+    // The length of the frame whose parts are being taken.
+    let mut len = 0;
+    loop {
+        let frame = frames.consume();
+        // Stand for the processing of the bytes.
+        let _checksum = frame.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        if !frame.intact() {
+            OVERWRITTEN.fetch_add(1, Ordering::SeqCst);
+        }
+
+        BYTES.fetch_add(frame.len, Ordering::SeqCst);
+        len += frame.len;
+        if frame.last {
+            FRAMES.fetch_add(1, Ordering::SeqCst);
+            LONGEST.fetch_max(len, Ordering::SeqCst);
+            len = 0;
+        }
+    }
+}
+
+/// Print the counts every second while bytes arrive.
+fn report(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nuart_dma_rx: {} baud, {} byte buffer\r\n",
+        BAUD_RATE, BUF_LEN
+    );
+    let mut last_bytes = 0;
+    loop {
+        time::sleep_ms(1000).unwrap();
+        let bytes = BYTES.load(Ordering::SeqCst);
+        if bytes == last_bytes {
+            continue;
+        }
+        let _ = write!(
+            tx,
+            "{} frames, {} bytes ({} B/s), longest {}, dropped {}, overwritten {}\r\n",
+            FRAMES.load(Ordering::SeqCst),
+            bytes,
+            bytes.wrapping_sub(last_bytes),
+            LONGEST.load(Ordering::SeqCst),
+            DROPPED.load(Ordering::SeqCst),
+            OVERWRITTEN.load(Ordering::SeqCst)
+        );
+        last_bytes = bytes;
+    }
+}
+
+#[handler(USART2)]
+fn usart2_handler() {
+    let mut reception = RECEPTION.lock();
+    let reception = reception.as_mut().unwrap();
+
+    // Acknowledge the IRQ. It is raised only for the idle line.
+    if reception.transfer.is_idle() {
+        reception.transfer.clear_idle_interrupt();
+        reception.advance();
+        reception.deliver(true);
+    }
+}
+
+#[handler(DMA1_STREAM5)]
+fn dma1_stream5_handler() {
+    let mut reception = RECEPTION.lock();
+    let reception = reception.as_mut().unwrap();
+
+    // Acknowledge the IRQ.
+    reception
+        .transfer
+        .clear_flags(DmaFlag::HalfTransfer | DmaFlag::TransferComplete);
+
+    // Hand over a long frame in parts of half the buffer.
+    if reception.advance().wrapping_sub(reception.delivered) >= BUF_LEN as u32 / 2 {
+        reception.deliver(false);
+    }
+}
@@ -0,0 +1,226 @@
+//! Measure distances with an HC-SR04 ultrasonic sensor, timing its echo
+//! pulse by input capture.
+//!
+//! The sensor sends a burst of ultrasound upon a 10 us pulse on its TRIG
+//! pin, and then holds its ECHO pin high until the echo comes back, for
+//! 58 us per centimeter of distance. The `fire` task pulses TRIG, wired
+//! to PB5, every [`PERIOD_MS`]. ECHO is wired to PB4, which is 5 V tolerant,
+//! and routed to TIM3 channel 1.
+//!
+//! TIM3 counts microseconds, and times the pulse without the CPU. Both
+//! channel 1 and channel 2 capture the counter from the ECHO pin, channel 1
+//! on the rising edge and channel 2 on the falling edge. The rising edge also
+//! resets the counter, so channel 2 captures the width of the pulse itself.
+//! The capture IRQ of channel 2 produces the width into a channel, and
+//! returns. The `range` task consumes the widths, drops those beyond the
+//! range of the sensor, and takes the median of the last [`MEDIAN_LEN`], so
+//! that a stray echo does not show. The distance is printed on USART2 every
+//! [`REPORT_EVERY`] measurements. The console wiring is the one of Part 11
+//! of the tutorial. Build and flash with `cargo run --release --example
+//! ultrasonic`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::peripheral::NVIC;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Producer, SpinIrqSafe},
+    task::{self, main},
+    time::IntervalBarrier,
+};
+use hopter_conf_params::{HCLK_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY};
+use stm32f4xx_hal::{
+    gpio::{Output, PB5},
+    pac::{self, TIM3, USART2},
+    prelude::*,
+    serial::Tx,
+    timer::FTimerUs,
+};
+
+/// The time between measurements. The datasheet asks for at least 60 ms, so
+/// that the echoes of a measurement die out before the next.
+const PERIOD_MS: u32 = 60;
+
+/// The width of the pulse on TRIG.
+const TRIGGER_US: u32 = 10;
+
+/// The widest echo pulse taken, i.e., 4 m, the range of the sensor. The
+/// sensor holds ECHO high for some 38 ms when no echo comes back.
+const MAX_WIDTH_US: u32 = 4000 * 2000 / 343;
+
+/// The number of widths the median is taken over.
+const MEDIAN_LEN: usize = 5;
+
+/// The measurements between reports.
+const REPORT_EVERY: u32 = 8;
+
+/// The widths queued before the task takes them.
+const WIDTH_QUEUE_LEN: usize = 4;
+
+irq!(Tim3Irq, pac::interrupt::TIM3);
+
+/// The producing end of the widths, used by the handler. It is `None` until
+/// the channel is created.
+static WIDTHS: SpinIrqSafe<Option<Producer<u32, WIDTH_QUEUE_LEN>>, Tim3Irq> =
+    SpinIrqSafe::new(None);
+
+/// The number of widths dropped because the task fell behind.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let gpiob = dp.GPIOB.split();
+    let trigger = gpiob.pb5.into_push_pull_output();
+    // Route ECHO to TIM3 channel 1.
+    let _echo = gpiob.pb4.into_alternate::<2>();
+
+    // Enables the timer and sets the prescaler for 1 MHz. The 16-bit counter
+    // wraps around after 65 ms, longer than any echo pulse.
+    let tim3 = FTimerUs::new(dp.TIM3, &clocks).release();
+    tim3.arr.write(|w| w.arr().bits(0xffff));
+    // Load the prescaler.
+    tim3.egr.write(|w| w.ug().set_bit());
+    // Both channels capture from the ECHO pin, TI1, the first on the rising
+    // edge and the second on the falling edge.
+    tim3.ccmr1_input()
+        .write(|w| w.cc1s().ti1().cc2s().ti1().ic1f().fck_int_n8());
+    tim3.ccer.write(|w| {
+        w.cc1p()
+            .clear_bit()
+            .cc1np()
+            .clear_bit()
+            .cc2p()
+            .set_bit()
+            .cc2np()
+            .clear_bit()
+            .cc1e()
+            .set_bit()
+            .cc2e()
+            .set_bit()
+    });
+    // Reset the counter upon the rising edge.
+    tim3.smcr.write(|w| w.ts().ti1fp1().sms().reset_mode());
+    // Clear the flags raised so far.
+    tim3.sr.reset();
+    tim3.dier.write(|w| w.cc2ie().set_bit());
+    tim3.cr1.write(|w| w.cen().set_bit());
+
+    let (widths, consumer) = sync::create_channel();
+    *WIDTHS.lock() = Some(widths);
+
+    let mut nvic = cp.NVIC;
+    unsafe {
+        nvic.set_priority(pac::interrupt::TIM3, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::TIM3);
+    }
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || fire(trigger))
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(config::DEFAULT_TASK_PRIORITY)
+        .set_entry(move || range(consumer, tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Pulse TRIG every [`PERIOD_MS`].
+fn fire(mut trigger: PB5<Output>) {
+    let mut barrier = IntervalBarrier::new(PERIOD_MS).unwrap();
+    loop {
+        barrier.wait();
+        trigger.set_high();
+        cortex_m::asm::delay(HCLK_FREQUENCY_HZ / 1_000_000 * TRIGGER_US);
+        trigger.set_low();
+    }
+}
+
+/// Filter the widths and print the distances.
+fn range(widths: Consumer<u32, WIDTH_QUEUE_LEN>, mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nultrasonic: a measurement every {} ms, median of {}\r\n",
+        PERIOD_MS, MEDIAN_LEN
+    );
+
+    let mut recent = [0; MEDIAN_LEN];
+    let mut taken = 0;
+    let mut out_of_range = 0;
+    let mut measured = 0u32;
+    // The widths taken since the last report.
+    let mut fresh = 0;
+
+    loop {
+        let width = widths.consume();
+        measured += 1;
+        if width <= MAX_WIDTH_US {
+            recent[taken % MEDIAN_LEN] = width;
+            taken += 1;
+            fresh += 1;
+        } else {
+            out_of_range += 1;
+        }
+
+        if measured % REPORT_EVERY != 0 {
+            continue;
+        }
+        // Do not report a distance from old widths only.
+        if taken < MEDIAN_LEN || fresh == 0 {
+            let _ = write!(tx, "no echo, {} out of range\r\n", out_of_range);
+            continue;
+        }
+        fresh = 0;
+        let mut sorted = recent;
+        sorted.sort_unstable();
+        let median = sorted[MEDIAN_LEN / 2];
+        // Sound travels 343 m/s at 20 C, and the pulse lasts the round trip.
+        let mm = median * 343 / 2000;
+        let _ = write!(
+            tx,
+            "{}.{} cm, echo {} us, {} out of range, {} dropped\r\n",
+            mm / 10,
+            mm % 10,
+            median,
+            out_of_range,
+            DROPPED.load(Ordering::SeqCst)
+        );
+    }
+}
+
+#[handler(TIM3)]
+fn tim3_handler() {
+    // Safety: Only the capture register is read here, which also clears the
+    // flag of the IRQ.
+    let tim3 = unsafe { &*TIM3::ptr() };
+    let width = tim3.ccr2().read().bits();
+
+    if let Some(widths) = WIDTHS.lock().as_ref() {
+        if widths.try_produce_allow_isr(width).is_err() {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,494 @@
+//! The board as a USB keyboard and mouse.
+//!
+//! The OTG_FS port enumerates as a HID device, which hosts drive without a
+//! custom driver. Pressing the user button types the right arrow key, which
+//! turns the page of most slide shows. Tilting the board moves the mouse
+//! pointer towards the lower side, faster the steeper the tilt.
+//!
+//! The device itself is served by the OTG_FS IRQ handler, for the reason
+//! given in `src/usb_console.rs`: the USB stack masks interrupts in places,
+//! which must not happen while a task may allocate a stacklet. The class
+//! logic deciding what to report runs in two tasks instead. The `keys` task
+//! wakes up upon the EXTI0 IRQ of the button, debounced as in Part 17 of the
+//! tutorial, and the `mouse` task reads the LIS3DSH accelerometer at its
+//! output rate. Both push reports into a channel and pend the OTG_FS IRQ,
+//! whose handler moves the reports into the interrupt IN endpoint as the host
+//! polls it. Reports produced while no host has configured the device are
+//! dropped.
+//!
+//! The accelerometer would signal a measurement on PE0, i.e., on EXTI0 as
+//! well, which the button already takes, hence the polling. The drivers live
+//! in `src/drivers/`. The example needs the 48 MHz clock of OTG_FS alongside
+//! the system clock, see `src/usb_console.rs`, so it runs on STM32F407
+//! Discovery as is. Build and flash with `cargo run --release --example
+//! usb_hid --features usb-hid`, then plug the micro USB connector of the
+//! OTG_FS port into the host.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+#[path = "../src/drivers/button.rs"]
+mod button;
+#[path = "../src/drivers/lis3dsh.rs"]
+mod lis3dsh;
+
+use button::DebouncedButton;
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mutex, Producer, SpinIrqSafe},
+    task::{self, main},
+    time::IntervalBarrier,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use lis3dsh::Lis3dsh;
+use stm32f4xx_hal::{
+    gpio::{Input, PA0},
+    otg_fs::{UsbBus, UsbBusType, USB},
+    pac,
+    prelude::*,
+    spi::Spi,
+};
+use usb_device::{
+    bus::{InterfaceNumber, UsbBusAllocator},
+    class::{ControlIn, ControlOut, UsbClass},
+    control::{Recipient, Request, RequestType},
+    descriptor::DescriptorWriter,
+    device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+    endpoint::EndpointIn,
+    UsbError,
+};
+
+/// The VID and PID shared by the HID devices of the pid.codes test range.
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+
+/// The size of the packet memory of OTG_FS in words.
+const EP_MEMORY_WORDS: usize = 320;
+
+/// The number of reports buffered before the handler sends them.
+const REPORT_QUEUE_LEN: usize = 8;
+
+/// The interval in ms at which the host polls the endpoint for a report.
+const POLL_MS: u8 = 10;
+
+/// The usage of the right arrow key, see the keyboard page of the HID usage
+/// tables.
+const RIGHT_ARROW: u8 = 0x4f;
+
+/// The tilt in mg below which the pointer stays still, about 6 degrees.
+const DEAD_ZONE_MG: i32 = 100;
+
+/// The tilt in mg moving the pointer by one count per measurement.
+const MG_PER_COUNT: i32 = 20;
+
+/// The HID class codes and requests, see the Device Class Definition for HID.
+const USB_CLASS_HID: u8 = 0x03;
+const DESCRIPTOR_TYPE_HID: u8 = 0x21;
+const DESCRIPTOR_TYPE_REPORT: u8 = 0x22;
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+
+/// A keyboard with the report ID 1 and a three-button mouse with the report
+/// ID 2, in the layouts written by [`Report::encode`].
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: [u8; 93] = [
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x06,       // Usage (Keyboard)
+    0xa1, 0x01,       // Collection (Application)
+    0x85, 0x01,       //   Report ID (1)
+    0x05, 0x07,       //   Usage Page (Keyboard)
+    0x19, 0xe0,       //   Usage Minimum (Left Control)
+    0x29, 0xe7,       //   Usage Maximum (Right GUI)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x75, 0x01,       //   Report Size (1)
+    0x95, 0x08,       //   Report Count (8)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute): modifiers
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x01,       //   Report Count (1)
+    0x81, 0x01,       //   Input (Constant): reserved
+    0x19, 0x00,       //   Usage Minimum (0)
+    0x29, 0x65,       //   Usage Maximum (Application)
+    0x25, 0x65,       //   Logical Maximum (101)
+    0x95, 0x06,       //   Report Count (6)
+    0x81, 0x00,       //   Input (Data, Array): keys
+    0xc0,             // End Collection
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x02,       // Usage (Mouse)
+    0xa1, 0x01,       // Collection (Application)
+    0x85, 0x02,       //   Report ID (2)
+    0x09, 0x01,       //   Usage (Pointer)
+    0xa1, 0x00,       //   Collection (Physical)
+    0x05, 0x09,       //     Usage Page (Button)
+    0x19, 0x01,       //     Usage Minimum (1)
+    0x29, 0x03,       //     Usage Maximum (3)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x75, 0x01,       //     Report Size (1)
+    0x95, 0x03,       //     Report Count (3)
+    0x81, 0x02,       //     Input (Data, Variable, Absolute): buttons
+    0x75, 0x05,       //     Report Size (5)
+    0x95, 0x01,       //     Report Count (1)
+    0x81, 0x01,       //     Input (Constant): padding
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x30,       //     Usage (X)
+    0x09, 0x31,       //     Usage (Y)
+    0x15, 0x81,       //     Logical Minimum (-127)
+    0x25, 0x7f,       //     Logical Maximum (127)
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x02,       //     Report Count (2)
+    0x81, 0x06,       //     Input (Data, Variable, Relative): motion
+    0xc0,             //   End Collection
+    0xc0,             // End Collection
+];
+
+/// The length of the longest report, that of the keyboard.
+const MAX_REPORT_LEN: usize = 9;
+
+/// A report sent to the host.
+#[derive(Clone, Copy)]
+enum Report {
+    /// The key held down, or none if 0.
+    Key(u8),
+    /// The pointer motion, positive towards the right and the bottom.
+    Motion { x: i8, y: i8 },
+}
+
+impl Report {
+    /// Write the report, preceded by its ID, and return its length.
+    fn encode(self, buf: &mut [u8; MAX_REPORT_LEN]) -> usize {
+        match self {
+            Report::Key(key) => {
+                *buf = [1, 0, 0, key, 0, 0, 0, 0, 0];
+                9
+            }
+            Report::Motion { x, y } => {
+                buf[..4].copy_from_slice(&[2, 0, x as u8, y as u8]);
+                4
+            }
+        }
+    }
+}
+
+/// The HID interface with its interrupt IN endpoint.
+struct Hid<'a> {
+    interface: InterfaceNumber,
+    endpoint: EndpointIn<'a, UsbBusType>,
+}
+
+impl<'a> Hid<'a> {
+    fn new(bus: &'a UsbBusAllocator<UsbBusType>) -> Self {
+        Self {
+            interface: bus.interface(),
+            endpoint: bus.interrupt(MAX_REPORT_LEN as u16, POLL_MS),
+        }
+    }
+
+    /// Whether the request is addressed to the interface.
+    fn is_ours(&self, req: &Request) -> bool {
+        req.recipient == Recipient::Interface && req.index == u8::from(self.interface) as u16
+    }
+}
+
+impl UsbClass<UsbBusType> for Hid<'_> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        // Neither a boot interface subclass nor a protocol, so that the
+        // report descriptor applies.
+        writer.interface(self.interface, USB_CLASS_HID, 0, 0)?;
+        let [len_lo, len_hi] = (REPORT_DESCRIPTOR.len() as u16).to_le_bytes();
+        writer.write(
+            DESCRIPTOR_TYPE_HID,
+            &[
+                0x11,
+                0x01, // HID 1.11
+                0,    // Not localized
+                1,    // One class descriptor, the report descriptor
+                DESCRIPTOR_TYPE_REPORT,
+                len_lo,
+                len_hi,
+            ],
+        )?;
+        writer.endpoint(&self.endpoint)
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<UsbBusType>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) {
+            return;
+        }
+        if req.request_type == RequestType::Standard
+            && req.request == Request::GET_DESCRIPTOR
+            && (req.value >> 8) as u8 == DESCRIPTOR_TYPE_REPORT
+        {
+            let _ = xfer.accept_with_static(&REPORT_DESCRIPTOR);
+        } else if req.request_type == RequestType::Class {
+            // The reports are only sent through the endpoint.
+            let _ = xfer.reject();
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<UsbBusType>) {
+        let req = *xfer.request();
+        if !self.is_ours(&req) || req.request_type != RequestType::Class {
+            return;
+        }
+        match req.request {
+            // Every report is sent once, whatever the host asks for.
+            REQUEST_SET_IDLE | REQUEST_SET_PROTOCOL => {
+                let _ = xfer.accept();
+            }
+            _ => {
+                let _ = xfer.reject();
+            }
+        }
+    }
+}
+
+irq!(OtgFsIrq, pac::interrupt::OTG_FS);
+irq!(Exti0Irq, pac::interrupt::EXTI0);
+
+/// The endpoint memory and the allocator of the USB bus, which the device
+/// borrows for the rest of the program.
+struct BusStorage {
+    ep_memory: UnsafeCell<[u32; EP_MEMORY_WORDS]>,
+    allocator: UnsafeCell<MaybeUninit<UsbBusAllocator<UsbBusType>>>,
+}
+
+// Safety: The storage is only touched once, by `main`.
+unsafe impl Sync for BusStorage {}
+
+static BUS: BusStorage = BusStorage {
+    ep_memory: UnsafeCell::new([0; EP_MEMORY_WORDS]),
+    allocator: UnsafeCell::new(MaybeUninit::uninit()),
+};
+
+/// The state of the device, owned by the OTG_FS IRQ handler.
+struct Port {
+    bus: &'static UsbBusAllocator<UsbBusType>,
+    /// The device and its HID class. They are `None` until the handler first
+    /// runs.
+    usb: Option<(UsbDevice<'static, UsbBusType>, Hid<'static>)>,
+    reports: Consumer<Report, REPORT_QUEUE_LEN>,
+    /// The report taken from the channel but refused by the busy endpoint.
+    pending: Option<Report>,
+}
+
+/// The device. OTG_FS IRQ is masked when the lock is held.
+static PORT: SpinIrqSafe<Option<Port>, OtgFsIrq> = SpinIrqSafe::new(None);
+
+/// The producing end of the channel carrying the reports. It is `None` until
+/// `main` creates the channel.
+static REPORTS: Mutex<Option<Producer<Report, REPORT_QUEUE_LEN>>> = Mutex::new(None);
+
+/// Set while the host has configured the device.
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+
+/// The user button on PA0, pressed when high. The edges within 200 ms after a
+/// press are bounces.
+static BUTTON: DebouncedButton<PA0<Input>, Exti0Irq> = DebouncedButton::new(true, 200);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below, and
+    // `src/usb_console.rs` for the 48 MHz clock.
+    let mut dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr.require_pll48clk(), &cp.SYST);
+    assert!(
+        clocks.is_pll48clk_valid(),
+        "the clock of OTG_FS is not 48 MHz"
+    );
+
+    let gpioa = dp.GPIOA.split();
+    let gpioe = dp.GPIOE.split();
+
+    let spi = Spi::new(
+        dp.SPI1,
+        (gpioa.pa5, gpioa.pa6, gpioa.pa7),
+        lis3dsh::SPI_MODE,
+        1.MHz(),
+        &clocks,
+    );
+    let accel = match Lis3dsh::new(spi, gpioe.pe3.into_push_pull_output()) {
+        Ok(accel) => accel,
+        Err(err) => panic!("LIS3DSH: {}", err),
+    };
+
+    let mut syscfg = dp.SYSCFG.constrain();
+    BUTTON.init(gpioa.pa0.into_floating_input(), &mut syscfg, &mut dp.EXTI);
+
+    // Safety: `main` runs once, so the storage is handed out once.
+    let (ep_memory, allocator) = unsafe { (&mut *BUS.ep_memory.get(), &mut *BUS.allocator.get()) };
+    let usb = USB::new(
+        (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+        (gpioa.pa11, gpioa.pa12),
+        &clocks,
+    );
+    let bus = allocator.write(UsbBus::new(usb, ep_memory));
+
+    let (producer, consumer) = sync::create_channel();
+    *REPORTS.lock() = Some(producer);
+    *PORT.lock() = Some(Port {
+        bus,
+        usb: None,
+        reports: consumer,
+        pending: None,
+    });
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::EXTI0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::EXTI0);
+        cp.NVIC
+            .set_priority(pac::interrupt::OTG_FS, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::OTG_FS);
+    }
+    // Let the handler build the device.
+    cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+
+    task::build().set_entry(keys).spawn().unwrap();
+    task::build()
+        .set_entry(move || mouse(accel))
+        .spawn()
+        .unwrap();
+}
+
+/// Type the right arrow key upon each press of the button.
+fn keys() {
+    loop {
+        BUTTON.wait_for_press();
+        send(Report::Key(RIGHT_ARROW));
+        send(Report::Key(0));
+    }
+}
+
+/// Move the pointer towards the lower side of the board upon each
+/// measurement.
+fn mouse(mut accel: Lis3dsh) {
+    let mut barrier = IntervalBarrier::new(1000 / lis3dsh::OUTPUT_RATE_HZ).unwrap();
+
+    loop {
+        barrier.wait();
+
+        let a = match accel.acceleration() {
+            Ok(a) => a,
+            Err(_) => continue,
+        };
+
+        // Keep the pointer still while the board is upside down, e.g., put
+        // down face first.
+        if a.z < 0 {
+            continue;
+        }
+
+        // The acceleration grows along the axis pointing to the lower side.
+        // The X axis of the accelerometer maps to the right of the screen,
+        // and its Y axis to the top.
+        let x = counts(a.x);
+        let y = counts(-a.y);
+        if x != 0 || y != 0 {
+            send(Report::Motion { x, y });
+        }
+    }
+}
+
+/// Return the pointer motion for the tilt along an axis.
+fn counts(mg: i32) -> i8 {
+    let beyond = mg.abs() - DEAD_ZONE_MG;
+    if beyond <= 0 {
+        return 0;
+    }
+    let counts = (beyond / MG_PER_COUNT).min(i8::MAX as i32) as i8;
+    if mg < 0 {
+        -counts
+    } else {
+        counts
+    }
+}
+
+/// Queue the report for the handler, blocking while the queue is full. Drop
+/// it if no host has configured the device.
+fn send(report: Report) {
+    if !CONFIGURED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Some(reports) = REPORTS.lock().as_ref() {
+        if let Err(report) = reports.try_produce_allow_isr(report) {
+            // Let the handler drain the queue, and wait for room.
+            cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+            reports.produce(report);
+        }
+    }
+    // Let the handler send the report.
+    cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+}
+
+impl Port {
+    /// Serve the device, then move the queued reports into the endpoint.
+    fn serve(&mut self) {
+        let bus = self.bus;
+        let (device, hid) = self.usb.get_or_insert_with(|| {
+            let hid = Hid::new(bus);
+            let strings = StringDescriptors::default()
+                .manufacturer("Hopter")
+                .product("Hopter quick start HID")
+                .serial_number("0");
+            let device = UsbDeviceBuilder::new(bus, VID_PID)
+                .strings(&[strings])
+                .unwrap()
+                .build();
+            (device, hid)
+        });
+
+        device.poll(&mut [hid]);
+
+        let configured = device.state() == UsbDeviceState::Configured;
+        CONFIGURED.store(configured, Ordering::SeqCst);
+        if !configured {
+            // Discard the reports, waking any task waiting for room.
+            while self.reports.try_consume_allow_isr().is_some() {}
+            self.pending = None;
+            return;
+        }
+
+        // Each report the host takes raises the IRQ again, which sends the
+        // next one.
+        if let Some(report) = self
+            .pending
+            .take()
+            .or_else(|| self.reports.try_consume_allow_isr())
+        {
+            let mut buf = [0; MAX_REPORT_LEN];
+            let len = report.encode(&mut buf);
+            if let Err(UsbError::WouldBlock) = hid.endpoint.write(&buf[..len]) {
+                self.pending = Some(report);
+            }
+        }
+    }
+}
+
+#[handler(OTG_FS)]
+fn otg_fs_handler() {
+    if let Some(port) = PORT.lock().as_mut() {
+        port.serve();
+    }
+}
+
+#[handler(EXTI0)]
+fn exti0_handler() {
+    BUTTON.handle_irq();
+}
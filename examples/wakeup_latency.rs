@@ -0,0 +1,250 @@
+//! Measure the time from an IRQ handler notifying a task to the task running.
+//!
+//! TIM2 fires once per millisecond. When the `bench` task is waiting, the
+//! handler reads the cycle counter of the DWT, then notifies a `Mailbox` on
+//! which the task waits. The task reads the counter again as soon as the
+//! wait returns, so the difference covers the notification, the return from
+//! the handler, the context switch in PendSV, and the return from the wait.
+//! The time the processor takes to enter the handler is not included.
+//!
+//! The handler runs at `IRQ_NORMAL_PRIORITY`. Hopter runs SVC above PendSV,
+//! and PendSV below every IRQ, see `SVC_NORMAL_PRIORITY` and
+//! `PENDSV_PRIORITY` in `hopter-conf-params/src/lib.rs`. The switch to the
+//! woken task therefore waits for the IRQ handlers to finish, and for an SVC
+//! being served to the interrupted task. The `load` task, below every
+//! priority of `bench`, exercises the latter by spinning and allocating from
+//! the heap, which goes through SVC. Without the load, the CPU is idle, and
+//! the latency includes the wakeup from `wfi` in the idle task.
+//!
+//! The `bench` task sweeps its priority over [`PRIORITIES`], each without
+//! and with the load, and prints the minimum, the mean, and the maximum of
+//! [`SAMPLES`] measurements on USART2, with a histogram in microseconds. The
+//! console wiring is the one of Part 11 of the tutorial. Build and flash
+//! with `cargo run --release --example wakeup_latency`.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use alloc::boxed::Box;
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use cortex_m::peripheral::DWT;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{Mailbox, SpinIrqSafe},
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::{HCLK_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY};
+use stm32f4xx_hal::{
+    pac::{self, TIM2, USART2},
+    prelude::*,
+    serial::Tx,
+    timer::{CounterHz, Event, Flag},
+};
+
+/// The rate of TIM2 IRQ.
+const IRQ_RATE_HZ: u32 = 1000;
+
+/// The measurements per priority and load.
+const SAMPLES: u32 = 2000;
+
+/// The priorities of the `bench` task swept over, from the highest one below
+/// the main task down to the lowest one above the `load` task.
+const PRIORITIES: [u8; 3] = [
+    1,
+    config::DEFAULT_TASK_PRIORITY,
+    config::IDLE_TASK_PRIORITY - 2,
+];
+
+/// The priority of the `load` task.
+const LOAD_PRIORITY: u8 = config::IDLE_TASK_PRIORITY - 1;
+
+/// The upper bounds of the histogram buckets in microseconds. The last
+/// bucket takes the rest.
+const BUCKETS_US: [u32; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+const CYCLES_PER_US: u32 = HCLK_FREQUENCY_HZ / 1_000_000;
+
+irq!(Tim2Irq, pac::interrupt::TIM2);
+
+/// The IRQ timer. TIM2 IRQ is masked when the lock is held.
+static TIMER: SpinIrqSafe<Option<CounterHz<TIM2>>, Tim2Irq> = SpinIrqSafe::new(None);
+
+/// Set by the task before it waits, and cleared by the handler when it
+/// notifies, so that each notification is measured once.
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// The cycle count read by the handler.
+static STAMP: AtomicU32 = AtomicU32::new(0);
+
+/// Notified by the handler when the task is armed.
+static WAKEUP: Mailbox = Mailbox::new();
+
+/// Whether the `load` task keeps the CPU busy.
+static LOADED: AtomicBool = AtomicBool::new(false);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    // Count the CPU cycles.
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+
+    let gpioa = dp.GPIOA.split();
+    let tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+
+    let mut timer = dp.TIM2.counter_hz(&clocks);
+    timer.listen(Event::Update);
+    timer.start(IRQ_RATE_HZ.Hz()).unwrap();
+    *TIMER.lock() = Some(timer);
+
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::TIM2, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::TIM2);
+    }
+
+    task::build()
+        .set_priority(LOAD_PRIORITY)
+        .set_entry(load)
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_priority(PRIORITIES[0])
+        .set_entry(move || bench(tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Measure and print the latency at every priority, without and with the
+/// load.
+fn bench(mut tx: Tx<USART2>) {
+    let _ = write!(
+        tx,
+        "\r\nwakeup latency, HCLK at {} Hz, {} samples per run\r\n",
+        HCLK_FREQUENCY_HZ, SAMPLES
+    );
+
+    for prio in PRIORITIES {
+        task::change_current_priority(prio).unwrap();
+        for loaded in [false, true] {
+            LOADED.store(loaded, Ordering::SeqCst);
+            let stats = measure();
+            let _ = write!(
+                tx,
+                "\r\npriority {}, {}\r\n",
+                prio,
+                if loaded { "loaded" } else { "idle" }
+            );
+            stats.print(&mut tx);
+        }
+    }
+
+    LOADED.store(false, Ordering::SeqCst);
+    let _ = write!(tx, "\r\ndone\r\n");
+}
+
+/// The latencies of a run, in cycles.
+struct Stats {
+    min: u32,
+    max: u32,
+    sum: u64,
+    histogram: [u32; BUCKETS_US.len() + 1],
+}
+
+/// Take [`SAMPLES`] measurements.
+fn measure() -> Stats {
+    let mut stats = Stats {
+        min: u32::MAX,
+        max: 0,
+        sum: 0,
+        histogram: [0; BUCKETS_US.len() + 1],
+    };
+
+    for _ in 0..SAMPLES {
+        ARMED.store(true, Ordering::SeqCst);
+        WAKEUP.wait();
+        let cycles = DWT::cycle_count().wrapping_sub(STAMP.load(Ordering::SeqCst));
+
+        stats.min = stats.min.min(cycles);
+        stats.max = stats.max.max(cycles);
+        stats.sum += u64::from(cycles);
+        let bucket = BUCKETS_US
+            .iter()
+            .position(|&us| cycles < us * CYCLES_PER_US)
+            .unwrap_or(BUCKETS_US.len());
+        stats.histogram[bucket] += 1;
+    }
+    stats
+}
+
+impl Stats {
+    fn print(&self, tx: &mut Tx<USART2>) {
+        let mean = (self.sum / u64::from(SAMPLES)) as u32;
+        for (name, cycles) in [("min", self.min), ("mean", mean), ("max", self.max)] {
+            let _ = write!(
+                tx,
+                "  {:<4} {:>6} cycles, {:>4}.{:03} us\r\n",
+                name,
+                cycles,
+                cycles / CYCLES_PER_US,
+                cycles % CYCLES_PER_US * 1000 / CYCLES_PER_US
+            );
+        }
+        for (i, count) in self.histogram.iter().enumerate() {
+            match BUCKETS_US.get(i) {
+                Some(us) => {
+                    let _ = write!(tx, "  < {:>2} us {:>6}\r\n", us, count);
+                }
+                None => {
+                    let _ = write!(tx, "  >={:>2} us {:>6}\r\n", BUCKETS_US[i - 1], count);
+                }
+            }
+        }
+    }
+}
+
+/// Spin and allocate while the load is on, sleep otherwise.
+fn load() {
+    loop {
+        if !LOADED.load(Ordering::SeqCst) {
+            time::sleep_ms(10).unwrap();
+            continue;
+        }
+        for _ in 0..100 {
+            core::hint::spin_loop();
+        }
+        // Allocate through SVC.
+        let block = Box::new([0u8; 64]);
+        core::hint::black_box(&block);
+    }
+}
+
+#[handler(TIM2)]
+fn tim2_handler() {
+    let now = DWT::cycle_count();
+
+    // Acknowledge the IRQ.
+    TIMER.lock().as_mut().unwrap().clear_flags(Flag::Update);
+
+    if ARMED.swap(false, Ordering::SeqCst) {
+        STAMP.store(now, Ordering::SeqCst);
+        WAKEUP.notify_allow_isr();
+    }
+}
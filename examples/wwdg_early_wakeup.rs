@@ -0,0 +1,186 @@
+//! Record the state of the program just before the window watchdog resets it.
+//!
+//! The WWDG counts down from 0x7f and resets the system when its counter
+//! drops below 0x40. Refreshing it is allowed only once the counter is below
+//! the window value, so a program looping too fast is caught as well as one
+//! stuck. A task polls the counter and refreshes it in the window. Press
+//! the user button to make the task stall.
+//!
+//! When the counter reaches 0x40, one watchdog tick before the reset, the
+//! WWDG raises its early wakeup interrupt (EWI). A tick lasts 4096 × 8 APB1
+//! cycles, under 1 ms, far too short to print anything. The handler instead
+//! writes a record of the state into the RTC backup registers, which keep
+//! their content across the reset, and the program prints it on USART2 at
+//! the next boot. The console wiring is the one of Part 11 of the tutorial.
+//! Build and flash with `cargo run --release --example wwdg_early_wakeup`.
+//!
+//! The EWI is given `IRQ_MAX_PRIORITY`, so it preempts every other IRQ
+//! handler. It is still masked whenever the kernel or a `SpinIrqSafe` lock
+//! masks the IRQs, because Hopter sets BASEPRI to the same level, see
+//! `IRQ_DISABLE_BASEPRI_PRIORITY` in `hopter-conf-params/src/lib.rs`. If
+//! the IRQs stay masked for longer than the last tick, the reset comes first
+//! and the record is lost. The counter value read by the handler tells how
+//! late it ran. The handler must also be short, and take no lock shared with
+//! tasks.
+
+#![no_std]
+#![no_main]
+// Required by `#[handler]` macro.
+#![feature(naked_functions)]
+
+// Required by the `#[main]` macro.
+extern crate alloc;
+
+#[path = "common/board.rs"]
+mod board;
+
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hopter::{
+    config,
+    interrupt::declare::handler,
+    task::{self, main},
+    time,
+};
+use hopter_conf_params::IRQ_MAX_PRIORITY;
+use stm32f4xx_hal::{
+    gpio::{Input, PA0},
+    pac::{self, USART2},
+    prelude::*,
+    serial::Tx,
+};
+
+/// The counter value the WWDG starts from upon each refresh.
+const COUNTER_START: u8 = 0x7f;
+
+/// The counter value below which the WWDG may be refreshed. Refreshing it
+/// earlier causes a reset.
+const WINDOW: u8 = 0x50;
+
+/// The counter value at which the task refreshes the WWDG, inside
+/// the window and some ticks before the EWI at 0x40.
+const REFRESH_AT: u8 = 0x48;
+
+/// Marks a valid record in the backup registers.
+const MAGIC: u32 = 0x5757_4447;
+
+/// The number of refreshes so far.
+static REFRESHES: AtomicU32 = AtomicU32::new(0);
+
+/// The tick of the last refresh.
+static LAST_REFRESH: AtomicU32 = AtomicU32::new(0);
+
+#[main]
+fn main(mut cp: cortex_m::Peripherals) {
+    // See Part 1 of the tutorial for the initialization below.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let clocks = board::init_clocks(dp.RCC.constrain().cfgr, &cp.SYST);
+
+    let gpioa = dp.GPIOA.split();
+    let mut tx = dp.USART2.tx(gpioa.pa2, 115_200.bps(), &clocks).unwrap();
+    let button = gpioa.pa0.into_floating_input();
+
+    // Safety: The RCC registers touched below are used by no other code.
+    let rcc = unsafe { &*pac::RCC::ptr() };
+
+    // Unlock the backup domain, which holds the backup registers.
+    rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+    dp.PWR.cr.modify(|_, w| w.dbp().set_bit());
+
+    // Report the reset, then clear the record and the reset flags.
+    let _ = write!(
+        tx,
+        "\r\nwindow watchdog example, APB1 at {} Hz\r\n",
+        clocks.pclk1().raw()
+    );
+    // WWDGRSTF, which the PACs of some parts name differently.
+    if rcc.csr.read().bits() & 1 << 30 != 0 {
+        let bkpr = &dp.RTC.bkpr;
+        let _ = write!(tx, "reset by the window watchdog");
+        if bkpr[0].read().bits() == MAGIC {
+            let _ = write!(
+                tx,
+                ", warned at tick {} with the counter at {:#x}, {} refreshes, the last at tick {}",
+                bkpr[1].read().bits(),
+                bkpr[2].read().bits(),
+                bkpr[3].read().bits(),
+                bkpr[4].read().bits(),
+            );
+        }
+        let _ = write!(tx, "\r\n");
+    }
+    dp.RTC.bkpr[0].reset();
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    // Freeze the WWDG while the core is halted by a debugger.
+    dp.DBGMCU.apb1_fz.modify(|_, w| w.dbg_wwdg_stop().set_bit());
+
+    // Start the WWDG with the EWI. A tick is 4096 × 8 APB1 cycles.
+    rcc.apb1enr.modify(|_, w| w.wwdgen().set_bit());
+    dp.WWDG.cfr.modify(|_, w| {
+        w.wdgtb().div8();
+        w.ewi().set_bit();
+        w.w().bits(WINDOW)
+    });
+    dp.WWDG.sr.reset();
+    dp.WWDG
+        .cr
+        .write(|w| w.wdga().set_bit().t().bits(COUNTER_START));
+
+    unsafe {
+        cp.NVIC.set_priority(pac::interrupt::WWDG, IRQ_MAX_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::WWDG);
+    }
+
+    task::build()
+        // Above the default priority, so that the refresh is not delayed by
+        // other tasks.
+        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
+        .set_entry(move || feed(dp.WWDG, button, tx))
+        .spawn()
+        .unwrap();
+}
+
+/// Refresh the WWDG in the window until the button is pressed.
+fn feed(wwdg: pac::WWDG, button: PA0<Input>, mut tx: Tx<USART2>) {
+    loop {
+        if button.is_high() {
+            let _ = write!(tx, "stalled at tick {}\r\n", time::get_tick());
+            // Stop refreshing, as a task stuck in a loop would.
+            loop {
+                core::hint::spin_loop();
+            }
+        }
+
+        if wwdg.cr.read().t().bits() <= REFRESH_AT {
+            wwdg.cr
+                .write(|w| w.wdga().set_bit().t().bits(COUNTER_START));
+            REFRESHES.fetch_add(1, Ordering::SeqCst);
+            LAST_REFRESH.store(time::get_tick(), Ordering::SeqCst);
+        }
+
+        time::sleep_ms(1).unwrap();
+    }
+}
+
+#[handler(WWDG)]
+fn wwdg_handler() {
+    // Safety: The handler only reads the WWDG counter and acknowledges the
+    // IRQ, and the backup registers are used by no other code meanwhile.
+    let dp = unsafe { pac::Peripherals::steal() };
+    let counter = dp.WWDG.cr.read().t().bits();
+
+    // The record is marked valid last, in case the reset cuts it short.
+    let bkpr = &dp.RTC.bkpr;
+    bkpr[1].write(|w| w.bits(time::get_tick()));
+    bkpr[2].write(|w| w.bits(u32::from(counter)));
+    bkpr[3].write(|w| w.bits(REFRESHES.load(Ordering::SeqCst)));
+    bkpr[4].write(|w| w.bits(LAST_REFRESH.load(Ordering::SeqCst)));
+    bkpr[0].write(|w| w.bits(MAGIC));
+
+    // Acknowledge the IRQ. The counter is not refreshed, so the reset
+    // follows.
+    dp.WWDG.sr.reset();
+}
@@ -0,0 +1,46 @@
+//! Read the environment variables overriding configuration parameters at
+//! compile time, so that the same tree can be built for several boards
+//! without editing the source. Each variable is turned into an
+//! `Option` constant in `$OUT_DIR/overrides.rs`, which is `None` when the
+//! variable is unset.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+/// The supported variables, the generated constants, and their types.
+const OVERRIDES: &[(&str, &str, &str)] = &[
+    ("HOPTER_RAM_END", "RAM_END_OVERRIDE", "u32"),
+    ("HOPTER_SYSTICK_HZ", "SYSTICK_HZ_OVERRIDE", "u32"),
+    ("HOPTER_MAX_TASKS", "MAX_TASKS_OVERRIDE", "usize"),
+    ("HOPTER_PRIORITY_LEVELS", "PRIORITY_LEVELS_OVERRIDE", "u8"),
+];
+
+fn main() {
+    let mut code = String::new();
+
+    for (var, name, ty) in OVERRIDES {
+        println!("cargo:rerun-if-env-changed={var}");
+
+        let value = match env::var(var) {
+            Ok(text) => {
+                let value =
+                    parse(&text).unwrap_or_else(|| panic!("{var}={text} is not a valid number"));
+                format!("Some({value})")
+            }
+            Err(_) => "None".into(),
+        };
+        writeln!(code, "const {name}: Option<{ty}> = {value};").unwrap();
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("overrides.rs"), code).unwrap();
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number. Underscores are
+/// allowed as separators.
+fn parse(text: &str) -> Option<u64> {
+    let text = text.trim().replace('_', "");
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
@@ -2,161 +2,935 @@
 //! operating system. Clients of Hopter can change them as needed.
 //!
 //! For the correctness of system functioning, make sure the following
-//! parameter is correctly set.
-//! - [`SYSTICK_FREQUENCY_HZ`] : Hopter depends on it to generate 1 millisecond
-//!   interval ticks.
+//! parameters are correctly set.
+//! - [`HSE_FREQUENCY_HZ`] and the PLL factors : The system clock and hence
+//!   [`HCLK_FREQUENCY_HZ`] are derived from them. Hopter depends on the
+//!   latter to generate 1 millisecond interval ticks, and the quick-start
+//!   configures the clock tree to produce it.
 //!
 //! Names that are prefixed with single underscore are considered semi-private.
 //! One should not change it unless being familiar with Hopter kernel's source
 //! code. Names that are prefixed with double underscores are considered
 //! private. One should not change it unless the corresponding kernel code and
 //! the compiler's source code are also changed accordingly.
+//!
+//! Both kinds are hidden from the documentation unless the `expert` feature
+//! is enabled. Moreover, changing a private parameter fails the build unless
+//! the `expert` feature is enabled. The kernel still reads them regardless.
+//!
+//! The parameters assume an ARMv7-M core, e.g., Cortex-M4, by default. Enable
+//! the `armv6m` feature for Cortex-M0/M0+ parts. ARMv6-M implements only two
+//! priority bits and has no BASEPRI register, so interrupts are globally
+//! masked with PRIMASK instead, and the BASEPRI-specific parameters are
+//! removed. Enable the `armv8m` feature for TrustZone parts, e.g., Cortex-M33,
+//! to get the parameters describing the Secure and Non-secure worlds.
+//!
+//! Enable one of the `nvic-prio-bits-3`, `nvic-prio-bits-4`, or
+//! `nvic-prio-bits-5` features to match the number of priority bits that the
+//! part implements. All interrupt and exception priorities are derived from
+//! it. See [`NVIC_PRIO_BITS`].
+//!
+//! Enable one of the profile features to switch a coherent bundle of
+//! parameters at once instead of tuning them one by one.
+//! - `profile-small-ram` : Fewer tasks, smaller stacklets, and a single
+//!   breathing task at work at a time. Saves RAM at the cost of more frequent
+//!   stacklet allocation and less concurrency. The full quick-start demo
+//!   spawns more tasks than the profile allows.
+//! - `profile-low-latency` : Larger stacklets and a more eager hot-split
+//!   prevention, so that tasks rarely pause to extend their stacks, and more
+//!   breathing tasks at work at a time. Costs RAM.
+//! - `profile-debug` : Halt the system upon a panic, widen the contiguous
+//!   stack guard, and keep longer task names. Combinable with either profile
+//!   above.
+//!
+//! The kernel tick is fixed at 1 millisecond, so no profile changes it.
+//!
+//! Enable the `static-alloc` feature for applications that must not allocate
+//! after initialization. It turns off dynamic stack extension, so that each
+//! task runs on a stack of a fixed size from [`STACK_POOLS`], allocated in
+//! full when the task is spawned. Tasks should then all be spawned during
+//! initialization. Note that the kernel still allocates from its heap when a
+//! task is spawned, restarted, or unwound after a panic.
+//!
+//! A few parameters can be overridden at compile time by environment
+//! variables, which lets CI build the same tree for several boards. Numbers
+//! are given in decimal or in `0x`-prefixed hexadecimal.
+//! - `HOPTER_RAM_END` : The ending address of the internal SRAM, i.e.,
+//!   [`SRAM_END_ADDR`]. The RAM length in `memory.x` must agree with it,
+//!   unless a DMA region is carved from its top. See [`DMA_REGION_START`].
+//! - `HOPTER_SYSTICK_HZ` : [`SYSTICK_FREQUENCY_HZ`].
+//! - `HOPTER_MAX_TASKS` : [`MAX_TASK_NUMBER`].
+//! - `HOPTER_PRIORITY_LEVELS` : [`TASK_PRIORITY_LEVELS`].
 
 #![no_std]
+// `is_multiple_of` cannot be used in constants on the toolchain building the
+// kernel, so the checks compare remainders with zero instead.
+#![allow(unknown_lints, clippy::manual_is_multiple_of)]
+
+// The `*_OVERRIDE` constants generated by `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/overrides.rs"));
+
+/// Reject a change to the value of a private parameter unless the `expert`
+/// feature is enabled.
+macro_rules! pin_private {
+    ($name:ident, $value:expr) => {
+        #[cfg(not(feature = "expert"))]
+        const _: () = assert!(
+            $name == $value,
+            concat!(
+                "changing `",
+                stringify!($name),
+                "` requires the `expert` feature"
+            )
+        );
+    };
+}
+
+#[cfg(any(
+    all(feature = "nvic-prio-bits-3", feature = "nvic-prio-bits-4"),
+    all(feature = "nvic-prio-bits-3", feature = "nvic-prio-bits-5"),
+    all(feature = "nvic-prio-bits-4", feature = "nvic-prio-bits-5"),
+))]
+compile_error!("at most one of the `nvic-prio-bits-*` features can be enabled");
+
+#[cfg(all(
+    feature = "armv6m",
+    any(
+        feature = "nvic-prio-bits-3",
+        feature = "nvic-prio-bits-4",
+        feature = "nvic-prio-bits-5"
+    )
+))]
+compile_error!("ARMv6-M always implements 2 priority bits");
+
+#[cfg(all(feature = "profile-small-ram", feature = "profile-low-latency"))]
+compile_error!("features `profile-small-ram` and `profile-low-latency` are mutually exclusive");
 
 /* ############################ */
 /* ### Clock Configurations ### */
 /* ############################ */
 
+/// The frequency of the external oscillator, i.e., HSE. The STM32F4
+/// Discovery boards feed an 8 MHz clock to it.
+pub const HSE_FREQUENCY_HZ: u32 = 8_000_000;
+
+/// The division factor of the HSE frequency at the input of the main PLL,
+/// i.e., PLLM. The divided frequency must be within 1 to 2 MHz.
+pub const PLL_M: u32 = 8;
+
+/// The multiplication factor of the main PLL, i.e., PLLN. The VCO output
+/// frequency must be within 100 to 432 MHz.
+pub const PLL_N: u32 = 336;
+
+/// The division factor of the VCO output for the system clock, i.e., PLLP.
+/// Must be 2, 4, 6, or 8.
+pub const PLL_P: u32 = 2;
+
+/// The division factor of the VCO output for the 48 MHz clock of USB OTG FS,
+/// SDIO, and RNG, i.e., PLLQ. Must be within 2 to 15.
+pub const PLL_Q: u32 = 7;
+
+/// The frequency of the system clock, i.e., SYSCLK, produced by the main PLL.
+/// The application configures the clock tree to produce it. The HAL used by
+/// the quick-start searches the PLL factors on its own given the frequency,
+/// and may settle on factors different from but equivalent to the ones above.
+pub const TARGET_SYSCLK_HZ: u32 = HSE_FREQUENCY_HZ / PLL_M * PLL_N / PLL_P;
+
+/// The division factor of the system clock for the AHB bus and the CPU, i.e.,
+/// HPRE. Must be 1, 2, 4, 8, 16, 64, 128, 256, or 512.
+pub const AHB_PRESCALER: u32 = 1;
+
+/// The frequency of the CPU clock, i.e., HCLK.
+pub const HCLK_FREQUENCY_HZ: u32 = TARGET_SYSCLK_HZ / AHB_PRESCALER;
+
+const _: () = {
+    assert!(
+        HSE_FREQUENCY_HZ >= 4_000_000 && HSE_FREQUENCY_HZ <= 26_000_000,
+        "the HSE frequency must be within 4 to 26 MHz"
+    );
+    assert!(
+        HSE_FREQUENCY_HZ % PLL_M == 0,
+        "PLLM must divide the HSE frequency exactly"
+    );
+    let vco_in = HSE_FREQUENCY_HZ / PLL_M;
+    assert!(
+        vco_in >= 1_000_000 && vco_in <= 2_000_000,
+        "the PLL input frequency must be within 1 to 2 MHz"
+    );
+    let vco_out = vco_in * PLL_N;
+    assert!(
+        vco_out >= 100_000_000 && vco_out <= 432_000_000,
+        "the VCO output frequency must be within 100 to 432 MHz"
+    );
+    assert!(matches!(PLL_P, 2 | 4 | 6 | 8), "PLLP must be 2, 4, 6, or 8");
+    assert!(PLL_Q >= 2 && PLL_Q <= 15, "PLLQ must be within 2 to 15");
+    assert!(
+        vco_out / PLL_Q <= 48_000_000,
+        "the 48 MHz clock must not exceed 48 MHz"
+    );
+    // The fastest STM32F4 parts run at 180 MHz.
+    assert!(TARGET_SYSCLK_HZ <= 180_000_000);
+    assert!(
+        AHB_PRESCALER.is_power_of_two() && AHB_PRESCALER <= 512 && AHB_PRESCALER != 32,
+        "invalid AHB prescaler"
+    );
+};
+
+/// Whether the SysTick timer is driven by the CPU clock. When set to false,
+/// it is driven by the external reference clock, which runs at HCLK/8 on
+/// STM32F4 parts.
+///
+/// Hopter 0.2.3 never reads this parameter. It always starts the SysTick
+/// timer on the CPU clock and only reads [`SYSTICK_FREQUENCY_HZ`], which is
+/// derived from this parameter. Switching the clock source is left to the
+/// application, as done upon startup in the quick-start's `main.rs`.
+pub const SYSTICK_USE_CPU_CLOCK: bool = true;
+
 /// The frequency of the SysTick timer clock. Must be set correctly because
 /// Hopter relies on it to configure the SysTick counter to trigger the
 /// interrupt at 1 millisecond interval.
-pub const SYSTICK_FREQUENCY_HZ: u32 = 168_000_000;
+pub const SYSTICK_FREQUENCY_HZ: u32 = match SYSTICK_HZ_OVERRIDE {
+    Some(hz) => hz,
+    None if SYSTICK_USE_CPU_CLOCK => HCLK_FREQUENCY_HZ,
+    None => HCLK_FREQUENCY_HZ / 8,
+};
+
+const _: () = {
+    assert!(
+        SYSTICK_FREQUENCY_HZ % 1000 == 0,
+        "the SysTick clock cannot produce 1 millisecond ticks"
+    );
+    // The reload value is a 24-bit field.
+    assert!(SYSTICK_FREQUENCY_HZ / 1000 <= 0x00FF_FFFF);
+};
+
+/// The sources of the 1 millisecond kernel tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickSource {
+    /// The SysTick timer, clocked as described by [`SYSTICK_USE_CPU_CLOCK`].
+    SysTick,
+    /// A general-purpose or basic timer, e.g., TIM7. The kernel advances the
+    /// tick in the SysTick exception handler, so the update IRQ handler of the
+    /// timer pends the SysTick exception rather than advancing the tick
+    /// itself. The SysTick counter is then stopped and left free for the
+    /// application, but the SysTick exception remains owned by the kernel.
+    /// See the quick-start's `tick_source` module for the wiring.
+    Tim {
+        /// The number of the timer, e.g., 7 for TIM7.
+        tim: u8,
+        /// The IRQ number of the timer's update interrupt.
+        irq: u16,
+    },
+}
+
+/// The source of the kernel tick.
+pub const TICK_SOURCE: TickSource = TickSource::SysTick;
+
+const _: () = {
+    if let TickSource::Tim { tim, irq } = TICK_SOURCE {
+        assert!(tim > 0, "invalid timer number");
+        // The NVIC supports at most 240 external interrupts.
+        assert!(irq < 240, "invalid IRQ number");
+    }
+};
 
 /* ############################ */
 /* ### Stack Configurations ### */
 /* ############################ */
 
-/// Whether dynamic extension of the stack is allowed.
-pub const ALLOW_DYNAMIC_STACK: bool = true;
+/// Whether dynamic extension of the stack is allowed. Forced off by the
+/// `static-alloc` feature. When off, a task overflowing the stack allocated
+/// at its spawn brings down the whole system, so every task must be given a
+/// stack large enough, e.g., from [`STACK_POOLS`].
+pub const ALLOW_DYNAMIC_STACK: bool = !cfg!(feature = "static-alloc");
 
 /// The extra size added to a stacklet allocation request in addition to the
-/// allocation size requested by the function prologue.
-pub const STACKLET_ADDITION_ALLOC_SIZE: usize = 64;
+/// allocation size requested by the function prologue. The `ps` shell
+/// command of the quick-start shows the stacklets each task holds and the
+/// peak of their bytes, against which this value can be tuned.
+pub const STACKLET_ADDITION_ALLOC_SIZE: usize = if cfg!(feature = "profile-small-ram") {
+    32
+} else if cfg!(feature = "profile-low-latency") {
+    128
+} else {
+    64
+};
 
 /// The number of hot-split site that a task can address. The larger the number
 /// is, the unlikely that a task will suffer from hot-split, but the task
 /// struct also becomes larger.
-pub const HOT_SPLIT_PREVENTION_CACHE_SIZE: usize = 4;
+pub const HOT_SPLIT_PREVENTION_CACHE_SIZE: usize = if cfg!(feature = "profile-small-ram") {
+    2
+} else if cfg!(feature = "profile-low-latency") {
+    8
+} else {
+    4
+};
 
 /// During the existance of a stacklet, if [`HOT_SPLIT_DETECTION_THRESHOLD`] or
 /// more new stacklet allocation is requested while the task is running with the
 /// stacklet, it will be considered as a hot-split site.
-pub const HOT_SPLIT_DETECTION_THRESHOLD: usize = 10;
+pub const HOT_SPLIT_DETECTION_THRESHOLD: usize = if cfg!(feature = "profile-low-latency") {
+    4
+} else {
+    10
+};
 
 /// The stack size of the main task when it is just created. If
 /// [`ALLOW_DYNAMIC_STACK`] is set to true, this value can be kept to 0 so
 /// that the stack will be allocated completely dynamically.
-pub const MAIN_TASK_INITIAL_STACK_SIZE: usize = 0;
+pub const MAIN_TASK_INITIAL_STACK_SIZE: usize = if ALLOW_DYNAMIC_STACK { 0 } else { 8192 };
 
 /// The stack size of the idle task when it is just created. If
 /// [`ALLOW_DYNAMIC_STACK`] is set to true, this value can be kept to 0 so
 /// that the stack will be allocated completely dynamically.
-pub const _IDLE_TASK_INITIAL_STACK_SIZE: usize = 0;
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const _IDLE_TASK_INITIAL_STACK_SIZE: usize = if ALLOW_DYNAMIC_STACK { 0 } else { 1024 };
+
+/// A number of task stacks of the same size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackPool {
+    /// The size in bytes of each stack.
+    pub size: usize,
+    /// The number of stacks.
+    pub count: usize,
+}
+
+/// The stacks available to the application tasks when
+/// [`ALLOW_DYNAMIC_STACK`] is false, ordered from the smallest size to the
+/// largest. A task takes a stack from a pool when it is spawned, and the
+/// stack is never returned. The restarted instances of a restartable task
+/// count as the same task. The `stack_pool` module of the quick-start hands
+/// out the stacks.
+///
+/// The pools bound the memory taken by the task stacks, but they are not
+/// placed in dedicated memory. Hopter 0.2.3 does not read this parameter and
+/// allocates each stack from the heap when the task is spawned.
+///
+/// The first pool covers the tasks the quick-start spawns under the
+/// `static-alloc` feature with a few stacks to spare. Spawning a task from an
+/// exhausted pool panics, so a pool too small shows up at boot.
+pub const STACK_POOLS: &[StackPool] = &[
+    StackPool {
+        size: 1024,
+        count: 40,
+    },
+    StackPool {
+        size: 4096,
+        count: 2,
+    },
+];
+
+const _: () = {
+    let mut total = MAIN_TASK_INITIAL_STACK_SIZE + _IDLE_TASK_INITIAL_STACK_SIZE;
+    let mut i = 0;
+    while i < STACK_POOLS.len() {
+        let pool = &STACK_POOLS[i];
+        assert!(pool.size > 0 && pool.size % 8 == 0);
+        assert!(
+            i == 0 || STACK_POOLS[i - 1].size < pool.size,
+            "stack pools must be ordered by size"
+        );
+        total += pool.size * pool.count;
+        i += 1;
+    }
+    assert!(
+        ALLOW_DYNAMIC_STACK || total <= (RAM_END_ADDR - _CONTIGUOUS_STACK_BOTTOM) as usize,
+        "the stack pools do not fit in the primary heap region"
+    );
+};
 
 /// The length of the contiguous stack placed at the beginning of the RAM region.
 /// The value must match the one in `memory.x`.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
 pub const _CONTIGUOUS_STACK_LENGTH: u32 = 0x1000;
 
 /// The bottom of the congituous stack.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
 pub const _CONTIGUOUS_STACK_BOTTOM: u32 = 0x2000_0000 + _CONTIGUOUS_STACK_LENGTH;
 
+/// The size in bytes of the guard region right beyond
+/// [`__CONTIGUOUS_STACK_BOUNDARY`]. The region sits between the task local
+/// storage, which occupies the first 16 bytes of RAM, and the boundary.
+pub const CONTIGUOUS_STACK_GUARD_SIZE: u32 = if cfg!(feature = "profile-debug") {
+    0x40
+} else {
+    0x10
+};
+
 /// The boundary of the contiguous stack that its top should not grow beyond.
-pub const __CONTIGUOUS_STACK_BOUNDARY: u32 = 0x2000_0020;
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const __CONTIGUOUS_STACK_BOUNDARY: u32 = __TLS_MEM_ADDR + 0x10 + CONTIGUOUS_STACK_GUARD_SIZE;
+pin_private!(
+    __CONTIGUOUS_STACK_BOUNDARY,
+    0x2000_0010 + CONTIGUOUS_STACK_GUARD_SIZE
+);
+
+/// Whether to fill the guard region with [`STACK_CANARY_VALUE`]. The
+/// contiguous stack serves the boot code, the kernel, and the IRQ handlers.
+/// Code running on it may write past the boundary without being caught,
+/// e.g., handlers written in assembly. A damaged canary reveals such an
+/// overflow. The check is performed by the `stack_guard` module of the
+/// quick-start, which panics upon a violation so that the offending handler
+/// or task is killed and unwound just as when it hits a stack limit.
+pub const ENABLE_STACK_CANARY: bool = true;
+
+/// The word repeated across the guard region when
+/// [`ENABLE_STACK_CANARY`] is set.
+pub const STACK_CANARY_VALUE: u32 = 0xDEAD_BEEF;
+
+const _: () = {
+    assert!(CONTIGUOUS_STACK_GUARD_SIZE % 8 == 0);
+    assert!(
+        !ENABLE_STACK_CANARY || CONTIGUOUS_STACK_GUARD_SIZE > 0,
+        "the stack canary needs a non-empty guard region"
+    );
+    assert!(__CONTIGUOUS_STACK_BOUNDARY < _CONTIGUOUS_STACK_BOTTOM);
+};
 
 /* ########################### */
 /* ### Heap Configurations ### */
 /* ########################### */
 
-/// The ending address of the heap region.
-pub const RAM_END_ADDR: u32 = 0x2002_0000;
+/// Properties of a heap region that decide what the memory is suitable for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapAttributes {
+    /// Whether DMA controllers can access the region. E.g., the core coupled
+    /// memory (CCM) on STM32F4 is reachable only by the CPU.
+    pub dma_accessible: bool,
+    /// Whether accesses to the region go through the data cache. DMA buffers
+    /// in cacheable memory require cache maintenance.
+    pub cacheable: bool,
+}
+
+/// A contiguous range `[start, end)` of memory used as heap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapRegion {
+    /// The starting address of the region.
+    pub start: u32,
+    /// The ending address of the region.
+    pub end: u32,
+    /// The properties of the region.
+    pub attributes: HeapAttributes,
+}
+
+/// The ending address of the internal SRAM holding the primary heap region.
+pub const SRAM_END_ADDR: u32 = match RAM_END_OVERRIDE {
+    Some(addr) => addr,
+    None => 0x2002_0000,
+};
+
+/// The length in bytes of the memory region reserved for DMA buffers. Set to
+/// 0 to reserve none. The region is excluded from all heap regions and is
+/// managed by the `dma_heap` module of the quick-start instead.
+///
+/// The CCM on STM32F4 is not reachable by DMA, and the SRAM of parts with a
+/// data cache, e.g., STM32F7 and STM32H7, needs cache maintenance around DMA
+/// transfers unless the MPU marks it uncacheable. A dedicated region lets DMA
+/// buffers be placed in memory that is known to be safe for DMA.
+pub const DMA_REGION_LEN: u32 = 0;
+
+/// The starting address of the region reserved for DMA buffers. By default
+/// the region is carved from the top of the internal SRAM, and the primary
+/// heap region ends where it starts. The RAM region in `memory.x` must then
+/// end at this address as well, because the kernel allocator extends the heap
+/// to the end of it. The region may also be placed in another memory, e.g.,
+/// the uncacheable SRAM4 on STM32H7.
+pub const DMA_REGION_START: u32 = SRAM_END_ADDR - DMA_REGION_LEN;
+
+/// Whether the region reserved for DMA buffers is carved from the top of the
+/// internal SRAM.
+const DMA_REGION_IN_SRAM: bool = DMA_REGION_LEN != 0
+    && DMA_REGION_START >= _CONTIGUOUS_STACK_BOTTOM
+    && DMA_REGION_START < SRAM_END_ADDR;
+
+/// The memory regions used as heap.
+///
+/// The first region is the primary heap. It is managed by the kernel
+/// allocator, which serves the `alloc` types and also the stacklets. The
+/// region must lie in the internal SRAM right above the contiguous stack. The
+/// linker places the `.data` and `.bss` sections at its start, and the heap
+/// begins after them.
+///
+/// The remaining regions are secondary heaps, e.g., the CCM or an external
+/// SDRAM behind the FSMC/FMC. The kernel does not allocate from them.
+/// Instead, applications allocate from them explicitly through the allocators
+/// in the `region_heap` module of the quick-start. External memory must be
+/// initialized before the first allocation from it.
+pub const HEAP_REGIONS: &[HeapRegion] = &[
+    HeapRegion {
+        start: _CONTIGUOUS_STACK_BOTTOM,
+        end: if DMA_REGION_IN_SRAM {
+            DMA_REGION_START
+        } else {
+            SRAM_END_ADDR
+        },
+        attributes: HeapAttributes {
+            dma_accessible: true,
+            cacheable: false,
+        },
+    },
+    // Uncomment to use the 64 KiB CCM on STM32F407 as a secondary heap. It is
+    // not listed in `memory.x`, so the linker leaves it untouched.
+    //
+    // HeapRegion {
+    //     start: 0x1000_0000,
+    //     end: 0x1001_0000,
+    //     attributes: HeapAttributes {
+    //         dma_accessible: false,
+    //         cacheable: false,
+    //     },
+    // },
+];
+
+/// The ending address of the primary heap region.
+pub const RAM_END_ADDR: u32 = HEAP_REGIONS[0].end;
 
-/// Free memory chunks use 16-bit links to form linked lists. Since memory
-/// chunks are 4-byte aligned, the linkes can represent a range of 2^18 bytes.
-/// The represented range is
-/// `[__MEM_CHUNK_LINK_OFFSET, __MEM_CHUNK_LINK_OFFSET + 2^18)`.
-pub const __MEM_CHUNK_LINK_OFFSET: u32 = 0x2000_0000;
+const _: () = {
+    assert!(HEAP_REGIONS[0].start == _CONTIGUOUS_STACK_BOTTOM);
+
+    if DMA_REGION_LEN != 0 {
+        assert!(
+            DMA_REGION_START % 8 == 0 && DMA_REGION_LEN % 8 == 0,
+            "the DMA region must be 8-byte aligned"
+        );
+        assert!(
+            !DMA_REGION_IN_SRAM || DMA_REGION_START + DMA_REGION_LEN == SRAM_END_ADDR,
+            "a DMA region in the internal SRAM must be carved from its top"
+        );
+    }
+
+    let mut i = 0;
+    while i < HEAP_REGIONS.len() {
+        let region = &HEAP_REGIONS[i];
+        assert!(region.start < region.end, "empty heap region");
+        assert!(region.start % 8 == 0 && region.end % 8 == 0);
+
+        let mut j = 0;
+        while j < i {
+            let other = &HEAP_REGIONS[j];
+            assert!(
+                region.end <= other.start || other.end <= region.start,
+                "overlapping heap regions"
+            );
+            j += 1;
+        }
+        assert!(
+            DMA_REGION_LEN == 0
+                || region.end <= DMA_REGION_START
+                || DMA_REGION_START + DMA_REGION_LEN <= region.start,
+            "the DMA region overlaps a heap region"
+        );
+        i += 1;
+    }
+};
+
+/// Free memory chunks use links of this width in bits to form linked lists.
+/// 16-bit links keep free chunks small but can reach only 2^18 bytes of
+/// memory, which rules out parts with more RAM, e.g., STM32F7 and STM32H7.
+/// Enable the `large-heap` feature to use 32-bit links instead.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const __MEM_CHUNK_LINK_BITS: u32 = if cfg!(feature = "large-heap") { 32 } else { 16 };
+
+/// The alignment in bytes of memory chunks. A link stores the offset of a
+/// chunk from [`__MEM_CHUNK_LINK_OFFSET`] divided by the alignment.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const __MEM_CHUNK_ALIGN: u32 = 4;
+
+/// The lowest address that a link can represent. Since memory chunks are
+/// 4-byte aligned, 16-bit links can represent a range of 2^18 bytes. The
+/// represented range is
+/// `[__MEM_CHUNK_LINK_OFFSET, __MEM_CHUNK_LINK_OFFSET + __MEM_CHUNK_LINK_RANGE)`.
+/// 32-bit links can represent the whole address space, so the offset is 0.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const __MEM_CHUNK_LINK_OFFSET: u32 = if cfg!(feature = "large-heap") {
+    0
+} else {
+    0x2000_0000
+};
+
+/// The size in bytes of the memory range that links can represent.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
+pub const __MEM_CHUNK_LINK_RANGE: u64 = (1 << __MEM_CHUNK_LINK_BITS) * __MEM_CHUNK_ALIGN as u64;
+
+pin_private!(
+    __MEM_CHUNK_LINK_BITS,
+    if cfg!(feature = "large-heap") { 32 } else { 16 }
+);
+pin_private!(__MEM_CHUNK_ALIGN, 4);
+pin_private!(
+    __MEM_CHUNK_LINK_OFFSET,
+    if cfg!(feature = "large-heap") {
+        0
+    } else {
+        0x2000_0000
+    }
+);
+pin_private!(__MEM_CHUNK_LINK_RANGE, 1 << (__MEM_CHUNK_LINK_BITS + 2));
+
+const _: () = {
+    assert!(__MEM_CHUNK_LINK_BITS == 16 || __MEM_CHUNK_LINK_BITS == 32);
+    assert!(__MEM_CHUNK_ALIGN.is_power_of_two() && __MEM_CHUNK_ALIGN >= 4);
+    assert!(
+        RAM_END_ADDR as u64 <= __MEM_CHUNK_LINK_OFFSET as u64 + __MEM_CHUNK_LINK_RANGE,
+        "the heap is out of the reach of 16-bit chunk links, enable `large-heap`"
+    );
+};
+
+/// The reactions of the system to a failed heap allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// Panic the allocating task, which is then unwound and possibly
+    /// restarted as described by [`PANIC_POLICY`]. The memory held by the
+    /// task is freed during unwinding.
+    PanicTask,
+    /// Report the failure to the allocating task and let it decide.
+    ReturnNull,
+    /// Kill the task with the lowest priority to reclaim its memory, then
+    /// retry the allocation. Hopter 0.2.3 cannot kill a task other than the
+    /// current one, so the `oom_policy` module of the quick-start treats it as
+    /// [`OomPolicy::PanicTask`].
+    KillLowestPriority,
+}
+
+/// The reaction of the system to a failed heap allocation. The kernel treats
+/// a failure of an infallible allocation, e.g., `Arc::new`, as fatal and
+/// stops the whole system with interrupts masked. The policy is applied by the
+/// `oom_policy` module of the quick-start to the allocations made through it.
+pub const OOM_POLICY: OomPolicy = OomPolicy::PanicTask;
 
 /* ################################ */
 /* ### Interrupt Configurations ### */
 /* ################################ */
 
-/// The numerical stepping between two adjacent IRQ priority levels.
-/// The interrupt priority registers on Cortex-M reserves 8-bits for each
-/// interrupt, but for most MCU implementations only a few significant bits
-/// are used. For example, if only the top 5 significant bits are used, then
-/// the numerical granularity will be 16. If the top 3 significant bits are
-/// used, then the numerical granularity will be 32. See Nested Vectored
-/// Interrupt Controller (NVIC) in Cortex-M for details.
-///
-/// If the MCU supports more functional bits than the default configuration,
-/// one can reduce the value of [`IRQ_PRIORITY_GRANULARITY`] to a smaller
-/// power of 2, and scale up the IRQ priority constants accordingly. This
-/// will allow more levels between [`IRQ_MAX_PRIORITY`] and
-/// [`IRQ_MIN_PRIORITY`] for applications to use.
-pub const IRQ_PRIORITY_GRANULARITY: u8 = 32;
+/// The number of the most significant bits of the 8-bit priority fields that
+/// the NVIC implements. The remaining bits read as zero. Selected by the
+/// `nvic-prio-bits-*` features. Without any of them, 3 bits are assumed,
+/// which wastes priority levels but works on all ARMv7-M and ARMv8-M parts.
+/// ARMv6-M implements only 2 bits.
+pub const NVIC_PRIO_BITS: u8 = if cfg!(feature = "armv6m") {
+    2
+} else if cfg!(feature = "nvic-prio-bits-5") {
+    5
+} else if cfg!(feature = "nvic-prio-bits-4") {
+    4
+} else {
+    3
+};
+
+/// The numerical stepping between two adjacent IRQ priority levels, i.e.,
+/// the smallest priority step that the NVIC can tell apart. For example, if
+/// only the top 5 significant bits are used, then the numerical granularity
+/// will be 8. If the top 3 significant bits are used, then the numerical
+/// granularity will be 32. See Nested Vectored Interrupt Controller (NVIC) in
+/// Cortex-M for details.
+///
+/// All priorities below are given in levels and scaled by the granularity
+/// through [`irq_priority`], so they follow [`NVIC_PRIO_BITS`] automatically.
+/// Applications should likewise compute IRQ priorities with [`irq_priority`]
+/// instead of hard-coding numerical values.
+pub const IRQ_PRIORITY_GRANULARITY: u8 = 1 << (8 - NVIC_PRIO_BITS);
+
+/// Return the numerical priority of the given priority level, i.e., the level
+/// multiplied by [`IRQ_PRIORITY_GRANULARITY`]. Level 0 is the highest.
+///
+/// Panics if the result does not fit in the 8-bit priority field. When used to
+/// define a constant, the panic becomes a compilation error.
+pub const fn irq_priority(level: u8) -> u8 {
+    match level.checked_mul(IRQ_PRIORITY_GRANULARITY) {
+        Some(prio) => prio,
+        None => panic!("IRQ priority level does not fit in the 8-bit priority field"),
+    }
+}
+
+/// Return the numerical priority that is the given number of levels lower
+/// than [`IRQ_MIN_PRIORITY`]. The exceptions doing work on behalf of tasks,
+/// i.e., SVC and PendSV, sit on this floor, so that they stay lower than all
+/// IRQs however the granularity and the IRQ levels are configured.
+///
+/// Panics if `levels` is zero or if the result does not fit in the 8-bit
+/// priority field. When used to define a constant, the panic becomes a
+/// compilation error.
+pub const fn task_to_irq_floor(levels: u8) -> u8 {
+    assert!(levels > 0, "the floor must be lower than all IRQs");
+    match IRQ_MIN_PRIORITY.checked_add(irq_priority(levels)) {
+        Some(prio) => prio,
+        None => panic!("IRQ priority level does not fit in the 8-bit priority field"),
+    }
+}
 
 /// The maximum priority of an interrupt. It has the smallest numerical value.
-pub const IRQ_MAX_PRIORITY: u8 = 1 * IRQ_PRIORITY_GRANULARITY;
+/// On ARMv6-M, level 0 is given to IRQs because SVC need not be raised above
+/// them.
+pub const IRQ_MAX_PRIORITY: u8 = irq_priority(if cfg!(feature = "armv6m") { 0 } else { 1 });
 
 /// The higher priority of an interrupt. Defined for convenience.
-pub const IRQ_HIGH_PRIORITY: u8 = 2 * IRQ_PRIORITY_GRANULARITY;
+pub const IRQ_HIGH_PRIORITY: u8 = irq_priority(if cfg!(feature = "armv6m") { 0 } else { 2 });
 
 /// The normal priority of an interrupt. Defined for convenience.
-pub const IRQ_NORMAL_PRIORITY: u8 = 3 * IRQ_PRIORITY_GRANULARITY;
+pub const IRQ_NORMAL_PRIORITY: u8 = irq_priority(if cfg!(feature = "armv6m") { 1 } else { 3 });
 
 /// The lower priority of an interrupt. Defined for convenience.
-pub const IRQ_LOW_PRIORITY: u8 = 4 * IRQ_PRIORITY_GRANULARITY;
+pub const IRQ_LOW_PRIORITY: u8 = irq_priority(if cfg!(feature = "armv6m") { 1 } else { 4 });
 
 /// The minimum priority of an interrupt. It has the largest numerical value.
-pub const IRQ_MIN_PRIORITY: u8 = 5 * IRQ_PRIORITY_GRANULARITY;
+/// On ARMv6-M, the two levels below it are taken by SVC and PendSV.
+pub const IRQ_MIN_PRIORITY: u8 = irq_priority(if cfg!(feature = "armv6m") { 1 } else { 5 });
 
 /// Hopter globally enables or disables interrupts by configuring the BASEPRI
 /// register. When set to 0, no interrupt will be masked.
-pub const IRQ_ENABLE_BASEPRI_PRIORITY: u8 = 0 * IRQ_PRIORITY_GRANULARITY;
+#[cfg(not(feature = "armv6m"))]
+pub const IRQ_ENABLE_BASEPRI_PRIORITY: u8 = irq_priority(0);
 
 /// Hopter globally enables or disables interrupts by configuring the BASEPRI
 /// register. IRQs with lower or equal priority (greater or qeual numerical
 /// value) than BASEPRI are disabled. This value should be set to be higher or
 /// equal than all IRQ priority levels.
-pub const IRQ_DISABLE_BASEPRI_PRIORITY: u8 = 1 * IRQ_PRIORITY_GRANULARITY;
+#[cfg(not(feature = "armv6m"))]
+pub const IRQ_DISABLE_BASEPRI_PRIORITY: u8 = irq_priority(1);
 
 /// When the interrupt is not globally masked, i.e. the normal case, the SVC
 /// is set to a priority lower than all IRQs, so that IRQs can nest above an
 /// active SVC and get served promptly.
-pub const SVC_NORMAL_PRIORITY: u8 = 6 * IRQ_PRIORITY_GRANULARITY;
+pub const SVC_NORMAL_PRIORITY: u8 = task_to_irq_floor(1);
 
 /// When the interrupt is globally masked, SVC still need to be allowed because
 /// growing segmented stacks depend on it. During the period that the interrupt
 /// is globally masked, the priority of SVC is raised to keep it higher than
 /// BASEPRI.
-pub const SVC_RAISED_PRIORITY: u8 = 0 * IRQ_PRIORITY_GRANULARITY;
+///
+/// On ARMv6-M, interrupts are globally masked by setting PRIMASK, which also
+/// masks SVC regardless of its priority. The priority is therefore never
+/// raised, and the stack must not be extended while PRIMASK is set.
+pub const SVC_RAISED_PRIORITY: u8 = if cfg!(feature = "armv6m") {
+    SVC_NORMAL_PRIORITY
+} else {
+    irq_priority(0)
+};
 
 /// PendSV is used to implement context switch. Since an SVC may tail chain a
 /// PendSV to perform context switch, PendSV's priority must be lower than SVC.
-pub const PENDSV_PRIORITY: u8 = 7 * IRQ_PRIORITY_GRANULARITY;
+pub const PENDSV_PRIORITY: u8 = task_to_irq_floor(2);
 
 /// The priority of SysTick interrupt.
 pub const SYSTICK_PRIORITY: u8 = IRQ_LOW_PRIORITY;
 
+/// The number of distinct priority levels between [`IRQ_MAX_PRIORITY`] and
+/// [`IRQ_MIN_PRIORITY`], inclusive. An IRQ preempts only those with lower
+/// priority, so at most this many IRQ handlers can nest.
+pub const IRQ_PRIORITY_LEVELS: usize =
+    ((IRQ_MIN_PRIORITY - IRQ_MAX_PRIORITY) / IRQ_PRIORITY_GRANULARITY) as usize + 1;
+
+/// The maximum number of IRQ handlers allowed to be active at the same time,
+/// i.e., nested above each other. All of them run on the contiguous stack, so
+/// deep nesting can overflow it. Handlers counting their nesting through the
+/// `irq_nesting` module of the quick-start panic when the depth exceeds the
+/// limit, before their own frames are pushed further. Set it to
+/// [`IRQ_PRIORITY_LEVELS`] to only record the depth without ever panicking,
+/// which is the case on ARMv6-M by default.
+pub const MAX_IRQ_NESTING_DEPTH: usize = if IRQ_PRIORITY_LEVELS < 3 {
+    IRQ_PRIORITY_LEVELS
+} else {
+    3
+};
+
+const _: () = {
+    assert!(MAX_IRQ_NESTING_DEPTH > 0);
+    assert!(
+        MAX_IRQ_NESTING_DEPTH <= IRQ_PRIORITY_LEVELS,
+        "IRQs cannot nest deeper than the number of priority levels"
+    );
+};
+
+/// The SVC numbers, i.e., the immediate operands of the `svc` instruction.
+///
+/// The numbers used by the kernel are fixed by the kernel and, for the
+/// stacklet allocation ones, by the compiler toolchain. They are listed here
+/// for applications, not read by the kernel, so changing them has no effect.
+/// Note that the kernel's SVC handler halts the system upon a number it does
+/// not use, so an application-defined SVC service must be dispatched before
+/// the kernel's handler, e.g., by a custom SVC vector.
+pub mod svc {
+    /// Request a context switch, i.e., yield the CPU. The switch itself is
+    /// carried out by a tail-chained PendSV.
+    pub const TASK_YIELD: u8 = 1;
+    /// Terminate the calling task and release its task struct.
+    pub const TASK_DESTROY: u8 = 2;
+    /// Release the top stacklet of the calling task.
+    pub const TASK_LESS_STACK: u8 = 3;
+    /// Allocate dynamic memory.
+    pub const MEM_ALLOC: u8 = 4;
+    /// Free dynamic memory.
+    pub const MEM_FREE: u8 = 5;
+    /// Allocate a stacklet to run the stack unwinder.
+    pub const TASK_UNWIND_PREPARE: u8 = 252;
+    /// Release the stacklet of the unwinder and jump to the landing pad.
+    pub const TASK_UNWIND_LAND: u8 = 253;
+    /// Allocate a stacklet when calling a drop handler. Fixed by the compiler
+    /// toolchain.
+    pub const TASK_MORE_STACK_FROM_DROP: u8 = 254;
+    /// Allocate a stacklet when calling a function other than a drop handler.
+    /// Fixed by the compiler toolchain.
+    pub const TASK_MORE_STACK: u8 = 255;
+
+    /// All SVC numbers used by the kernel.
+    pub const KERNEL: &[u8] = &[
+        TASK_YIELD,
+        TASK_DESTROY,
+        TASK_LESS_STACK,
+        MEM_ALLOC,
+        MEM_FREE,
+        TASK_UNWIND_PREPARE,
+        TASK_UNWIND_LAND,
+        TASK_MORE_STACK_FROM_DROP,
+        TASK_MORE_STACK,
+    ];
+
+    /// The SVC numbers used by the application's own SVC services. List them
+    /// here to have them checked against each other and against [`KERNEL`]
+    /// at compile time. Numbers 6 to 251 are free.
+    pub const APPLICATION: &[u8] = &[];
+
+    /// Return whether the SVC number is used by the kernel.
+    pub const fn is_kernel(num: u8) -> bool {
+        contains(KERNEL, num)
+    }
+
+    const fn contains(nums: &[u8], num: u8) -> bool {
+        let mut i = 0;
+        while i < nums.len() {
+            if nums[i] == num {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+
+    const _: () = {
+        let mut i = 0;
+        while i < APPLICATION.len() {
+            assert!(
+                !is_kernel(APPLICATION[i]),
+                "an application SVC number collides with the kernel"
+            );
+            let mut j = i + 1;
+            while j < APPLICATION.len() {
+                assert!(
+                    APPLICATION[i] != APPLICATION[j],
+                    "an application SVC number is used twice"
+                );
+                j += 1;
+            }
+            i += 1;
+        }
+    };
+}
+
 /* ########################### */
 /* ### Task Configurations ### */
 /* ########################### */
 
 /// The maximum number of tasks. Must be a power of 2.
-pub const MAX_TASK_NUMBER: usize = 16;
+pub const MAX_TASK_NUMBER: usize = match MAX_TASKS_OVERRIDE {
+    Some(num) => num,
+    None if cfg!(feature = "profile-small-ram") => 8,
+    None => 64,
+};
 
 /// Whether a ready higher priority task should cause a lower priority running
 /// task to yield.
 pub const ALLOW_TASK_PREEMPTION: bool = true;
 
+/// A group of breathing tasks sharing a concurrency limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreathingGroup {
+    /// The name by which breathing tasks select the group.
+    pub name: &'static str,
+    /// The number of tasks in the group that can run concurrently, i.e. not
+    /// blocked on the `wait` function.
+    pub concurrency: usize,
+}
+
+/// The breathing groups. Breathing tasks with different stack profiles can
+/// be put into different groups, e.g., a "sensor" group of shallow tasks with
+/// a high limit and a "network" group of deep tasks with a low limit. A task
+/// selects its group by name through the `breathing_group` module of the
+/// quick-start. Tasks not selecting any group are limited only by
+/// [`BREATHING_CONCURRENCY`].
+///
+/// Hopter 0.2.3 has no notion of groups and reads only
+/// [`BREATHING_CONCURRENCY`]. The group limits are enforced by the semaphores
+/// of the `breathing_group` module alone.
+pub const BREATHING_GROUPS: &[BreathingGroup] = &[BreathingGroup {
+    name: "default",
+    concurrency: if cfg!(feature = "profile-small-ram") {
+        1
+    } else if cfg!(feature = "profile-low-latency") {
+        4
+    } else {
+        3
+    },
+}];
+
 /// The number of breathing tasks that can run concurrently, i.e. not blocked
-/// on the `wait` function.
-pub const BREATHING_CONCURRENCY: usize = 3;
+/// on the `wait` function. It is the sum of the group limits, so that the
+/// groups never contend for the global limit among themselves.
+pub const BREATHING_CONCURRENCY: usize = {
+    let mut sum = 0;
+    let mut i = 0;
+    while i < BREATHING_GROUPS.len() {
+        sum += BREATHING_GROUPS[i].concurrency;
+        i += 1;
+    }
+    sum
+};
+
+const _: () = {
+    const fn str_eq(a: &str, b: &str) -> bool {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    assert!(BREATHING_CONCURRENCY > 0);
+
+    let mut i = 0;
+    while i < BREATHING_GROUPS.len() {
+        assert!(BREATHING_GROUPS[i].concurrency > 0, "empty breathing group");
+
+        let mut j = 0;
+        while j < i {
+            assert!(
+                !str_eq(BREATHING_GROUPS[i].name, BREATHING_GROUPS[j].name),
+                "duplicate breathing group name"
+            );
+            j += 1;
+        }
+        i += 1;
+    }
+};
 
 /// Maximum priority number. Lower numerical numbers represent higher priorities.
 /// Allowed priority range: 0 to (TASK_PRIORITY_LEVELS - 1).
-pub const TASK_PRIORITY_LEVELS: u8 = 16;
+///
+/// 16 is only the default of the template. Hopter 0.2.3 accepts any number of
+/// levels from 4 up to 255, e.g., 24 for a rate-monotonic assignment with as
+/// many distinct rates. The levels must leave [`DEFAULT_TASK_PRIORITY`] above
+/// [`UNWIND_PRIORITY`], so that unwinding tasks do not hold up the others.
+pub const TASK_PRIORITY_LEVELS: u8 = match PRIORITY_LEVELS_OVERRIDE {
+    Some(levels) => levels,
+    None => 16,
+};
 
 /// The priority of the idle task. Typically this is set to the lowest allowed
 /// priority.
@@ -173,6 +947,42 @@ pub const DEFAULT_TASK_PRIORITY: u8 = 8;
 /// which is very low but still higher than idle priority.
 pub const UNWIND_PRIORITY: u8 = TASK_PRIORITY_LEVELS - 3;
 
+const _: () = {
+    assert!(
+        TASK_PRIORITY_LEVELS >= 4,
+        "the kernel needs at least 4 task priority levels"
+    );
+    assert!(
+        DEFAULT_TASK_PRIORITY < UNWIND_PRIORITY,
+        "too few task priority levels for the default task priority"
+    );
+};
+
+/// The reactions of the system to a panic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Unwind the panicked task and restart it if it was spawned as a
+    /// restartable task.
+    UnwindAndRestart,
+    /// Unwind the panicked task without restarting it, even if it was spawned
+    /// as a restartable task.
+    KillTaskOnly,
+    /// Reset the MCU by requesting a system reset through `SCB::AIRCR`.
+    ResetSystem,
+    /// Stop the whole system with interrupts masked, keeping its state intact
+    /// for inspection with a debugger.
+    Halt,
+}
+
+/// The reaction of the system to a panic. The kernel always unwinds the
+/// panicked task. The policy is then applied by the `panic_policy` module of
+/// the quick-start to the code it guards.
+pub const PANIC_POLICY: PanicPolicy = if cfg!(feature = "profile-debug") {
+    PanicPolicy::Halt
+} else {
+    PanicPolicy::UnwindAndRestart
+};
+
 /// The ID for the idle task. A task ID does not have functional purpose. It
 /// might be helpful for diagnosing bugs.
 pub const IDLE_TASK_ID: u8 = 0;
@@ -185,7 +995,265 @@ pub const MAIN_TASK_ID: u8 = 1;
 /// Tasks can have the same ID.
 pub const DEFAULT_TASK_ID: u8 = 255;
 
+/// The IDs in `[0, FIRST_APP_TASK_ID)` are reserved for kernel tasks, i.e.,
+/// the idle task and the main task. The IDs in
+/// `[FIRST_APP_TASK_ID, DEFAULT_TASK_ID)` are given to application tasks.
+///
+/// Task IDs are 8-bit wide because the kernel stores them in a `u8`. The
+/// `task_name` module of the quick-start binds at most [`MAX_TASK_NUMBER`]
+/// names to IDs, so only the IDs in
+/// `[FIRST_APP_TASK_ID, FIRST_APP_TASK_ID + MAX_TASK_NUMBER)` are ever given.
+/// Tasks named beyond that keep [`DEFAULT_TASK_ID`].
+pub const FIRST_APP_TASK_ID: u8 = 2;
+
+/// Whether tasks named alike get distinct IDs when spawned at different
+/// places in the code. When set to false, tasks given the same name share the
+/// ID bound to the name. When set to true, e.g., a `worker` task spawned by
+/// two different parts gets two IDs, so that their log lines can be told
+/// apart. Either way, a task spawned again at the same place gets the ID bound
+/// on the first spawn, and a restarted instance of a restartable task keeps
+/// the ID of the panicked instance. IDs are never recycled, but spawning a task
+/// repeatedly takes only one.
+pub const ENABLE_UNIQUE_TASK_IDS: bool = true;
+
+const _: () = {
+    assert!(IDLE_TASK_ID != MAIN_TASK_ID);
+    assert!(IDLE_TASK_ID < FIRST_APP_TASK_ID && MAIN_TASK_ID < FIRST_APP_TASK_ID);
+    assert!(FIRST_APP_TASK_ID < DEFAULT_TASK_ID);
+};
+
+/// Whether tasks can be given human-readable names when they are built. When
+/// set to false, the names are discarded and tasks are tagged only by their
+/// numerical IDs.
+pub const ENABLE_TASK_NAMES: bool = true;
+
+/// The maximum length of a task name in bytes. Longer names are truncated.
+pub const MAX_TASK_NAME_LEN: usize = if cfg!(feature = "profile-debug") {
+    32
+} else {
+    16
+};
+
+/// The number of task-local storage slots available to each task. The
+/// kernel swaps only its own fields at [`__TLS_MEM_ADDR`] upon context
+/// switches. The application slots are kept by the `task_local` module of the
+/// quick-start instead.
+pub const TLS_SLOT_COUNT: usize = 2;
+
+/// The size in bytes of each task-local storage slot. Must be a multiple of 4.
+pub const TLS_SLOT_SIZE: usize = 4;
+
+const _: () = assert!(TLS_SLOT_SIZE > 0 && TLS_SLOT_SIZE % 4 == 0);
+
 /// The address in memory where the task local storage is placed. Currently
 /// this must be the fixed value `0x2000_0000` because the compiler toolchain
 /// assumes this value.
+#[cfg_attr(not(feature = "expert"), doc(hidden))]
 pub const __TLS_MEM_ADDR: u32 = 0x2000_0000;
+pin_private!(__TLS_MEM_ADDR, 0x2000_0000);
+
+/* ############################ */
+/* ### Timer Configurations ### */
+/* ############################ */
+
+/// The longest delay or period in milliseconds accepted by the software
+/// timers of the `soft_timer` module of the quick-start. The tick counter is
+/// 32-bit and wraps around, so deadlines can only be ordered correctly within
+/// half of its range.
+///
+/// Hopter 0.2.3 does not read this parameter. Its `sleep_ms` and
+/// `IntervalBarrier::new` reject durations over `i32::MAX` milliseconds with
+/// `SleepError::TooLong` whatever the value.
+pub const MAX_SLEEP_MS: u32 = i32::MAX as u32;
+
+const _: () = {
+    assert!(
+        MAX_SLEEP_MS > 0 && MAX_SLEEP_MS <= i32::MAX as u32,
+        "sleep durations must fit in half of the tick counter range"
+    );
+};
+
+/* ################################## */
+/* ### Backup SRAM Configurations ### */
+/* ################################## */
+
+/// The starting address of the backup SRAM. Its content survives resets, and
+/// also standby mode and the loss of the main supply if the backup regulator
+/// is powered by the battery. The 4 KiB backup SRAM of STM32F405/407/415/417
+/// and STM32F427/429/437/439 starts at `0x4002_4000`.
+pub const BACKUP_SRAM_ADDR: u32 = 0x4002_4000;
+
+/// The length in bytes of the backup SRAM. Set to 0 on parts without one,
+/// e.g., STM32F411 and STM32F412.
+pub const BACKUP_SRAM_LEN: u32 = 0x1000;
+
+/// Whether a record of the last panic is kept in the backup SRAM, so that it
+/// survives a reset, e.g., one requested by [`PanicPolicy::ResetSystem`]. The
+/// kernel discards the panic message, so the record holds the ID and the name
+/// of the panicked task, the tick of the panic, and the number of panics
+/// recorded so far instead. The record is written by the `panic_persist`
+/// module of the quick-start when code guarded by its `panic_policy` module
+/// panics. Must be false if [`BACKUP_SRAM_LEN`] is 0.
+pub const ENABLE_PANIC_PERSIST: bool = true;
+
+const _: () = {
+    assert!(BACKUP_SRAM_ADDR % 4 == 0 && BACKUP_SRAM_LEN % 4 == 0);
+    assert!(
+        !ENABLE_PANIC_PERSIST || BACKUP_SRAM_LEN != 0,
+        "persisting panics requires a backup SRAM"
+    );
+};
+
+/* ########################## */
+/* ### ABI Configurations ### */
+/* ########################## */
+
+/// The checksum over the private parameters read by the kernel, computed by
+/// [`private_params_checksum`] over the values of
+/// [`__CONTIGUOUS_STACK_BOUNDARY`], [`_CONTIGUOUS_STACK_BOTTOM`],
+/// [`__MEM_CHUNK_LINK_OFFSET`], and [`__TLS_MEM_ADDR`], in this order.
+///
+/// Hopter 0.2.3 verifies nothing at boot. Instead, the quick-start's
+/// `main.rs` computes the checksum over the values re-exported by
+/// `hopter::config`, i.e., those the kernel was built with, and fails the
+/// build if it differs from this one. This catches a kernel silently built
+/// with the crates.io defaults instead of the local override.
+pub const PRIVATE_PARAMS_CHECKSUM: u32 = private_params_checksum(&[
+    __CONTIGUOUS_STACK_BOUNDARY,
+    _CONTIGUOUS_STACK_BOTTOM,
+    __MEM_CHUNK_LINK_OFFSET,
+    __TLS_MEM_ADDR,
+]);
+
+/// Compute the 32-bit FNV-1a hash of the given parameter values. See
+/// [`PRIVATE_PARAMS_CHECKSUM`].
+pub const fn private_params_checksum(values: &[u32]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < values.len() {
+        let bytes = values[i].to_le_bytes();
+        let mut j = 0;
+        while j < bytes.len() {
+            hash ^= bytes[j] as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+            j += 1;
+        }
+        i += 1;
+    }
+    hash
+}
+
+/* ########################################## */
+/* ### ARMv8-M (TrustZone) Configurations ### */
+/* ########################################## */
+
+#[cfg(all(feature = "armv6m", feature = "armv8m"))]
+compile_error!("features `armv6m` and `armv8m` are mutually exclusive");
+
+/// A region programmed into the Security Attribution Unit (SAU). Memory
+/// covered by a region is Non-secure, or Non-secure callable if
+/// `non_secure_callable` is set. Memory not covered by any region is Secure.
+#[cfg(feature = "armv8m")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SauRegion {
+    /// The first address of the region. Must be 32-byte aligned.
+    pub base: u32,
+    /// The last address of the region, inclusive. Must be one byte before a
+    /// 32-byte boundary.
+    pub limit: u32,
+    /// Whether the region holds the secure gateway veneers, i.e., the entry
+    /// points through which Non-secure code calls Secure functions.
+    pub non_secure_callable: bool,
+}
+
+/// The number of regions implemented by the SAU. This is 8 on STM32L5 and
+/// nRF9160.
+#[cfg(feature = "armv8m")]
+pub const SAU_REGION_NUMBER: usize = 8;
+
+/// The SAU regions. The defaults split the memory of an STM32L552 in half,
+/// with Hopter running in the Non-secure world.
+#[cfg(feature = "armv8m")]
+pub const SAU_REGIONS: &[SauRegion] = &[
+    // Non-secure flash.
+    SauRegion {
+        base: 0x0804_0000,
+        limit: 0x0807_FFFF,
+        non_secure_callable: false,
+    },
+    // Secure gateway veneers at the end of the Secure flash.
+    SauRegion {
+        base: 0x0C03_E000,
+        limit: 0x0C03_FFFF,
+        non_secure_callable: true,
+    },
+    // Non-secure SRAM.
+    SauRegion {
+        base: 0x2002_0000,
+        limit: 0x2003_FFFF,
+        non_secure_callable: false,
+    },
+    // Non-secure peripherals.
+    SauRegion {
+        base: 0x4000_0000,
+        limit: 0x4FFF_FFFF,
+        non_secure_callable: false,
+    },
+];
+
+/// SysTick is banked between the Secure and the Non-secure world. When set to
+/// true, the Secure instance generates the kernel tick. Otherwise, the
+/// Non-secure instance is used. In either case, [`SYSTICK_FREQUENCY_HZ`] must
+/// match the clock of the selected instance.
+#[cfg(feature = "armv8m")]
+pub const SYSTICK_USE_SECURE_INSTANCE: bool = false;
+
+/// The size in bytes of the Secure main stack, used by Secure exception
+/// handlers.
+#[cfg(feature = "armv8m")]
+pub const SECURE_MAIN_STACK_SIZE: usize = 0x800;
+
+/// The size in bytes of the Secure process stack given to each task that
+/// calls into the Secure world.
+#[cfg(feature = "armv8m")]
+pub const SECURE_TASK_STACK_SIZE: usize = 0x400;
+
+/// The maximum number of tasks that can have a Secure process stack at the
+/// same time. Must not exceed [`MAX_TASK_NUMBER`].
+#[cfg(feature = "armv8m")]
+pub const SECURE_CONTEXT_NUMBER: usize = 4;
+
+#[cfg(feature = "armv8m")]
+const _: () = {
+    assert!(SAU_REGIONS.len() <= SAU_REGION_NUMBER);
+
+    let mut i = 0;
+    while i < SAU_REGIONS.len() {
+        let region = SAU_REGIONS[i];
+        assert!(
+            region.base % 32 == 0,
+            "SAU region base must be 32-byte aligned"
+        );
+        assert!(
+            region.limit % 32 == 31,
+            "SAU region limit must end a 32-byte block"
+        );
+        assert!(region.base < region.limit, "SAU region must not be empty");
+
+        let mut j = i + 1;
+        while j < SAU_REGIONS.len() {
+            let other = SAU_REGIONS[j];
+            assert!(
+                region.limit < other.base || other.limit < region.base,
+                "SAU regions must not overlap"
+            );
+            j += 1;
+        }
+        i += 1;
+    }
+
+    // Stacks must keep the 8-byte alignment required by the AAPCS.
+    assert!(SECURE_MAIN_STACK_SIZE % 8 == 0);
+    assert!(SECURE_TASK_STACK_SIZE % 8 == 0);
+    assert!(SECURE_CONTEXT_NUMBER <= MAX_TASK_NUMBER);
+};
@@ -0,0 +1,96 @@
+//! Check the properties of the const helper functions over their whole input
+//! domain, or a range covering every case for the wider ones.
+
+use hopter_conf_params::*;
+use std::panic;
+
+/// Return the result of `f`, or `None` if it panics.
+fn try_call<T>(f: impl FnOnce() -> T + panic::UnwindSafe) -> Option<T> {
+    // The expected panics would otherwise flood the test output.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(f).ok();
+    panic::set_hook(hook);
+    result
+}
+
+#[test]
+fn irq_priority_scales_levels_by_granularity() {
+    let mut last = None;
+
+    for level in 0..=u8::MAX {
+        let expected = level.checked_mul(IRQ_PRIORITY_GRANULARITY);
+        let prio = try_call(|| irq_priority(level));
+        assert_eq!(prio, expected, "level {level}");
+
+        if let Some(prio) = prio {
+            assert_eq!(prio % IRQ_PRIORITY_GRANULARITY, 0);
+            // Lower levels map to strictly higher numerical values.
+            assert!(last.is_none_or(|last| last < prio));
+            last = Some(prio);
+        }
+    }
+
+    // Every value representable by the priority bits is reachable.
+    assert_eq!(last, Some(u8::MAX - (IRQ_PRIORITY_GRANULARITY - 1)));
+}
+
+#[test]
+fn irq_floor_stays_below_min_priority() {
+    assert_eq!(try_call(|| task_to_irq_floor(0)), None);
+
+    for levels in 1..=u8::MAX {
+        let expected = levels
+            .checked_mul(IRQ_PRIORITY_GRANULARITY)
+            .and_then(|offset| IRQ_MIN_PRIORITY.checked_add(offset));
+        let floor = try_call(|| task_to_irq_floor(levels));
+        assert_eq!(floor, expected, "levels {levels}");
+
+        if let Some(floor) = floor {
+            assert!(floor > IRQ_MIN_PRIORITY);
+            assert_eq!(floor % IRQ_PRIORITY_GRANULARITY, 0);
+        }
+    }
+}
+
+#[test]
+fn checksum_is_fnv1a() {
+    // The offset basis and the published hash of four zero bytes.
+    assert_eq!(private_params_checksum(&[]), 0x811c_9dc5);
+    assert_eq!(private_params_checksum(&[0]), 0x4b95_f515);
+}
+
+#[test]
+fn checksum_is_sensitive_to_values_and_order() {
+    let params = [
+        __CONTIGUOUS_STACK_BOUNDARY,
+        _CONTIGUOUS_STACK_BOTTOM,
+        __MEM_CHUNK_LINK_OFFSET,
+        __TLS_MEM_ADDR,
+    ];
+    let checksum = private_params_checksum(&params);
+
+    for i in 0..params.len() {
+        // Any single bit flip changes the checksum.
+        for bit in 0..32 {
+            let mut flipped = params;
+            flipped[i] ^= 1 << bit;
+            assert_ne!(private_params_checksum(&flipped), checksum);
+        }
+
+        for j in i + 1..params.len() {
+            let mut swapped = params;
+            swapped.swap(i, j);
+            if swapped != params {
+                assert_ne!(private_params_checksum(&swapped), checksum);
+            }
+        }
+    }
+}
+
+#[test]
+fn svc_kernel_numbers_are_recognized() {
+    for num in 0..=u8::MAX {
+        assert_eq!(svc::is_kernel(num), svc::KERNEL.contains(&num), "SVC {num}");
+    }
+}
@@ -0,0 +1,343 @@
+//! Check the invariants among the configuration parameters.
+//!
+//! Most of them are also asserted at compile time inside the crate. Checking
+//! them here again documents them in one place and keeps them from being
+//! dropped silently when the compile-time checks are edited. Run the suite
+//! with the feature combinations used by the boards, e.g.,
+//! `cargo test --features armv6m`.
+
+// Asserting on constants is the point of the suite. Some of them are also
+// trivially true under some feature combinations.
+#![allow(clippy::assertions_on_constants, clippy::absurd_extreme_comparisons)]
+
+use hopter_conf_params::*;
+
+mod clock {
+    use super::*;
+
+    #[test]
+    fn pll_stays_within_ranges() {
+        let vco_in = HSE_FREQUENCY_HZ / PLL_M;
+        let vco_out = vco_in * PLL_N;
+
+        assert_eq!(HSE_FREQUENCY_HZ % PLL_M, 0);
+        assert!((1_000_000..=2_000_000).contains(&vco_in));
+        assert!((100_000_000..=432_000_000).contains(&vco_out));
+        assert!([2, 4, 6, 8].contains(&PLL_P));
+        assert!((2..=15).contains(&PLL_Q));
+        assert!(vco_out / PLL_Q <= 48_000_000);
+    }
+
+    #[test]
+    fn hclk_follows_the_clock_tree() {
+        assert_eq!(TARGET_SYSCLK_HZ, HSE_FREQUENCY_HZ / PLL_M * PLL_N / PLL_P);
+        assert_eq!(HCLK_FREQUENCY_HZ, TARGET_SYSCLK_HZ / AHB_PRESCALER);
+        assert!(TARGET_SYSCLK_HZ <= 180_000_000);
+    }
+
+    #[test]
+    fn systick_yields_millisecond_ticks() {
+        if SYSTICK_USE_CPU_CLOCK {
+            assert_eq!(SYSTICK_FREQUENCY_HZ, HCLK_FREQUENCY_HZ);
+        }
+        assert_eq!(SYSTICK_FREQUENCY_HZ % 1000, 0);
+        assert!(SYSTICK_FREQUENCY_HZ / 1000 <= 0x00FF_FFFF);
+    }
+}
+
+mod stack {
+    use super::*;
+
+    #[test]
+    fn initial_stacks_exist_without_dynamic_extension() {
+        assert_eq!(ALLOW_DYNAMIC_STACK, !cfg!(feature = "static-alloc"));
+        if !ALLOW_DYNAMIC_STACK {
+            assert!(MAIN_TASK_INITIAL_STACK_SIZE > 0);
+            assert!(_IDLE_TASK_INITIAL_STACK_SIZE > 0);
+        }
+    }
+
+    #[test]
+    fn stack_pools_fit_in_the_primary_heap() {
+        assert!(STACK_POOLS.windows(2).all(|w| w[0].size < w[1].size));
+
+        let pools: usize = STACK_POOLS.iter().map(|pool| pool.size * pool.count).sum();
+        for pool in STACK_POOLS {
+            assert!(pool.size > 0);
+            assert_eq!(pool.size % 8, 0);
+        }
+        if !ALLOW_DYNAMIC_STACK {
+            let total = pools + MAIN_TASK_INITIAL_STACK_SIZE + _IDLE_TASK_INITIAL_STACK_SIZE;
+            assert!(total <= (RAM_END_ADDR - _CONTIGUOUS_STACK_BOTTOM) as usize);
+        }
+    }
+
+    #[test]
+    fn contiguous_stack_layout_is_ordered() {
+        // From the bottom of the RAM: the task local storage, the guard
+        // region, and the contiguous stack growing down to the boundary.
+        assert_eq!(
+            __CONTIGUOUS_STACK_BOUNDARY,
+            __TLS_MEM_ADDR + 0x10 + CONTIGUOUS_STACK_GUARD_SIZE
+        );
+        assert!(__CONTIGUOUS_STACK_BOUNDARY < _CONTIGUOUS_STACK_BOTTOM);
+        assert_eq!(
+            _CONTIGUOUS_STACK_BOTTOM,
+            0x2000_0000 + _CONTIGUOUS_STACK_LENGTH
+        );
+        assert_eq!(CONTIGUOUS_STACK_GUARD_SIZE % 8, 0);
+        assert!(!ENABLE_STACK_CANARY || CONTIGUOUS_STACK_GUARD_SIZE > 0);
+    }
+}
+
+mod heap {
+    use super::*;
+
+    #[test]
+    fn heap_regions_are_aligned_and_disjoint() {
+        assert_eq!(HEAP_REGIONS[0].start, _CONTIGUOUS_STACK_BOTTOM);
+        assert_eq!(RAM_END_ADDR, HEAP_REGIONS[0].end);
+
+        for (i, region) in HEAP_REGIONS.iter().enumerate() {
+            assert!(region.start < region.end);
+            assert_eq!(region.start % 8, 0);
+            assert_eq!(region.end % 8, 0);
+
+            for other in &HEAP_REGIONS[..i] {
+                assert!(region.end <= other.start || other.end <= region.start);
+            }
+        }
+    }
+
+    #[test]
+    fn dma_region_is_excluded_from_the_heap() {
+        if DMA_REGION_LEN == 0 {
+            return;
+        }
+
+        let dma = DMA_REGION_START..DMA_REGION_START + DMA_REGION_LEN;
+        assert_eq!(dma.start % 8, 0);
+        assert_eq!(DMA_REGION_LEN % 8, 0);
+        for region in HEAP_REGIONS {
+            assert!(region.end <= dma.start || dma.end <= region.start);
+        }
+    }
+
+    #[test]
+    fn heap_is_within_reach_of_chunk_links() {
+        assert_eq!(
+            __MEM_CHUNK_LINK_RANGE,
+            (1u64 << __MEM_CHUNK_LINK_BITS) * __MEM_CHUNK_ALIGN as u64
+        );
+        assert!(__MEM_CHUNK_LINK_OFFSET <= HEAP_REGIONS[0].start);
+        assert!(RAM_END_ADDR as u64 <= __MEM_CHUNK_LINK_OFFSET as u64 + __MEM_CHUNK_LINK_RANGE);
+    }
+
+    #[test]
+    fn allocator_parameters_are_powers_of_two() {
+        assert!(__MEM_CHUNK_ALIGN.is_power_of_two());
+    }
+}
+
+mod interrupt {
+    use super::*;
+
+    #[test]
+    fn granularity_matches_the_priority_bits() {
+        let bits = if cfg!(feature = "armv6m") {
+            2
+        } else if cfg!(feature = "nvic-prio-bits-5") {
+            5
+        } else if cfg!(feature = "nvic-prio-bits-4") {
+            4
+        } else {
+            3
+        };
+        assert_eq!(NVIC_PRIO_BITS, bits);
+        assert_eq!(IRQ_PRIORITY_GRANULARITY as u32, 1 << (8 - bits));
+    }
+
+    #[test]
+    fn priorities_are_representable() {
+        // The NVIC ignores the bits below the granularity, so every priority
+        // must be a multiple of it to be stored as is.
+        let priorities = [
+            IRQ_MAX_PRIORITY,
+            IRQ_HIGH_PRIORITY,
+            IRQ_NORMAL_PRIORITY,
+            IRQ_LOW_PRIORITY,
+            IRQ_MIN_PRIORITY,
+            SVC_NORMAL_PRIORITY,
+            SVC_RAISED_PRIORITY,
+            PENDSV_PRIORITY,
+            SYSTICK_PRIORITY,
+        ];
+        for prio in priorities {
+            assert_eq!(prio % IRQ_PRIORITY_GRANULARITY, 0);
+        }
+    }
+
+    #[test]
+    fn irq_priorities_are_ordered() {
+        assert!(IRQ_MAX_PRIORITY <= IRQ_HIGH_PRIORITY);
+        assert!(IRQ_HIGH_PRIORITY <= IRQ_NORMAL_PRIORITY);
+        assert!(IRQ_NORMAL_PRIORITY <= IRQ_LOW_PRIORITY);
+        assert!(IRQ_LOW_PRIORITY <= IRQ_MIN_PRIORITY);
+        assert!(IRQ_MAX_PRIORITY < IRQ_MIN_PRIORITY);
+        assert!((IRQ_MAX_PRIORITY..=IRQ_MIN_PRIORITY).contains(&SYSTICK_PRIORITY));
+    }
+
+    #[test]
+    fn exceptions_for_tasks_stay_below_all_irqs() {
+        // Numerically greater means lower priority.
+        assert!(SVC_NORMAL_PRIORITY > IRQ_MIN_PRIORITY);
+        assert!(PENDSV_PRIORITY > SVC_NORMAL_PRIORITY);
+    }
+
+    #[cfg(not(feature = "armv6m"))]
+    #[test]
+    fn basepri_masks_all_irqs() {
+        assert_eq!(IRQ_ENABLE_BASEPRI_PRIORITY, 0);
+        assert!(IRQ_DISABLE_BASEPRI_PRIORITY <= IRQ_MAX_PRIORITY);
+        // The raised SVC must stay above BASEPRI to extend stacks while
+        // interrupts are masked.
+        assert!(SVC_RAISED_PRIORITY < IRQ_DISABLE_BASEPRI_PRIORITY);
+    }
+
+    #[test]
+    fn nesting_depth_is_bounded_by_priority_levels() {
+        let levels = (IRQ_MIN_PRIORITY - IRQ_MAX_PRIORITY) / IRQ_PRIORITY_GRANULARITY + 1;
+        assert_eq!(IRQ_PRIORITY_LEVELS, levels as usize);
+        assert!((1..=IRQ_PRIORITY_LEVELS).contains(&MAX_IRQ_NESTING_DEPTH));
+    }
+
+    #[test]
+    fn svc_numbers_do_not_collide() {
+        let mut used = [false; 256];
+        for &num in svc::KERNEL.iter().chain(svc::APPLICATION) {
+            assert!(!used[num as usize], "SVC number {num} used twice");
+            used[num as usize] = true;
+        }
+        // Fixed by the compiler toolchain.
+        assert_eq!(svc::TASK_MORE_STACK, 255);
+        assert_eq!(svc::TASK_MORE_STACK_FROM_DROP, 254);
+    }
+}
+
+mod task {
+    use super::*;
+
+    #[test]
+    fn task_number_is_a_power_of_two() {
+        assert!(MAX_TASK_NUMBER.is_power_of_two());
+    }
+
+    #[test]
+    fn task_priorities_are_in_range() {
+        for prio in [
+            IDLE_TASK_PRIORITY,
+            MAIN_TASK_PRIORITY,
+            DEFAULT_TASK_PRIORITY,
+            UNWIND_PRIORITY,
+        ] {
+            assert!(prio < TASK_PRIORITY_LEVELS);
+        }
+        assert!(TASK_PRIORITY_LEVELS >= 4);
+        assert_eq!(IDLE_TASK_PRIORITY, TASK_PRIORITY_LEVELS - 1);
+        assert!(MAIN_TASK_PRIORITY < DEFAULT_TASK_PRIORITY);
+        assert!(DEFAULT_TASK_PRIORITY < UNWIND_PRIORITY);
+        assert!(UNWIND_PRIORITY < IDLE_TASK_PRIORITY);
+    }
+
+    #[test]
+    fn task_ids_are_distinct() {
+        assert_ne!(IDLE_TASK_ID, MAIN_TASK_ID);
+        assert!(FIRST_APP_TASK_ID > IDLE_TASK_ID);
+        assert!(FIRST_APP_TASK_ID > MAIN_TASK_ID);
+        assert!(FIRST_APP_TASK_ID < DEFAULT_TASK_ID);
+    }
+
+    #[test]
+    fn breathing_groups_are_distinct_and_sum_up() {
+        for (i, group) in BREATHING_GROUPS.iter().enumerate() {
+            assert!(group.concurrency > 0);
+            assert!(BREATHING_GROUPS[..i]
+                .iter()
+                .all(|other| other.name != group.name));
+        }
+        let sum: usize = BREATHING_GROUPS.iter().map(|group| group.concurrency).sum();
+        assert_eq!(BREATHING_CONCURRENCY, sum);
+    }
+
+    #[test]
+    fn task_local_storage_fits_below_the_guard() {
+        assert!(TLS_SLOT_COUNT > 0);
+        assert!(TLS_SLOT_SIZE > 0);
+        assert!(__TLS_MEM_ADDR + 0x10 <= __CONTIGUOUS_STACK_BOUNDARY - CONTIGUOUS_STACK_GUARD_SIZE);
+    }
+}
+
+mod timer {
+    use super::*;
+
+    #[test]
+    fn sleeps_fit_in_signed_ticks() {
+        assert!(MAX_SLEEP_MS > 0);
+        assert!(MAX_SLEEP_MS <= i32::MAX as u32);
+    }
+}
+
+mod backup_sram {
+    use super::*;
+
+    #[test]
+    fn backup_sram_is_word_aligned() {
+        assert_eq!(BACKUP_SRAM_ADDR % 4, 0);
+        assert_eq!(BACKUP_SRAM_LEN % 4, 0);
+        assert!(!ENABLE_PANIC_PERSIST || BACKUP_SRAM_LEN != 0);
+    }
+}
+
+mod abi {
+    use super::*;
+
+    #[test]
+    fn checksum_covers_the_private_parameters() {
+        assert_eq!(
+            PRIVATE_PARAMS_CHECKSUM,
+            private_params_checksum(&[
+                __CONTIGUOUS_STACK_BOUNDARY,
+                _CONTIGUOUS_STACK_BOTTOM,
+                __MEM_CHUNK_LINK_OFFSET,
+                __TLS_MEM_ADDR,
+            ])
+        );
+    }
+}
+
+#[cfg(feature = "armv8m")]
+mod armv8m {
+    use super::*;
+
+    #[test]
+    fn sau_regions_are_aligned_and_disjoint() {
+        assert!(SAU_REGIONS.len() <= SAU_REGION_NUMBER);
+
+        for (i, region) in SAU_REGIONS.iter().enumerate() {
+            assert_eq!(region.base % 32, 0);
+            assert_eq!(region.limit % 32, 31);
+            assert!(region.base < region.limit);
+
+            for other in &SAU_REGIONS[..i] {
+                assert!(region.limit < other.base || other.limit < region.base);
+            }
+        }
+    }
+
+    #[test]
+    fn secure_stacks_are_aligned() {
+        assert_eq!(SECURE_MAIN_STACK_SIZE % 8, 0);
+        assert_eq!(SECURE_TASK_STACK_SIZE % 8, 0);
+        assert!(SECURE_CONTEXT_NUMBER <= MAX_TASK_NUMBER);
+    }
+}
@@ -0,0 +1,16 @@
+#!/bin/bash
+set -e
+
+# Generate a binary image from the compiled ELF file, as `flash-board.sh` does.
+arm-none-eabi-objcopy -O binary --pad-to 0 --remove-section=.bss $1 hopter-quick-start.bin
+
+# Append the trailer checked by `src/updater.rs`: the length and the CRC-32 of
+# the image, and a magic word.
+python3 - hopter-quick-start.bin hopter-quick-start.update <<'END'
+import struct, sys, zlib
+image = open(sys.argv[1], 'rb').read()
+trailer = struct.pack('<II', len(image), zlib.crc32(image)) + b'HQS1'
+open(sys.argv[2], 'wb').write(image + trailer)
+END
+
+echo "Send hopter-quick-start.update to the \`update\` shell command."
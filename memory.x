@@ -4,9 +4,25 @@ MEMORY
 {
   /* NOTE 1 K = 1 KiB = 1024 bytes */
   RAM (xrw) : ORIGIN = 0x20000000, LENGTH = 128K
-  FLASH (rx) : ORIGIN = 0x8000000, LENGTH = 1024K
+  FLASH (rx) : ORIGIN = 0x8000000, LENGTH = 512K
+  /* Where `src/updater.rs` receives a new image of the program. */
+  STAGING (r) : ORIGIN = 0x8080000, LENGTH = 256K
+  /* Where `src/crash_dump.rs` saves a dump upon a fault. */
+  CRASH (r) : ORIGIN = 0x80C0000, LENGTH = 128K
+  /* The last sector of the flash, kept by `src/storage.rs`. */
+  STORAGE (r) : ORIGIN = 0x80E0000, LENGTH = 128K
 }
 
+/* The bounds of the STAGING, CRASH, and STORAGE regions, read by
+   `src/updater.rs`, `src/crash_dump.rs`, and `src/storage.rs`. */
+_staging_start = ORIGIN(STAGING);
+_staging_end = ORIGIN(STAGING) + LENGTH(STAGING);
+_crash_start = ORIGIN(CRASH);
+_crash_end = ORIGIN(CRASH) + LENGTH(CRASH);
+_storage_start = ORIGIN(STORAGE);
+_storage_end = ORIGIN(STORAGE) + LENGTH(STORAGE);
+
 /* Length of the contiguous stack placed at the beginning of the RAM region.
-   The value must match the one in Hopter configuration parameters. */
+   The value must match the one in Hopter configuration parameters, which is
+   checked by `build.rs`. */
 _contiguous_stack_length = 0x1000;
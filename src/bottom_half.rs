@@ -0,0 +1,116 @@
+//! Deferred interrupt processing, running the bulk of an IRQ handler's work
+//! in the `bottom_half` task.
+//!
+//! An IRQ handler delays every task and every handler of lower priority for
+//! as long as it runs, so it should do only what cannot wait, usually
+//! acknowledging the IRQ, and leave the rest to task context. A handler
+//! calls [`defer`] with a function and a word of argument, e.g., a status
+//! register value or a buffer index. The pair is queued, and the
+//! `bottom_half` task calls the function with the argument, in the order
+//! queued. The work then runs with the IRQs unmasked, may block, and cannot
+//! pile up on the contiguous stack. Call [`spawn`] to start the task before
+//! unmasking the IRQs deferring work.
+//!
+//! The queue holds [`QUEUE_LEN`] entries and never allocates, since
+//! allocating in an IRQ handler may have to wait for the heap lock. When it
+//! is full, e.g., when the task falls behind an IRQ storm, [`defer`] drops
+//! the entry and counts it, and [`peak`] tells how close the queue came to
+//! being full.
+//!
+//! The task runs above every task but `main` and `watchdog`, so the
+//! deferred work follows the IRQ almost as closely as if it ran in the
+//! handler. The work must therefore be short. The task is restartable. A
+//! panicking function restarts it, and the entries still queued are kept.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hopter::{
+    interrupt::mask::AllIrqExceptSvc,
+    sync::{self, Consumer, Producer, SpinIrqSafe},
+    task,
+};
+
+use crate::{stack_pool::SetStackPool, task_name::SetName};
+
+/// The number of entries queued before the task runs them.
+pub const QUEUE_LEN: usize = 16;
+
+/// The priority of the `bottom_half` task, just below the `watchdog` task.
+const PRIORITY: u8 = 2;
+
+/// A function deferred with its argument.
+#[derive(Clone, Copy)]
+struct Entry {
+    work: fn(u32),
+    arg: u32,
+}
+
+/// The producing end of the queue. It is `None` until [`spawn`] is called.
+/// Every IRQ is masked when the lock is held, since any handler may defer
+/// work.
+static QUEUE: SpinIrqSafe<Option<Producer<Entry, QUEUE_LEN>>, AllIrqExceptSvc> =
+    SpinIrqSafe::new(None);
+
+/// The number of entries queued but not yet taken by the task.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// The largest value `PENDING` has reached.
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of entries dropped because the queue was full or the task was
+/// not started.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Spawn the `bottom_half` task.
+pub fn spawn() {
+    let (producer, consumer) = sync::create_channel();
+    *QUEUE.lock() = Some(producer);
+
+    task::build()
+        .set_name_and_priority("bottom_half", PRIORITY)
+        .set_stack_pool(0)
+        .set_entry(move || run(&consumer))
+        .spawn_restartable()
+        .unwrap();
+}
+
+/// Have the `bottom_half` task call `work` with `arg`. Return false if the
+/// entry is dropped. It can be called from IRQ handlers as well as tasks.
+pub fn defer(work: fn(u32), arg: u32) -> bool {
+    // Count the entry before queuing it, so that the task never takes an
+    // entry not counted yet.
+    let pending = PENDING.fetch_add(1, Ordering::SeqCst) + 1;
+    let queued = match QUEUE.lock().as_ref() {
+        Some(producer) => producer.try_produce_allow_isr(Entry { work, arg }).is_ok(),
+        None => false,
+    };
+    if queued {
+        PEAK.fetch_max(pending, Ordering::SeqCst);
+    } else {
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+    queued
+}
+
+/// Return the number of entries queued but not yet taken.
+pub fn pending() -> usize {
+    PENDING.load(Ordering::SeqCst)
+}
+
+/// Return the largest number of entries that have been queued at once.
+pub fn peak() -> usize {
+    PEAK.load(Ordering::SeqCst)
+}
+
+/// Return the number of entries dropped so far.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+fn run(consumer: &Consumer<Entry, QUEUE_LEN>) {
+    loop {
+        let Entry { work, arg } = consumer.consume();
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+        work(arg);
+    }
+}
@@ -0,0 +1,92 @@
+//! Concurrency limits for groups of breathing tasks.
+//!
+//! The kernel bounds the number of breathing tasks running their `work`
+//! closures at once by `BREATHING_CONCURRENCY`. This module further divides
+//! breathing tasks into the groups listed in `BREATHING_GROUPS`, each with a
+//! limit of its own, so that tasks with deep stacks can be limited more
+//! tightly than those with shallow ones.
+//!
+//! A breathing task joins a group by wrapping its `wait` closure with
+//! [`in_group`] and its `work` closure with [`grouped`]:
+//!
+//! ```ignore
+//! task::build_breathing()
+//!     .set_init(init)
+//!     .set_wait(breathing_group::in_group("default", wait))
+//!     .set_work(breathing_group::grouped(work))
+//!     .spawn()
+//!     .unwrap();
+//! ```
+
+use core::mem::MaybeUninit;
+use hopter::sync::Semaphore;
+use hopter_conf_params::BREATHING_GROUPS;
+
+/// The semaphores enforcing the group limits. The semaphore at index `i`
+/// belongs to `BREATHING_GROUPS[i]`.
+static SEMAPHORES: [Semaphore; BREATHING_GROUPS.len()] = new_semaphores();
+
+const fn new_semaphores() -> [Semaphore; BREATHING_GROUPS.len()] {
+    let mut sems = [const { MaybeUninit::uninit() }; BREATHING_GROUPS.len()];
+    let mut i = 0;
+    while i < BREATHING_GROUPS.len() {
+        let concurrency = BREATHING_GROUPS[i].concurrency;
+        sems[i] = MaybeUninit::new(Semaphore::new(concurrency, concurrency));
+        i += 1;
+    }
+    // Safety: All elements are initialized above.
+    unsafe { core::mem::transmute(sems) }
+}
+
+/// The item produced by a `wait` closure wrapped by [`in_group`]. It holds a
+/// slot of the group until the `work` closure finishes.
+pub struct Grouped<I> {
+    item: I,
+    _slot: Slot,
+}
+
+/// A slot of a group. Dropping it releases the slot, so that the slot is
+/// returned also when the `work` closure panics.
+struct Slot(&'static Semaphore);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        // Do not block in a drop handler. Releasing a held slot never blocks.
+        let _ = self.0.try_up_allow_isr();
+    }
+}
+
+/// Wrap the `wait` closure of a breathing task so that the task joins the
+/// named group. After `wait` returns, the task blocks until the group has a
+/// free slot. Panic if no group has the name.
+pub fn in_group<S, I, G>(
+    name: &str,
+    wait: G,
+) -> impl Fn(&mut S) -> Grouped<I> + Clone + Send + Sync + 'static
+where
+    G: Fn(&mut S) -> I + Clone + Send + Sync + 'static,
+{
+    let idx = BREATHING_GROUPS
+        .iter()
+        .position(|group| group.name == name)
+        .expect("unknown breathing group");
+    let sem = &SEMAPHORES[idx];
+
+    move |state| {
+        let item = wait(state);
+        sem.down();
+        Grouped {
+            item,
+            _slot: Slot(sem),
+        }
+    }
+}
+
+/// Wrap the `work` closure of a breathing task whose `wait` closure is
+/// wrapped by [`in_group`]. The group slot is released after `work` returns.
+pub fn grouped<S, I, H>(work: H) -> impl Fn(&mut S, Grouped<I>) + Clone + Send + Sync + 'static
+where
+    H: Fn(&mut S, I) + Clone + Send + Sync + 'static,
+{
+    move |state, grouped| work(state, grouped.item)
+}
@@ -0,0 +1,266 @@
+//! A node on a CAN bus through CAN1, exchanging typed messages.
+//!
+//! The controller transmits on PD1 and receives on PD0, to be wired to a CAN
+//! transceiver when joining a bus. In loopback mode, the controller receives
+//! its own frames internally and keeps PD1 recessive, so the node works with
+//! nothing wired to the pins, and disturbs no bus if one is.
+//!
+//! The frames carry the [`Message`] variants, with the ID of the sending
+//! node in the low 7 bits of the standard identifier. [`send`] encodes a
+//! message into a frame and queues it in one of the three transmit mailboxes
+//! of the controller, sleeping while all of them are taken. The IRQ of
+//! receive FIFO 0 decodes each frame into a message and pushes it into a
+//! channel, whose consuming end [`init`] returns. Frames that decode to no
+//! message are ignored.
+//!
+//! The controller reads the bus at [`BITRATE_HZ`], a common rate for both
+//! automotive and industrial buses. The bit timing is derived from the APB1
+//! clock, which must be a multiple of the bit rate.
+
+use bxcan::{filter::Mask32, Fifo, Frame, Id, Interrupt, Rx0, StandardId, Tx};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mailbox, Producer, SpinIrqSafe},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    can::{Can1, CanExt},
+    gpio::{PD0, PD1},
+    nb,
+    pac::{self, CAN1},
+    rcc::Clocks,
+};
+
+use crate::irq_nesting;
+
+/// The bit rate of the bus.
+pub const BITRATE_HZ: u32 = 500_000;
+
+/// The number of received messages buffered before task context consumes
+/// them.
+pub const RX_QUEUE_LEN: usize = 16;
+
+/// The consuming end of the channel carrying the received messages.
+pub type RxConsumer = Consumer<Message, RX_QUEUE_LEN>;
+
+/// The bits of a standard identifier holding the node ID.
+const NODE_MASK: u16 = 0x7f;
+
+/// The identifiers of the messages, before adding the node ID. A lower
+/// identifier wins the arbitration of the bus.
+const TEMPERATURE_ID: u16 = 0x180;
+const HEARTBEAT_ID: u16 = 0x700;
+
+/// A message exchanged by the nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// Sent periodically by each node, counting up from 0.
+    Heartbeat { node: u8, count: u32 },
+    /// The chip temperature of a node in hundredths of a degree Celsius.
+    Temperature { node: u8, centi_celsius: i32 },
+}
+
+impl Message {
+    /// Return the frame carrying the message. Panic if the node ID does not
+    /// fit in 7 bits.
+    pub fn to_frame(self) -> Frame {
+        let (base, node, data) = match self {
+            Message::Heartbeat { node, count } => (HEARTBEAT_ID, node, count.to_le_bytes()),
+            Message::Temperature {
+                node,
+                centi_celsius,
+            } => (TEMPERATURE_ID, node, centi_celsius.to_le_bytes()),
+        };
+        assert!(u16::from(node) <= NODE_MASK);
+        let id = StandardId::new(base | u16::from(node)).unwrap();
+        Frame::new_data(id, data)
+    }
+
+    /// Return the message carried by the frame, or `None` if it carries
+    /// none.
+    pub fn from_frame(frame: &Frame) -> Option<Self> {
+        let id = match frame.id() {
+            Id::Standard(id) => id.as_raw(),
+            Id::Extended(_) => return None,
+        };
+        let data: [u8; 4] = (**frame.data()?).try_into().ok()?;
+        let node = (id & NODE_MASK) as u8;
+        match id & !NODE_MASK {
+            HEARTBEAT_ID => Some(Message::Heartbeat {
+                node,
+                count: u32::from_le_bytes(data),
+            }),
+            TEMPERATURE_ID => Some(Message::Temperature {
+                node,
+                centi_celsius: i32::from_le_bytes(data),
+            }),
+            _ => None,
+        }
+    }
+}
+
+irq!(Can1TxIrq, pac::interrupt::CAN1_TX);
+irq!(Can1Rx0Irq, pac::interrupt::CAN1_RX0);
+
+/// The transmit side. The TX IRQ is masked when the lock is held.
+static TX: SpinIrqSafe<Option<Tx<Can1>>, Can1TxIrq> = SpinIrqSafe::new(None);
+
+/// Notified by the handler when a transmit mailbox becomes empty.
+static TX_EMPTY: Mailbox = Mailbox::new();
+
+/// Receive FIFO 0 and the producing end of the channel carrying the received
+/// messages. The RX0 IRQ is masked when the lock is held.
+static RX: SpinIrqSafe<Option<RxSide>, Can1Rx0Irq> = SpinIrqSafe::new(None);
+
+type RxSide = (Rx0<Can1>, Producer<Message, RX_QUEUE_LEN>);
+
+/// The number of received messages dropped because the channel was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of frames lost because receive FIFO 0 was full.
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Configure CAN1, in loopback mode if `loopback` is set, wait until it
+/// synchronizes with the bus, and unmask its IRQs. Return the consuming end of
+/// the channel carrying the received messages. Panic if called twice, or if the
+/// bit rate cannot be derived from the APB1 clock.
+pub fn init(
+    can1: CAN1,
+    tx: PD1,
+    rx: PD0,
+    loopback: bool,
+    clocks: &Clocks,
+    nvic: &mut cortex_m::peripheral::NVIC,
+) -> RxConsumer {
+    let btr = bit_timing(clocks.pclk1().raw(), BITRATE_HZ)
+        .expect("the APB1 clock cannot produce the CAN bit rate");
+    let mut can = bxcan::Can::builder(can1.can((tx, rx)))
+        .set_bit_timing(btr)
+        .set_loopback(loopback)
+        .set_silent(loopback)
+        .leave_disabled();
+
+    // Pass every frame to FIFO 0. The messages are told apart once decoded.
+    can.modify_filters()
+        .enable_bank(0, Fifo::Fifo0, Mask32::accept_all());
+    can.enable_interrupt(Interrupt::TransmitMailboxEmpty);
+    can.enable_interrupt(Interrupt::Fifo0MessagePending);
+
+    // The controller joins the bus after 11 recessive bits in a row, which
+    // never come on a bus stuck dominant. Sleep rather than spin meanwhile.
+    while let Err(nb::Error::WouldBlock) = can.enable_non_blocking() {
+        time::sleep_ms(1).unwrap();
+    }
+
+    let (tx, rx0, _) = can.split();
+    let (producer, consumer) = sync::create_channel();
+    {
+        let mut slot = TX.lock();
+        assert!(slot.is_none());
+        *slot = Some(tx);
+    }
+    *RX.lock() = Some((rx0, producer));
+
+    // Safety: The handlers only touch the controller behind the locks.
+    unsafe {
+        nvic.set_priority(pac::interrupt::CAN1_TX, IRQ_NORMAL_PRIORITY);
+        nvic.set_priority(pac::interrupt::CAN1_RX0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::CAN1_TX);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::CAN1_RX0);
+    }
+
+    consumer
+}
+
+/// Queue the message for transmission, sleeping while the transmit mailboxes
+/// are all taken. Panic if called before [`init`].
+pub fn send(message: Message) {
+    let mut frame = message.to_frame();
+    loop {
+        let result = TX.lock().as_mut().unwrap().transmit(&frame);
+        match result {
+            Ok(status) => match status.dequeued_frame() {
+                // The controller made room for the frame by taking out a
+                // pending frame of lower priority, which is queued again.
+                Some(dequeued) => frame = dequeued.clone(),
+                None => return,
+            },
+            Err(nb::Error::WouldBlock) => TX_EMPTY.wait(),
+            Err(nb::Error::Other(never)) => match never {},
+        }
+    }
+}
+
+/// Return the number of received messages dropped so far because the
+/// channel was full.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// Return the number of frames lost so far because receive FIFO 0 was full.
+pub fn overruns() -> usize {
+    OVERRUNS.load(Ordering::SeqCst)
+}
+
+/// Return the value of the bit timing register giving the bit rate from the
+/// clock, or `None` if no prescaler divides the clock into 8 to 25 time
+/// quanta per bit. The sample point is placed near 87.5 % of the bit.
+fn bit_timing(clock_hz: u32, bitrate_hz: u32) -> Option<u32> {
+    (8..=25).rev().find_map(|quanta| {
+        let divisor = bitrate_hz * quanta;
+        if clock_hz % divisor != 0 {
+            return None;
+        }
+        let prescaler = clock_hz / divisor;
+        // One quantum to synchronize, then the two segments around the
+        // sample point.
+        let segment2 = (quanta + 4) / 8;
+        let segment1 = quanta - 1 - segment2;
+        if !(1..=1024).contains(&prescaler) || segment1 > 16 {
+            return None;
+        }
+        // A resynchronization jump width of one quantum.
+        Some((segment2 - 1) << 20 | (segment1 - 1) << 16 | (prescaler - 1))
+    })
+}
+
+#[handler(CAN1_TX)]
+fn can1_tx_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    if let Some(tx) = TX.lock().as_mut() {
+        tx.clear_interrupt_flags();
+    }
+
+    TX_EMPTY.notify_allow_isr();
+}
+
+#[handler(CAN1_RX0)]
+fn can1_rx0_handler() {
+    let _nesting = irq_nesting::enter();
+
+    let mut rx = RX.lock();
+    let Some((fifo, producer)) = rx.as_mut() else {
+        return;
+    };
+
+    // The IRQ stays pending until the FIFO is empty.
+    loop {
+        match fifo.receive() {
+            Ok(frame) => {
+                if let Some(message) = Message::from_frame(&frame) {
+                    if producer.try_produce_allow_isr(message).is_err() {
+                        DROPPED.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            }
+            Err(nb::Error::WouldBlock) => break,
+            Err(nb::Error::Other(_)) => {
+                OVERRUNS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
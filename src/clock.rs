@@ -0,0 +1,120 @@
+//! A monotonic 64-bit clock counting microseconds, independent of the tick.
+//!
+//! The kernel tick counts milliseconds, and the cycle counter of the DWT
+//! wraps around within a minute at the usual clock rates. This module runs
+//! TIM5, a 32-bit timer, at 1 MHz from [`init`] on, and counts its overflows,
+//! which happen every 71 minutes, in the upper 32 bits. TIM2, the other
+//! 32-bit timer, is taken by Part 5B, so the upper bits are counted by the
+//! IRQ handler rather than by a second timer chained to the first.
+//!
+//! [`Instant::now`] may be called from tasks and IRQ handlers alike. It reads
+//! the upper bits, the counter, and the pending overflow flag, then the upper
+//! bits again, and starts over if the handler ran in between. An overflow
+//! not yet counted, e.g., when the caller is an IRQ handler of the highest
+//! priority, or when the kernel has the IRQs masked, is told by the flag.
+//! The handler runs at the highest IRQ priority, so no reader interrupts it
+//! between clearing the flag and counting the overflow.
+//!
+//! The clock reads zero before [`init`].
+
+use core::{
+    ops::{Add, Sub},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+use cortex_m::peripheral::NVIC;
+use hopter::interrupt::declare::handler;
+use hopter_conf_params::IRQ_MAX_PRIORITY;
+use stm32f4xx_hal::{
+    pac::{self, TIM5},
+    rcc::Clocks,
+    timer::FTimerUs,
+};
+
+/// The overflows of the counter, i.e., the upper 32 bits of the clock.
+static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+
+/// A reading of the clock, in microseconds since [`init`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Read the clock.
+    pub fn now() -> Self {
+        // Safety: The registers are only read here.
+        let tim5 = unsafe { &*TIM5::ptr() };
+        loop {
+            let high = OVERFLOWS.load(Ordering::SeqCst);
+            let low = tim5.cnt.read().bits();
+            let pending = tim5.sr.read().uif().bit_is_set();
+            if OVERFLOWS.load(Ordering::SeqCst) != high {
+                continue;
+            }
+            // A pending overflow belongs to this reading only if the counter
+            // was read after it, i.e., has not gone far since.
+            let high = if pending && low < 1 << 31 {
+                high.wrapping_add(1)
+            } else {
+                high
+            };
+            return Self(u64::from(high) << 32 | u64::from(low));
+        }
+    }
+
+    /// Return the microseconds since [`init`].
+    pub fn as_micros(&self) -> u64 {
+        self.0
+    }
+
+    /// Return the time since this reading.
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+
+    /// Return the time from `earlier` to this reading, or zero if `earlier`
+    /// is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.as_micros() as u64)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Start TIM5 counting microseconds over its whole 32-bit range.
+pub fn init(tim5: TIM5, clocks: &Clocks, nvic: &mut NVIC) {
+    // Enables the timer and sets the prescaler for 1 MHz.
+    let tim5 = FTimerUs::new(tim5, clocks).release();
+    tim5.arr.write(|w| w.bits(u32::MAX));
+    // Load the prescaler, then clear the flag raised by doing so.
+    tim5.egr.write(|w| w.ug().set_bit());
+    tim5.sr.write(|w| w.uif().clear_bit());
+    tim5.dier.write(|w| w.uie().set_bit());
+    tim5.cr1.write(|w| w.cen().set_bit());
+
+    unsafe {
+        nvic.set_priority(pac::interrupt::TIM5, IRQ_MAX_PRIORITY);
+        NVIC::unmask(pac::interrupt::TIM5);
+    }
+}
+
+#[handler(TIM5)]
+fn tim5_handler() {
+    // Safety: Only the flag is cleared here.
+    let tim5 = unsafe { &*TIM5::ptr() };
+    tim5.sr.write(|w| w.uif().clear_bit());
+    OVERFLOWS.fetch_add(1, Ordering::SeqCst);
+}
@@ -0,0 +1,139 @@
+//! Serial console over USART2.
+//!
+//! USART2 transmits on PA2 and receives on PA3 at [`BAUD_RATE`], 8N1. Connect
+//! a 3.3 V USB-to-serial adapter to the pins. On STM32F412-Discovery, the
+//! pins are also routed to the virtual COM port of the on-board ST-LINK.
+//!
+//! Tasks print with the [`print!`] and [`println!`] macros. The transmitter
+//! is guarded by a mutex, and each byte is written by busy waiting for the
+//! transmit register, so printing must not happen in IRQ handlers.
+//!
+//! Received bytes are drained by the USART2 IRQ handler into a channel, and
+//! [`init`] returns the consuming end, so that a task processes them in task
+//! context. Bytes arriving while the channel is full are dropped and counted.
+
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mutex, Producer, SpinIrqSafe},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    gpio::{PA2, PA3},
+    nb,
+    pac::{self, USART2},
+    prelude::*,
+    rcc::Clocks,
+    serial::{Rx, Tx},
+};
+
+use crate::irq_nesting;
+
+/// The baud rate of the console.
+pub const BAUD_RATE: u32 = 115_200;
+
+/// The number of received bytes buffered before task context consumes them.
+pub const RX_BUFFER_LEN: usize = 32;
+
+/// The consuming end of the channel carrying the received bytes.
+pub type RxConsumer = Consumer<u8, RX_BUFFER_LEN>;
+
+irq!(Usart2Irq, pac::interrupt::USART2);
+
+/// The transmitter. It is `None` until [`init`] is called.
+static TX: Mutex<Option<Tx<USART2>>> = Mutex::new(None);
+
+/// The receiver and the producing end of the channel.
+type Receiver = (Rx<USART2>, Producer<u8, RX_BUFFER_LEN>);
+
+/// The receiver. USART2 IRQ is masked when the lock is held.
+static RX: SpinIrqSafe<Option<Receiver>, Usart2Irq> = SpinIrqSafe::new(None);
+
+/// The number of received bytes dropped because the channel was full or the
+/// reception failed, e.g., due to an overrun.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Bring up USART2 and unmask its IRQ. Return the consuming end of the
+/// channel carrying the received bytes.
+pub fn init(
+    usart: USART2,
+    tx: PA2,
+    rx: PA3,
+    clocks: &Clocks,
+    nvic: &mut cortex_m::peripheral::NVIC,
+) -> RxConsumer {
+    let serial = usart.serial((tx, rx), BAUD_RATE.bps(), clocks).unwrap();
+    let (tx, mut rx) = serial.split();
+    rx.listen();
+
+    let (producer, consumer) = sync::create_channel();
+    *TX.lock() = Some(tx);
+    *RX.lock() = Some((rx, producer));
+
+    unsafe {
+        nvic.set_priority(pac::interrupt::USART2, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::USART2);
+    }
+
+    consumer
+}
+
+/// Return the number of received bytes dropped so far.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// Write the formatted text to the console. Do nothing before [`init`] is
+/// called. Used by the [`print!`] and [`println!`] macros.
+pub fn write_fmt(args: fmt::Arguments) {
+    if let Some(tx) = TX.lock().as_mut() {
+        // Writing blocks until the bytes are sent, so it never fails.
+        let _ = tx.write_fmt(args);
+    }
+}
+
+/// Print to the console.
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::write_fmt(format_args!($($arg)*))
+    };
+}
+
+/// Print to the console, with a CR LF line ending.
+macro_rules! println {
+    () => {
+        $crate::console::print!("\r\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::console::write_fmt(format_args!("{}\r\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use {print, println};
+
+#[handler(USART2)]
+fn usart2_handler() {
+    let _nesting = irq_nesting::enter();
+
+    let mut rx = RX.lock();
+    let (rx, producer) = rx.as_mut().unwrap();
+
+    // Drain the receive register. Reading it also acknowledges the IRQ.
+    loop {
+        match rx.read() {
+            Ok(byte) => {
+                if producer.try_produce_allow_isr(byte).is_err() {
+                    DROPPED.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            Err(nb::Error::WouldBlock) => break,
+            // The HAL clears the error flags upon reporting them.
+            Err(nb::Error::Other(_)) => {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Crash dumps written to the flash upon a fault.
+//!
+//! When the `fault` module catches a fault, it calls [`save`] before the
+//! reset. The dump holds the registers stacked by the fault, the fault
+//! status registers, and the content of the RAM: the contiguous stack, the
+//! `.data` and `.bss` sections, and the heap, which holds the stacklets and
+//! the control blocks of the tasks. The `crash` shell command prints the dump
+//! as hexadecimal lines, and the `crash-decoder` host crate turns the printed
+//! lines, or the region read by a debug probe, into a report of every task.
+//!
+//! The dump goes to the CRASH region of `memory.x`, which must span whole
+//! sectors. The dump is cut short if the RAM is larger than the region. A
+//! region of zero length disables the dumps, e.g., on STM32F411, whose flash
+//! has no sector to spare.
+//!
+//! A dump is kept until it is erased with `crash erase`, and further faults
+//! are then only reported over the console. This keeps the first of a series
+//! of faults, which is usually the telling one, and spares the sector from
+//! an erase upon each reset if the program faults while starting.
+//!
+//! The fault may strike anywhere, e.g., while the `storage` module holds the
+//! flash lock, so [`save`] drives the flash controller through its registers
+//! and takes no lock. The magic word is programmed last, so that a dump cut
+//! short by a reset is not mistaken for a valid one.
+
+use crate::{
+    console::{print, println},
+    shell::Line,
+    storage,
+};
+use core::{fmt, ptr};
+use hopter::time;
+use hopter_conf_params::{
+    HeapRegion, _CONTIGUOUS_STACK_BOTTOM, _CONTIGUOUS_STACK_LENGTH, HEAP_REGIONS,
+};
+use log::LevelFilter;
+use stm32f4xx_hal::{
+    flash::{FlashExt, FlashSector},
+    pac,
+};
+
+/// The first word of a dump.
+pub const MAGIC: u32 = u32::from_le_bytes(*b"HQSD");
+
+/// The version of the layout of a dump, bumped upon each change.
+pub const VERSION: u32 = 1;
+
+/// The maximum number of memory regions in a dump.
+const MAX_REGIONS: usize = 4;
+
+/// The index of the words in the header of a dump. The header is followed
+/// by the region table, [`MAX_REGIONS`] pairs of the start and the length of
+/// each region, unused pairs being zero, and then by the content of the
+/// regions, in the order of the table.
+mod word {
+    pub const MAGIC: usize = 0;
+    pub const VERSION: usize = 1;
+    /// The length of the header and the region table, in bytes.
+    pub const HEADER_LEN: usize = 2;
+    /// The length of the whole dump, in bytes.
+    pub const LEN: usize = 3;
+    pub const TICK: usize = 4;
+    /// The number of the exception taken upon the fault.
+    pub const EXCEPTION: usize = 5;
+    pub const EXC_RETURN: usize = 6;
+    /// The stack pointer upon the fault, i.e., the address of the frame.
+    pub const SP: usize = 7;
+    /// The eight registers of the frame, from r0 to xPSR.
+    pub const FRAME: usize = 8;
+    /// The stacklet boundary of the running task.
+    pub const STKLET_BOUND: usize = 16;
+    /// The process stack pointer, i.e., that of the running task if the
+    /// fault struck a handler.
+    pub const PSP: usize = 17;
+    /// The address of the context of the running task in its control block.
+    pub const CUR_CTXT: usize = 18;
+    pub const CFSR: usize = 19;
+    pub const HFSR: usize = 20;
+    pub const MMFAR: usize = 21;
+    pub const BFAR: usize = 22;
+    pub const REGION_COUNT: usize = 23;
+    pub const REGIONS: usize = 24;
+}
+
+/// The number of words of the header and the region table.
+const HEADER_WORDS: usize = word::REGIONS + 2 * MAX_REGIONS;
+
+/// The flash controller status bits telling an error: OPERR, WRPERR,
+/// PGAERR, PGPERR, PGSERR, and RDERR.
+const SR_ERRORS: u32 = 0x1f2;
+
+/// The unlock sequence of the flash controller.
+const KEYS: [u32; 2] = [0x4567_0123, 0xcdef_89ab];
+
+extern "C" {
+    // The bounds of the CRASH region, defined in `memory.x`.
+    static _crash_start: u32;
+    static _crash_end: u32;
+
+    // Defined by Hopter, pointing to the context in the control block of
+    // the running task.
+    static CUR_TASK_CTXT_PTR: u32;
+}
+
+/// The outcome of [`save`].
+pub enum Outcome {
+    /// The dump was written, this many bytes long.
+    Saved(usize),
+    /// A previous dump was kept.
+    Kept,
+    /// The CRASH region is empty.
+    Disabled,
+    /// The flash controller reported an error, with its status register.
+    Failed(u32),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Saved(len) => write!(f, "saved, {} bytes", len),
+            Self::Kept => write!(f, "previous one kept"),
+            Self::Disabled => write!(f, "disabled"),
+            Self::Failed(sr) => write!(f, "failed, flash status {:#x}", sr),
+        }
+    }
+}
+
+/// Check the CRASH region. Panic if it does not span whole sectors.
+pub fn init() {
+    assert_eq!(
+        sectors_len(),
+        len(),
+        "the CRASH region does not span whole flash sectors"
+    );
+}
+
+/// Write the dump of a fault, unless a dump is present. `frame` points to
+/// the registers stacked by the fault, and `frame_valid` tells whether they
+/// lie in the RAM. Only called by the fault handler, which resets afterwards.
+pub fn save(frame: *const u32, frame_valid: bool, stklet_bound: u32, exc_return: u32) -> Outcome {
+    if len() == 0 {
+        return Outcome::Disabled;
+    }
+    if read(word::MAGIC) == MAGIC {
+        return Outcome::Kept;
+    }
+
+    // Safety: The system control registers are only read.
+    let scb = unsafe { &*cortex_m::peripheral::SCB::PTR };
+    let mut header = [0; HEADER_WORDS];
+    header[word::MAGIC] = MAGIC;
+    header[word::VERSION] = VERSION;
+    header[word::HEADER_LEN] = (HEADER_WORDS * 4) as u32;
+    header[word::TICK] = time::get_tick();
+    header[word::EXCEPTION] = scb.icsr.read() & 0x1ff;
+    header[word::EXC_RETURN] = exc_return;
+    header[word::SP] = frame as u32;
+    if frame_valid {
+        for i in 0..8 {
+            // Safety: The caller checked that the frame lies in the RAM.
+            header[word::FRAME + i] = unsafe { ptr::read_volatile(frame.add(i)) };
+        }
+    }
+    header[word::STKLET_BOUND] = stklet_bound;
+    header[word::PSP] = cortex_m::register::psp::read();
+    // Safety: The kernel writes the pointer only upon a context switch, which
+    // does not happen while the fault is handled.
+    header[word::CUR_CTXT] = unsafe { ptr::read_volatile(ptr::addr_of!(CUR_TASK_CTXT_PTR)) };
+    header[word::CFSR] = scb.cfsr.read();
+    header[word::HFSR] = scb.hfsr.read();
+    header[word::MMFAR] = scb.mmfar.read();
+    header[word::BFAR] = scb.bfar.read();
+
+    // The RAM regions, cut to the room left in the CRASH region.
+    let mut room = len() - HEADER_WORDS * 4;
+    let mut count = 0;
+    for (start, end) in regions() {
+        let region_len = ((end - start) as usize).min(room) & !3;
+        if region_len == 0 {
+            break;
+        }
+        header[word::REGIONS + 2 * count] = start;
+        header[word::REGIONS + 2 * count + 1] = region_len as u32;
+        room -= region_len;
+        count += 1;
+    }
+    header[word::REGION_COUNT] = count as u32;
+    header[word::LEN] = (len() - room) as u32;
+
+    match write(&header) {
+        Ok(()) => Outcome::Saved(len() - room),
+        Err(sr) => Outcome::Failed(sr),
+    }
+}
+
+/// The `crash` shell command. Without an argument, summarize the dump.
+/// `crash dump` prints it as lines of the offset and 32 bytes in hexadecimal,
+/// which `crash-decoder` reads. `crash erase` erases it. The `storage` module
+/// must be initialized.
+pub fn command(line: &Line) {
+    if len() == 0 {
+        println!("the CRASH region in memory.x is empty");
+        return;
+    }
+    let present = read(word::MAGIC) == MAGIC;
+    match line.args().next() {
+        None if present => summarize(),
+        None => println!("no crash dump"),
+        Some("dump") if present => print_hex(),
+        Some("erase") => match erase() {
+            Ok(()) => println!("crash dump erased"),
+            Err(err) => println!("erase failed: {:?}", err),
+        },
+        Some("dump") => println!("no crash dump"),
+        Some(_) => println!("usage: crash [dump|erase]"),
+    }
+}
+
+/// Return the length of a valid dump, or `None` if there is none. Used to
+/// report the dump at boot.
+pub fn present() -> Option<usize> {
+    (len() != 0 && read(word::MAGIC) == MAGIC).then(|| read(word::LEN) as usize)
+}
+
+/// Print the header of the dump.
+fn summarize() {
+    let kind = match read(word::EXCEPTION) {
+        3 => "HardFault",
+        4 => "MemManage",
+        5 => "BusFault",
+        6 => "UsageFault",
+        _ => "fault",
+    };
+    println!(
+        "{} at tick {}, pc {:#010x}, lr {:#010x}, CFSR {:#010x}",
+        kind,
+        read(word::TICK),
+        read(word::FRAME + 6),
+        read(word::FRAME + 5),
+        read(word::CFSR)
+    );
+    println!(
+        "{} bytes dumped, print them with `crash dump` and decode them with crash-decoder",
+        read(word::LEN)
+    );
+}
+
+/// Print the dump as hexadecimal lines.
+fn print_hex() {
+    // Mute the log output, which would break the lines.
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let len = (read(word::LEN) as usize).min(len());
+    for offset in (0..len).step_by(32) {
+        print!("{:08x}:", offset);
+        for at in offset..(offset + 32).min(len) {
+            // Safety: The offset is within the CRASH region.
+            let byte = unsafe { ptr::read_volatile((start() + at) as *const u8) };
+            print!("{:02x}", byte);
+        }
+        println!();
+    }
+    println!("end of crash dump");
+    log::set_max_level(level);
+}
+
+/// Erase the CRASH region through the `storage` module, like a sector of
+/// the STAGING region.
+fn erase() -> Result<(), stm32f4xx_hal::flash::Error> {
+    for sector in sectors() {
+        storage::with_flash(|flash| flash.unlocked().erase(sector.number))?;
+    }
+    Ok(())
+}
+
+/// Return the memory regions to dump: the internal SRAM from the contiguous
+/// stack to the end of the primary heap, then the secondary heaps.
+fn regions() -> impl Iterator<Item = (u32, u32)> {
+    let ram = (
+        _CONTIGUOUS_STACK_BOTTOM - _CONTIGUOUS_STACK_LENGTH,
+        HEAP_REGIONS[0].end,
+    );
+    let heaps = HEAP_REGIONS[1..]
+        .iter()
+        .map(|&HeapRegion { start, end, .. }| (start, end));
+    core::iter::once(ram).chain(heaps).take(MAX_REGIONS)
+}
+
+/// Program the header and the regions, the magic word last, erasing the
+/// CRASH region first if it is not blank. Return the status register upon an
+/// error.
+fn write(header: &[u32; HEADER_WORDS]) -> Result<(), u32> {
+    // Safety: The fault handler runs alone, and resets once done.
+    let flash = unsafe { &*pac::FLASH::ptr() };
+
+    // Let an operation started before the fault end.
+    while flash.sr.read().bsy().bit_is_set() {}
+    if flash.cr.read().lock().bit_is_set() {
+        for key in KEYS {
+            flash.keyr.write(|w| w.key().bits(key));
+        }
+        if flash.cr.read().lock().bit_is_set() {
+            return Err(flash.sr.read().bits());
+        }
+    }
+    flash.sr.write(|w| unsafe { w.bits(SR_ERRORS) });
+
+    let result = (|| {
+        if (0..len() / 4).any(|i| read(i) != u32::MAX) {
+            for sector in sectors() {
+                erase_sector(flash, sector.number)?;
+            }
+        }
+
+        let mut offset = 4;
+        for &value in &header[1..] {
+            program(flash, offset, value)?;
+            offset += 4;
+        }
+        let regions = header[word::REGIONS..].chunks(2);
+        for region in regions.take(header[word::REGION_COUNT] as usize) {
+            for addr in (region[0]..region[0] + region[1]).step_by(4) {
+                // Safety: The region is one of the RAM regions, read in
+                // whole words.
+                let value = unsafe { ptr::read_volatile(addr as *const u32) };
+                program(flash, offset, value)?;
+                offset += 4;
+            }
+        }
+        program(flash, 0, MAGIC)
+    })();
+
+    flash.cr.write(|w| w.lock().set_bit());
+    result
+}
+
+/// Erase a sector, and feed the independent watchdog, in case it runs.
+fn erase_sector(flash: &pac::flash::RegisterBlock, number: u8) -> Result<(), u32> {
+    // The numbers of the sectors of the second bank skip four values.
+    let snb = if number < 12 { number } else { number + 4 };
+    flash
+        .cr
+        .write(|w| unsafe { w.psize().psize32().snb().bits(snb).ser().set_bit() });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    let result = wait(flash);
+    // Safety: Writing the key register of the IWDG only reloads it.
+    unsafe { ptr::write_volatile(0x4000_3000 as *mut u32, 0xaaaa) };
+    result
+}
+
+/// Program a word at the offset in the CRASH region.
+fn program(flash: &pac::flash::RegisterBlock, offset: usize, value: u32) -> Result<(), u32> {
+    flash.cr.write(|w| w.psize().psize32().pg().set_bit());
+    // Safety: The offset is within the CRASH region, which holds no code.
+    unsafe { ptr::write_volatile((start() + offset) as *mut u32, value) };
+    wait(flash)
+}
+
+/// Wait until the flash controller is done, and return its status register
+/// upon an error.
+fn wait(flash: &pac::flash::RegisterBlock) -> Result<(), u32> {
+    while flash.sr.read().bsy().bit_is_set() {}
+    let sr = flash.sr.read().bits();
+    if sr & SR_ERRORS != 0 {
+        return Err(sr);
+    }
+    Ok(())
+}
+
+/// Return the sectors of the CRASH region, up to the first one it does not
+/// span whole, which [`init`] rules out.
+fn sectors() -> impl Iterator<Item = FlashSector> {
+    // Safety: The controller is only queried for its size and layout.
+    let flash = unsafe { pac::Peripherals::steal() }.FLASH;
+    let (start, end) = (start() - flash.address(), start() - flash.address() + len());
+    let mut offset = start;
+    core::iter::from_fn(move || {
+        let sector = flash
+            .sector(offset)
+            .filter(|sector| sector.offset == offset && offset + sector.size <= end)?;
+        offset += sector.size;
+        Some(sector)
+    })
+}
+
+/// Return the number of bytes spanned by [`sectors`].
+fn sectors_len() -> usize {
+    sectors().map(|sector| sector.size).sum()
+}
+
+/// Read a word of the CRASH region.
+fn read(index: usize) -> u32 {
+    // Safety: The index is within the CRASH region, see the callers.
+    unsafe { ptr::read_volatile((start() as *const u32).add(index)) }
+}
+
+/// Return the address of the CRASH region.
+fn start() -> usize {
+    ptr::addr_of!(_crash_start) as usize
+}
+
+/// Return the size of the CRASH region.
+fn len() -> usize {
+    ptr::addr_of!(_crash_end) as usize - start()
+}
@@ -0,0 +1,67 @@
+//! Allocation of DMA buffers from the region reserved for them.
+//!
+//! The region given by `DMA_REGION_START` and `DMA_REGION_LEN` is excluded
+//! from all heap regions, so neither the kernel allocator nor the allocators
+//! in `region_heap` hand out memory from it. The allocator of this module
+//! manages it instead. It implements the `Allocator` trait like those in
+//! `region_heap`, and [`DmaHeap::buffer`] is a shorthand for the common case
+//! of a zeroed byte buffer.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+use hopter::sync::SpinSchedSafe;
+use hopter_conf_params::{DMA_REGION_LEN, DMA_REGION_START};
+use linked_list_allocator::Heap;
+
+/// The free list of the DMA region. It is initialized upon the first
+/// allocation from it.
+static HEAP: SpinSchedSafe<Heap> = SpinSchedSafe::new(Heap::empty());
+
+/// A handle to the allocator of the DMA region.
+#[derive(Clone, Copy)]
+pub struct DmaHeap {
+    _private: (),
+}
+
+impl DmaHeap {
+    /// Return the allocator of the DMA region. Return `None` if no region is
+    /// reserved.
+    pub fn get() -> Option<Self> {
+        if DMA_REGION_LEN == 0 {
+            return None;
+        }
+        Some(Self { _private: () })
+    }
+
+    /// Allocate a zeroed buffer of `len` bytes from the DMA region. Return
+    /// `None` if the region does not have enough free memory.
+    pub fn buffer(self, len: usize) -> Option<Box<[u8], Self>> {
+        let mut buf = Vec::new_in(self);
+        buf.try_reserve_exact(len).ok()?;
+        buf.resize(len, 0);
+        Some(buf.into_boxed_slice())
+    }
+}
+
+unsafe impl Allocator for DmaHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut heap = HEAP.lock();
+
+        if heap.size() == 0 {
+            // Safety: The region is excluded from all heap regions and is not
+            // touched by the linker or the kernel.
+            unsafe { heap.init(DMA_REGION_START as *mut u8, DMA_REGION_LEN as usize) };
+        }
+
+        heap.allocate_first_fit(layout)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .map_err(|_| AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        HEAP.lock().deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,244 @@
+//! The BME280 temperature, humidity, and pressure sensor on I2C1.
+//!
+//! The sensor is not on the boards. Breakout boards wire it to the bus
+//! through their SDA and SCL pins, with pull-up resistors, and answer at
+//! [`ADDRESS_PRIMARY`] or, with their SDO pin pulled high,
+//! [`ADDRESS_SECONDARY`].
+//!
+//! The driver does not own the bus, which other devices and tasks may share.
+//! Each method takes it for the duration of the call instead. A measurement
+//! is started with [`Bme280::start`] and read with [`Bme280::read`] once
+//! [`MEASUREMENT_TIME_MS`] has passed, so the bus is free in between. The
+//! sensor measures once per start, in its forced mode, and sleeps otherwise.
+//!
+//! The raw values are compensated with the calibration values written in
+//! the sensor at the factory, following the integer formulas of the
+//! datasheet.
+
+use core::fmt;
+use stm32f4xx_hal::{
+    i2c::{self, I2c},
+    pac::I2C1,
+};
+
+/// The I2C address of the sensor with its SDO pin pulled low.
+pub const ADDRESS_PRIMARY: u8 = 0x76;
+
+/// The I2C address of the sensor with its SDO pin pulled high.
+pub const ADDRESS_SECONDARY: u8 = 0x77;
+
+/// The longest time a measurement takes with the oversampling used.
+pub const MEASUREMENT_TIME_MS: u32 = 10;
+
+/// The value of the `Id` register.
+const CHIP_ID: u8 = 0x60;
+
+/// The registers used by the driver.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Register {
+    /// The first of the 26 bytes of calibration values for the temperature
+    /// and the pressure, followed by the first one for the humidity.
+    Calib00 = 0x88,
+    Id = 0xd0,
+    /// The first of the 7 other bytes of calibration values for the humidity.
+    Calib26 = 0xe1,
+    CtrlHum = 0xf2,
+    CtrlMeas = 0xf4,
+    Config = 0xf5,
+    /// The first of the 8 bytes of raw values, pressure, temperature, and
+    /// humidity, most significant byte first.
+    PressMsb = 0xf7,
+}
+
+/// The value of `CtrlHum` sampling the humidity once.
+const HUMIDITY_X1: u8 = 0x01;
+
+/// The value of `CtrlMeas` sampling the temperature and the pressure once,
+/// in the forced mode.
+const FORCED_X1: u8 = 0x25;
+
+/// A measurement.
+#[derive(Clone, Copy)]
+pub struct Measurement {
+    /// The temperature in hundredths of a degree Celsius.
+    pub centi_celsius: i32,
+    /// The relative humidity in hundredths of a percent.
+    pub centi_percent_rh: u32,
+    /// The pressure in pascals.
+    pub pascals: u32,
+}
+
+/// The errors of the sensor.
+#[derive(Debug)]
+pub enum Error {
+    /// The I2C transaction failed.
+    I2c(i2c::Error),
+    /// Another chip answered at the address, with the given ID register.
+    UnknownChip(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::I2c(err) => write!(f, "I2C error {:?}", err),
+            Self::UnknownChip(id) => write!(f, "unknown chip ID {:#04x}", id),
+        }
+    }
+}
+
+impl From<i2c::Error> for Error {
+    fn from(err: i2c::Error) -> Self {
+        Self::I2c(err)
+    }
+}
+
+/// The calibration values, named after the datasheet.
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+/// The sensor.
+pub struct Bme280 {
+    address: u8,
+    calibration: Calibration,
+}
+
+impl Bme280 {
+    /// Check that the sensor answers at the address, read its calibration
+    /// values, and configure the oversampling.
+    pub fn new(i2c: &mut I2c<I2C1>, address: u8) -> Result<Self, Error> {
+        let mut id = [0];
+        read(i2c, address, Register::Id, &mut id)?;
+        if id[0] != CHIP_ID {
+            return Err(Error::UnknownChip(id[0]));
+        }
+
+        let mut c = [0; 26];
+        read(i2c, address, Register::Calib00, &mut c)?;
+        let mut h = [0; 7];
+        read(i2c, address, Register::Calib26, &mut h)?;
+
+        let u16_at = |i: usize| u16::from_le_bytes([c[i], c[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([c[i], c[i + 1]]);
+        let calibration = Calibration {
+            t1: u16_at(0),
+            t2: i16_at(2),
+            t3: i16_at(4),
+            p1: u16_at(6),
+            p2: i16_at(8),
+            p3: i16_at(10),
+            p4: i16_at(12),
+            p5: i16_at(14),
+            p6: i16_at(16),
+            p7: i16_at(18),
+            p8: i16_at(20),
+            p9: i16_at(22),
+            h1: c[25],
+            h2: i16::from_le_bytes([h[0], h[1]]),
+            h3: h[2],
+            // Two 12-bit values share the middle byte.
+            h4: (i16::from(h[3] as i8) << 4) | i16::from(h[4] & 0x0f),
+            h5: (i16::from(h[5] as i8) << 4) | i16::from(h[4] >> 4),
+            h6: h[6] as i8,
+        };
+
+        // The filter is off, and the humidity setting takes effect upon the
+        // next write to `CtrlMeas`.
+        i2c.write(address, &[Register::Config as u8, 0x00])?;
+        i2c.write(address, &[Register::CtrlHum as u8, HUMIDITY_X1])?;
+
+        Ok(Self {
+            address,
+            calibration,
+        })
+    }
+
+    /// Start a measurement, which is ready after [`MEASUREMENT_TIME_MS`].
+    pub fn start(&self, i2c: &mut I2c<I2C1>) -> Result<(), Error> {
+        Ok(i2c.write(self.address, &[Register::CtrlMeas as u8, FORCED_X1])?)
+    }
+
+    /// Read the last measurement.
+    pub fn read(&self, i2c: &mut I2c<I2C1>) -> Result<Measurement, Error> {
+        let mut raw = [0; 8];
+        read(i2c, self.address, Register::PressMsb, &mut raw)?;
+
+        let adc_p = (i32::from(raw[0]) << 12) | (i32::from(raw[1]) << 4) | (i32::from(raw[2]) >> 4);
+        let adc_t = (i32::from(raw[3]) << 12) | (i32::from(raw[4]) << 4) | (i32::from(raw[5]) >> 4);
+        let adc_h = (i32::from(raw[6]) << 8) | i32::from(raw[7]);
+
+        let t_fine = self.t_fine(adc_t);
+        Ok(Measurement {
+            centi_celsius: (t_fine * 5 + 128) >> 8,
+            centi_percent_rh: self.humidity(adc_h, t_fine) * 100 / 1024,
+            pascals: self.pressure(adc_p, t_fine) / 256,
+        })
+    }
+
+    /// Return the fine temperature, on which the other values depend.
+    fn t_fine(&self, adc_t: i32) -> i32 {
+        let c = &self.calibration;
+        let t1 = i32::from(c.t1);
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * i32::from(c.t2)) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * i32::from(c.t3)) >> 14;
+        var1 + var2
+    }
+
+    /// Return the pressure in 1/256 Pa.
+    fn pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let c = &self.calibration;
+        let mut var1 = i64::from(t_fine) - 128000;
+        let mut var2 = var1 * var1 * i64::from(c.p6);
+        var2 += (var1 * i64::from(c.p5)) << 17;
+        var2 += i64::from(c.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(c.p3)) >> 8) + ((var1 * i64::from(c.p2)) << 12);
+        var1 = (((1i64 << 47) + var1) * i64::from(c.p1)) >> 33;
+        if var1 == 0 {
+            // Avoid dividing by zero.
+            return 0;
+        }
+        let mut p = 1048576 - i64::from(adc_p);
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (i64::from(c.p9) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (i64::from(c.p8) * p) >> 19;
+        (((p + var1 + var2) >> 8) + (i64::from(c.p7) << 4)) as u32
+    }
+
+    /// Return the relative humidity in 1/1024 %.
+    fn humidity(&self, adc_h: i32, t_fine: i32) -> u32 {
+        let c = &self.calibration;
+        let (h1, h2, h3) = (i32::from(c.h1), i32::from(c.h2), i32::from(c.h3));
+        let (h4, h5, h6) = (i32::from(c.h4), i32::from(c.h5), i32::from(c.h6));
+
+        let x = t_fine - 76800;
+        let a = ((adc_h << 14) - (h4 << 20) - (h5 * x) + 16384) >> 15;
+        let b =
+            ((((((x * h6) >> 10) * (((x * h3) >> 11) + 32768)) >> 10) + 2097152) * h2 + 8192) >> 14;
+        let mut v = a * b;
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * h1) >> 4;
+        (v.clamp(0, 419430400) >> 12) as u32
+    }
+}
+
+/// Read consecutive registers starting at the given one.
+fn read(i2c: &mut I2c<I2C1>, address: u8, register: Register, buf: &mut [u8]) -> Result<(), Error> {
+    Ok(i2c.write_read(address, &[register as u8], buf)?)
+}
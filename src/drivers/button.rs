@@ -0,0 +1,143 @@
+//! A push button raising an EXTI IRQ, debounced in task context.
+//!
+//! A [`DebouncedButton`] lives in a static so that both the IRQ handler of
+//! its EXTI line and a task can reach it. The handler calls
+//! [`DebouncedButton::handle_irq`], which acknowledges the IRQ and notifies a
+//! mailbox upon every edge that could start a press. A task blocks in
+//! [`DebouncedButton::wait_for_press`] or
+//! [`DebouncedButton::wait_for_long_press`], which wake up on the
+//! notifications and filter out the bounces.
+//!
+//! An edge is accepted as a press only if it comes at least the debounce
+//! window after the previous press, and if the button still reads pressed
+//! once the contact settles. Only one task may wait on a button at a time.
+//!
+//! The caller sets the NVIC priority of the EXTI line and unmasks it, as for
+//! any other IRQ.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use hopter::{
+    interrupt::mask::RecursivelyMaskable,
+    sync::{Mailbox, SpinIrqSafe},
+    time,
+};
+use stm32f4xx_hal::{
+    gpio::{Edge, ExtiPin, ReadPin},
+    pac::EXTI,
+    syscfg::SysCfg,
+};
+
+/// The time for the contact to settle before the button is read.
+pub const SETTLE_MS: u32 = 20;
+
+/// The interval at which a held button is read by
+/// [`DebouncedButton::wait_for_long_press`].
+pub const HOLD_POLL_MS: u32 = 10;
+
+/// A button on the pin `P` raising the IRQ `I`, generated by `irq!`.
+pub struct DebouncedButton<P, I: RecursivelyMaskable> {
+    /// The pin. It is `None` until [`init`](Self::init) is called. The IRQ
+    /// is masked when the lock is held.
+    pin: SpinIrqSafe<Option<P>, I>,
+    /// Notified upon every edge starting a press, bounces included.
+    edges: Mailbox,
+    /// Whether the pin reads high when the button is pressed.
+    active_high: bool,
+    /// The edges within this time after a press are bounces.
+    debounce_ms: u32,
+    /// The tick of the last press, valid if `pressed_before` is set.
+    last_press: AtomicU32,
+    pressed_before: AtomicBool,
+}
+
+impl<P, I> DebouncedButton<P, I>
+where
+    P: ExtiPin + ReadPin,
+    I: RecursivelyMaskable,
+{
+    /// Create a button reading high or low when pressed, as given by
+    /// `active_high`, and ignoring the edges within `debounce_ms` after a
+    /// press.
+    pub const fn new(active_high: bool, debounce_ms: u32) -> Self {
+        Self {
+            pin: SpinIrqSafe::new(None),
+            edges: Mailbox::new(),
+            active_high,
+            debounce_ms,
+            last_press: AtomicU32::new(0),
+            pressed_before: AtomicBool::new(false),
+        }
+    }
+
+    /// Route the pin to its EXTI line, and raise the IRQ upon the edges at
+    /// which the button gets pressed. The pin must already be an input.
+    pub fn init(&self, mut pin: P, syscfg: &mut SysCfg, exti: &mut EXTI) {
+        let edge = if self.active_high {
+            Edge::Rising
+        } else {
+            Edge::Falling
+        };
+        pin.make_interrupt_source(syscfg);
+        pin.trigger_on_edge(exti, edge);
+        pin.enable_interrupt(exti);
+        *self.pin.lock() = Some(pin);
+    }
+
+    /// Acknowledge the IRQ and wake up the waiting task. Must be called by
+    /// the handler of the EXTI line.
+    pub fn handle_irq(&self) {
+        self.pin
+            .lock()
+            .as_mut()
+            .unwrap()
+            .clear_interrupt_pending_bit();
+        self.edges.notify_allow_isr();
+    }
+
+    /// Return whether the button reads pressed.
+    pub fn is_pressed(&self) -> bool {
+        let high = self.pin.lock().as_ref().unwrap().is_high();
+        high == self.active_high
+    }
+
+    /// Block until the button is pressed.
+    pub fn wait_for_press(&self) {
+        loop {
+            self.edges.wait();
+
+            let now = time::get_tick();
+            if self.pressed_before.load(Ordering::SeqCst)
+                && now.wrapping_sub(self.last_press.load(Ordering::SeqCst)) < self.debounce_ms
+            {
+                continue;
+            }
+
+            time::sleep_ms(SETTLE_MS).unwrap();
+            if !self.is_pressed() {
+                continue;
+            }
+
+            self.last_press.store(time::get_tick(), Ordering::SeqCst);
+            self.pressed_before.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+
+    /// Block until the button is pressed and held for at least `hold_ms`.
+    /// Shorter presses are ignored. Return while the button is still held.
+    // Not used by the quick start itself, which only cycles on presses.
+    #[allow(dead_code)]
+    pub fn wait_for_long_press(&self, hold_ms: u32) {
+        loop {
+            self.wait_for_press();
+
+            let start = time::get_tick();
+            while self.is_pressed() {
+                if time::get_tick().wrapping_sub(start) >= hold_ms {
+                    return;
+                }
+                time::sleep_ms(HOLD_POLL_MS).unwrap();
+            }
+        }
+    }
+}
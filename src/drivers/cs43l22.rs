@@ -0,0 +1,235 @@
+//! The CS43L22 audio DAC on STM32F407 and STM32F411 Discovery.
+//!
+//! The codec has two interfaces. [`Cs43l22`] reaches its registers through
+//! I2C1 to configure it, power it, and set the volume. [`I2sOut`] streams the
+//! samples to it through SPI3 in I2S mode, as the I2S master also providing
+//! the master clock the codec runs from. A DMA stream feeds [`I2sOut`], which
+//! implements the HAL's DMA traits for DMA1 stream 5.
+//!
+//! The codec expects 16-bit stereo samples in the Philips I2S format, the
+//! left channel first. It drives the headphone jack only.
+//!
+//! The master clock must be running when the codec is powered up with
+//! [`Cs43l22::play`], so start the I2S stream first.
+
+use core::fmt;
+use hopter::time;
+use stm32f4xx_hal::{
+    dma::{
+        traits::{DMASet, PeriAddress},
+        MemoryToPeripheral, Stream5,
+    },
+    gpio::{Output, PD4},
+    i2c::{self, I2c},
+    i2s::{
+        stm32_i2s_v12x::{
+            driver::{DataFormat, I2sDriver, I2sDriverConfig},
+            marker::{Master, Philips, Transmit},
+        },
+        I2s,
+    },
+    pac::{DMA1, I2C1, SPI3},
+};
+
+/// The I2C address of the codec, with its AD0 pin pulled low on the board.
+pub const I2C_ADDRESS: u8 = 0x4a;
+
+/// The chip ID in the upper five bits of the ID register.
+const CHIP_ID: u8 = 0b11100;
+
+/// The registers used by the driver.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Register {
+    /// The registers 0x00, 0x32, and 0x47 are written by the start-up
+    /// sequence of the datasheet and are not documented otherwise.
+    Magic00 = 0x00,
+    Id = 0x01,
+    PowerCtl1 = 0x02,
+    PowerCtl2 = 0x04,
+    ClockingCtl = 0x05,
+    InterfaceCtl1 = 0x06,
+    MiscCtl = 0x0e,
+    MasterAVol = 0x20,
+    MasterBVol = 0x21,
+    HeadphoneAVol = 0x22,
+    HeadphoneBVol = 0x23,
+    Magic32 = 0x32,
+    Magic47 = 0x47,
+}
+
+/// The value of `PowerCtl1` powering the codec down or up.
+const POWER_DOWN: u8 = 0x01;
+const POWER_UP: u8 = 0x9e;
+
+/// The value of `PowerCtl2` turning on the headphone and off the speaker.
+const HEADPHONE_ONLY: u8 = 0xaf;
+
+/// The value of `PowerCtl2` turning off both outputs.
+const OUTPUTS_OFF: u8 = 0xff;
+
+/// The errors of the codec.
+#[derive(Debug)]
+pub enum Error {
+    /// The I2C transaction failed.
+    I2c(i2c::Error),
+    /// Another chip answered at the address, with the given ID register.
+    UnknownChip(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::I2c(err) => write!(f, "I2C error {:?}", err),
+            Self::UnknownChip(id) => write!(f, "unknown chip ID {:#04x}", id),
+        }
+    }
+}
+
+impl From<i2c::Error> for Error {
+    fn from(err: i2c::Error) -> Self {
+        Self::I2c(err)
+    }
+}
+
+/// The control interface of the codec.
+pub struct Cs43l22 {
+    i2c: I2c<I2C1>,
+    /// Holds the codec in reset when low.
+    reset: PD4<Output>,
+}
+
+impl Cs43l22 {
+    /// Take over the bus and the reset pin. The codec is left untouched until
+    /// [`init`](Self::init) is called.
+    pub fn new(i2c: I2c<I2C1>, reset: PD4<Output>) -> Self {
+        Self { i2c, reset }
+    }
+
+    /// Give back the bus and the reset pin. The codec keeps its state.
+    pub fn release(self) -> (I2c<I2C1>, PD4<Output>) {
+        (self.i2c, self.reset)
+    }
+
+    /// Release the codec from reset, check that it answers, and configure it
+    /// as an I2S slave with the volume at `volume` percent. The codec stays
+    /// powered down until [`play`](Self::play) is called.
+    pub fn init(&mut self, volume: u8) -> Result<(), Error> {
+        self.reset.set_low();
+        time::sleep_ms(1).unwrap();
+        self.reset.set_high();
+        time::sleep_ms(1).unwrap();
+
+        let id = self.read(Register::Id)?;
+        if id >> 3 != CHIP_ID {
+            return Err(Error::UnknownChip(id));
+        }
+
+        self.write(Register::PowerCtl1, POWER_DOWN)?;
+        self.write(Register::PowerCtl2, HEADPHONE_ONLY)?;
+        // Detect the speed from the master clock.
+        self.write(Register::ClockingCtl, 0x80)?;
+        // Slave mode, I2S format.
+        self.write(Register::InterfaceCtl1, 0x04)?;
+
+        // The start-up sequence of the datasheet.
+        self.write(Register::Magic00, 0x99)?;
+        self.write(Register::Magic47, 0x80)?;
+        let magic = self.read(Register::Magic32)?;
+        self.write(Register::Magic32, magic | 0x80)?;
+        self.write(Register::Magic32, magic & !0x80)?;
+        self.write(Register::Magic00, 0x00)?;
+
+        self.set_volume(volume)
+    }
+
+    /// Set the master volume in percent, from -102 dB at 0 to +12 dB at 100.
+    /// The gain is 0 dB at 91.
+    pub fn set_volume(&mut self, volume: u8) -> Result<(), Error> {
+        // The register holds the gain in steps of 0.5 dB, from -102 dB at
+        // 0x19 to +12 dB at 0x18, wrapping around through 0x00 at 0 dB.
+        let level = u32::from(volume.min(100)) * 0xff / 100;
+        let value = if level > 0xe6 {
+            level - 0xe7
+        } else {
+            level + 0x19
+        };
+        self.write(Register::MasterAVol, value as u8)?;
+        self.write(Register::MasterBVol, value as u8)
+    }
+
+    /// Power up the codec and unmute the headphone. The master clock must be
+    /// running.
+    pub fn play(&mut self) -> Result<(), Error> {
+        // Ramp the volume changes to avoid clicks.
+        self.write(Register::MiscCtl, 0x06)?;
+        self.write(Register::PowerCtl2, HEADPHONE_ONLY)?;
+        self.write(Register::HeadphoneAVol, 0x00)?;
+        self.write(Register::HeadphoneBVol, 0x00)?;
+        self.write(Register::PowerCtl1, POWER_UP)
+    }
+
+    /// Mute the headphone and power down the codec.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.write(Register::HeadphoneAVol, 0x01)?;
+        self.write(Register::HeadphoneBVol, 0x01)?;
+        self.write(Register::PowerCtl2, OUTPUTS_OFF)?;
+        self.write(Register::PowerCtl1, POWER_DOWN)
+    }
+
+    fn write(&mut self, register: Register, value: u8) -> Result<(), Error> {
+        Ok(self.i2c.write(I2C_ADDRESS, &[register as u8, value])?)
+    }
+
+    fn read(&mut self, register: Register) -> Result<u8, Error> {
+        let mut value = [0];
+        self.i2c
+            .write_read(I2C_ADDRESS, &[register as u8], &mut value)?;
+        Ok(value[0])
+    }
+}
+
+/// The I2S stream to the codec, written by DMA.
+pub struct I2sOut {
+    driver: I2sDriver<I2s<SPI3>, Master, Transmit, Philips>,
+}
+
+impl I2sOut {
+    /// Set up SPI3 as the I2S master at the sample rate closest to
+    /// `sample_rate_hz` that the I2S clock allows. The stream starts when the
+    /// DMA transfer starts, see [`enable`](Self::enable).
+    pub fn new(i2s: I2s<SPI3>, sample_rate_hz: u32) -> Self {
+        let mut driver = I2sDriverConfig::new_master()
+            .transmit()
+            .standard(Philips)
+            .data_format(DataFormat::Data16Channel16)
+            .master_clock(true)
+            .request_frequency(sample_rate_hz)
+            .i2s_driver(i2s);
+        driver.set_tx_dma(true);
+        Self { driver }
+    }
+
+    /// Return the actual sample rate.
+    pub fn sample_rate(&self) -> u32 {
+        self.driver.sample_rate()
+    }
+
+    /// Start the clocks and the stream. Call it once the DMA stream is
+    /// enabled, e.g., in the closure given to `Transfer::start`.
+    pub fn enable(&mut self) {
+        self.driver.enable();
+    }
+}
+
+// Safety: The address is that of the data register, which takes 16 bits.
+unsafe impl PeriAddress for I2sOut {
+    type MemSize = u16;
+
+    fn address(&self) -> u32 {
+        self.driver.data_register_address()
+    }
+}
+
+// Safety: SPI3_TX is routed to channel 0 of DMA1 stream 5.
+unsafe impl DMASet<Stream5<DMA1>, 0, MemoryToPeripheral> for I2sOut {}
@@ -0,0 +1,159 @@
+//! The LIS3DSH accelerometer on STM32F407 Discovery.
+//!
+//! The accelerometer sits on SPI1, with SCK on PA5, MISO on PA6, MOSI on PA7,
+//! and its chip select on PE3. [`Lis3dsh::new`] configures it to measure the
+//! three axes at [`OUTPUT_RATE_HZ`] in the ±2 g range, and to pulse its INT1
+//! pin, wired to PE0, whenever a new measurement is ready. The caller routes
+//! PE0 to its EXTI line and reads the measurement with
+//! [`Lis3dsh::acceleration`] from the task woken by the IRQ. Reading it
+//! clears the data-ready signal.
+//!
+//! Older STM32F407 Discovery boards, marked MB997B, carry a LIS302DL instead,
+//! which [`Lis3dsh::new`] rejects. STM32F411 Discovery carries an LSM303DLHC
+//! on I2C1.
+
+use core::fmt;
+use stm32f4xx_hal::{
+    gpio::{Output, PE3},
+    hal::spi::{Mode, MODE_3},
+    pac::SPI1,
+    spi::{self, Spi},
+};
+
+/// The SPI mode of the accelerometer.
+pub const SPI_MODE: Mode = MODE_3;
+
+/// The rate of the measurements.
+pub const OUTPUT_RATE_HZ: u32 = 25;
+
+/// The value of the `WhoAmI` register.
+const WHO_AM_I: u8 = 0x3f;
+
+/// Set in the address byte to read the register rather than write it.
+const READ: u8 = 0x80;
+
+/// The acceleration in mg per least significant bit, times 100, in the ±2 g
+/// range.
+const SENSITIVITY: i32 = 6;
+
+/// The registers used by the driver.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Register {
+    WhoAmI = 0x0f,
+    CtrlReg4 = 0x20,
+    CtrlReg3 = 0x23,
+    CtrlReg5 = 0x24,
+    CtrlReg6 = 0x25,
+    /// The first of the six output registers, the low byte of the X axis.
+    OutXL = 0x28,
+}
+
+/// The value of `CtrlReg4` measuring the three axes at 25 Hz, with the output
+/// registers updated only once both of their bytes have been read.
+const MEASURE_XYZ: u8 = 0x4f;
+
+/// The value of `CtrlReg3` driving INT1 high when a measurement is ready.
+const DATA_READY_INT1: u8 = 0xc8;
+
+/// The value of `CtrlReg6` incrementing the address during multi-byte
+/// accesses.
+const ADDRESS_INCREMENT: u8 = 0x10;
+
+/// The errors of the accelerometer.
+#[derive(Debug)]
+pub enum Error {
+    /// The SPI transaction failed.
+    Spi(spi::Error),
+    /// Another chip answered, with the given `WhoAmI` register.
+    UnknownChip(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Spi(err) => write!(f, "SPI error {:?}", err),
+            Self::UnknownChip(id) => write!(f, "unknown chip ID {:#04x}", id),
+        }
+    }
+}
+
+impl From<spi::Error> for Error {
+    fn from(err: spi::Error) -> Self {
+        Self::Spi(err)
+    }
+}
+
+/// The acceleration along the three axes in mg. The axes are printed on the
+/// board next to the chip.
+#[derive(Clone, Copy, Debug)]
+pub struct Acceleration {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+/// The accelerometer.
+pub struct Lis3dsh {
+    /// Configured with [`SPI_MODE`] at up to 10 MHz.
+    spi: Spi<SPI1>,
+    /// Selects the chip when low.
+    cs: PE3<Output>,
+}
+
+impl Lis3dsh {
+    /// Check that the accelerometer answers, and start the measurements with
+    /// the data-ready signal on INT1.
+    pub fn new(spi: Spi<SPI1>, mut cs: PE3<Output>) -> Result<Self, Error> {
+        cs.set_high();
+        let mut accel = Self { spi, cs };
+
+        let id = accel.read(Register::WhoAmI)?;
+        if id != WHO_AM_I {
+            return Err(Error::UnknownChip(id));
+        }
+
+        accel.write(Register::CtrlReg6, ADDRESS_INCREMENT)?;
+        // The ±2 g range.
+        accel.write(Register::CtrlReg5, 0x00)?;
+        accel.write(Register::CtrlReg3, DATA_READY_INT1)?;
+        accel.write(Register::CtrlReg4, MEASURE_XYZ)?;
+        Ok(accel)
+    }
+
+    /// Read the latest measurement.
+    pub fn acceleration(&mut self) -> Result<Acceleration, Error> {
+        let mut bytes = [0; 7];
+        bytes[0] = Register::OutXL as u8 | READ;
+        self.transfer(&mut bytes)?;
+
+        let axis = |i: usize| {
+            let raw = i16::from_le_bytes([bytes[1 + 2 * i], bytes[2 + 2 * i]]);
+            i32::from(raw) * SENSITIVITY / 100
+        };
+        Ok(Acceleration {
+            x: axis(0),
+            y: axis(1),
+            z: axis(2),
+        })
+    }
+
+    fn write(&mut self, register: Register, value: u8) -> Result<(), Error> {
+        self.transfer(&mut [register as u8, value])
+    }
+
+    fn read(&mut self, register: Register) -> Result<u8, Error> {
+        let mut bytes = [register as u8 | READ, 0];
+        self.transfer(&mut bytes)?;
+        Ok(bytes[1])
+    }
+
+    /// Send the bytes with the chip selected, replacing them with those
+    /// received.
+    fn transfer(&mut self, bytes: &mut [u8]) -> Result<(), Error> {
+        self.cs.set_low();
+        let result = self.spi.transfer_in_place(bytes);
+        self.cs.set_high();
+        Ok(result?)
+    }
+}
@@ -0,0 +1,19 @@
+//! Reusable drivers combining board peripherals with Hopter primitives.
+
+pub mod bme280;
+pub mod button;
+pub mod cs43l22;
+pub mod mp45dt02;
+#[cfg(not(feature = "static-alloc"))]
+pub mod st7789;
+pub mod ws2812;
+
+// The `lis3dsh` driver is only used by `examples/tilt_leds.rs`, which
+// includes it by path, because the accelerometer interrupt shares EXTI0 with
+// the user button of the tutorial.
+
+// The `servo` driver is only used by `examples/servo_sweep.rs`, which
+// includes it by path, as the tutorial has no servo to drive.
+
+// The `rng` driver is only used by `examples/rng.rs`, which includes it by
+// path, as the tutorial draws no random numbers.
@@ -0,0 +1,327 @@
+//! The MEMS microphone on STM32F407 and STM32F411 Discovery.
+//!
+//! The microphone, an MP45DT02 on STM32F407 Discovery and an MP34DT01 on
+//! STM32F411 Discovery, outputs pulse density modulation (PDM): one bit per
+//! clock cycle, whose density of ones follows the sound pressure. SPI2 in
+//! I2S master mode clocks it at [`PDM_CLOCK_HZ`] on PB10 and shifts in the
+//! bits on PC3. I2S also needs a word select pin, PB12, which the microphone
+//! does not use.
+//!
+//! DMA1 stream 3 moves the bits into two buffers in double buffer mode, and
+//! its IRQ notifies the `pdm` task whenever a buffer is full. The task
+//! decimates the bits into 16-bit PCM samples at [`PCM_RATE_HZ`] while the
+//! DMA fills the other buffer, and sends them in frames of [`FRAME_LEN`]
+//! samples through a channel, whose consuming end is returned by [`init`].
+//! Frames are dropped and counted while the channel is full.
+//!
+//! The decimation counts the ones in each group of [`DECIMATION`] bits, a
+//! first-order CIC filter, then removes the DC offset. It is good enough to
+//! measure the sound level, but it lets some noise alias into the audio
+//! band.
+
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use embedded_dma::WriteBuffer;
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mailbox, Producer, SpinIrqSafe},
+    task,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    dma::{
+        config::DmaConfig,
+        traits::{DMASet, PeriAddress, Stream},
+        CurrentBuffer, DmaFlag, PeripheralToMemory, Stream3, Transfer,
+    },
+    gpio::{NoPin, PB10, PB12, PC3},
+    i2s::{
+        stm32_i2s_v12x::{
+            driver::{DataFormat, I2sDriver, I2sDriverConfig},
+            marker::{Master, Msb, Receive},
+        },
+        I2s,
+    },
+    pac::{self, DMA1, SPI2},
+    rcc::Clocks,
+    ClearFlags,
+};
+
+use crate::{irq_nesting, stack_pool::SetStackPool, task_name::SetName};
+
+/// The rate of the PCM samples.
+pub const PCM_RATE_HZ: u32 = 16_000;
+
+/// The number of PDM bits per PCM sample.
+pub const DECIMATION: u32 = 64;
+
+/// The PDM clock, within the 1 to 3.25 MHz range of the microphone.
+pub const PDM_CLOCK_HZ: u32 = PCM_RATE_HZ * DECIMATION;
+
+/// The number of PCM samples in a frame, 10 ms at 16 kHz.
+pub const FRAME_LEN: usize = 160;
+
+/// The number of frames buffered before a task consumes them.
+pub const FRAME_QUEUE_LEN: usize = 4;
+
+/// A frame of PCM samples.
+pub type Frame = [i16; FRAME_LEN];
+
+/// The consuming end of the channel carrying the frames.
+pub type Frames = Consumer<Frame, FRAME_QUEUE_LEN>;
+
+/// The number of 16-bit words received per PCM sample.
+const WORDS_PER_SAMPLE: usize = DECIMATION as usize / 16;
+
+/// The number of words in each DMA buffer, which holds a frame.
+const BUFFER_WORDS: usize = FRAME_LEN * WORDS_PER_SAMPLE;
+
+/// A buffer written by the DMA.
+struct PdmStorage {
+    /// Set once the buffer has been handed to the DMA.
+    taken: AtomicBool,
+    words: UnsafeCell<[u16; BUFFER_WORDS]>,
+}
+
+// Safety: The words are only accessed through raw pointers, by the DMA and by
+// the `pdm` task.
+unsafe impl Sync for PdmStorage {}
+
+impl PdmStorage {
+    const fn new() -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            words: UnsafeCell::new([0; BUFFER_WORDS]),
+        }
+    }
+}
+
+static BUFFERS: [PdmStorage; 2] = [PdmStorage::new(), PdmStorage::new()];
+
+/// The right of the DMA to write one of [`BUFFERS`].
+struct PdmBuffer(usize);
+
+impl PdmBuffer {
+    /// Return the right to write the buffer at the index, or `None` if it was
+    /// already taken.
+    fn take(index: usize) -> Option<Self> {
+        if BUFFERS[index].taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(index))
+        }
+    }
+}
+
+// Safety: The buffers are statics, so their location is stable, and any bit
+// pattern is a valid `u16`.
+unsafe impl WriteBuffer for PdmBuffer {
+    type Word = u16;
+
+    unsafe fn write_buffer(&mut self) -> (*mut u16, usize) {
+        (BUFFERS[self.0].words.get().cast(), BUFFER_WORDS)
+    }
+}
+
+/// The I2S stream from the microphone, read by DMA.
+struct PdmIn {
+    driver: I2sDriver<I2s<SPI2>, Master, Receive, Msb>,
+}
+
+// Safety: The address is that of the data register, which takes 16 bits.
+unsafe impl PeriAddress for PdmIn {
+    type MemSize = u16;
+
+    fn address(&self) -> u32 {
+        self.driver.data_register_address()
+    }
+}
+
+// Safety: SPI2_RX is routed to channel 0 of DMA1 stream 3.
+unsafe impl DMASet<Stream3<DMA1>, 0, PeripheralToMemory> for PdmIn {}
+
+type PdmTransfer = Transfer<Stream3<DMA1>, 0, PdmIn, PeripheralToMemory, PdmBuffer>;
+
+irq!(Dma1Stream3Irq, pac::interrupt::DMA1_STREAM3);
+
+/// The running transfer. The DMA IRQ is masked when the lock is held.
+static TRANSFER: SpinIrqSafe<Option<PdmTransfer>, Dma1Stream3Irq> = SpinIrqSafe::new(None);
+
+/// Notified by the handler whenever a buffer is full.
+static FILLED: Mailbox = Mailbox::new();
+
+/// The number of buffers filled by the DMA.
+static FILLED_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// The number of buffers overwritten before being decimated.
+static OVERRUNS: AtomicU32 = AtomicU32::new(0);
+
+/// The number of frames dropped because the channel was full.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+/// Start clocking the microphone and spawn the `pdm` task. Return the
+/// consuming end of the channel carrying the frames. The I2S clock must be
+/// enabled in the clock configuration.
+pub fn init(
+    spi: SPI2,
+    ws: PB12,
+    ck: PB10,
+    sd: PC3,
+    stream: Stream3<DMA1>,
+    clocks: &Clocks,
+    nvic: &mut cortex_m::peripheral::NVIC,
+) -> Frames {
+    // Each I2S frame carries two 16-bit words, i.e., 32 bit clock cycles.
+    let i2s = I2s::new(spi, (ws, ck, NoPin::new(), sd), clocks);
+    let mut driver = I2sDriverConfig::new_master()
+        .receive()
+        .standard(Msb)
+        .data_format(DataFormat::Data16Channel16)
+        .request_frequency(PDM_CLOCK_HZ / 32)
+        .i2s_driver(i2s);
+    driver.set_rx_dma(true);
+
+    // Let the DMA alternate between the two buffers, raising the IRQ whenever
+    // it switches.
+    let dma_config = DmaConfig::default()
+        .memory_increment(true)
+        .double_buffer(true)
+        .transfer_complete_interrupt(true);
+    let mut transfer = Transfer::init_peripheral_to_memory(
+        stream,
+        PdmIn { driver },
+        PdmBuffer::take(0).unwrap(),
+        Some(PdmBuffer::take(1).unwrap()),
+        dma_config,
+    );
+    transfer.start(|pdm| pdm.driver.enable());
+    *TRANSFER.lock() = Some(transfer);
+
+    unsafe {
+        nvic.set_priority(pac::interrupt::DMA1_STREAM3, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::DMA1_STREAM3);
+    }
+
+    // A buffer must be decimated before the DMA fills the other one, so the
+    // task runs above the default priority.
+    let (producer, consumer) = sync::create_channel();
+    task::build()
+        .set_name_and_priority("pdm", config::DEFAULT_TASK_PRIORITY - 1)
+        .set_stack_pool(0)
+        .set_entry(move || decimate(producer))
+        .spawn()
+        .unwrap();
+
+    consumer
+}
+
+/// Return the number of buffers overwritten before being decimated.
+pub fn overruns() -> u32 {
+    OVERRUNS.load(Ordering::SeqCst)
+}
+
+/// Return the number of frames dropped because the channel was full.
+pub fn dropped() -> u32 {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// The filter turning PDM bits into PCM samples.
+struct Decimator {
+    /// The previous input and output of the DC-blocking filter.
+    last_in: i32,
+    last_out: i32,
+}
+
+impl Decimator {
+    /// The pole of the DC-blocking filter, 0.995 in Q15.
+    const POLE: i32 = 32604;
+
+    /// Return the PCM sample for the given PDM words.
+    fn sample(&mut self, words: [u16; WORDS_PER_SAMPLE]) -> i16 {
+        let ones: u32 = words.iter().map(|word| word.count_ones()).sum();
+
+        // Center the count on zero and scale it to the 16-bit range.
+        let x = (ones as i32 - DECIMATION as i32 / 2) * (i16::MAX as i32 / (DECIMATION as i32 / 2));
+
+        let y = x - self.last_in + ((Self::POLE * self.last_out) >> 15);
+        self.last_in = x;
+        self.last_out = y;
+        y.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
+
+fn decimate(producer: Producer<Frame, FRAME_QUEUE_LEN>) {
+    let mut decimator = Decimator {
+        last_in: 0,
+        last_out: 0,
+    };
+    let mut decimated: u32 = 0;
+
+    loop {
+        FILLED.wait();
+
+        // Only the buffer filled last is not being written by the DMA. Skip
+        // the others if the task fell behind.
+        let filled = FILLED_COUNT.load(Ordering::SeqCst);
+        let missed = filled.wrapping_sub(decimated);
+        if missed == 0 {
+            // A notification for a buffer already skipped.
+            continue;
+        }
+        OVERRUNS.fetch_add(missed - 1, Ordering::SeqCst);
+        decimated = filled;
+
+        // The buffer not being written is the one just filled.
+        let index = {
+            let mut transfer = TRANSFER.lock();
+            // Safety: The stream is only read.
+            let stream = unsafe { transfer.as_mut().unwrap().stream() };
+            match stream.current_buffer() {
+                CurrentBuffer::FirstBuffer => 1,
+                CurrentBuffer::SecondBuffer => 0,
+            }
+        };
+
+        let words = BUFFERS[index].words.get().cast::<u16>();
+        let mut frame = [0; FRAME_LEN];
+        for (i, sample) in frame.iter_mut().enumerate() {
+            // Safety: The index is within the buffer, which the DMA does not
+            // write until it switches to it. The read is volatile because
+            // the DMA writes the buffer.
+            let group = core::array::from_fn(|j| unsafe {
+                ptr::read_volatile(words.add(i * WORDS_PER_SAMPLE + j))
+            });
+            *sample = decimator.sample(group);
+        }
+
+        // The DMA starts writing this buffer again once it fills the other
+        // one, in which case the bits read may be torn.
+        if FILLED_COUNT.load(Ordering::SeqCst) != filled {
+            OVERRUNS.fetch_add(1, Ordering::SeqCst);
+            continue;
+        }
+
+        if producer.try_produce_allow_isr(frame).is_err() {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+#[handler(DMA1_STREAM3)]
+fn dma1_stream3_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    TRANSFER
+        .lock()
+        .as_mut()
+        .unwrap()
+        .clear_flags(DmaFlag::TransferComplete);
+
+    FILLED_COUNT.fetch_add(1, Ordering::SeqCst);
+    FILLED.notify_allow_isr();
+}
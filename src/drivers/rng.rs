@@ -0,0 +1,182 @@
+//! The true random number generator of the MCU, shared among tasks.
+//!
+//! The RNG peripheral turns analog noise into a 32-bit word every 40
+//! periods of the 48 MHz clock, i.e., within a microsecond, so a reader
+//! waits for it by polling. The clock must be on, e.g., through
+//! `require_pll48clk` when freezing the clocks.
+//!
+//! [`HwRng`] keeps the peripheral behind a mutex, so any number of tasks may
+//! draw from a shared reference. It runs the checks the reference manual
+//! asks of the software. The peripheral raises a seed error upon a run of
+//! more than 64 equal bits from the noise source, after which the generator
+//! is restarted and the words produced meanwhile discarded. It raises a
+//! clock error if its clock is too slow, which is not recovered from. The
+//! first word after enabling the generator is not used but kept, and every
+//! word is compared with the previous one, a repeated word being discarded,
+//! which is the continuous test of FIPS 140-2. The events are counted, see
+//! [`HwRng::errors`].
+//!
+//! `&HwRng` implements `rand_core::RngCore`, for the crates drawing from
+//! one. Its infallible methods retry after the recovered errors, and panic
+//! upon a clock error. It also implements `CryptoRng`. Keys and nonces
+//! should still be drawn only after a health check of the output, such as
+//! the one of `examples/rng.rs`.
+
+use hopter::sync::Mutex;
+use rand_core::{CryptoRng, RngCore};
+use stm32f4xx_hal::{pac::RNG, rcc::Clocks, rng::RngExt};
+
+/// The reasons a word is not returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The clock of the peripheral is too slow. See the module
+    /// documentation.
+    Clock,
+    /// The noise source got stuck. The generator was restarted.
+    Seed,
+    /// A word equal to the previous one was produced, and discarded.
+    Repeated,
+}
+
+impl Error {
+    /// Return whether drawing again may succeed.
+    pub fn is_recovered(self) -> bool {
+        self != Self::Clock
+    }
+}
+
+/// The errors met since the generator was created.
+#[derive(Clone, Copy, Default)]
+pub struct Errors {
+    pub clock: u32,
+    pub seed: u32,
+    pub repeated: u32,
+}
+
+struct State {
+    rng: RNG,
+    /// The word last produced, compared with the next one.
+    last: Option<u32>,
+    errors: Errors,
+}
+
+/// The random number generator.
+pub struct HwRng {
+    state: Mutex<State>,
+}
+
+impl HwRng {
+    /// Enable the peripheral. Panics if its clock is too slow, see the
+    /// module documentation.
+    pub fn new(rng: RNG, clocks: &Clocks) -> Self {
+        // The HAL enables the clock, checks its rate, and starts the
+        // generator.
+        let rng = rng.constrain(clocks).release();
+        Self {
+            state: Mutex::new(State {
+                rng,
+                last: None,
+                errors: Errors::default(),
+            }),
+        }
+    }
+
+    /// Return a random word.
+    pub fn next_u32(&self) -> Result<u32, Error> {
+        let mut state = self.state.lock();
+        // Keep the first word for the comparison only.
+        if state.last.is_none() {
+            let first = state.read()?;
+            state.last = Some(first);
+        }
+        let word = state.read()?;
+        if state.last.replace(word) == Some(word) {
+            state.errors.repeated += 1;
+            return Err(Error::Repeated);
+        }
+        Ok(word)
+    }
+
+    /// Fill `dest` with random bytes.
+    pub fn fill_bytes(&self, dest: &mut [u8]) -> Result<(), Error> {
+        for chunk in dest.chunks_mut(4) {
+            let word = self.next_u32()?.to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    /// Return the errors met so far.
+    pub fn errors(&self) -> Errors {
+        self.state.lock().errors
+    }
+
+    /// Return a random word, drawing again after the recovered errors.
+    /// Panics upon a clock error.
+    fn next_u32_retrying(&self) -> u32 {
+        loop {
+            match self.next_u32() {
+                Ok(word) => return word,
+                Err(err) if err.is_recovered() => continue,
+                Err(err) => panic!("RNG: {:?}", err),
+            }
+        }
+    }
+}
+
+impl State {
+    /// Wait for the next word from the peripheral.
+    fn read(&mut self) -> Result<u32, Error> {
+        loop {
+            let sr = self.rng.sr.read();
+            if sr.cecs().bit_is_set() {
+                self.errors.clock += 1;
+                return Err(Error::Clock);
+            }
+            if sr.secs().bit_is_set() {
+                self.errors.seed += 1;
+                self.restart();
+                return Err(Error::Seed);
+            }
+            if sr.drdy().bit_is_set() {
+                return Ok(self.rng.dr.read().bits());
+            }
+        }
+    }
+
+    /// Recover from a seed error, following the reference manual. The word
+    /// kept for the comparison is dropped, as a restarted generator starts
+    /// over with a word not to be used.
+    fn restart(&mut self) {
+        self.rng.sr.modify(|_, w| w.seis().clear_bit());
+        self.rng.cr.modify(|_, w| w.rngen().clear_bit());
+        self.rng.cr.modify(|_, w| w.rngen().set_bit());
+        self.last = None;
+    }
+}
+
+impl RngCore for &HwRng {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u32_retrying()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        u64::from(self.next_u32_retrying()) << 32 | u64::from(self.next_u32_retrying())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let word = self.next_u32_retrying().to_le_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        HwRng::fill_bytes(self, dest).map_err(|err| {
+            let code = rand_core::Error::CUSTOM_START + err as u32;
+            rand_core::Error::from(core::num::NonZeroU32::new(code).unwrap())
+        })
+    }
+}
+
+impl CryptoRng for &HwRng {}
@@ -0,0 +1,106 @@
+//! Hobby servos and ESCs driven by a 50 Hz PWM channel.
+//!
+//! A hobby servo takes a pulse every 20 ms, whose width sets the angle of its
+//! horn, from [`MIN_PULSE_US`] at one end of its travel to [`MAX_PULSE_US`]
+//! at the other by default. The speed controllers of brushless motors, i.e.,
+//! ESCs, take the same signal, the width setting the throttle.
+//!
+//! [`Servo`] drives the signal from any PWM channel implementing
+//! `SetDutyCycle`, e.g., a channel split from the `Pwm` of the HAL. The
+//! caller sets up the timer for a period of [`PERIOD_US`], e.g., with
+//! `pwm_us`, and enables the channel. The width is written as a fraction of
+//! the period, so the tick rate of the timer does not matter, though a
+//! coarse one limits the resolution.
+//!
+//! [`Servo::set_angle`] and [`Servo::set_pulse_us`] may be called from a
+//! task at any time while the timer runs. The HAL turns on the preload of
+//! the compare registers, so a new width only takes effect at the update
+//! event starting the next period. Without it, a width written during a
+//! pulse could end the pulse early or, once the counter is past the new
+//! width, stretch it over the whole period, which the servo would follow as
+//! a jerk of its horn.
+
+use stm32f4xx_hal::hal::pwm::SetDutyCycle;
+
+/// The period of the pulses.
+pub const PERIOD_US: u16 = 20_000;
+
+/// The default width at angle zero.
+pub const MIN_PULSE_US: u16 = 1000;
+
+/// The default width at the largest angle.
+pub const MAX_PULSE_US: u16 = 2000;
+
+/// The default travel of the horn in degrees.
+pub const TRAVEL_DEG: u16 = 180;
+
+/// A servo, or an ESC, on a PWM channel.
+pub struct Servo<P> {
+    pwm: P,
+    min_pulse_us: u16,
+    max_pulse_us: u16,
+    travel_deg: u16,
+    pulse_us: u16,
+}
+
+impl<P: SetDutyCycle> Servo<P> {
+    /// Take over the channel, and move the horn to the middle of its travel.
+    pub fn new(pwm: P) -> Result<Self, P::Error> {
+        let mut servo = Self {
+            pwm,
+            min_pulse_us: MIN_PULSE_US,
+            max_pulse_us: MAX_PULSE_US,
+            travel_deg: TRAVEL_DEG,
+            pulse_us: 0,
+        };
+        servo.set_angle(TRAVEL_DEG / 2)?;
+        Ok(servo)
+    }
+
+    /// Set the widths at both ends of the travel and the travel in degrees,
+    /// for servos departing from the defaults. The width currently output is
+    /// kept.
+    pub fn set_range(&mut self, min_pulse_us: u16, max_pulse_us: u16, travel_deg: u16) {
+        assert!(min_pulse_us < max_pulse_us && max_pulse_us < PERIOD_US);
+        assert!(travel_deg > 0);
+        self.min_pulse_us = min_pulse_us;
+        self.max_pulse_us = max_pulse_us;
+        self.travel_deg = travel_deg;
+    }
+
+    /// Move the horn to `deg` degrees from the end of angle zero, limited to
+    /// the travel.
+    pub fn set_angle(&mut self, deg: u16) -> Result<(), P::Error> {
+        let deg = u32::from(deg.min(self.travel_deg));
+        let span = u32::from(self.max_pulse_us - self.min_pulse_us);
+        let offset = span * deg / u32::from(self.travel_deg);
+        self.set_pulse_us(self.min_pulse_us + offset as u16)
+    }
+
+    /// Return the angle of the width currently output, rounded down.
+    pub fn angle(&self) -> u16 {
+        let span = u32::from(self.max_pulse_us - self.min_pulse_us);
+        let offset = u32::from(self.pulse_us.saturating_sub(self.min_pulse_us)).min(span);
+        (offset * u32::from(self.travel_deg) / span) as u16
+    }
+
+    /// Output pulses `pulse_us` wide, limited to the range set. An ESC takes
+    /// the width as the throttle, and is usually armed by holding it at the
+    /// lower end for a while.
+    pub fn set_pulse_us(&mut self, pulse_us: u16) -> Result<(), P::Error> {
+        let pulse_us = pulse_us.clamp(self.min_pulse_us, self.max_pulse_us);
+        self.pwm.set_duty_cycle_fraction(pulse_us, PERIOD_US)?;
+        self.pulse_us = pulse_us;
+        Ok(())
+    }
+
+    /// Return the width currently output.
+    pub fn pulse_us(&self) -> u16 {
+        self.pulse_us
+    }
+
+    /// Give the channel back.
+    pub fn release(self) -> P {
+        self.pwm
+    }
+}
@@ -0,0 +1,361 @@
+//! A 240x240 TFT display with an ST7789 controller on SPI1.
+//!
+//! Cheap display modules wire the controller's serial interface to a few
+//! pins: SCK to PA5, SDA (MOSI) to PA7, CS to PE7, DC to PE8, and RES to
+//! PE9. The controller is write-only here, so MISO is left unconnected and
+//! SPI1 runs in its transmit-only bidirectional mode. Modules without a CS
+//! pin, which expect [`SPI_MODE`], work as well.
+//!
+//! [`St7789::draw`] sends a whole frame. The pixels are pushed by DMA2
+//! stream 3 in bands of [`BAND_ROWS`] rows, from two buffers in the internal
+//! SRAM: while the DMA sends one band, the caller renders the next one into
+//! the other buffer. The DMA IRQ notifies the drawing task when a band has
+//! been sent, so the task sleeps in the meantime.
+//!
+//! The pixels are in the RGB565 format, sent most significant byte first.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use embedded_dma::ReadBuffer;
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{Mailbox, SpinIrqSafe},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    dma::{
+        config::DmaConfig,
+        traits::{DMASet, PeriAddress},
+        DmaFlag, MemoryToPeripheral, Stream3, Transfer,
+    },
+    gpio::{Output, PE7, PE8, PE9},
+    hal::spi::{Mode, MODE_3},
+    pac::{self, DMA2, SPI1},
+    spi::{self, Spi},
+    ClearFlags,
+};
+
+use crate::irq_nesting;
+
+/// The SPI mode of the controller.
+pub const SPI_MODE: Mode = MODE_3;
+
+/// The width of the display in pixels.
+pub const WIDTH: usize = 240;
+
+/// The height of the display in pixels.
+pub const HEIGHT: usize = 240;
+
+/// The number of rows in each band pushed by DMA.
+pub const BAND_ROWS: usize = 8;
+
+/// The number of bytes in a row.
+pub const ROW_BYTES: usize = WIDTH * 2;
+
+/// The number of bytes in each band buffer.
+const BAND_BYTES: usize = ROW_BYTES * BAND_ROWS;
+
+const _: () = assert!(HEIGHT % BAND_ROWS == 0);
+
+/// The commands used by the driver.
+#[derive(Clone, Copy)]
+#[repr(u8)]
+enum Command {
+    SoftwareReset = 0x01,
+    SleepOut = 0x11,
+    NormalMode = 0x13,
+    InversionOn = 0x21,
+    DisplayOn = 0x29,
+    ColumnAddress = 0x2a,
+    RowAddress = 0x2b,
+    MemoryWrite = 0x2c,
+    MemoryAccess = 0x36,
+    PixelFormat = 0x3a,
+}
+
+/// The value of `PixelFormat` selecting 16 bits per pixel.
+const RGB565: u8 = 0x55;
+
+/// The errors of the display.
+#[derive(Debug)]
+pub enum Error {
+    /// The SPI transfer failed.
+    Spi(spi::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Spi(err) => write!(f, "SPI error {:?}", err),
+        }
+    }
+}
+
+impl From<spi::Error> for Error {
+    fn from(err: spi::Error) -> Self {
+        Self::Spi(err)
+    }
+}
+
+/// A band buffer read by the DMA.
+struct BandStorage {
+    /// Set once the buffer has been handed out.
+    taken: AtomicBool,
+    bytes: UnsafeCell<[u8; BAND_BYTES]>,
+}
+
+// Safety: The bytes are only accessed through the unique `BandBuffer`
+// handle, by the DMA or by the drawing task.
+unsafe impl Sync for BandStorage {}
+
+impl BandStorage {
+    const fn new() -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            bytes: UnsafeCell::new([0; BAND_BYTES]),
+        }
+    }
+}
+
+static BANDS: [BandStorage; 2] = [BandStorage::new(), BandStorage::new()];
+
+/// The right to use one of [`BANDS`].
+struct BandBuffer(usize);
+
+impl BandBuffer {
+    /// Return the right to use the buffer at the index, or `None` if it was
+    /// already taken.
+    fn take(index: usize) -> Option<Self> {
+        if BANDS[index].taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(index))
+        }
+    }
+
+    /// Return the bytes to render into.
+    fn bytes(&mut self) -> &mut [u8; BAND_BYTES] {
+        // Safety: The handle is unique, and it is not held by a transfer.
+        unsafe { &mut *BANDS[self.0].bytes.get() }
+    }
+}
+
+// Safety: The buffers are statics, so their location is stable.
+unsafe impl ReadBuffer for BandBuffer {
+    type Word = u8;
+
+    unsafe fn read_buffer(&self) -> (*const u8, usize) {
+        (BANDS[self.0].bytes.get().cast(), BAND_BYTES)
+    }
+}
+
+/// The SPI bus to the display, written by DMA.
+struct PixelOut {
+    spi: Spi<SPI1, true>,
+}
+
+impl PixelOut {
+    /// Wait until the last byte has been shifted out.
+    fn flush(&self) {
+        while !self.spi.is_tx_empty() || self.spi.is_busy() {}
+    }
+}
+
+// Safety: The address is that of the data register, which takes 8 bits in
+// the 8-bit frame format.
+unsafe impl PeriAddress for PixelOut {
+    type MemSize = u8;
+
+    fn address(&self) -> u32 {
+        // Safety: Only the address of the register is taken.
+        unsafe { core::ptr::addr_of!((*SPI1::ptr()).dr) as u32 }
+    }
+}
+
+// Safety: SPI1_TX is routed to channel 3 of DMA2 stream 3.
+unsafe impl DMASet<Stream3<DMA2>, 3, MemoryToPeripheral> for PixelOut {}
+
+type PixelTransfer = Transfer<Stream3<DMA2>, 3, PixelOut, MemoryToPeripheral, BandBuffer>;
+
+irq!(Dma2Stream3Irq, pac::interrupt::DMA2_STREAM3);
+
+/// The band being sent. The DMA IRQ is masked when the lock is held.
+static TRANSFER: SpinIrqSafe<Option<PixelTransfer>, Dma2Stream3Irq> = SpinIrqSafe::new(None);
+
+/// Notified by the handler when a band has been sent.
+static SENT: Mailbox = Mailbox::new();
+
+/// The display.
+pub struct St7789 {
+    /// The stream and the bus while no band is being sent.
+    idle: Option<(Stream3<DMA2>, PixelOut)>,
+    /// The band buffers not held by a transfer.
+    bands: [Option<BandBuffer>; 2],
+    /// Selects the controller when low.
+    cs: PE7<Output>,
+    /// Marks the bytes as parameters or pixels when high, and as a command
+    /// when low.
+    dc: PE8<Output>,
+    /// Holds the controller in reset when low.
+    reset: PE9<Output>,
+}
+
+impl St7789 {
+    /// Take over the bus, the DMA stream, and the pins, and unmask the DMA
+    /// IRQ. The controller is left untouched until [`init`](Self::init) is
+    /// called. Panic if called twice.
+    pub fn new(
+        spi: Spi<SPI1, true>,
+        stream: Stream3<DMA2>,
+        mut cs: PE7<Output>,
+        dc: PE8<Output>,
+        reset: PE9<Output>,
+        nvic: &mut cortex_m::peripheral::NVIC,
+    ) -> Self {
+        let bands = [BandBuffer::take(0), BandBuffer::take(1)];
+        assert!(bands.iter().all(Option::is_some));
+        cs.set_high();
+
+        // Let the DMA stream feed the data register. The blocking writes of
+        // the commands are unaffected while the stream is disabled.
+        // Safety: The bus is owned by the display.
+        unsafe { (*SPI1::ptr()).cr2.modify(|_, w| w.txdmaen().set_bit()) };
+
+        // Safety: The DMA stream is owned by the display, and the handler
+        // only touches the transfer behind the IRQ-masking lock.
+        unsafe {
+            nvic.set_priority(pac::interrupt::DMA2_STREAM3, IRQ_NORMAL_PRIORITY);
+            cortex_m::peripheral::NVIC::unmask(pac::interrupt::DMA2_STREAM3);
+        }
+
+        Self {
+            idle: Some((stream, PixelOut { spi })),
+            bands,
+            cs,
+            dc,
+            reset,
+        }
+    }
+
+    /// Reset the controller and turn the display on. The display shows
+    /// whatever its memory holds until the first [`draw`](Self::draw).
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.reset.set_low();
+        time::sleep_ms(1).unwrap();
+        self.reset.set_high();
+        time::sleep_ms(120).unwrap();
+
+        self.command(Command::SoftwareReset, &[])?;
+        time::sleep_ms(150).unwrap();
+        self.command(Command::SleepOut, &[])?;
+        time::sleep_ms(10).unwrap();
+        self.command(Command::PixelFormat, &[RGB565])?;
+        // Rows top to bottom, columns left to right.
+        self.command(Command::MemoryAccess, &[0x00])?;
+        // The IPS panels of the modules need the colors inverted.
+        self.command(Command::InversionOn, &[])?;
+        self.command(Command::NormalMode, &[])?;
+        self.command(Command::DisplayOn, &[])
+    }
+
+    /// Send a frame. `render` is called for each row, top to bottom, with
+    /// the row index and the [`ROW_BYTES`] bytes to fill.
+    pub fn draw(&mut self, mut render: impl FnMut(usize, &mut [u8])) -> Result<(), Error> {
+        let last = (WIDTH - 1) as u16;
+        self.command(Command::ColumnAddress, &window(last))?;
+        let last = (HEIGHT - 1) as u16;
+        self.command(Command::RowAddress, &window(last))?;
+        self.command(Command::MemoryWrite, &[])?;
+
+        self.dc.set_high();
+        self.cs.set_low();
+        for band in 0..HEIGHT / BAND_ROWS {
+            let mut buffer = self.bands[band % 2].take().unwrap();
+            for (i, row) in buffer.bytes().chunks_exact_mut(ROW_BYTES).enumerate() {
+                render(band * BAND_ROWS + i, row);
+            }
+
+            // Only one band is sent at a time, so wait for the previous one
+            // before starting this one.
+            self.finish_band();
+            let (stream, out) = self.idle.take().unwrap();
+            // Start the transfer with the lock held, so that the handler
+            // finds it to acknowledge the IRQ.
+            let mut slot = TRANSFER.lock();
+            let transfer = slot.insert(Transfer::init_memory_to_peripheral(
+                stream,
+                out,
+                buffer,
+                None,
+                DmaConfig::default()
+                    .memory_increment(true)
+                    .transfer_complete_interrupt(true),
+            ));
+            transfer.start(|_| {});
+        }
+        self.finish_band();
+
+        self.idle.as_ref().unwrap().1.flush();
+        self.cs.set_high();
+        Ok(())
+    }
+
+    /// Wait until the band being sent, if any, has been sent, and take back
+    /// its resources.
+    fn finish_band(&mut self) {
+        if self.idle.is_some() {
+            return;
+        }
+        loop {
+            SENT.wait();
+            let mut transfer = TRANSFER.lock();
+            if transfer
+                .as_ref()
+                .is_some_and(|transfer| transfer.number_of_transfers() == 0)
+            {
+                let (stream, out, buffer, _) = transfer.take().unwrap().release();
+                let index = buffer.0;
+                self.bands[index] = Some(buffer);
+                self.idle = Some((stream, out));
+                return;
+            }
+        }
+    }
+
+    /// Send a command followed by its parameters.
+    fn command(&mut self, command: Command, params: &[u8]) -> Result<(), Error> {
+        let out = &mut self.idle.as_mut().unwrap().1;
+        self.cs.set_low();
+        self.dc.set_low();
+        let result = out.spi.write(&[command as u8]).and_then(|()| {
+            out.flush();
+            self.dc.set_high();
+            out.spi.write(params)
+        });
+        out.flush();
+        self.cs.set_high();
+        Ok(result?)
+    }
+}
+
+/// Return the parameters of an address command selecting 0 to `last`.
+fn window(last: u16) -> [u8; 4] {
+    let [hi, lo] = last.to_be_bytes();
+    [0, 0, hi, lo]
+}
+
+#[handler(DMA2_STREAM3)]
+fn dma2_stream3_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    if let Some(transfer) = TRANSFER.lock().as_mut() {
+        transfer.clear_flags(DmaFlag::TransferComplete);
+    }
+
+    SENT.notify_allow_isr();
+}
@@ -0,0 +1,261 @@
+//! A strip of WS2812 addressable LEDs on PA8.
+//!
+//! The LEDs take their colors from a single data line, 24 bits per LED at
+//! 800 kbit/s, chained from one LED to the next. Each bit is a pulse of
+//! 1.25 µs, high for about 0.4 µs for a 0 and 0.8 µs for a 1. A low line for
+//! more than 50 µs latches the colors.
+//!
+//! The pulses are generated by TIM1 channel 1, in PWM mode at 800 kHz, whose
+//! output is PA8. DMA2 stream 5 writes the duty cycle of the next bit into
+//! the preload register of the channel upon each update event of the timer,
+//! so the timing never depends on the CPU. A task preempted in the middle of
+//! a strip, an IRQ, or a stacklet allocation taking its SVC, all of which
+//! would stretch a pulse bit-banged by the CPU and garble the colors, leave
+//! the output untouched. The task calling [`Ws2812::write`] sleeps until the
+//! DMA IRQ tells it that the last bit has been sent.
+//!
+//! The strip is shared: [`Ws2812`] is a copyable handle whose
+//! [`write`](Ws2812::write) can be called from any task. Concurrent writes
+//! are serialized by a `Mutex`.
+//!
+//! Most strips take 5 V on their data line, whereas PA8 drives 3.3 V. Short
+//! wires usually work. Otherwise, a level shifter or a first LED powered
+//! through a diode drop brings the levels together.
+
+use core::cell::UnsafeCell;
+use embedded_dma::ReadBuffer;
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{Mailbox, Mutex, SpinIrqSafe},
+    time,
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    dma::{
+        config::{DmaConfig, Priority},
+        traits::{DMASet, PeriAddress},
+        DmaFlag, MemoryToPeripheral, Stream5, Transfer,
+    },
+    gpio::PA8,
+    pac::{self, DMA2, TIM1},
+    prelude::*,
+    rcc::Clocks,
+    timer::{Channel, Channel1, PwmHz},
+    ClearFlags,
+};
+
+use crate::irq_nesting;
+
+/// The longest strip the driver can write.
+pub const MAX_LEDS: usize = 64;
+
+/// The bit rate of the data line.
+const BIT_RATE_HZ: u32 = 800_000;
+
+/// The time the line is held low after the last bit so that the LEDs latch
+/// their colors. Recent WS2812B need more than the 50 µs of the original
+/// part.
+const LATCH_MS: u32 = 1;
+
+/// The number of duty cycles: 24 bits per LED, then a zero keeping the line
+/// low once the transfer ends.
+const SLOTS: usize = MAX_LEDS * 24 + 1;
+
+/// A color with 8 bits per component.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    /// Return the color with the given components.
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// The duty cycles read by the DMA.
+struct Duties(UnsafeCell<[u16; SLOTS]>);
+
+// Safety: The duty cycles are only written by the task holding the `STRIP`
+// lock, while no transfer reads them.
+unsafe impl Sync for Duties {}
+
+static DUTIES: Duties = Duties(UnsafeCell::new([0; SLOTS]));
+
+/// The first duty cycles of [`DUTIES`], as many as the strip being written
+/// needs.
+struct DutyBuffer(usize);
+
+// Safety: The duty cycles are a static, so their location is stable.
+unsafe impl ReadBuffer for DutyBuffer {
+    type Word = u16;
+
+    unsafe fn read_buffer(&self) -> (*const u16, usize) {
+        (DUTIES.0.get().cast(), self.0)
+    }
+}
+
+/// The compare register of TIM1 channel 1, written by DMA.
+struct DutyOut;
+
+// Safety: The address is that of the compare register, which takes 16 bits.
+unsafe impl PeriAddress for DutyOut {
+    type MemSize = u16;
+
+    fn address(&self) -> u32 {
+        // Safety: Only the address of the register is taken.
+        unsafe { (*TIM1::ptr()).ccr1() as *const _ as u32 }
+    }
+}
+
+// Safety: TIM1_UP is routed to channel 6 of DMA2 stream 5.
+unsafe impl DMASet<Stream5<DMA2>, 6, MemoryToPeripheral> for DutyOut {}
+
+type DutyTransfer = Transfer<Stream5<DMA2>, 6, DutyOut, MemoryToPeripheral, DutyBuffer>;
+
+/// The state of the strip between writes.
+struct Strip {
+    /// Taken by the transfer during a write.
+    stream: Option<Stream5<DMA2>>,
+    /// The duty cycles of a 0 and a 1.
+    zero: u16,
+    one: u16,
+    /// Kept so that the timer keeps running.
+    _pwm: PwmHz<TIM1, Channel1<TIM1>>,
+}
+
+/// `None` until [`init`] is called.
+static STRIP: Mutex<Option<Strip>> = Mutex::new(None);
+
+irq!(Dma2Stream5Irq, pac::interrupt::DMA2_STREAM5);
+
+/// The strip being written. The DMA IRQ is masked when the lock is held.
+static TRANSFER: SpinIrqSafe<Option<DutyTransfer>, Dma2Stream5Irq> = SpinIrqSafe::new(None);
+
+/// Notified by the handler when the last bit has been sent.
+static SENT: Mailbox = Mailbox::new();
+
+/// A handle to the strip.
+#[derive(Clone, Copy)]
+pub struct Ws2812 {
+    _private: (),
+}
+
+/// Start the timer with the line low, and return the handle to the strip.
+/// Panic if called twice.
+pub fn init(
+    tim1: TIM1,
+    pin: PA8,
+    stream: Stream5<DMA2>,
+    clocks: &Clocks,
+    nvic: &mut cortex_m::peripheral::NVIC,
+) -> Ws2812 {
+    let mut pwm = tim1.pwm_hz(Channel1::new(pin), BIT_RATE_HZ.Hz(), clocks);
+    let period = u32::from(pwm.get_max_duty());
+    pwm.set_duty(Channel::C1, 0);
+    pwm.enable(Channel::C1);
+
+    // Request a DMA transfer upon each update event.
+    // Safety: The timer is owned by the driver.
+    unsafe { (*TIM1::ptr()).dier.modify(|_, w| w.ude().set_bit()) };
+
+    let mut strip = STRIP.lock();
+    assert!(strip.is_none());
+    *strip = Some(Strip {
+        stream: Some(stream),
+        zero: (period * 8 / 25) as u16,
+        one: (period * 16 / 25) as u16,
+        _pwm: pwm,
+    });
+
+    // Safety: The DMA stream is owned by the driver, and the handler only
+    // touches the transfer behind the IRQ-masking lock.
+    unsafe {
+        nvic.set_priority(pac::interrupt::DMA2_STREAM5, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::DMA2_STREAM5);
+    }
+
+    Ws2812 { _private: () }
+}
+
+impl Ws2812 {
+    /// Send the colors to the strip, the first one to the LED nearest to
+    /// PA8, and return once the LEDs have latched them. The colors beyond
+    /// [`MAX_LEDS`] are ignored. The calling task sleeps while the bits are
+    /// sent, about 30 µs per LED.
+    pub fn write(&self, colors: &[Rgb]) {
+        let mut guard = STRIP.lock();
+        let strip = guard.as_mut().unwrap();
+        let colors = &colors[..colors.len().min(MAX_LEDS)];
+
+        // Safety: The lock is held and no transfer is running, see
+        // `Duties`.
+        let duties = unsafe { &mut *DUTIES.0.get() };
+        let mut len = 0;
+        for color in colors {
+            // The LEDs take green first, then red and blue, each from the
+            // most significant bit.
+            let bits = u32::from_be_bytes([0, color.g, color.r, color.b]);
+            for i in (0..24).rev() {
+                duties[len] = if bits & (1 << i) != 0 {
+                    strip.one
+                } else {
+                    strip.zero
+                };
+                len += 1;
+            }
+        }
+        duties[len] = 0;
+        len += 1;
+
+        let stream = strip.stream.take().unwrap();
+        {
+            // Start the transfer with the lock held, so that the handler
+            // finds it to acknowledge the IRQ.
+            let mut slot = TRANSFER.lock();
+            let transfer = slot.insert(Transfer::init_memory_to_peripheral(
+                stream,
+                DutyOut,
+                DutyBuffer(len),
+                None,
+                DmaConfig::default()
+                    .memory_increment(true)
+                    .priority(Priority::VeryHigh)
+                    .transfer_complete_interrupt(true),
+            ));
+            transfer.start(|_| {});
+        }
+
+        loop {
+            SENT.wait();
+            let mut slot = TRANSFER.lock();
+            if slot
+                .as_ref()
+                .is_some_and(|transfer| transfer.number_of_transfers() == 0)
+            {
+                let (stream, _, _, _) = slot.take().unwrap().release();
+                strip.stream = Some(stream);
+                break;
+            }
+        }
+
+        // The last duty cycle, a zero, is loaded at the next update event,
+        // after which the line stays low.
+        time::sleep_ms(LATCH_MS).unwrap();
+    }
+}
+
+#[handler(DMA2_STREAM5)]
+fn dma2_stream5_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    if let Some(transfer) = TRANSFER.lock().as_mut() {
+        transfer.clear_flags(DmaFlag::TransferComplete);
+    }
+
+    SENT.notify_allow_isr();
+}
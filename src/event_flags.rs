@@ -0,0 +1,134 @@
+//! A group of 32 event flags, on which tasks wait for any or all of a
+//! combination of flags.
+//!
+//! Hopter provides no event flags group. Its `CondVar` wakes one task per
+//! notification, and drops the notification if the task finds its condition
+//! unmet, so tasks waiting for different flags would miss the notifications
+//! meant for the others. An [`EventFlags`] instead gives each waiting task a
+//! `Mailbox` of its own, taken from [`MAX_WAITERS`] slots for the time of
+//! the wait. Setting flags, from a task or an IRQ handler, notifies the
+//! mailbox of every waiting task, and each task checks its own condition.
+//!
+//! [`EventFlags::take`] blocks until any or all of the given flags are set,
+//! as chosen by [`Mode`], and clears those of them that are set, so that an
+//! event is handled once. The check and the clearing are one atomic step, so
+//! two tasks taking the same flag never both get it. A flag set again
+//! before it is taken is taken once.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use hopter::sync::Mailbox;
+
+/// The most tasks waiting on a group at the same time.
+pub const MAX_WAITERS: usize = 4;
+
+/// Whether a wait ends upon any or all of the flags waited for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Any,
+    All,
+}
+
+impl Mode {
+    /// Return whether `flags` end a wait for `bits`.
+    fn is_met(self, flags: u32, bits: u32) -> bool {
+        match self {
+            Self::Any => flags & bits != 0,
+            Self::All => flags & bits == bits,
+        }
+    }
+}
+
+/// The slot of a waiting task.
+struct Waiter {
+    taken: AtomicBool,
+    /// Notified whenever flags are set while the slot is taken.
+    mailbox: Mailbox,
+}
+
+/// A group of 32 event flags.
+pub struct EventFlags {
+    flags: AtomicU32,
+    waiters: [Waiter; MAX_WAITERS],
+}
+
+impl EventFlags {
+    pub const fn new() -> Self {
+        Self {
+            flags: AtomicU32::new(0),
+            waiters: [const {
+                Waiter {
+                    taken: AtomicBool::new(false),
+                    mailbox: Mailbox::new(),
+                }
+            }; MAX_WAITERS],
+        }
+    }
+
+    /// Return the flags currently set.
+    pub fn get(&self) -> u32 {
+        self.flags.load(Ordering::SeqCst)
+    }
+
+    /// Set the flags given as bits, and wake up the waiting tasks. It can
+    /// be called from IRQ handlers as well as tasks.
+    pub fn set_allow_isr(&self, bits: u32) {
+        self.flags.fetch_or(bits, Ordering::SeqCst);
+        for waiter in &self.waiters {
+            if waiter.taken.load(Ordering::SeqCst) {
+                waiter.mailbox.notify_allow_isr();
+            }
+        }
+    }
+
+    /// Clear the flags given as bits.
+    pub fn clear(&self, bits: u32) {
+        self.flags.fetch_and(!bits, Ordering::SeqCst);
+    }
+
+    /// Block until any or all of the flags given as bits are set, as chosen
+    /// by `mode`. Return the flags set at that moment, and clear those given
+    /// as bits. Panics if [`MAX_WAITERS`] tasks already wait.
+    pub fn take(&self, bits: u32, mode: Mode) -> u32 {
+        let slot = self.claim();
+        loop {
+            let flags = self.flags.load(Ordering::SeqCst);
+            if !mode.is_met(flags, bits) {
+                // A flag set since the load above notifies the mailbox, as
+                // the slot is already taken, so the wait returns at once.
+                slot.0.mailbox.wait();
+                continue;
+            }
+            if self
+                .flags
+                .compare_exchange(flags, flags & !bits, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                return flags;
+            }
+        }
+    }
+
+    /// Take a free waiter slot. A notification left in its mailbox by an
+    /// earlier waiter only causes a spurious wake-up.
+    fn claim(&self) -> Slot {
+        self.waiters
+            .iter()
+            .find(|waiter| {
+                waiter
+                    .taken
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })
+            .map(Slot)
+            .expect("too many tasks waiting on event flags")
+    }
+}
+
+/// Frees the waiter slot when dropped, also by the unwinding of the task.
+struct Slot<'a>(&'a Waiter);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.taken.store(false, Ordering::SeqCst);
+    }
+}
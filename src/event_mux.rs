@@ -0,0 +1,73 @@
+//! Waiting on several events at once.
+//!
+//! A task blocks on one primitive at a time, e.g., one `Mailbox`, and Hopter
+//! has no call waiting on several of them. A task reacting to a button, a
+//! timer, and messages would need a task per source, each relaying to the
+//! next. An [`EventMux`] multiplexes the sources into a single `Mailbox`
+//! instead, along with a set of pending events, one bit each. A source
+//! sets its bit with [`EventMux::signal_allow_isr`], which also notifies the
+//! mailbox, and the waiting task takes all the bits set so far with
+//! [`EventMux::wait`] or [`EventMux::wait_until_timeout`], as `select` does
+//! with file descriptors.
+//!
+//! An event signalled again before the task takes it is reported once, so
+//! a source delivering data along with its event, e.g., a channel, must be
+//! drained upon the event. The bits are taken in one atomic swap, so no
+//! event is lost between the check and the wait. A notification whose bits
+//! were taken by an earlier wake-up is skipped. Only one task may wait on a
+//! mux at a time, as on a `Mailbox`.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use hopter::{sync::Mailbox, time};
+
+/// Events signalled to a single waiting task.
+pub struct EventMux {
+    /// The events signalled and not yet taken, one bit each.
+    pending: AtomicU32,
+    /// Notified upon every signal.
+    mailbox: Mailbox,
+}
+
+impl EventMux {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicU32::new(0),
+            mailbox: Mailbox::new(),
+        }
+    }
+
+    /// Signal the events given as bits. It can be called from IRQ handlers
+    /// as well as tasks.
+    pub fn signal_allow_isr(&self, events: u32) {
+        self.pending.fetch_or(events, Ordering::SeqCst);
+        self.mailbox.notify_allow_isr();
+    }
+
+    /// Block until an event is signalled. Return the events signalled
+    /// meanwhile, and clear them.
+    pub fn wait(&self) -> u32 {
+        loop {
+            let events = self.pending.swap(0, Ordering::SeqCst);
+            if events != 0 {
+                return events;
+            }
+            self.mailbox.wait();
+        }
+    }
+
+    /// Block until an event is signalled or `timeout_ms` elapses. Return the
+    /// events signalled meanwhile, and clear them, or 0 upon timeout.
+    pub fn wait_until_timeout(&self, timeout_ms: u32) -> u32 {
+        let start = time::get_tick();
+        loop {
+            let events = self.pending.swap(0, Ordering::SeqCst);
+            if events != 0 {
+                return events;
+            }
+            let elapsed = time::get_tick().wrapping_sub(start);
+            if elapsed >= timeout_ms || !self.mailbox.wait_until_timeout(timeout_ms - elapsed) {
+                return self.pending.swap(0, Ordering::SeqCst);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! A minimal executor running `async` code in a Hopter task.
+//!
+//! Hopter schedules tasks, and knows nothing of futures. An [`Executor`]
+//! lets a task run a future to completion with [`Executor::block_on`],
+//! which polls the future, and blocks the task on a `Mailbox` while the
+//! future is pending. The waker handed to the future notifies the mailbox,
+//! from a task or from an IRQ handler alike, so the task is woken up to poll
+//! again. Each task running async code owns an executor, held in a static
+//! so that wakers may outlive any poll, and the tasks are scheduled by
+//! Hopter as usual. Async code thus migrates one task at a time.
+//!
+//! Several futures run concurrently in one task through [`join`], which polls
+//! both whenever the task is woken up. [`sleep_ms`] returns a future ready
+//! after a delay, and [`timeout`] gives up on a future after one. The deadlines
+//! of the sleeping futures of every task are kept in a table of
+//! [`MAX_SLEEPING`] entries, and an executor blocks no longer than the earliest
+//! of them, then wakes up the futures whose deadline has passed.
+//!
+//! `src/main.rs` does not use the module. It is only used by
+//! `examples/async_echo.rs`, which includes it by path.
+
+use core::{
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+use hopter::{
+    sync::{Mailbox, SpinSchedSafe},
+    time,
+};
+
+/// The most futures sleeping at the same time, in all tasks.
+pub const MAX_SLEEPING: usize = 8;
+
+/// Runs futures in the task calling [`Executor::block_on`].
+pub struct Executor {
+    /// Notified by the wakers.
+    mailbox: Mailbox,
+}
+
+impl Executor {
+    pub const fn new() -> Self {
+        Self {
+            mailbox: Mailbox::new(),
+        }
+    }
+
+    /// Run the future to completion in the calling task, and return its
+    /// output. The task blocks while the future is pending. Only one task
+    /// may run an executor.
+    pub fn block_on<F: Future>(&'static self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        // Safety: The data pointer is a `&'static Mailbox`, as the vtable
+        // expects.
+        let waker = unsafe { Waker::from_raw(raw_waker(&self.mailbox)) };
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            match next_timeout_ms() {
+                Some(timeout_ms) => {
+                    self.mailbox.wait_until_timeout(timeout_ms);
+                }
+                None => self.mailbox.wait(),
+            }
+            wake_expired();
+        }
+    }
+}
+
+static VTABLE: RawWakerVTable =
+    RawWakerVTable::new(|data| raw_waker(data.cast()), wake, wake, |_| {});
+
+fn raw_waker(mailbox: *const Mailbox) -> RawWaker {
+    RawWaker::new(mailbox.cast(), &VTABLE)
+}
+
+fn wake(data: *const ()) {
+    // Safety: The data pointer of a waker of this vtable is always a
+    // `&'static Mailbox`.
+    unsafe { &*data.cast::<Mailbox>() }.notify_allow_isr();
+}
+
+/// Run both futures concurrently, and return both outputs once both are
+/// ready.
+pub async fn join<A: Future, B: Future>(a: A, b: B) -> (A::Output, B::Output) {
+    let (mut a, mut b) = (pin!(a), pin!(b));
+    let (mut out_a, mut out_b) = (None, None);
+    poll_fn(|cx| {
+        if out_a.is_none() {
+            if let Poll::Ready(out) = a.as_mut().poll(cx) {
+                out_a = Some(out);
+            }
+        }
+        if out_b.is_none() {
+            if let Poll::Ready(out) = b.as_mut().poll(cx) {
+                out_b = Some(out);
+            }
+        }
+        match (out_a.is_some(), out_b.is_some()) {
+            (true, true) => Poll::Ready((out_a.take().unwrap(), out_b.take().unwrap())),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Run the future until it is ready or `ms` milliseconds elapse, and return
+/// its output, or `None` upon timeout. The future is dropped upon timeout.
+pub async fn timeout<F: Future>(ms: u32, future: F) -> Option<F::Output> {
+    let (mut future, mut sleep) = (pin!(future), pin!(sleep_ms(ms)));
+    poll_fn(|cx| {
+        if let Poll::Ready(out) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(out));
+        }
+        sleep.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
+
+/// A sleeping future, by its ID, with its deadline and waker.
+struct Sleeper {
+    id: u32,
+    deadline: u32,
+    waker: Waker,
+}
+
+static SLEEPERS: SpinSchedSafe<[Option<Sleeper>; MAX_SLEEPING]> =
+    SpinSchedSafe::new([const { None }; MAX_SLEEPING]);
+
+/// The ID of the next sleeping future.
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Return whether the deadline has passed.
+fn is_expired(deadline: u32) -> bool {
+    time::get_tick().wrapping_sub(deadline) as i32 >= 0
+}
+
+/// Return the time until the earliest deadline, if any future sleeps.
+fn next_timeout_ms() -> Option<u32> {
+    let now = time::get_tick();
+    SLEEPERS
+        .lock()
+        .iter()
+        .flatten()
+        .map(|sleeper| (sleeper.deadline.wrapping_sub(now) as i32).max(0) as u32)
+        .min()
+}
+
+/// Wake up the futures whose deadline has passed, and forget them.
+fn wake_expired() {
+    for entry in SLEEPERS.lock().iter_mut() {
+        if entry
+            .as_ref()
+            .is_some_and(|sleeper| is_expired(sleeper.deadline))
+        {
+            entry.take().unwrap().waker.wake();
+        }
+    }
+}
+
+/// Return a future ready after `ms` milliseconds. Polling it panics if
+/// [`MAX_SLEEPING`] futures already sleep.
+pub fn sleep_ms(ms: u32) -> Sleep {
+    Sleep {
+        id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+        deadline: time::get_tick().wrapping_add(ms),
+    }
+}
+
+/// The future returned by [`sleep_ms`].
+pub struct Sleep {
+    id: u32,
+    deadline: u32,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if is_expired(self.deadline) {
+            forget(self.id);
+            return Poll::Ready(());
+        }
+
+        // Add the future to the table, or update its waker.
+        let mut sleepers = SLEEPERS.lock();
+        let sleeper = Sleeper {
+            id: self.id,
+            deadline: self.deadline,
+            waker: cx.waker().clone(),
+        };
+        let slot = match sleepers
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|s| s.id == self.id))
+        {
+            Some(index) => &mut sleepers[index],
+            None => sleepers
+                .iter_mut()
+                .find(|entry| entry.is_none())
+                .expect("too many sleeping futures"),
+        };
+        *slot = Some(sleeper);
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    /// Forget a future dropped before its deadline, e.g., by a timeout.
+    fn drop(&mut self) {
+        forget(self.id);
+    }
+}
+
+/// Remove the sleeping future with the ID from the table, if present.
+fn forget(id: u32) {
+    for entry in SLEEPERS.lock().iter_mut() {
+        if entry.as_ref().is_some_and(|sleeper| sleeper.id == id) {
+            *entry = None;
+        }
+    }
+}
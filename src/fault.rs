@@ -0,0 +1,313 @@
+//! Reporting of faults over USART2 before resetting.
+//!
+//! Hopter loops forever upon a HardFault, and leaves MemManage, BusFault,
+//! and UsageFault disabled, so that they escalate to a HardFault. This
+//! module enables the three and handles all four faults alike. The handler
+//! prints the registers stacked by the fault, the fault status registers,
+//! the ID of the running task, and the chain of its stacklets, then resets
+//! the system.
+//!
+//! The HardFault handler is wired by Hopter in the vector table in the
+//! flash. [`init`] thus copies the vector table into the RAM, replaces the
+//! HardFault entry, and points VTOR to the copy. The other faults are
+//! overridden by defining their handler symbols. With the `expert` feature,
+//! other modules may replace further entries of the copy with
+//! [`replace_vector`].
+//!
+//! The report is written to USART2 by polling its registers, bypassing the
+//! `console` module, whose lock may be held by the faulting code. Nothing is
+//! printed if USART2 is not enabled, e.g., before Part 11 of `main.rs` or
+//! under the `usb-console` feature, but the system still resets.
+//!
+//! Before the report, the handler saves a dump of the RAM to the flash, see
+//! the `crash_dump` module.
+//!
+//! The handler runs on the contiguous stack, like the IRQ handlers, and the
+//! task ID is looked up last, because the lookup goes through the kernel,
+//! which may be the faulting code.
+
+use crate::crash_dump;
+use core::{
+    arch::asm,
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use cortex_m::peripheral::{
+    scb::{Exception, VectActive},
+    SCB,
+};
+use hopter::task;
+use hopter_conf_params::{__CONTIGUOUS_STACK_BOUNDARY, __TLS_MEM_ADDR, SRAM_END_ADDR};
+use stm32f4xx_hal::pac;
+
+/// The number of entries of the vector table in the RAM. The table is
+/// aligned to its size, as VTOR requires.
+const VECTOR_CAPACITY: usize = 128;
+
+/// The index of the HardFault entry in the vector table.
+const HARDFAULT_VECTOR: usize = 3;
+
+/// The distance from the boundary of a stacklet down to its metadata, which
+/// holds the boundary of the previous stacklet. It follows the stacklet
+/// layout of Hopter: the metadata, a padding word, a trap frame with the
+/// floating point registers, and a saved word.
+pub const STACKLET_META_OFFSET: u32 = 16 + 4 + 104 + 4;
+
+/// The number of stacklets printed at most, in case the chain is damaged.
+const MAX_STACKLETS: usize = 16;
+
+extern "C" {
+    // Defined by the linker script of Hopter, right after the reset vector
+    // and at the end of the vector table, respectively.
+    static __hopter_reset_vector: u32;
+    static _stext: u32;
+}
+
+/// The vector table in the RAM.
+#[repr(C, align(512))]
+struct Vectors(UnsafeCell<[usize; VECTOR_CAPACITY]>);
+
+// Safety: The table is copied once, see `init`, and its entries are then
+// replaced by single word writes, see `replace_vector`.
+unsafe impl Sync for Vectors {}
+
+const _: () = assert!(core::mem::align_of::<Vectors>() == VECTOR_CAPACITY * 4);
+
+static VECTORS: Vectors = Vectors(UnsafeCell::new([0; VECTOR_CAPACITY]));
+
+/// Set once the vector table has been copied.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Enable MemManage, BusFault, and UsageFault, and route the HardFault to
+/// the handler of this module. Panic if called twice, or if the vector table
+/// does not fit in [`VECTOR_CAPACITY`] entries.
+pub fn init(scb: &mut SCB) {
+    assert!(!INSTALLED.swap(true, Ordering::SeqCst));
+
+    // The vector table starts with the initial stack pointer and the reset
+    // vector.
+    let start = ptr::addr_of!(__hopter_reset_vector) as usize - 8;
+    let len = (ptr::addr_of!(_stext) as usize - start) / 4;
+    assert!(len <= VECTOR_CAPACITY, "the vector table is too large");
+
+    // Safety: The flash table is read within its bounds, and the RAM table is
+    // written only here, guarded by `INSTALLED`, before VTOR points to it.
+    unsafe {
+        let vectors = &mut *VECTORS.0.get();
+        ptr::copy_nonoverlapping(start as *const usize, vectors.as_mut_ptr(), len);
+        vectors[HARDFAULT_VECTOR] = trampoline as usize;
+        cortex_m::asm::dsb();
+        scb.vtor.write(vectors.as_ptr() as u32);
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+    }
+
+    // The three faults keep priority 0, above the BASEPRI level with which
+    // Hopter masks the IRQs, so they are never masked into a HardFault.
+    scb.enable(Exception::MemoryManagement);
+    scb.enable(Exception::BusFault);
+    scb.enable(Exception::UsageFault);
+}
+
+/// Route the exception or IRQ at `index` of the vector table to `handler`.
+/// Panic if [`init`] has not run, or if the index is out of the table.
+#[cfg(feature = "expert")]
+pub fn replace_vector(index: usize, handler: unsafe extern "C" fn()) {
+    assert!(
+        INSTALLED.load(Ordering::SeqCst),
+        "the vector table is in the flash"
+    );
+    assert!(index < VECTOR_CAPACITY && index != HARDFAULT_VECTOR);
+
+    // Safety: The entry is replaced by a single word write, which the
+    // processor reads either before or after, and VTOR already points to
+    // the table.
+    unsafe {
+        let vectors = VECTORS.0.get() as *mut usize;
+        ptr::write_volatile(vectors.add(index), handler as usize);
+    }
+    cortex_m::asm::dsb();
+}
+
+// The handler symbols of the configurable faults, left to the default
+// handler by the linker script of Hopter unless defined.
+
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn MemoryManagement() {
+    asm!("b {}", sym trampoline, options(noreturn))
+}
+
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn BusFault() {
+    asm!("b {}", sym trampoline, options(noreturn))
+}
+
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn UsageFault() {
+    asm!("b {}", sym trampoline, options(noreturn))
+}
+
+/// Pass the stacked registers, the stacklet boundary of the task, and the
+/// exception return value to [`report`]. Like the IRQ entry of Hopter, set
+/// the stacklet boundary in the task local storage to that of the contiguous
+/// stack, so that the handler does not try to allocate stacklets.
+#[naked]
+unsafe extern "C" fn trampoline() {
+    asm!(
+        // The registers were stacked on the stack in use before the fault.
+        "tst   lr, #4",
+        "ite   eq",
+        "mrseq r0, msp",
+        "mrsne r0, psp",
+        "ldr   r12, ={tls_mem_addr}",
+        "ldr   r1, [r12]",
+        "ldr   r2, ={kern_stk_boundary}",
+        "str   r2, [r12]",
+        // Clear the other fields, so that no panic is injected.
+        "mov   r2, #0",
+        "str   r2, [r12, #4]",
+        "str   r2, [r12, #8]",
+        "mov   r2, lr",
+        "b     {report}",
+        tls_mem_addr = const __TLS_MEM_ADDR,
+        kern_stk_boundary = const __CONTIGUOUS_STACK_BOUNDARY,
+        report = sym report,
+        options(noreturn)
+    )
+}
+
+/// Save the dump, print the report, wait until it is sent, and reset.
+extern "C" fn report(frame: *const u32, stklet_bound: u32, exc_return: u32) -> ! {
+    let saved = crash_dump::save(frame, in_ram(frame as u32, 32), stklet_bound, exc_return);
+    let mut uart = Uart;
+    let _ = write_report(&mut uart, frame, stklet_bound, exc_return, &saved);
+    uart.flush();
+    SCB::sys_reset()
+}
+
+/// Write the report of the fault.
+fn write_report(
+    uart: &mut Uart,
+    frame: *const u32,
+    stklet_bound: u32,
+    exc_return: u32,
+    saved: &crash_dump::Outcome,
+) -> fmt::Result {
+    // Safety: The system control registers are only read.
+    let scb = unsafe { &*SCB::PTR };
+    let kind = match SCB::vect_active() {
+        VectActive::Exception(Exception::MemoryManagement) => "MemManage",
+        VectActive::Exception(Exception::BusFault) => "BusFault",
+        VectActive::Exception(Exception::UsageFault) => "UsageFault",
+        _ => "HardFault",
+    };
+    // Bit 3 of the exception return value tells the mode before the fault.
+    let mode = if exc_return & 1 << 3 != 0 {
+        "thread"
+    } else {
+        "handler"
+    };
+    write!(uart, "\r\n*** {} in {} mode ***\r\n", kind, mode)?;
+
+    // The registers stacked by the processor upon the fault.
+    const NAMES: [&str; 8] = ["r0", "r1", "r2", "r3", "r12", "lr", "pc", "xpsr"];
+    if in_ram(frame as u32, 32) {
+        for (i, name) in NAMES.iter().enumerate() {
+            // Safety: The frame lies in the RAM, as checked above.
+            let value = unsafe { ptr::read_volatile(frame.add(i)) };
+            write!(uart, "{:>4} {:#010x}", name, value)?;
+            write!(uart, "{}", if i % 4 == 3 { "\r\n" } else { "  " })?;
+        }
+    } else {
+        write!(uart, "stack pointer {:#010x} out of RAM\r\n", frame as u32)?;
+    }
+
+    let cfsr = scb.cfsr.read();
+    write!(uart, "CFSR {:#010x}  HFSR {:#010x}", cfsr, scb.hfsr.read())?;
+    // MMARVALID and BFARVALID.
+    if cfsr & 1 << 7 != 0 {
+        write!(uart, "  MMFAR {:#010x}", scb.mmfar.read())?;
+    }
+    if cfsr & 1 << 15 != 0 {
+        write!(uart, "  BFAR {:#010x}", scb.bfar.read())?;
+    }
+    write!(uart, "\r\n")?;
+
+    // The stacklets of the task, the newest first, each found from the
+    // metadata of the one after it.
+    write!(uart, "stacklets:")?;
+    let mut bound = stklet_bound;
+    for _ in 0..MAX_STACKLETS {
+        if bound == 0 {
+            break;
+        }
+        let meta = bound.wrapping_sub(STACKLET_META_OFFSET);
+        if !in_ram(meta, 16) {
+            write!(uart, " {:#010x}?", bound)?;
+            break;
+        }
+        // Safety: The metadata lies in the RAM, as checked above.
+        let (prev_bound, prev_sp, size) = unsafe {
+            let meta = meta as *const u32;
+            (
+                ptr::read_volatile(meta),
+                ptr::read_volatile(meta.add(1)),
+                ptr::read_volatile(meta.add(3)),
+            )
+        };
+        write!(uart, " {:#010x} ({} B)", bound, size)?;
+        if prev_bound != 0 {
+            write!(uart, " <- sp {:#010x}", prev_sp)?;
+        }
+        bound = prev_bound;
+    }
+    write!(uart, "\r\n")?;
+
+    write!(uart, "crash dump {}\r\n", saved)?;
+    write!(uart, "task {}\r\nresetting\r\n", task::get_current_id())
+}
+
+/// Return whether `len` bytes from `addr` lie in the SRAM.
+pub fn in_ram(addr: u32, len: u32) -> bool {
+    addr >= 0x2000_0000
+        && addr
+            .checked_add(len)
+            .is_some_and(|end| end <= SRAM_END_ADDR)
+}
+
+/// USART2 driven by polling its registers.
+struct Uart;
+
+impl Uart {
+    /// Return the registers of USART2, or `None` if it is not enabled.
+    fn regs() -> Option<&'static pac::usart1::RegisterBlock> {
+        // Safety: The registers are only polled and written to send bytes.
+        let regs = unsafe { &*pac::USART2::ptr() };
+        regs.cr1.read().ue().bit_is_set().then_some(regs)
+    }
+
+    /// Wait until the last byte is sent.
+    fn flush(&mut self) {
+        if let Some(regs) = Self::regs() {
+            while regs.sr.read().tc().bit_is_clear() {}
+        }
+    }
+}
+
+impl Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let Some(regs) = Self::regs() else {
+            return Ok(());
+        };
+        for byte in s.bytes() {
+            while regs.sr.read().txe().bit_is_clear() {}
+            regs.dr.write(|w| w.dr().bits(u16::from(byte)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Messages over a byte stream, framed by COBS and checked by a CRC-32.
+//!
+//! A UART carries bytes, not messages. Once a byte is lost or corrupted, a
+//! receiver reading messages by their length loses track of where the next
+//! one starts. A frame here is the payload followed by its CRC-32, little
+//! endian, encoded by COBS, and ended by a zero byte. COBS, i.e., consistent
+//! overhead byte stuffing, replaces every zero byte with the distance to the
+//! next one, adding one byte per 254 at most, so that a zero byte only ever
+//! ends a frame. A receiver starting midway, or meeting garbage, finds the
+//! start of the next frame at the next zero byte. A frame corrupted on the
+//! way fails its CRC, or does not decode, and is dropped alone.
+//!
+//! The CRC is computed by the CRC unit of the MCU, which [`init`] takes. The
+//! unit computes the CRC-32/MPEG-2 of words, the most significant bit first.
+//! The bits of each word written and of the result are reversed, and the
+//! result inverted, which gives the usual CRC-32 of Ethernet and zlib
+//! instead, so that a host checks frames with, e.g., `zlib.crc32` in Python.
+//! The bytes past the last whole word are taken in software. The unit is
+//! behind a mutex, so any task may encode and decode frames.
+//!
+//! [`encode`] writes a frame into a buffer, to be sent in one go. A
+//! [`Decoder`] takes the received bytes one at a time, as they come, and
+//! returns the payload of each intact frame. It counts the frames dropped,
+//! see [`Stats`].
+
+use hopter::sync::Mutex;
+use stm32f4xx_hal::{crc32::Crc32, pac::CRC};
+
+/// The length of the CRC following the payload.
+pub const CRC_LEN: usize = 4;
+
+/// The CRC unit, `None` before [`init`].
+static CRC_UNIT: Mutex<Option<CRC>> = Mutex::new(None);
+
+/// The error returned by [`encode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferTooSmall;
+
+/// The frames seen by a [`Decoder`].
+#[derive(Clone, Copy, Default)]
+pub struct Stats {
+    /// The intact frames returned.
+    pub frames: u32,
+    /// The frames failing their CRC.
+    pub corrupted: u32,
+    /// The frames that do not decode, e.g., ended midway through a run of
+    /// COBS, or shorter than the CRC.
+    pub malformed: u32,
+    /// The frames longer than the buffer of the decoder.
+    pub oversized: u32,
+}
+
+/// Enable the CRC unit.
+pub fn init(crc: CRC) {
+    // The HAL enables the clock of the unit.
+    *CRC_UNIT.lock() = Some(Crc32::new(crc).release());
+}
+
+/// Return the CRC-32 of the data, as computed by `zlib.crc32`. Panics before
+/// [`init`].
+pub fn crc32(data: &[u8]) -> u32 {
+    let unit = CRC_UNIT.lock();
+    let unit = unit.as_ref().expect("framing::init not called");
+
+    unit.cr.write(|w| w.reset().reset());
+    let words = data.chunks_exact(4);
+    let rest = words.remainder();
+    for word in words {
+        let word = u32::from_le_bytes(word.try_into().unwrap());
+        unit.dr.write(|w| w.bits(word.reverse_bits()));
+    }
+    // The state of the bit-reversed CRC, before the final inversion.
+    let crc = unit.dr.read().bits().reverse_bits();
+
+    !rest.iter().fold(crc, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Return the length of the frame of a payload `len` bytes long, with the
+/// zero byte ending it, at most.
+pub const fn max_frame_len(len: usize) -> usize {
+    let len = len + CRC_LEN;
+    len + len / 254 + 2
+}
+
+/// Write the frame of the payload into `buf`, and return its length, the
+/// zero byte ending it included.
+pub fn encode(payload: &[u8], buf: &mut [u8]) -> Result<usize, BufferTooSmall> {
+    if buf.len() < max_frame_len(payload.len()) {
+        return Err(BufferTooSmall);
+    }
+    let crc = crc32(payload).to_le_bytes();
+
+    // Each run starts with a code byte, the length of the run plus one. A
+    // run shorter than 254 bytes stands for the run followed by a zero byte.
+    let mut code_at = 0;
+    let mut code = 1u8;
+    let mut len = 1;
+    for &byte in payload.iter().chain(&crc) {
+        if byte == 0 {
+            buf[code_at] = code;
+            code_at = len;
+            code = 1;
+        } else {
+            buf[len] = byte;
+            code += 1;
+            if code == 0xff {
+                buf[code_at] = code;
+                code_at = len + 1;
+                code = 1;
+                len += 1;
+            }
+        }
+        len += 1;
+    }
+    buf[code_at] = code;
+    buf[len] = 0;
+    Ok(len + 1)
+}
+
+/// Decodes the frames of a byte stream, whose payloads are up to `N` bytes
+/// long, the CRC excluded.
+pub struct Decoder<const N: usize> {
+    /// The bytes decoded so far, with room for the CRC.
+    buf: [u8; N],
+    crc: [u8; CRC_LEN],
+    len: usize,
+    /// The bytes left in the current run.
+    left: u8,
+    /// Whether the current run is followed by a zero byte.
+    zero_after: bool,
+    /// Whether the frame overflowed the buffer, and is dropped at its end.
+    oversized: bool,
+    stats: Stats,
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            crc: [0; CRC_LEN],
+            len: 0,
+            left: 0,
+            zero_after: false,
+            oversized: false,
+            stats: Stats {
+                frames: 0,
+                corrupted: 0,
+                malformed: 0,
+                oversized: 0,
+            },
+        }
+    }
+
+    /// Take the next byte received. Return the payload of the frame if the
+    /// byte ends an intact one.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == 0 {
+            return self.end();
+        }
+        if self.oversized {
+            return None;
+        }
+
+        if self.left == 0 {
+            // A code byte.
+            if self.zero_after {
+                self.store(0);
+            }
+            self.left = byte - 1;
+            self.zero_after = byte != 0xff;
+        } else {
+            self.store(byte);
+            self.left -= 1;
+        }
+        None
+    }
+
+    /// Return the frames seen so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Store a decoded byte. The last [`CRC_LEN`] bytes seen are kept apart,
+    /// since the end of the payload is only known at the end of the frame.
+    fn store(&mut self, byte: u8) {
+        if self.len >= CRC_LEN {
+            let Some(slot) = self.buf.get_mut(self.len - CRC_LEN) else {
+                self.oversized = true;
+                return;
+            };
+            *slot = self.crc[self.len % CRC_LEN];
+        }
+        self.crc[self.len % CRC_LEN] = byte;
+        self.len += 1;
+    }
+
+    /// End the frame upon a zero byte.
+    fn end(&mut self) -> Option<&[u8]> {
+        let len = self.len;
+        let oversized = core::mem::take(&mut self.oversized);
+        let midway = self.left != 0;
+        self.len = 0;
+        self.left = 0;
+        self.zero_after = false;
+
+        match len {
+            _ if oversized => self.stats.oversized += 1,
+            // Zero bytes in a row, e.g., sent to flush a receiver, end no
+            // frame.
+            0 if !midway => {}
+            _ if midway || len < CRC_LEN => self.stats.malformed += 1,
+            _ => {
+                let payload_len = len - CRC_LEN;
+                // The ring of the last bytes starts at the oldest one.
+                let crc = core::array::from_fn(|i| self.crc[(len + i) % CRC_LEN]);
+                if crc32(&self.buf[..payload_len]) == u32::from_le_bytes(crc) {
+                    self.stats.frames += 1;
+                    return Some(&self.buf[..payload_len]);
+                }
+                self.stats.corrupted += 1;
+            }
+        }
+        None
+    }
+}
+
+impl<const N: usize> Default for Decoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,230 @@
+//! The usage of the primary heap region, and the heap operations since boot.
+//!
+//! Hopter keeps no statistics of its heap that an application can read, but
+//! the layout of the heap is documented in `allocator/heap.rs` of the kernel.
+//! The heap starts right after the static data, and is a row of chunks up to
+//! `RAM_END_ADDR`, each starting with a header holding its length and whether
+//! it is allocated. [`usage`] walks the headers. The heap is changed only by
+//! SVC and PendSV, so the walk runs with the scheduler suspended, and calls
+//! no function, which could enter SVC to extend the stack.
+//!
+//! A task allocates and frees through SVC, as it does its stacklets. With the
+//! `expert` feature, the `monitor` module hooks SVC and passes the number of
+//! every SVC to [`count`]. Without it, no operation is counted. The blocks
+//! freed by the kernel on behalf of a task, e.g., the stack of a task that
+//! ended, are not seen. The secondary heap regions of the `region_heap` module
+//! are not included either.
+//!
+//! A task may have its own operations counted apart with [`watch`], e.g.,
+//! to show that it no longer touches the heap once running. The operations
+//! of a watched task are read with [`operations_of`].
+//!
+//! The `free` shell command prints the figures, or prints them every
+//! [`PRINT_PERIOD_MS`]. The reporting task of `monitor` does the periodic
+//! printing, rather than a task of its own, see [`poll`].
+
+use crate::{console::println, shell::Line};
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
+};
+use hopter::{debug::segmented_stack, sync::SpinSchedSafe, task, time};
+use hopter_conf_params::RAM_END_ADDR;
+
+/// The period at which the figures are printed when turned on.
+pub const PRINT_PERIOD_MS: u32 = 10_000;
+
+/// The most tasks whose operations are counted apart.
+pub const MAX_WATCHED: usize = 4;
+
+/// The allocations and frees requested by the tasks, and those of
+/// stacklets, since the hooks were set.
+static ALLOCS: AtomicU32 = AtomicU32::new(0);
+static FREES: AtomicU32 = AtomicU32::new(0);
+static STACKLET_ALLOCS: AtomicU32 = AtomicU32::new(0);
+static STACKLET_FREES: AtomicU32 = AtomicU32::new(0);
+
+/// The IDs of the tasks whose operations are counted apart, or
+/// [`UNWATCHED`], and their operations, stacklets included.
+static WATCHED: [AtomicU16; MAX_WATCHED] = [const { AtomicU16::new(UNWATCHED) }; MAX_WATCHED];
+static WATCHED_OPS: [AtomicU32; MAX_WATCHED] = [const { AtomicU32::new(0) }; MAX_WATCHED];
+const UNWATCHED: u16 = u16::MAX;
+
+/// Suspends the scheduler while the heap is walked.
+static WALK: SpinSchedSafe<()> = SpinSchedSafe::new(());
+
+/// Whether the figures are printed periodically.
+static PRINTING: AtomicBool = AtomicBool::new(false);
+
+/// The tick of the last periodic printing.
+static LAST_PRINT: AtomicU32 = AtomicU32::new(0);
+
+/// The usage of the primary heap region, in bytes.
+pub struct Usage {
+    /// The whole region, from the first chunk to the end.
+    pub total: u32,
+    /// The allocated chunks, with their headers.
+    pub used: u32,
+    pub free: u32,
+    /// The largest free chunk. A block fitting in it may still fail to be
+    /// allocated, because the free chunks are kept in size classes.
+    pub largest_free: u32,
+    pub used_chunks: u32,
+    pub free_chunks: u32,
+}
+
+/// Count the heap operation of the SVC with the number. Called by the SVC
+/// hook of `monitor`.
+#[cfg(feature = "expert")]
+pub fn count(number: u8) {
+    use hopter_conf_params::svc;
+
+    let counter = match number {
+        svc::MEM_ALLOC => &ALLOCS,
+        svc::MEM_FREE => &FREES,
+        svc::TASK_MORE_STACK | svc::TASK_MORE_STACK_FROM_DROP | svc::TASK_UNWIND_PREPARE => {
+            &STACKLET_ALLOCS
+        }
+        svc::TASK_LESS_STACK | svc::TASK_UNWIND_LAND => &STACKLET_FREES,
+        _ => return,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+
+    // The kernel is not switching tasks, so the lookup is safe.
+    let id = u16::from(task::get_current_id());
+    if let Some(index) = WATCHED
+        .iter()
+        .position(|watched| watched.load(Ordering::Relaxed) == id)
+    {
+        WATCHED_OPS[index].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Count the operations of the calling task apart from now on. Panics if
+/// [`MAX_WATCHED`] tasks are already watched.
+pub fn watch() {
+    let id = u16::from(task::get_current_id());
+    let index = WATCHED
+        .iter()
+        .position(|watched| {
+            watched
+                .compare_exchange(UNWATCHED, id, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        })
+        .expect("too many watched tasks");
+    WATCHED_OPS[index].store(0, Ordering::SeqCst);
+}
+
+/// Return the operations of the task with the ID since it called [`watch`],
+/// or `None` if it did not, or if the operations are not counted.
+pub fn operations_of(id: u8) -> Option<u32> {
+    if !cfg!(feature = "expert") {
+        return None;
+    }
+    let index = WATCHED
+        .iter()
+        .position(|watched| watched.load(Ordering::SeqCst) == u16::from(id))?;
+    Some(WATCHED_OPS[index].load(Ordering::SeqCst))
+}
+
+/// Walk the chunks of the primary heap region.
+pub fn usage() -> Usage {
+    extern "C" {
+        // The end of the static data, defined by the linker script of the
+        // kernel.
+        static __sheap: u32;
+    }
+
+    // The heap starts at a multiple of 4 that is not a multiple of 8, so
+    // that the payloads are 8-byte aligned.
+    let mut start = (ptr::addr_of!(__sheap) as u32 + 3) & !3;
+    if start % 8 == 0 {
+        start += 4;
+    }
+
+    let mut usage = Usage {
+        total: RAM_END_ADDR - start,
+        used: 0,
+        free: 0,
+        largest_free: 0,
+        used_chunks: 0,
+        free_chunks: 0,
+    };
+
+    let _suspended = WALK.lock();
+    let mut header = start;
+    while header < RAM_END_ADDR {
+        // Safety: The header lies in the heap, and the heap does not change
+        // with the scheduler suspended.
+        let word = unsafe { ptr::read_volatile(header as *const u32) };
+        // The guard at the end of the heap is the only chunk of length zero.
+        let len = word & !0x3;
+        if len == 0 {
+            break;
+        }
+        // Bit 1 is set for an allocated chunk.
+        if word & 0x2 != 0 {
+            usage.used += len;
+            usage.used_chunks += 1;
+        } else {
+            usage.free += len;
+            usage.free_chunks += 1;
+            usage.largest_free = usage.largest_free.max(len);
+        }
+        header += len;
+    }
+    usage
+}
+
+/// Print the usage of the heap, and the operations if they are counted.
+pub fn print() {
+    let usage = usage();
+    println!("heap:          {} bytes", usage.total);
+    println!(
+        "used:          {} bytes in {} chunks",
+        usage.used, usage.used_chunks
+    );
+    println!(
+        "free:          {} bytes in {} chunks",
+        usage.free, usage.free_chunks
+    );
+    println!("largest free:  {} bytes", usage.largest_free);
+    if !cfg!(feature = "expert") {
+        return;
+    }
+    println!(
+        "operations:    {} allocations, {} frees",
+        ALLOCS.load(Ordering::Relaxed),
+        FREES.load(Ordering::Relaxed)
+    );
+    println!(
+        "stacklets:     {} active, {} allocations, {} frees",
+        segmented_stack::get_active_stacklet_count(),
+        STACKLET_ALLOCS.load(Ordering::Relaxed),
+        STACKLET_FREES.load(Ordering::Relaxed)
+    );
+}
+
+/// The `free` shell command: print the figures, or turn the periodic
+/// printing on or off.
+pub fn command(line: &Line) {
+    match line.args().next() {
+        None => print(),
+        Some("on") => PRINTING.store(true, Ordering::SeqCst),
+        Some("off") => PRINTING.store(false, Ordering::SeqCst),
+        Some(_) => println!("usage: free [on|off]"),
+    }
+}
+
+/// Print the figures if the periodic printing is on and a period has passed
+/// since the last one. Called by the reporting task of `monitor`.
+pub fn poll() {
+    let now = time::get_tick();
+    if !PRINTING.load(Ordering::SeqCst)
+        || now.wrapping_sub(LAST_PRINT.load(Ordering::Relaxed)) < PRINT_PERIOD_MS
+    {
+        return;
+    }
+    LAST_PRINT.store(now, Ordering::Relaxed);
+    print();
+}
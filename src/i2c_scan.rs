@@ -0,0 +1,175 @@
+//! A scanner listing the devices on the I2C1 bus, served by a task.
+//!
+//! Enter `i2c` in the shell to probe each 7-bit address from
+//! [`FIRST_ADDRESS`] to [`LAST_ADDRESS`]. A probe sends the address for a
+//! write and stops right after the acknowledge bit, so no data reaches the
+//! device. The addresses answering with an ACK are reported.
+//!
+//! The bus is shared with the other tasks using I2C1 through a `Mutex`,
+//! which the scan holds throughout.
+//!
+//! The blocking transactions of the HAL wait for the bus without a time
+//! limit, so a bus held low by a device, or lacking its pull-up resistors,
+//! would hang the task. The probes thus drive the registers directly, giving
+//! up after [`PROBE_TIMEOUT_MS`]. After a timeout, an arbitration loss, or a
+//! bus error, the peripheral is reset and the scan stops with a diagnosis.
+//! The task then waits for the next command, as after a complete scan.
+
+use core::fmt;
+use hopter::{sync::Mutex, task, time};
+use stm32f4xx_hal::{i2c::I2c, pac::I2C1};
+
+use crate::{console, shared::Shared, shell, stack_pool::SetStackPool, task_name::SetName};
+
+/// The lowest address probed. The lower ones are reserved by the I2C
+/// specification.
+pub const FIRST_ADDRESS: u8 = 0x08;
+
+/// The highest address probed. The higher ones are reserved by the I2C
+/// specification.
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// The time after which a probe gives up on the bus.
+pub const PROBE_TIMEOUT_MS: u32 = 5;
+
+/// A failure of the bus, which stops a scan.
+#[derive(Clone, Copy)]
+enum Fault {
+    /// The bus stayed busy, or a step of the transaction did not complete.
+    Timeout,
+    /// Another master took over the bus.
+    ArbitrationLoss,
+    /// A misplaced START or STOP condition was seen.
+    Bus,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timeout, check the pull-ups and the wiring"),
+            Self::ArbitrationLoss => write!(f, "arbitration lost to another master"),
+            Self::Bus => write!(f, "bus error"),
+        }
+    }
+}
+
+/// Register the `i2c` command and spawn the `i2c` task serving it.
+pub fn spawn(i2c: Shared<Mutex<I2c<I2C1>>>) {
+    let requests = shell::register_task("i2c", "scan the I2C1 bus for devices");
+
+    task::build()
+        .set_name("i2c")
+        .set_stack_pool(0)
+        .set_entry(move || loop {
+            let _ = requests.consume();
+            let _bus = i2c.lock();
+            scan();
+        })
+        .spawn()
+        .unwrap();
+}
+
+/// Probe every address and report the devices found. Reset the peripheral
+/// if the scan hits a fault. The bus lock must be held.
+fn scan() {
+    let mut found = 0;
+    for address in FIRST_ADDRESS..=LAST_ADDRESS {
+        match probe(address) {
+            Ok(true) => {
+                console::println!("{:#04x}", address);
+                found += 1;
+            }
+            Ok(false) => {}
+            Err(fault) => {
+                console::println!("stopped at {:#04x}: {}", address, fault);
+                reset();
+                return;
+            }
+        }
+        // Let the tasks of the same priority run between the probes.
+        task::yield_current();
+    }
+    console::println!("{} devices found", found);
+}
+
+/// Send the address for a write and return whether a device acknowledged it.
+fn probe(address: u8) -> Result<bool, Fault> {
+    // Safety: The bus lock is held, so the probes are the only accesses to
+    // the registers.
+    let regs = unsafe { &*I2C1::ptr() };
+    let start = time::get_tick();
+    let timed_out = || time::get_tick().wrapping_sub(start) > PROBE_TIMEOUT_MS;
+
+    // Wait until the bus is idle.
+    while regs.sr2.read().busy().bit_is_set() {
+        if timed_out() {
+            return Err(Fault::Timeout);
+        }
+    }
+
+    regs.cr1.modify(|_, w| w.start().set_bit());
+    loop {
+        let sr1 = regs.sr1.read();
+        if sr1.arlo().bit_is_set() {
+            return Err(Fault::ArbitrationLoss);
+        }
+        if sr1.sb().bit_is_set() {
+            break;
+        }
+        if timed_out() {
+            return Err(Fault::Timeout);
+        }
+    }
+
+    regs.dr
+        .write(|w| unsafe { w.bits(u32::from(address) << 1) });
+    let acked = loop {
+        let sr1 = regs.sr1.read();
+        if sr1.arlo().bit_is_set() {
+            return Err(Fault::ArbitrationLoss);
+        }
+        if sr1.berr().bit_is_set() {
+            return Err(Fault::Bus);
+        }
+        if sr1.addr().bit_is_set() {
+            // Reading SR2 after SR1 clears the flag.
+            let _ = regs.sr2.read();
+            break true;
+        }
+        if sr1.af().bit_is_set() {
+            regs.sr1.modify(|_, w| w.af().clear_bit());
+            break false;
+        }
+        if timed_out() {
+            return Err(Fault::Timeout);
+        }
+    };
+
+    // Release the bus in both cases.
+    regs.cr1.modify(|_, w| w.stop().set_bit());
+    while regs.cr1.read().stop().bit_is_set() {
+        if timed_out() {
+            return Err(Fault::Timeout);
+        }
+    }
+    Ok(acked)
+}
+
+/// Reset the peripheral, leaving any stuck transaction, and restore its
+/// configuration. The bus lock must be held.
+fn reset() {
+    // Safety: See `probe`.
+    let regs = unsafe { &*I2C1::ptr() };
+    let cr2 = regs.cr2.read().bits();
+    let ccr = regs.ccr.read().bits();
+    let trise = regs.trise.read().bits();
+
+    regs.cr1.write(|w| w.swrst().set_bit());
+    regs.cr1.write(|w| w.swrst().clear_bit());
+
+    // Safety: The values were read from the same registers.
+    regs.cr2.write(|w| unsafe { w.bits(cr2) });
+    regs.ccr.write(|w| unsafe { w.bits(ccr) });
+    regs.trise.write(|w| unsafe { w.bits(trise) });
+    regs.cr1.modify(|_, w| w.pe().set_bit());
+}
@@ -0,0 +1,91 @@
+//! The identity of the board and of the firmware it runs, printed at boot.
+//!
+//! Every STM32F4 carries a 96-bit unique device ID in its system memory,
+//! made of the wafer coordinates of the die, the wafer number, and the lot
+//! number in ASCII, and the size of its flash memory in KiB. [`unique_id`]
+//! reads the former as the three words shown by ST's tools. The build script
+//! passes the git revision of the source and the time of the build, see
+//! `build.rs`.
+//!
+//! [`print_banner`] prints them along with the configuration parameters that
+//! most often differ between builds, so that the console log of a board
+//! tells which board ran which firmware. The `id` shell command prints the
+//! banner again, for a terminal opened after boot.
+
+use crate::{console::println, shell::Line};
+use core::ptr;
+use hopter::time;
+use hopter_conf_params::{
+    HCLK_FREQUENCY_HZ, MAX_TASK_NUMBER, OOM_POLICY, PANIC_POLICY, SRAM_END_ADDR, TARGET_SYSCLK_HZ,
+    TICK_SOURCE,
+};
+use stm32f4xx_hal::signature::{FlashSize, Uid};
+
+/// The address of the unique device ID, in the system memory.
+const UID_ADDR: usize = 0x1FFF_7A10;
+
+/// The git revision of the source, suffixed with `-dirty` for uncommitted
+/// changes.
+pub const GIT_REVISION: &str = env!("GIT_REVISION");
+
+/// The time of the build, in UTC.
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Return the unique device ID, the word at the lowest address first.
+pub fn unique_id() -> [u32; 3] {
+    // Safety: The words are always readable, and never change.
+    core::array::from_fn(|i| unsafe { ptr::read_volatile((UID_ADDR as *const u32).add(i)) })
+}
+
+/// Print the identity of the board and of the firmware.
+pub fn print_banner() {
+    let [id0, id1, id2] = unique_id();
+    let uid = Uid::get();
+
+    println!(
+        "Hopter quick start {}, revision {}, built {}",
+        env!("CARGO_PKG_VERSION"),
+        GIT_REVISION,
+        BUILD_TIMESTAMP
+    );
+    println!(
+        "device {:08x}{:08x}{:08x}, lot {} wafer {} at ({}, {}), {} KiB flash",
+        id2,
+        id1,
+        id0,
+        uid.lot_num(),
+        uid.waf_num(),
+        uid.x(),
+        uid.y(),
+        FlashSize::get().kilo_bytes()
+    );
+    println!(
+        "SYSCLK {} MHz, HCLK {} MHz, tick {:?}, RAM up to {:#x}, {} tasks at most",
+        TARGET_SYSCLK_HZ / 1_000_000,
+        HCLK_FREQUENCY_HZ / 1_000_000,
+        TICK_SOURCE,
+        SRAM_END_ADDR,
+        MAX_TASK_NUMBER
+    );
+    println!(
+        "OOM {:?}, panic {:?}, {} stacks, console on {}",
+        OOM_POLICY,
+        PANIC_POLICY,
+        if cfg!(feature = "static-alloc") {
+            "static"
+        } else {
+            "dynamic"
+        },
+        if cfg!(feature = "usb-console") {
+            "USB"
+        } else {
+            "USART2"
+        }
+    );
+    println!("tick {}", time::get_tick());
+}
+
+/// The `id` shell command: print the banner again.
+pub fn command(_: &Line) {
+    print_banner();
+}
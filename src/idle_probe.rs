@@ -0,0 +1,127 @@
+//! The time spent in the idle task, shown on a pin and counted in ticks.
+//!
+//! Hopter runs its idle task, which sleeps in `wfe`, whenever no other task
+//! is ready. The kernel tells nothing of it to the application, except
+//! through `hopter::debug::cpu_load::LoadInspector`, whose clock it reads
+//! whenever it switches the idle task in or out. [`init`] creates an
+//! inspector with a [`Probe`] as its clock. The readings alternate between
+//! switching in and switching out, starting with switching in, as [`init`]
+//! runs in the main task rather than the idle one. So the probe drives its
+//! pin high upon every other reading and low upon the others, and adds the
+//! time in between, taken from the `clock` module, to the idle time.
+//!
+//! The pin is thus high while the core sleeps, apart from the IRQ handlers
+//! that wake it up without making a task ready, which run with the idle
+//! task switched in. A logic analyzer shows the sleep ratio directly, and a
+//! power analyzer triggered by the pin tells the current drawn in `wfe` from
+//! that drawn while running. A future tickless mode would show as long high
+//! stretches, no longer cut by the tick.
+//!
+//! The idle time is counted in ticks, i.e., milliseconds, with the
+//! microseconds left over carried to the next count, so it wraps around
+//! along with the tick. The readings come from PendSV, which never preempts
+//! itself, so the counts are only written there. The `idle` shell command
+//! prints the share of the time spent idle since it was last entered, along
+//! with the load computed by the inspector itself.
+
+use crate::{clock::Instant, console::println, shell::Line};
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use hopter::{
+    debug::cpu_load::{LoadInspector, MicrosecPrecision},
+    sync::SpinSchedSafe,
+    time,
+};
+use stm32f4xx_hal::gpio::{ErasedPin, Output};
+
+/// The clock of the inspector, driving the pin.
+pub struct Probe {
+    pin: UnsafeCell<ErasedPin<Output>>,
+}
+
+// Safety: Only the kernel reads the clock, from PendSV, which never preempts
+// itself.
+unsafe impl Sync for Probe {}
+
+/// Whether the idle task is switched in.
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+/// The lower 32 bits of the clock when the idle task was switched in.
+static IDLE_SINCE: AtomicU32 = AtomicU32::new(0);
+
+/// The ticks spent idle, the microseconds left over, and the times the idle
+/// task was switched in, since [`init`].
+static IDLE_TICKS: AtomicU32 = AtomicU32::new(0);
+static IDLE_CARRY_US: AtomicU32 = AtomicU32::new(0);
+static SWITCHES_IN: AtomicU32 = AtomicU32::new(0);
+
+/// The tick and the counts when the `idle` command was last entered.
+static LAST: SpinSchedSafe<(u32, u32, u32)> = SpinSchedSafe::new((0, 0, 0));
+
+/// The inspector, `None` before [`init`].
+static INSPECTOR: SpinSchedSafe<Option<Arc<LoadInspector<Probe>>>> = SpinSchedSafe::new(None);
+
+impl MicrosecPrecision for Probe {
+    fn read_clock_us(&self) -> u64 {
+        let now = Instant::now().as_micros();
+        // Safety: See the `Sync` implementation.
+        let pin = unsafe { &mut *self.pin.get() };
+
+        if !IDLE.load(Ordering::Relaxed) {
+            pin.set_high();
+            IDLE.store(true, Ordering::Relaxed);
+            IDLE_SINCE.store(now as u32, Ordering::Relaxed);
+            SWITCHES_IN.fetch_add(1, Ordering::Relaxed);
+        } else {
+            pin.set_low();
+            IDLE.store(false, Ordering::Relaxed);
+            let idle_us = (now as u32).wrapping_sub(IDLE_SINCE.load(Ordering::Relaxed))
+                + IDLE_CARRY_US.load(Ordering::Relaxed);
+            IDLE_TICKS.fetch_add(idle_us / 1000, Ordering::Relaxed);
+            IDLE_CARRY_US.store(idle_us % 1000, Ordering::Relaxed);
+        }
+        now
+    }
+}
+
+/// Start observing the idle task, driving `pin` high while it runs. Must be
+/// called from a task other than the idle one, after `clock::init`.
+pub fn init(mut pin: ErasedPin<Output>) {
+    pin.set_low();
+    *LAST.lock() = (time::get_tick(), 0, 0);
+    let probe = Probe {
+        pin: UnsafeCell::new(pin),
+    };
+    *INSPECTOR.lock() = Some(LoadInspector::new(probe));
+}
+
+/// The `idle` shell command: print the share of the time spent idle since
+/// the command was last entered, or since [`init`].
+pub fn command(_: &Line) {
+    let tick = time::get_tick();
+    let idle = IDLE_TICKS.load(Ordering::Relaxed);
+    let switches = SWITCHES_IN.load(Ordering::Relaxed);
+    let (last_tick, last_idle, last_switches) =
+        core::mem::replace(&mut *LAST.lock(), (tick, idle, switches));
+
+    let elapsed = tick.wrapping_sub(last_tick);
+    let idle = idle.wrapping_sub(last_idle);
+    // The idle time still running is not counted yet.
+    let permille = (u64::from(idle) * 1000 / u64::from(elapsed.max(1))) as u32;
+    println!(
+        "idle {}.{}% over {} ms, {} ms in {} switches in",
+        permille / 10,
+        permille % 10,
+        elapsed,
+        idle,
+        switches.wrapping_sub(last_switches)
+    );
+
+    if let Some(inspector) = INSPECTOR.lock().as_ref() {
+        let (integer, decimal) = inspector.get_cpu_load();
+        println!("load {}.{}% over the last second", integer, decimal);
+    }
+}
@@ -0,0 +1,175 @@
+//! The `inversion` shell command, which measures priority inversion with and
+//! without priority inheritance.
+//!
+//! Three tasks share a lock. The `inv_low` task takes it and works for
+//! [`HOLD_MS`]. Meanwhile, the `inv_high` task asks for the lock, and the
+//! `inv_medium` task, which does not touch the lock, spins for [`SPIN_MS`].
+//! The time `inv_high` waits for the lock is printed for two kinds of lock:
+//! - A `Semaphore` with a single permit. It has no owner, so no priority is
+//!   inherited, `inv_medium` keeps `inv_low` from running, and `inv_high`
+//!   waits for about `SPIN_MS + HOLD_MS`. This is the inversion.
+//! - A `Mutex`. `inv_low` inherits the priority of `inv_high` while holding
+//!   it, runs ahead of `inv_medium`, and `inv_high` waits for about
+//!   `HOLD_MS`.
+//!
+//! The shell task raises itself above the three while it starts them, so
+//! that `inv_low` takes the lock first and the others arrive while it is
+//! held. Every task below `inv_medium`, i.e., most tasks of the quick start,
+//! is stalled for `SPIN_MS` each run. The three tasks are spawned at a single
+//! place, so every run reuses the task IDs bound on the first one. See the
+//! `task_name` module.
+//!
+//! The stack pools of the `static-alloc` feature do not take stacks back, so
+//! the command, which spawns tasks each time, is left out under the feature.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use hopter::{
+    config,
+    sync::{Mailbox, Mutex, Semaphore},
+    task, time,
+};
+
+use crate::{
+    console::println,
+    shell::Line,
+    task_name::{self, SetName},
+};
+
+/// How long `inv_low` works while holding the lock.
+pub const HOLD_MS: u32 = 50;
+
+/// How long `inv_medium` spins.
+pub const SPIN_MS: u32 = 300;
+
+/// The priorities of the three tasks, all above the default one so that the
+/// periodic spinning of `busy_loop` in Part 8 does not get in the way.
+const HIGH_PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY - 3;
+const MEDIUM_PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY - 2;
+const LOW_PRIORITY: u8 = config::DEFAULT_TASK_PRIORITY - 1;
+
+/// The priority of the shell task while it starts the three.
+const STARTER_PRIORITY: u8 = HIGH_PRIORITY - 1;
+
+/// The two locks compared.
+static SEMAPHORE: Semaphore = Semaphore::new(1, 1);
+static MUTEX: Mutex<()> = Mutex::new(());
+
+/// Notified by `inv_low` once it holds the lock.
+static LOCKED: Mailbox = Mailbox::new();
+
+/// Counted up by each of the three tasks when it ends.
+static ENDED: Semaphore = Semaphore::new(3, 0);
+
+/// The time `inv_high` waited for the lock, in milliseconds.
+static WAITED_MS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy)]
+enum Lock {
+    Semaphore,
+    Mutex,
+}
+
+impl Lock {
+    fn name(self) -> &'static str {
+        match self {
+            Lock::Semaphore => "semaphore",
+            Lock::Mutex => "mutex",
+        }
+    }
+
+    /// Run `f` holding the lock.
+    fn with(self, f: impl FnOnce()) {
+        match self {
+            Lock::Semaphore => {
+                SEMAPHORE.down();
+                f();
+                SEMAPHORE.up();
+            }
+            Lock::Mutex => {
+                let _guard = MUTEX.lock();
+                f();
+            }
+        }
+    }
+}
+
+/// The `inversion` shell command: run the three tasks with each lock and
+/// print the time `inv_high` waited.
+pub fn command(_line: &Line) {
+    println!(
+        "inv_low holds the lock for {} ms, inv_medium spins for {} ms",
+        HOLD_MS, SPIN_MS
+    );
+    let priority =
+        task_name::priority_of(task::get_current_id()).unwrap_or(config::DEFAULT_TASK_PRIORITY);
+
+    for lock in [Lock::Semaphore, Lock::Mutex] {
+        task::change_current_priority(STARTER_PRIORITY).unwrap();
+
+        // Lower than this task, `inv_low` runs once this task waits.
+        let mut started = 0;
+        if spawn("inv_low", LOW_PRIORITY, move || low(lock)).is_ok() {
+            started += 1;
+            LOCKED.wait();
+            if spawn("inv_high", HIGH_PRIORITY, move || high(lock)).is_ok() {
+                started += 1;
+            }
+            if spawn("inv_medium", MEDIUM_PRIORITY, medium).is_ok() {
+                started += 1;
+            }
+        }
+
+        // Let those started run, and wait for them to end.
+        task::change_current_priority(priority).unwrap();
+        for _ in 0..started {
+            ENDED.down();
+        }
+        if started < 3 {
+            println!("no task slot free");
+            break;
+        }
+        println!(
+            "{:<9}  inv_high waited {} ms",
+            lock.name(),
+            WAITED_MS.load(Ordering::SeqCst)
+        );
+    }
+}
+
+fn spawn(name: &'static str, priority: u8, f: impl FnOnce() + Send + 'static) -> Result<(), ()> {
+    task::build()
+        .set_name_and_priority(name, priority)
+        .set_entry(move || {
+            f();
+            ENDED.up();
+        })
+        .spawn()
+        .map_err(|_| ())
+}
+
+fn low(lock: Lock) {
+    lock.with(|| {
+        LOCKED.notify_allow_isr();
+        // Work in slices of a millisecond, so that the time spent preempted
+        // does not count towards the work.
+        for _ in 0..HOLD_MS {
+            busy_wait_ms(1);
+        }
+    });
+}
+
+fn medium() {
+    busy_wait_ms(SPIN_MS);
+}
+
+fn high(lock: Lock) {
+    let start = time::get_tick();
+    lock.with(|| {
+        WAITED_MS.store(time::get_tick().wrapping_sub(start), Ordering::SeqCst);
+    });
+}
+
+fn busy_wait_ms(ms: u32) {
+    let start = time::get_tick();
+    while time::get_tick().wrapping_sub(start) < ms {}
+}
@@ -0,0 +1,59 @@
+//! Accounting of the IRQ nesting depth.
+//!
+//! An IRQ handler preempts those with lower priority, and all of them run on
+//! the contiguous stack. A handler calls [`enter`] first thing, which counts
+//! it as nested above the active ones until the returned guard is dropped.
+//! When the depth exceeds `MAX_IRQ_NESTING_DEPTH`, [`enter`] panics, which
+//! forces the handler to return before it pushes more frames to the stack.
+//!
+//! Only handlers calling [`enter`] are counted. The depth is therefore a lower
+//! bound if some handlers do not.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hopter_conf_params::MAX_IRQ_NESTING_DEPTH;
+
+/// The number of counted handlers currently active.
+static DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The deepest nesting observed so far.
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the handler as active until dropped. The drop also runs when the
+/// handler panics and is forced to return.
+pub struct NestingGuard {
+    depth: usize,
+    new_peak: bool,
+}
+
+impl NestingGuard {
+    /// Return the nesting depth including the handler holding the guard.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Return whether the nesting is deeper than ever observed before.
+    pub fn is_new_peak(&self) -> bool {
+        self.new_peak
+    }
+}
+
+impl Drop for NestingGuard {
+    fn drop(&mut self) {
+        DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Count the calling IRQ handler as nested above the active ones. Panic if
+/// the depth exceeds `MAX_IRQ_NESTING_DEPTH`.
+pub fn enter() -> NestingGuard {
+    let depth = DEPTH.fetch_add(1, Ordering::SeqCst) + 1;
+    let new_peak = PEAK.fetch_max(depth, Ordering::SeqCst) < depth;
+    let guard = NestingGuard { depth, new_peak };
+
+    if depth > MAX_IRQ_NESTING_DEPTH {
+        // The guard is dropped while unwinding, which restores the depth.
+        panic!("IRQ nesting too deep");
+    }
+
+    guard
+}
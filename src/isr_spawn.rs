@@ -0,0 +1,162 @@
+//! Spawning tasks on behalf of IRQ handlers.
+//!
+//! A rare event calling for heavy work, e.g., recovering a peripheral from
+//! an error, fits in no existing task. Running the work in a task spawned
+//! for it keeps it apart from the other tasks: it runs at its own priority,
+//! may block, its stacklets are freed once it ends, and a panic ends only
+//! it. Hopter spawns a task through SVC, however, which an IRQ handler
+//! cannot issue, so a handler calls [`spawn_allow_isr`] with a [`Job`] and a
+//! word of argument instead. The pair is queued, and the `spawner` task
+//! spawns a task named after the job, which calls the function of the job
+//! with the argument.
+//!
+//! A job runs one task at a time. While its task is queued or running, the
+//! requests for the job are merged into it and counted, so that a storm of
+//! errors spawns no storm of tasks. The job is free again once its task
+//! ends, be it by returning or by panicking. The tasks of all jobs are
+//! spawned at the same place, so each job keeps the task ID bound to its name
+//! on its first spawn, however often it runs. See the `task_name` module.
+//!
+//! As with the `bottom_half` module, the queue holds [`QUEUE_LEN`] entries
+//! and never allocates. Entries arriving while it is full are dropped and
+//! counted, as are the tasks failing to spawn, e.g., when
+//! `MAX_TASK_NUMBER` tasks already exist. Call [`spawn`] to start the
+//! `spawner` task before unmasking the IRQs spawning tasks.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use hopter::{
+    interrupt::mask::AllIrqExceptSvc,
+    sync::{self, Consumer, Producer, SpinIrqSafe},
+    task,
+};
+
+use crate::{stack_pool::SetStackPool, task_name::SetName};
+
+/// The number of entries queued before the `spawner` task takes them.
+pub const QUEUE_LEN: usize = 4;
+
+/// The priority of the `spawner` task, just below the `bottom_half` task.
+/// Spawning is short, the work runs at the priority of the job.
+const PRIORITY: u8 = 3;
+
+/// Work to run in a task of its own.
+pub struct Job {
+    name: &'static str,
+    priority: u8,
+    work: fn(u32),
+    /// Set while the task of the job is queued or running.
+    busy: AtomicBool,
+}
+
+impl Job {
+    /// Return a job spawning a task with the name and the priority to run
+    /// `work`.
+    pub const fn new(name: &'static str, priority: u8, work: fn(u32)) -> Self {
+        Self {
+            name,
+            priority,
+            work,
+            busy: AtomicBool::new(false),
+        }
+    }
+}
+
+/// A job requested with its argument.
+#[derive(Clone, Copy)]
+struct Entry {
+    job: &'static Job,
+    arg: u32,
+}
+
+/// The producing end of the queue. It is `None` until [`spawn`] is called.
+/// Every IRQ is masked when the lock is held, since any handler may spawn
+/// a task.
+static QUEUE: SpinIrqSafe<Option<Producer<Entry, QUEUE_LEN>>, AllIrqExceptSvc> =
+    SpinIrqSafe::new(None);
+
+/// The number of tasks spawned, of requests merged into a busy job, and of
+/// requests dropped.
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+static MERGED: AtomicUsize = AtomicUsize::new(0);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Spawn the `spawner` task.
+pub fn spawn() {
+    let (producer, consumer) = sync::create_channel();
+    *QUEUE.lock() = Some(producer);
+
+    task::build()
+        .set_name_and_priority("spawner", PRIORITY)
+        .set_stack_pool(0)
+        .set_entry(move || run(&consumer))
+        .spawn_restartable()
+        .unwrap();
+}
+
+/// Have the `spawner` task spawn a task calling the work of `job` with
+/// `arg`. Return false if the request is merged into the busy job, or
+/// dropped. It can be called from IRQ handlers as well as tasks.
+pub fn spawn_allow_isr(job: &'static Job, arg: u32) -> bool {
+    if job.busy.swap(true, Ordering::SeqCst) {
+        MERGED.fetch_add(1, Ordering::SeqCst);
+        return false;
+    }
+    let queued = match QUEUE.lock().as_ref() {
+        Some(producer) => producer.try_produce_allow_isr(Entry { job, arg }).is_ok(),
+        None => false,
+    };
+    if !queued {
+        job.busy.store(false, Ordering::SeqCst);
+        DROPPED.fetch_add(1, Ordering::SeqCst);
+    }
+    queued
+}
+
+/// Return the number of tasks spawned so far.
+pub fn spawned() -> usize {
+    SPAWNED.load(Ordering::SeqCst)
+}
+
+/// Return the number of requests merged into a busy job so far.
+pub fn merged() -> usize {
+    MERGED.load(Ordering::SeqCst)
+}
+
+/// Return the number of requests dropped so far.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// Frees the job when dropped, be it upon the return of its task or by the
+/// unwinding of the task.
+struct Busy(&'static Job);
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        self.0.busy.store(false, Ordering::SeqCst);
+    }
+}
+
+fn run(consumer: &Consumer<Entry, QUEUE_LEN>) {
+    loop {
+        let Entry { job, arg } = consumer.consume();
+        // The guard is moved into the task, or dropped along with the entry
+        // closure if the task fails to spawn.
+        let busy = Busy(job);
+        let spawned = task::build()
+            .set_name_and_priority(job.name, job.priority)
+            .set_stack_pool(0)
+            .set_entry(move || {
+                let _busy = busy;
+                (job.work)(arg)
+            })
+            .spawn();
+        match spawned {
+            Ok(()) => SPAWNED.fetch_add(1, Ordering::SeqCst),
+            Err(_) => {
+                log::warn!("cannot spawn the {} task", job.name);
+                DROPPED.fetch_add(1, Ordering::SeqCst)
+            }
+        };
+    }
+}
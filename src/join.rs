@@ -0,0 +1,116 @@
+//! Waiting for a task to end, and taking the value it returned.
+//!
+//! Hopter spawns a task from a closure returning nothing, and tells nothing
+//! of its end to the other tasks. [`joinable`] turns a closure returning a
+//! value into an entry closure for `task::build().set_entry`, along with a
+//! [`JoinHandle`]. The task and the handle share a cell, set once, when the
+//! task returns or panics, and a `Mailbox`, notified once the cell is set.
+//! [`JoinHandle::join`] waits on the mailbox, and takes the value out of the
+//! cell.
+//!
+//! A panicking task is unwound, which drops the locals of its entry closure.
+//! One of them is a guard setting the cell to panicked unless the task
+//! returned, so a join never waits for a task that is gone. So does a task
+//! that failed to spawn, whose entry closure is dropped unrun. A restartable
+//! task never ends for good, so it cannot be joined.
+//!
+//! The cell is shared by an `Arc`, allocated at every spawn, so the module is
+//! left out under the `static-alloc` feature.
+
+use alloc::sync::Arc;
+use core::mem;
+use hopter::sync::{Mailbox, SpinSchedSafe};
+
+/// The error returned by [`JoinHandle::join`] when the task panicked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Panicked;
+
+enum Outcome<T> {
+    Running,
+    Returned(T),
+    Panicked,
+    /// Taken by the handle.
+    Joined,
+}
+
+struct Shared<T> {
+    outcome: SpinSchedSafe<Outcome<T>>,
+    /// Notified once `outcome` is set.
+    ended: Mailbox,
+}
+
+impl<T> Shared<T> {
+    /// Set the outcome, unless the task already returned.
+    fn end(&self, outcome: Outcome<T>) {
+        {
+            let mut current = self.outcome.lock();
+            if !matches!(*current, Outcome::Running) {
+                return;
+            }
+            *current = outcome;
+        }
+        self.ended.notify_allow_isr();
+    }
+}
+
+/// The right to wait for a task to end and take its value.
+pub struct JoinHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Sets the outcome to panicked when dropped by the unwinding of the task.
+struct Guard<T>(Arc<Shared<T>>);
+
+impl<T> Drop for Guard<T> {
+    fn drop(&mut self) {
+        // Neither blocks, as a drop handler must not.
+        self.0.end(Outcome::Panicked);
+    }
+}
+
+/// Return an entry closure running `f` and a handle to join the task spawned
+/// from it.
+pub fn joinable<T, F>(f: F) -> (impl FnOnce() + Send + 'static, JoinHandle<T>)
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Shared {
+        outcome: SpinSchedSafe::new(Outcome::Running),
+        ended: Mailbox::new(),
+    });
+    let guard = Guard(shared.clone());
+    let entry = move || {
+        let guard = guard;
+        let value = f();
+        guard.0.end(Outcome::Returned(value));
+    };
+    (entry, JoinHandle { shared })
+}
+
+impl<T> JoinHandle<T> {
+    /// Block until the task ends, and return the value it returned, or
+    /// [`Panicked`].
+    pub fn join(self) -> Result<T, Panicked> {
+        self.shared.ended.wait();
+        self.take()
+    }
+
+    /// Block until the task ends or `timeout_ms` elapses. Return the result
+    /// of [`JoinHandle::join`], or the handle back upon timeout.
+    pub fn join_timeout(self, timeout_ms: u32) -> Result<Result<T, Panicked>, Self> {
+        if !self.shared.ended.wait_until_timeout(timeout_ms) {
+            return Err(self);
+        }
+        Ok(self.take())
+    }
+
+    /// Take the outcome of the task once it ended.
+    fn take(&self) -> Result<T, Panicked> {
+        match mem::replace(&mut *self.shared.outcome.lock(), Outcome::Joined) {
+            Outcome::Returned(value) => Ok(value),
+            Outcome::Panicked => Err(Panicked),
+            Outcome::Running | Outcome::Joined => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Termination of tasks on request, by name.
+//!
+//! Hopter ends a task only when the task returns or panics, and offers no
+//! way for a task to end another one. A killable task is instead built
+//! around a [`KillSwitch`], registered under its name with [`register`]. The
+//! switch holds a `Mailbox`, which the task blocks on through
+//! [`KillSwitch::wait`] or [`KillSwitch::sleep_ms`], and a flag set by
+//! [`request`]. A request sets the flag and notifies the mailbox, so that a
+//! task blocked on it wakes up at once. Upon waking, and at every
+//! [`KillSwitch::check`] made between the steps of a long computation, the
+//! task finds the flag set and panics.
+//!
+//! Hopter then unwinds the task as any panicking one. The locals of the task
+//! are dropped, which frees whatever they hold on the heap and releases the
+//! locks they hold, and the stacklets of the task are freed. The `ps` shell
+//! command shows the task ended, and `free` shows the heap given back. A
+//! killable task must be spawned with `spawn` rather than
+//! `spawn_restartable`, or it is restarted right away.
+//!
+//! The termination is cooperative. A task never reaching its switch is never
+//! ended, the same as with the cancellation points of POSIX threads, but a
+//! task is never ended midway through updating shared state either.
+
+use crate::{console::println, shell::Line};
+use core::sync::atomic::{AtomicBool, Ordering};
+use hopter::sync::{Mailbox, SpinSchedSafe};
+
+/// The most killable tasks registered at once.
+const MAX_KILLABLE: usize = 8;
+
+/// The means of ending a killable task.
+pub struct KillSwitch {
+    requested: AtomicBool,
+    /// The mailbox the task blocks on.
+    mailbox: Mailbox,
+}
+
+/// The registered switches, with the names of their tasks.
+static SWITCHES: SpinSchedSafe<[Option<(&'static str, &'static KillSwitch)>; MAX_KILLABLE]> =
+    SpinSchedSafe::new([None; MAX_KILLABLE]);
+
+impl KillSwitch {
+    pub const fn new() -> Self {
+        Self {
+            requested: AtomicBool::new(false),
+            mailbox: Mailbox::new(),
+        }
+    }
+
+    /// Panic if the task is requested to end.
+    pub fn check(&self) {
+        if self.requested.load(Ordering::SeqCst) {
+            panic!("killed on request");
+        }
+    }
+
+    /// Block until the mailbox is notified, then [`check`](Self::check).
+    pub fn wait(&self) {
+        self.mailbox.wait();
+        self.check();
+    }
+
+    /// Block until the mailbox is notified or `ms` elapse, then
+    /// [`check`](Self::check). Return whether the mailbox was notified.
+    pub fn sleep_ms(&self, ms: u32) -> bool {
+        let notified = self.mailbox.wait_until_timeout(ms);
+        self.check();
+        notified
+    }
+}
+
+/// Register the switch of the task with the name. Panics if too many tasks
+/// are registered.
+pub fn register(name: &'static str, switch: &'static KillSwitch) {
+    let mut switches = SWITCHES.lock();
+    let slot = switches
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many killable tasks");
+    *slot = Some((name, switch));
+}
+
+/// Request the task with the name to end. Return `false` if no task with the
+/// name is registered.
+pub fn request(name: &str) -> bool {
+    let switches = *SWITCHES.lock();
+    let Some((_, switch)) = switches.into_iter().flatten().find(|(n, _)| *n == name) else {
+        return false;
+    };
+    switch.requested.store(true, Ordering::SeqCst);
+    switch.mailbox.notify_allow_isr();
+    true
+}
+
+/// The `kill` shell command: request the named task to end, or list the
+/// killable tasks.
+pub fn command(line: &Line) {
+    match line.args().next() {
+        Some(name) if request(name) => println!("requested {} to end", name),
+        Some(name) => println!("no killable task named {}", name),
+        None => {
+            let switches = *SWITCHES.lock();
+            for (name, switch) in switches.into_iter().flatten() {
+                let requested = switch.requested.load(Ordering::SeqCst);
+                println!("{}{}", name, if requested { " (requested)" } else { "" });
+            }
+        }
+    }
+}
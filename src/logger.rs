@@ -0,0 +1,259 @@
+//! A backend of the `log` crate printing to the serial console.
+//!
+//! Logging must not block, since the `log` macros may be invoked in IRQ
+//! handlers and while a task holds a lock. Each record is hence formatted in
+//! place and pushed into a lock-free ring buffer of [`BUFFER_RECORDS`]
+//! records, and the low priority `log` task spawned by [`init`] drains the
+//! buffer to the console. Records pushed while the buffer is full are
+//! dropped and counted.
+//!
+//! Each record is tagged with the tick at which it was logged and with the
+//! task logging it. The name of the task is looked up by the `task_name`
+//! module when the record is printed. Records logged by IRQ handlers are
+//! tagged with `irq` instead.
+//!
+//! The buffer is a bounded queue with a sequence number in every slot, after
+//! Dmitry Vyukov's design. Any number of producers push concurrently, and the
+//! single `log` task pops.
+
+use crate::{console, stack_pool::SetStackPool, task_name, task_name::SetName};
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use cortex_m::peripheral::{scb::VectActive, SCB};
+use hopter::{config, task, time};
+use log::{Level, LevelFilter, Log, Metadata};
+
+/// The number of records buffered before the `log` task prints them.
+pub const BUFFER_RECORDS: usize = 16;
+
+/// The maximum length in bytes of a formatted message. Longer messages are
+/// truncated.
+pub const MESSAGE_LEN: usize = 80;
+
+/// The period at which the `log` task checks the buffer when it is empty.
+pub const DRAIN_PERIOD_MS: u32 = 20;
+
+// The sequence numbers below rely on wrapping around at a multiple of the
+// buffer length.
+const _: () = assert!(BUFFER_RECORDS.is_power_of_two());
+
+#[derive(Clone, Copy)]
+struct Record {
+    tick: u32,
+    /// The ID of the logging task, or `None` for an IRQ handler.
+    task: Option<u8>,
+    level: Level,
+    len: usize,
+    message: [u8; MESSAGE_LEN],
+}
+
+impl Record {
+    const fn new() -> Self {
+        Self {
+            tick: 0,
+            task: None,
+            level: Level::Error,
+            len: 0,
+            message: [0; MESSAGE_LEN],
+        }
+    }
+}
+
+struct Slot {
+    /// Equal to the position of the slot in the queue when the slot is free
+    /// to be pushed into, and to the position plus one when the record is
+    /// ready to be popped.
+    seq: AtomicUsize,
+    record: UnsafeCell<Record>,
+}
+
+struct Buffer {
+    slots: [Slot; BUFFER_RECORDS],
+    /// The position of the next push.
+    head: AtomicUsize,
+    /// The position of the next pop.
+    tail: AtomicUsize,
+}
+
+// Safety: A record is only accessed by the producer or the consumer owning
+// the slot, as decided by the sequence number.
+unsafe impl Sync for Buffer {}
+
+static BUFFER: Buffer = Buffer {
+    slots: {
+        let mut slots = [const {
+            Slot {
+                seq: AtomicUsize::new(0),
+                record: UnsafeCell::new(Record::new()),
+            }
+        }; BUFFER_RECORDS];
+        let mut i = 0;
+        while i < BUFFER_RECORDS {
+            slots[i].seq = AtomicUsize::new(i);
+            i += 1;
+        }
+        slots
+    },
+    head: AtomicUsize::new(0),
+    tail: AtomicUsize::new(0),
+};
+
+/// The number of records dropped because the buffer was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+impl Buffer {
+    /// Push the record. Return `false` if the buffer is full.
+    fn push(&self, record: &Record) -> bool {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % BUFFER_RECORDS];
+            let seq = slot.seq.load(Ordering::Acquire);
+            match seq.wrapping_sub(pos) as isize {
+                // The slot is free. Claim it.
+                0 => match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // Safety: The slot is owned until `seq` is advanced.
+                        unsafe { *slot.record.get() = *record };
+                        slot.seq.store(pos.wrapping_add(1), Ordering::Release);
+                        return true;
+                    }
+                    Err(head) => pos = head,
+                },
+                // The slot still holds the record from the previous lap.
+                diff if diff < 0 => return false,
+                // Another producer claimed the slot.
+                _ => pos = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Pop a record. Return `None` if the buffer is empty. Only the `log`
+    /// task pops.
+    fn pop(&self) -> Option<Record> {
+        let pos = self.tail.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % BUFFER_RECORDS];
+        if slot.seq.load(Ordering::Acquire) != pos.wrapping_add(1) {
+            return None;
+        }
+
+        // Safety: The slot is owned until `seq` is advanced.
+        let record = unsafe { *slot.record.get() };
+        slot.seq
+            .store(pos.wrapping_add(BUFFER_RECORDS), Ordering::Release);
+        self.tail.store(pos.wrapping_add(1), Ordering::Relaxed);
+        Some(record)
+    }
+}
+
+/// Write into a record's message, truncating what does not fit.
+struct MessageWriter<'a>(&'a mut Record);
+
+impl Write for MessageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let record = &mut *self.0;
+        let mut len = s.len().min(MESSAGE_LEN - record.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        record.message[record.len..record.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        record.len += len;
+        Ok(())
+    }
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let task = match SCB::vect_active() {
+            VectActive::ThreadMode => Some(task::get_current_id()),
+            _ => None,
+        };
+        let mut entry = Record {
+            tick: time::get_tick(),
+            task,
+            level: record.level(),
+            ..Record::new()
+        };
+        // Format before claiming a slot, so that the `log` task never waits
+        // for the formatting.
+        let _ = MessageWriter(&mut entry).write_fmt(*record.args());
+
+        if !BUFFER.push(&entry) {
+            DROPPED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the logger with the given maximum level, and spawn the `log` task
+/// printing the records. Panic if called twice.
+pub fn init(level: LevelFilter) {
+    static LOGGER: Logger = Logger;
+    log::set_logger(&LOGGER).expect("logger already installed");
+    log::set_max_level(level);
+
+    task::build()
+        // The lowest priority above the idle task.
+        .set_name_and_priority("log", config::IDLE_TASK_PRIORITY - 1)
+        .set_stack_pool(0)
+        .set_entry(drain)
+        .spawn()
+        .unwrap();
+}
+
+fn drain() {
+    let mut dropped = 0;
+    loop {
+        while let Some(record) = BUFFER.pop() {
+            console::println!(
+                "[{:>8}] {:<5} {}: {}",
+                record.tick,
+                record.level,
+                Origin(record.task),
+                // Only whole characters are copied.
+                core::str::from_utf8(&record.message[..record.len]).unwrap_or("")
+            );
+        }
+
+        let total = DROPPED.load(Ordering::SeqCst);
+        if total != dropped {
+            console::println!("({} log records dropped)", total - dropped);
+            dropped = total;
+        }
+
+        time::sleep_ms(DRAIN_PERIOD_MS).unwrap();
+    }
+}
+
+/// Display the name of the logging task, or its ID if it has no name.
+struct Origin(Option<u8>);
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("irq"),
+            Some(id) => match task_name::name_of(id) {
+                Some(name) => f.write_str(name),
+                None => write!(f, "task {}", id),
+            },
+        }
+    }
+}
@@ -2,30 +2,143 @@
 #![no_main]
 // Required by `#[handler]` macro.
 #![feature(naked_functions)]
+// Required by allocating from the secondary heap regions.
+#![feature(allocator_api)]
+// Required by the fault handler entry in `src/fault.rs`.
+#![feature(asm_const)]
 
 extern crate alloc;
 
-use alloc::sync::Arc;
+mod bottom_half;
+mod breathing_group;
+mod can_node;
+mod clock;
+// The console runs over USB instead of USART2 under the `usb-console`
+// feature. See Part 11.
+#[cfg_attr(feature = "usb-console", path = "usb_console.rs")]
+mod console;
+mod crash_dump;
+#[cfg(not(feature = "static-alloc"))]
+mod dma_heap;
+mod drivers;
+// `event_flags.rs` is only used by `examples/event_flags.rs`, which includes
+// it by path, as the tutorial waits on several events with `event_mux.rs`.
+mod event_mux;
+// `executor.rs` is only used by `examples/async_echo.rs`, which includes it
+// by path, as the tasks of the tutorial call the blocking APIs of Hopter.
+mod fault;
+// `framing.rs` is only used by `examples/framed_loopback.rs`, which includes
+// it by path, as the console of the tutorial carries text rather than frames.
+mod heap_stats;
+mod i2c_scan;
+mod identity;
+mod idle_probe;
+#[cfg(not(feature = "static-alloc"))]
+mod inversion;
+mod irq_nesting;
+#[cfg(not(feature = "static-alloc"))]
+mod isr_spawn;
+#[cfg(not(feature = "static-alloc"))]
+mod join;
+#[cfg(not(feature = "static-alloc"))]
+mod kill;
+mod logger;
+mod monitor;
+#[cfg(not(feature = "static-alloc"))]
+mod oom_policy;
+mod panic_persist;
+mod panic_policy;
+#[cfg(not(feature = "static-alloc"))]
+mod region_heap;
+mod rwlock;
+mod shared;
+mod shared_adc;
+mod shell;
+mod shutdown;
+mod soft_timer;
+mod stack_guard;
+mod stack_pool;
+mod storage;
+mod supervisor;
+mod supply;
+mod task_local;
+mod task_name;
+mod temperature;
+#[cfg(not(feature = "static-alloc"))]
+mod text_screen;
+mod tick_source;
+mod updater;
+mod watchdog;
+
+#[cfg(not(feature = "static-alloc"))]
+use alloc::vec::Vec;
+use can_node::Message;
+#[cfg(not(feature = "static-alloc"))]
+use core::fmt::Write;
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU8, Ordering},
+};
+use cortex_m::peripheral::DCB;
+#[cfg(not(feature = "static-alloc"))]
+use dma_heap::DmaHeap;
+#[cfg(not(feature = "static-alloc"))]
+use drivers::st7789::{self, St7789};
+use drivers::{
+    bme280::{self, Bme280, Measurement},
+    button::DebouncedButton,
+    cs43l22::{Cs43l22, I2sOut},
+    mp45dt02,
+    ws2812::{self, Rgb},
+};
+use embedded_dma::ReadBuffer;
+#[cfg(not(feature = "static-alloc"))]
+use hopter::debug::segmented_stack;
 use hopter::{
     config,
+    debug::semihosting::dbg_println,
     interrupt::declare::{handler, irq},
-    sync::{Mailbox, Mutex, SpinIrqSafe},
+    sync::{self, Consumer, Mailbox, Mutex, Producer, Semaphore, SpinIrqSafe, SpinSchedSafe},
     task::{self, main},
-    time::IntervalBarrier,
+    time::{self, IntervalBarrier},
+};
+use hopter_conf_params::{
+    ALLOW_DYNAMIC_STACK, HCLK_FREQUENCY_HZ, HSE_FREQUENCY_HZ, IRQ_NORMAL_PRIORITY,
+    MAX_IRQ_NESTING_DEPTH, SYSTICK_USE_CPU_CLOCK, TARGET_SYSCLK_HZ,
 };
+#[cfg(not(feature = "static-alloc"))]
+use region_heap::RegionHeap;
+use rwlock::RwLock;
+use shared::{share, Shared};
+use stack_pool::SetStackPool;
 use stm32f4xx_hal::{
     self,
-    gpio::{Output, Pin},
-    pac::TIM2,
+    dma::{
+        config::DmaConfig, traits::Stream, CurrentBuffer, DmaFlag, MemoryToPeripheral, Stream5,
+        StreamsTuple, Transfer,
+    },
+    gpio::{Input, Output, Pin, PA0},
+    i2c::I2c,
+    i2s::I2s,
+    pac::{DMA1, I2C1, TIM11, TIM2, TIM3},
     prelude::*,
+    qei::Qei,
     rcc::RccExt,
-    timer::{CounterUs, Event},
+    timer::{Counter, CounterUs, Event},
+    ClearFlags,
 };
+#[cfg(not(feature = "static-alloc"))]
+use stm32f4xx_hal::{gpio::PinState, spi::Spi};
+use task_name::SetName;
+#[cfg(not(feature = "static-alloc"))]
+use text_screen::TextScreen;
 
 type GreenLed = Pin<'D', 12, Output>;
 type OrangeLed = Pin<'D', 13, Output>;
 type RedLed = Pin<'D', 14, Output>;
 type BlueLed = Pin<'D', 15, Output>;
+type SpeedLed = Pin<'B', 8, Output>;
 
 // #################################
 // # Part 0: Project Configuration #
@@ -59,6 +172,22 @@ type BlueLed = Pin<'D', 15, Output>;
 //    See `hopter-conf-params/Cargo.toml` for an example. The compatibility
 //    rules are listed here:
 //    <https://doc.rust-lang.org/cargo/reference/resolver.html#semver-compatibility>
+//
+// 5. If the local override does not satisfy the version required by the
+//    kernel, the kernel is silently built with the default parameters from
+//    crates.io. The check below compares the private parameters seen by the
+//    kernel with the local ones at compile time, so that a mismatch in them
+//    fails the build.
+
+const _: () = assert!(
+    hopter_conf_params::private_params_checksum(&[
+        config::__CONTIGUOUS_STACK_BOUNDARY,
+        config::_CONTIGUOUS_STACK_BOTTOM,
+        config::__MEM_CHUNK_LINK_OFFSET,
+        config::__TLS_MEM_ADDR,
+    ]) == hopter_conf_params::PRIVATE_PARAMS_CHECKSUM,
+    "the kernel is built with a different configuration than the local one"
+);
 
 // #################################
 // # Part 1: System Initialization #
@@ -78,6 +207,18 @@ type BlueLed = Pin<'D', 15, Output>;
 // task will be automatically released. The same is also true for other tasks.
 #[main]
 fn main(mut cp: cortex_m::Peripherals) {
+    // Place the canary beyond the contiguous stack. See Part 6.
+    stack_guard::arm();
+
+    // Print a report over the console of Part 11 upon a fault, then reset,
+    // rather than hang. See `src/fault.rs`.
+    fault::init(&mut cp.SCB);
+
+    // Hook SVC and PendSV for the CPU, stack, and heap usage of Part 30 and
+    // Part 31, so that the heap operations are counted from here on. The
+    // hooks are only set with the `expert` feature, see `src/monitor.rs`.
+    monitor::init();
+
     // Acquire the board peripherals. Must not use `take()` because it
     // internally masks interrupts using `cpsid i` instruction. Hopter may
     // extend a function call stack via SVC, which leads to a hard fault when
@@ -86,15 +227,46 @@ fn main(mut cp: cortex_m::Peripherals) {
     let dp = unsafe { stm32f4xx_hal::pac::Peripherals::steal() };
 
     // Bring the system clock to the maximum speed on STM32F407.
-    let clocks = dp
+    // The frequencies come from the configuration crate, from which the
+    // kernel also derives the SysTick setup, so the two cannot diverge. See
+    // `TARGET_SYSCLK_HZ` in `hopter-conf-params/src/lib.rs`.
+    let cfgr = dp
         .RCC
         .constrain()
         .cfgr
-        .use_hse(8.MHz())
-        .sysclk(168.MHz())
-        .freeze();
+        .use_hse(HSE_FREQUENCY_HZ.Hz())
+        .sysclk(TARGET_SYSCLK_HZ.Hz())
+        .hclk(HCLK_FREQUENCY_HZ.Hz())
+        // The I2S clock feeding the audio codec. See Part 19A.
+        .i2s_clk(86.MHz());
+    // The 48 MHz clock of the USB port. See Part 11.
+    #[cfg(feature = "usb-console")]
+    let cfgr = cfgr.require_pll48clk();
+    let clocks = cfgr.freeze();
+
+    // The HAL settles on the closest achievable frequencies. The tick would
+    // drift if they were not exact.
+    assert_eq!(clocks.hclk().raw(), HCLK_FREQUENCY_HZ);
+
+    // Hopter starts the SysTick timer on the CPU clock. Switch it to the
+    // external reference clock if configured so. See `SYSTICK_USE_CPU_CLOCK`
+    // in `hopter-conf-params/src/lib.rs`.
+    if !SYSTICK_USE_CPU_CLOCK {
+        unsafe { cp.SYST.csr.modify(|val| val & !(1 << 2)) };
+    }
 
-    // Initialize the four LED lights.
+    // Drive the kernel tick from TIM7 instead if configured so. See
+    // `TICK_SOURCE` in `hopter-conf-params/src/lib.rs`.
+    tick_source::init(dp.TIM7, &clocks, &mut cp);
+
+    // Take the record of the panic that happened before the last reset, if
+    // any. See `ENABLE_PANIC_PERSIST` in `hopter-conf-params/src/lib.rs`. It
+    // is reported once the logger is ready, in Part 13.
+    panic_persist::init();
+    let last_panic = panic_persist::take();
+
+    // Initialize the four LED lights. The tutorial blinks them by toggling
+    // the pins. See `examples/pwm_breathing.rs` for dimming them with PWM.
     let gpiod = dp.GPIOD.split();
     let green_led = gpiod.pd12.into_push_pull_output();
     let orange_led = gpiod.pd13.into_push_pull_output();
@@ -110,6 +282,21 @@ fn main(mut cp: cortex_m::Peripherals) {
     // task. Any closure that is `FnOnce + Send + 'static` can be the entry
     // point.
     //
+    // Tasks can optionally be given human-readable names with `set_name`,
+    // which is provided by the `task_name` module of this quick start. Hopter
+    // itself tags tasks with numerical IDs, so each distinct name is bound to
+    // an ID of its own. See `ENABLE_TASK_NAMES` and `MAX_TASK_NAME_LEN` in
+    // `hopter-conf-params/src/lib.rs`.
+    //
+    // Tasks also pick a pool for their stacks with `set_stack_pool`, which is
+    // provided by the `stack_pool` module of this quick start. The pools are
+    // listed in `STACK_POOLS` in `hopter-conf-params/src/lib.rs`. They matter
+    // only when dynamic stack extension is turned off, e.g., by the
+    // `static-alloc` feature of this quick start. The feature also keeps the
+    // tutorial code below away from the heap, leaving out the parts built
+    // around it. A task spawned from an exhausted pool panics, which shows up
+    // at boot since the tasks are spawned then.
+    //
     // Panicking inside a task will not hang the whole system. Instead, if the
     // task is started by `spwan()`, the panic will be caught and the task
     // gracefully terminated with resources reclaimed. Moreover, the panicked
@@ -117,6 +304,8 @@ fn main(mut cp: cortex_m::Peripherals) {
     // next part of the tutorial.
 
     task::build()
+        .set_name("blink_green")
+        .set_stack_pool(0)
         .set_entry(move || blink_green(green_led))
         .spawn()
         .unwrap();
@@ -169,23 +358,78 @@ fn main(mut cp: cortex_m::Peripherals) {
     //    instance is cleaned up, Hopter will not attempt to further
     //    concurrently spawn yet another new instance. The restart will happen
     //    after the second instance is cleaned up.
+    //
+    // The reaction to a panic can be changed with `PANIC_POLICY` in
+    // `hopter-conf-params/src/lib.rs`. The policy applies to code run through
+    // `panic_policy::guarded`, as the `blink_orange` task below does. With
+    // the default `UnwindAndRestart` policy, the LED keeps blinking. With
+    // `KillTaskOnly`, the LED stops blinking after the first panic. With
+    // `ResetSystem`, the board resets every 5 seconds. With `Halt`, all LEDs
+    // freeze upon the first panic.
+    //
+    // A task failing again as soon as it restarts would be restarted in a
+    // tight loop. The `supervisor` module of this quick start puts a ladder
+    // of remedies on top of the restarts. The `blink_orange` task is
+    // restarted right away after its first three failures in a row, then
+    // after a delay doubling from half a second, and the board is rebooted
+    // upon the eighth failure. A run of 4 seconds before a failure starts the
+    // count anew, so the regular panics below never climb the ladder. Enter
+    // `ladder on` in the shell to make the task fail at start, and watch the
+    // log climb the ladder up to the reboot. See `src/supervisor.rs` for
+    // details.
 
     // Move the LED behind an `Arc`, so that the entry closure becomes `Clone`.
-    let orange_led = Arc::new(Mutex::new(orange_led));
+    // Under the `static-alloc` feature, the LED is moved into a static cell
+    // instead, and the closure captures a `&'static` reference. See the
+    // `shared` module of this quick start.
+    let orange_led = share!(Mutex<OrangeLed>, Mutex::new(orange_led));
 
-    // Spawn the task as a restartable one.
+    // Spawn the task as a restartable one. The LED is also shared with the
+    // task in Part 8.
+    let led = orange_led.clone();
     task::build()
-        .set_entry(move || blink_orange(&mut *orange_led.lock()))
+        .set_name("blink_orange")
+        .set_stack_pool(0)
+        .set_entry(move || {
+            supervisor::supervised(&ORANGE_LADDER, || {
+                panic_policy::guarded(|| blink_orange(&led))
+            })
+        })
         .spawn_restartable()
         .unwrap();
 
-    fn blink_orange(orange_led: &mut OrangeLed) {
+    const ORANGE_LADDER: supervisor::Ladder = supervisor::Ladder {
+        immediate: 3,
+        delay_ms: 500,
+        max_delay_ms: 8000,
+        reset_after: 8,
+        healthy_ms: 4000,
+    };
+
+    // Set by the `ladder` shell command to make `blink_orange` fail at start.
+    static ORANGE_FAILING: AtomicBool = AtomicBool::new(false);
+
+    shell::register(
+        "ladder",
+        "ladder on|off: make blink_orange fail at start",
+        |line| match line.args().next() {
+            Some("on") => ORANGE_FAILING.store(true, Ordering::SeqCst),
+            Some("off") => ORANGE_FAILING.store(false, Ordering::SeqCst),
+            _ => console::println!("usage: ladder on|off"),
+        },
+    );
+
+    fn blink_orange(orange_led: &Mutex<OrangeLed>) {
+        if ORANGE_FAILING.load(Ordering::SeqCst) {
+            panic!("failing at start");
+        }
+
         let mut barrier = IntervalBarrier::new(500).unwrap();
         let mut cnt = 0;
 
         loop {
             barrier.wait();
-            orange_led.toggle();
+            orange_led.lock().toggle();
 
             // Panic every 10 loop cycles. Since the task is restartable, the
             // LED appears to blink just as normal.
@@ -225,24 +469,37 @@ fn main(mut cp: cortex_m::Peripherals) {
     //
     // Also, some inlining heuristics are applied to the functions of breathing
     // tasks to keep the stack usage low when the task is blocked.
+    //
+    // Breathing tasks with different stack profiles can be put into groups
+    // with concurrency limits of their own. The groups are listed in
+    // `BREATHING_GROUPS` in `hopter-conf-params/src/lib.rs`. A task joins a
+    // group by wrapping its `wait` and `work` closures with the functions in
+    // the `breathing_group` module of this quick start.
 
-    let red_led = Arc::new(Mutex::new(red_led));
+    let red_led = share!(Mutex<RedLed>, Mutex::new(red_led));
 
     // Define a type of the `state`.
     struct BlinkRedCtxt {
-        red_led: Arc<Mutex<RedLed>>,
+        red_led: Shared<Mutex<RedLed>>,
         barrier: IntervalBarrier,
     }
 
     // A breathing task can also be restartable if all three closures are
     // `Clone`.
     task::build_breathing()
+        .set_name("blink_red")
+        .set_stack_pool(0)
         .set_init(move || BlinkRedCtxt {
             red_led,
             barrier: IntervalBarrier::new(500).unwrap(),
         })
-        .set_wait(|ctxt| ctxt.barrier.wait())
-        .set_work(|ctxt, _| ctxt.red_led.lock().toggle())
+        .set_wait(breathing_group::in_group(
+            "default",
+            |ctxt: &mut BlinkRedCtxt| ctxt.barrier.wait(),
+        ))
+        .set_work(breathing_group::grouped(|ctxt: &mut BlinkRedCtxt, _| {
+            ctxt.red_led.lock().toggle()
+        }))
         .spawn_restartable()
         .unwrap();
 
@@ -260,22 +517,35 @@ fn main(mut cp: cortex_m::Peripherals) {
     // Put the timer to the global variable so the IRQ handler can access it.
     *TIMER.lock() = Some(timer);
 
-    // Set a priority TIM2 IRQ and unmask it.
+    // Start the task running the work deferred by the handler. See Part 35.
+    bottom_half::spawn();
+
+    // Set a priority TIM2 IRQ and unmask it. The numerical value depends on
+    // the number of priority bits of the NVIC, so it is taken from the
+    // configuration crate rather than hard-coded.
     unsafe {
-        cp.NVIC.set_priority(stm32f4xx_hal::pac::interrupt::TIM2, 0);
+        cp.NVIC
+            .set_priority(stm32f4xx_hal::pac::interrupt::TIM2, IRQ_NORMAL_PRIORITY);
         cortex_m::peripheral::NVIC::unmask(stm32f4xx_hal::pac::interrupt::TIM2);
     }
 
     // Spawn a task that wait for the signal from the IRQ to blink the LED.
     task::build()
+        .set_name("blink_blue")
+        .set_stack_pool(0)
         .set_entry(|| blink_blue(blue_led))
         .spawn()
         .unwrap();
 
     fn blink_blue(mut blue_led: BlueLed) {
         loop {
-            MAILBOX.wait();
-            blue_led.toggle();
+            // Wait with a timeout so that the task notices if the IRQ stops
+            // arriving. See Part 16.
+            if MAILBOX.wait_until_timeout(TIM2_TIMEOUT_MS) {
+                blue_led.toggle();
+            } else {
+                blink_degraded(&mut blue_led);
+            }
         }
     }
 
@@ -298,22 +568,56 @@ fn main(mut cp: cortex_m::Peripherals) {
     // size limit, the panic diversion is deferred until the drop handler
     // finishes. This is because an unwinding must not be initiated inside a
     // drop handler.
+    //
+    // The boot code, the kernel, and the IRQ handlers instead run on the
+    // contiguous stack, which is protected by a canary placed beyond it. See
+    // `ENABLE_STACK_CANARY` in `hopter-conf-params/src/lib.rs`. The `check`
+    // function of the `stack_guard` module panics if the canary is damaged,
+    // which kills and unwinds the caller in the same way. It is called upon
+    // each TIM2 IRQ, in the work deferred by the handler in Part 5B.
+    //
+    // With dynamic stack extension turned off, a stack overflow is fatal to the
+    // whole system, so the tasks below are spawned only when extension is on.
+    //
+    // The `fibonacci` task overflows its stack by deep recursion, and is
+    // killed right away. The `drop_overflow` task overflows its stack inside
+    // a drop handler instead, to show the corner case above. The handler
+    // keeps running past the limit on extra stacklets, and the task is only
+    // unwound once the handler returns. The task logs each step: the start
+    // and the end of the handler, with the peak stack seen by the `monitor`
+    // module of Part 30, and its unwinding, which a guard created before
+    // checks to follow the end of the handler. An error is logged if the
+    // task ever runs on after the handler, or is unwound before it ends.
 
-    task::build()
-        // Set a stack size limit for the task.
-        .set_stack_limit(4096)
-        // Make the task higher priority than other tasks. Smaller numerical
-        // value represents higher priority. If the task hangs up, it will
-        // prevent other LED blinking tasks from running. But Hopter will
-        // gracefully terminate this task so it will not have visible
-        // effect on LED blinking.
-        .set_priority(config::DEFAULT_TASK_PRIORITY - 1)
-        // Attempt to overflow the stack by deep function recursion.
-        .set_entry(|| {
-            fibonacci(usize::MAX);
-        })
-        .spawn()
-        .unwrap();
+    if ALLOW_DYNAMIC_STACK {
+        task::build()
+            // Make the task higher priority than other tasks. Smaller numerical
+            // value represents higher priority. If the task hangs up, it will
+            // prevent other LED blinking tasks from running. But Hopter will
+            // gracefully terminate this task so it will not have visible
+            // effect on LED blinking.
+            .set_name_and_priority("fibonacci", config::DEFAULT_TASK_PRIORITY - 1)
+            // Set a stack size limit for the task.
+            .set_stack_limit(4096)
+            // Attempt to overflow the stack by deep function recursion.
+            .set_entry(|| {
+                fibonacci(usize::MAX);
+            })
+            .spawn()
+            .unwrap();
+
+        task::build()
+            .set_name("drop_overflow")
+            .set_stack_limit(DROP_OVERFLOW_STACK_LIMIT)
+            .set_entry(|| {
+                let _witness = UnwindWitness;
+                drop(DeepDrop);
+                DROP_STAGE.store(DROP_CONTINUED, Ordering::SeqCst);
+                log::error!("drop_overflow: not unwound after the drop handler");
+            })
+            .spawn()
+            .unwrap();
+    }
 
     fn fibonacci(x: usize) -> usize {
         if x >= 2 {
@@ -322,42 +626,2458 @@ fn main(mut cp: cortex_m::Peripherals) {
             x
         }
     }
-}
 
-// ################################################
-// # Part 5B: IRQs and Synchronization Primitives #
-// ################################################
-//
-// Hopter provides synchronization primitives under `hopter::sync`, which can
-// be used for synchronization among tasks. A subset of them can be used also
-// for synchronization between IRQs and tasks, including `Mailbox`, `Semaphore`,
-// and `Channel`. The methods that allow calling from IRQ handler context are
-// those with name ending in `allow_isr`.
-//
-// Some lock types with their names ending in `IrqSafe` are designed to use
-// with IRQ handlers. When these locks are acquired, the corresponding IRQ will
-// also be masked to avoid deadlock. The `irq!` macro generates types to
-// represent IRQs, which can be passed to the `IrqSafe` locks.
-//
-// The handler of a specific IRQ is marked with `#[handler(IRQ_NAME)]`.
-// Panicking inside a handler will not cause the system to hang, either. The
-// handler will be forced to return with resources reclaimed.
+    const DROP_OVERFLOW_STACK_LIMIT: usize = 2048;
+    const DROP_OVERFLOW_DEPTH: u32 = 64;
 
-// Generate the `Tim2Irq` type that represents the TIM2 IRQ.
-irq!(Tim2Irq, stm32f4xx_hal::pac::interrupt::TIM2);
+    // The steps of the `drop_overflow` task.
+    const DROP_STARTED: u8 = 1;
+    const DROP_FINISHED: u8 = 2;
+    const DROP_CONTINUED: u8 = 3;
 
-// The global `TIMER` variable is protected by the spin lock. TIM2 IRQ will be
-// masked when the lock is acquired.
-static TIMER: SpinIrqSafe<Option<CounterUs<TIM2>>, Tim2Irq> = SpinIrqSafe::new(None);
+    static DROP_STAGE: AtomicU8 = AtomicU8::new(0);
 
-// Provide synchronization between the IRQ handler and the task.
-static MAILBOX: Mailbox = Mailbox::new();
+    // Recurses past the stack limit of the task when dropped.
+    struct DeepDrop;
 
-#[handler(TIM2)]
-fn tim2_handler() {
-    // Notify the `blink_blue` task.
-    MAILBOX.notify_allow_isr();
+    impl Drop for DeepDrop {
+        fn drop(&mut self) {
+            DROP_STAGE.store(DROP_STARTED, Ordering::SeqCst);
+            log::info!(
+                "drop_overflow: drop handler started, limit {} bytes",
+                DROP_OVERFLOW_STACK_LIMIT
+            );
+            core::hint::black_box(deep_sum(DROP_OVERFLOW_DEPTH));
+            let peak = monitor::task_info(task::get_current_id()).map(|info| info.peak);
+            DROP_STAGE.store(DROP_FINISHED, Ordering::SeqCst);
+            match peak {
+                Some(peak) => log::info!(
+                    "drop_overflow: drop handler finished, peak stack {} bytes",
+                    peak
+                ),
+                None => log::info!("drop_overflow: drop handler finished"),
+            }
+        }
+    }
 
-    // Acknowledge the IRQ.
-    TIMER.lock().as_mut().unwrap().wait().unwrap();
+    // Checks the step reached when dropped by the unwinding of the task.
+    struct UnwindWitness;
+
+    impl Drop for UnwindWitness {
+        fn drop(&mut self) {
+            match DROP_STAGE.load(Ordering::SeqCst) {
+                DROP_FINISHED => log::info!("drop_overflow: unwound after the drop handler"),
+                DROP_CONTINUED => {}
+                _ => log::error!("drop_overflow: unwound inside the drop handler"),
+            }
+        }
+    }
+
+    // Take about 80 bytes of stack per level, which the optimizer can
+    // neither elide nor turn into a loop.
+    fn deep_sum(depth: u32) -> u32 {
+        let frame = core::hint::black_box([depth; 16]);
+        if depth == 0 {
+            return frame[0];
+        }
+        frame.iter().sum::<u32>().wrapping_add(deep_sum(depth - 1))
+    }
+
+    // ##################################
+    // # Part 7: Secondary Heap Regions #
+    // ##################################
+    //
+    // The kernel allocates from the primary heap region, which is the internal
+    // SRAM. Additional memory, e.g., the CCM on STM32F407 or an external SDRAM,
+    // can be listed as secondary regions in `HEAP_REGIONS` in
+    // `hopter-conf-params/src/lib.rs`. A commented entry for the CCM is
+    // provided there.
+    //
+    // Allocation from a secondary region is explicit. The `region_heap` module
+    // of this quick start provides an allocator for each secondary region,
+    // which can be passed to the `*_in` constructors of `alloc` types. The
+    // task below is spawned only when the second region is configured.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    if let Some(heap) = RegionHeap::get(1) {
+        task::build()
+            .set_name("region_user")
+            .set_stack_pool(0)
+            .set_entry(move || region_user(heap))
+            .spawn()
+            .unwrap();
+    }
+
+    // Buffers for DMA transfers should come from memory that DMA controllers
+    // can access, which excludes the CCM. A region can be reserved for them
+    // by setting `DMA_REGION_LEN` in `hopter-conf-params/src/lib.rs`. The
+    // `dma_heap` module of this quick start provides an allocator for it.
+    // Below, a buffer is allocated from the region when one is reserved. An
+    // application would hand the buffer to a DMA stream instead.
+
+    #[cfg(not(feature = "static-alloc"))]
+    if let Some(dma) = DmaHeap::get() {
+        let buf = dma.buffer(512).unwrap();
+        assert!(buf.as_ptr() as u32 >= hopter_conf_params::DMA_REGION_START);
+    }
+
+    #[cfg(not(feature = "static-alloc"))]
+    fn region_user(heap: RegionHeap) {
+        let mut barrier = IntervalBarrier::new(1000).unwrap();
+
+        loop {
+            barrier.wait();
+
+            // The buffer lives in the secondary region and is returned to it
+            // when dropped.
+            let mut samples = Vec::with_capacity_in(256, heap);
+            samples.extend(0..256u32);
+            let sum: u32 = samples.iter().sum();
+            assert_eq!(sum, 255 * 256 / 2);
+        }
+    }
+
+    // ################################
+    // # Part 8: Priority Inheritance #
+    // ################################
+    //
+    // Priority inversion happens when a high priority task waits for a mutex
+    // held by a low priority task, while a medium priority task keeps the low
+    // priority one from running and thus from releasing the mutex. The high
+    // priority task is then effectively delayed by the medium priority one.
+    //
+    // Hopter mutexes always apply priority inheritance to bound the inversion.
+    // The holder of a mutex temporarily runs at the priority of the highest
+    // priority task waiting for it.
+    //
+    // Below, the low priority `flash_orange` task periodically holds the
+    // orange LED to flash it rapidly, and the medium priority `busy_loop` task
+    // periodically hogs the CPU. When `blink_orange` in Part 3 waits for the
+    // LED, `flash_orange` inherits its priority and finishes flashing without
+    // being preempted by `busy_loop`. Thus, the orange LED keeps blinking at
+    // its regular pace. Part 32 measures the inversion with and without
+    // inheritance.
+
+    task::build()
+        .set_name_and_priority("flash_orange", config::DEFAULT_TASK_PRIORITY + 2)
+        .set_stack_pool(0)
+        .set_entry({
+            let led = orange_led.clone();
+            move || flash_orange(&led)
+        })
+        .spawn()
+        .unwrap();
+
+    task::build()
+        .set_name_and_priority("busy_loop", config::DEFAULT_TASK_PRIORITY + 1)
+        .set_stack_pool(0)
+        .set_entry(busy_loop)
+        .spawn()
+        .unwrap();
+
+    fn flash_orange(orange_led: &Mutex<OrangeLed>) {
+        loop {
+            time::sleep_ms(3000).unwrap();
+
+            // Busy wait instead of sleeping while holding the mutex, so that
+            // the task competes for the CPU inside the critical section.
+            let mut led = orange_led.lock();
+            for _ in 0..6 {
+                busy_wait_ms(50);
+                led.toggle();
+            }
+        }
+    }
+
+    fn busy_loop() {
+        loop {
+            time::sleep_ms(2000).unwrap();
+            busy_wait_ms(1000);
+        }
+    }
+
+    fn busy_wait_ms(ms: u32) {
+        let start = time::get_tick();
+        while time::get_tick().wrapping_sub(start) < ms {}
+    }
+
+    // ##############################
+    // # Part 9: Task-Local Storage #
+    // ##############################
+    //
+    // Each task has a few task-local storage slots, provided by the
+    // `task_local` module of this quick start. The number and the size of the
+    // slots are configured by `TLS_SLOT_COUNT` and `TLS_SLOT_SIZE` in
+    // `hopter-conf-params/src/lib.rs`.
+    //
+    // Slots are bound to the task ID, so a restarted instance of a
+    // restartable task sees the slots left by the panicked instance. The
+    // `flaky` task below keeps its error counter in a slot. Each time it
+    // restarts, it backs off for longer before retrying its work.
+
+    task::build()
+        .set_name("flaky")
+        .set_stack_pool(0)
+        .set_entry(flaky)
+        .spawn_restartable()
+        .unwrap();
+
+    fn flaky() {
+        // The slot holding the number of errors so far.
+        const ERROR_COUNT_SLOT: usize = 0;
+
+        let errors = task_local::with(ERROR_COUNT_SLOT, |slot| slot[0]);
+        time::sleep_ms(100 << errors.min(6)).unwrap();
+
+        // Fail on the work, and record the error before the panic.
+        task_local::with(ERROR_COUNT_SLOT, |slot| slot[0] += 1);
+        panic!("flaky work failed");
+    }
+
+    // ##########################
+    // # Part 10: Out of Memory #
+    // ##########################
+    //
+    // An allocation fails when the heap has no free chunk large enough. The
+    // kernel treats a failed infallible allocation, e.g., by `Arc::new` or
+    // `Vec::push`, as fatal and stops the whole system with interrupts masked.
+    // A task that may ask for more memory than available should use the
+    // fallible constructors instead, e.g., `Arc::try_new` or
+    // `Vec::try_reserve`, through the `oom_policy` module of this quick start.
+    // The module reacts to a failure as configured by `OOM_POLICY` in
+    // `hopter-conf-params/src/lib.rs`.
+    //
+    // The `greedy` task below periodically asks for a buffer larger than the
+    // whole RAM. Under `OomPolicy::PanicTask`, the task panics and gets
+    // restarted. Under `OomPolicy::ReturnNull`, the task gets `None` and
+    // backs off before asking again.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    task::build()
+        .set_name("greedy")
+        .set_stack_pool(0)
+        .set_entry(greedy)
+        .spawn_restartable()
+        .unwrap();
+
+    #[cfg(not(feature = "static-alloc"))]
+    fn greedy() {
+        // Larger than the RAM of the MCU.
+        const LEN: usize = 0x10_0000;
+
+        let mut backoff_ms = 5000;
+        loop {
+            time::sleep_ms(backoff_ms).unwrap();
+
+            let buf = oom_policy::allocate(|| {
+                let mut buf = Vec::<u8>::new();
+                buf.try_reserve_exact(LEN).map(|_| buf)
+            });
+            if buf.is_none() {
+                backoff_ms = (backoff_ms * 2).min(60_000);
+            }
+        }
+    }
+
+    // ###########################
+    // # Part 11: Serial Console #
+    // ###########################
+    //
+    // The `console` module of this quick start brings up USART2, which sends
+    // on PA2 and receives on PA3 at 115200 baud. Tasks print to it with the
+    // `console::print!` and `console::println!` macros.
+    //
+    // Received bytes arrive in the USART2 IRQ handler. A handler cannot block,
+    // and lengthy processing there delays the IRQs of lower priority. The
+    // handler instead pushes each byte into a `Channel` with
+    // `try_produce_allow_isr`, which never blocks, and the `shell` task of
+    // Part 12 takes the bytes out with `consume`, which blocks the task while
+    // the channel is empty. The bytes are thus processed in task context.
+    //
+    // The channel is allocated once here, during initialization, so the part
+    // is kept also under the `static-alloc` feature.
+    //
+    // Under the `usb-console` feature, the `console` module is replaced by
+    // `src/usb_console.rs`, which serves the same macros and channel over a
+    // USB virtual COM port on the OTG_FS connector, so no USB-to-serial
+    // adapter is needed. Build with `cargo run --release --features
+    // usb-console`, then open the port that appears on the host, e.g.,
+    // `/dev/ttyACM0`, with any terminal. Text printed before a terminal opens
+    // the port is dropped.
+    //
+    // The first lines printed are the banner of the `identity` module of this
+    // quick start: the unique ID of the MCU, its flash size, the git revision
+    // and the time of the build, and the main configuration parameters. With
+    // several boards on the bench, the log of each then tells which board ran
+    // which firmware. See `src/identity.rs`.
+
+    let gpioa = dp.GPIOA.split();
+    #[cfg(not(feature = "usb-console"))]
+    let rx = console::init(dp.USART2, gpioa.pa2, gpioa.pa3, &clocks, &mut cp.NVIC);
+    #[cfg(feature = "usb-console")]
+    let rx = console::init(
+        (dp.OTG_FS_GLOBAL, dp.OTG_FS_DEVICE, dp.OTG_FS_PWRCLK),
+        gpioa.pa11,
+        gpioa.pa12,
+        &clocks,
+        &mut cp.NVIC,
+    );
+    identity::print_banner();
+
+    // ##########################
+    // # Part 12: Command Shell #
+    // ##########################
+    //
+    // The `shell` module of this quick start runs a line-oriented shell on the
+    // console. Type `help` to list the commands. The built-in ones run in the
+    // `shell` task itself, while a command registered with
+    // `shell::register_task` is served by another task, which receives the
+    // command lines through a channel.
+    //
+    // Below, the `led` task serves the `led` command, which turns the orange
+    // LED on or off, or hands it back to the blinking tasks. It holds the LED
+    // mutex while the LED is forced on or off, so `blink_orange` and
+    // `flash_orange` wait until `led auto` is entered.
+
+    let requests = shell::register_task("led", "led on|off|auto: control the orange LED");
+    shell::spawn(rx);
+
+    // The `fault` command reads from an unmapped address to try the fault
+    // report of Part 1.
+    shell::register("fault", "trigger a bus fault and reset", |_| {
+        // Safety: None, the read faults on purpose.
+        unsafe { ptr::read_volatile(0x3000_0000 as *const u32) };
+    });
+
+    // The `id` command prints the banner of Part 11 again, e.g., for a
+    // terminal opened after boot.
+    shell::register(
+        "id",
+        "show the board and firmware identity",
+        identity::command,
+    );
+
+    task::build()
+        .set_name("led")
+        .set_stack_pool(0)
+        .set_entry(move || serve_led(&orange_led, requests))
+        .spawn()
+        .unwrap();
+
+    fn serve_led(orange_led: &Mutex<OrangeLed>, requests: shell::Requests) {
+        let mut forced = None;
+        loop {
+            let line = requests.consume();
+            match line.args().next() {
+                Some("on") => forced.get_or_insert_with(|| orange_led.lock()).set_high(),
+                Some("off") => forced.get_or_insert_with(|| orange_led.lock()).set_low(),
+                Some("auto") => forced = None,
+                _ => console::println!("usage: led on|off|auto"),
+            }
+        }
+    }
+
+    // ####################
+    // # Part 13: Logging #
+    // ####################
+    //
+    // The `logger` module of this quick start is a backend of the `log`
+    // crate, so the `log::info!` macro and its siblings can be used in tasks
+    // and IRQ handlers alike. Logging never blocks. A record is buffered and
+    // then printed to the console by the low priority `log` task, tagged with
+    // the tick and the name of the task logging it. Unlike RTT, this needs no
+    // debug probe.
+
+    logger::init(log::LevelFilter::Info);
+    log::info!("logger ready, {} tasks named", task_name::names().count());
+    if let Some(record) = last_panic {
+        log::warn!(
+            "task {} ({}) panicked at tick {} before the last reset, {} panics recorded",
+            record.task_id,
+            record.name().unwrap_or("unnamed"),
+            record.tick,
+            record.count
+        );
+    }
+
+    // #################################
+    // # Part 14A: Counting Semaphores #
+    // #################################
+    //
+    // See Part 14B for more descriptions.
+
+    // Initialize the TIM11 timer to post a job every 400 ms. TIM11 counts in
+    // 16 bits, so it ticks at 10 kHz rather than at 1 MHz like TIM2, whose
+    // counter would wrap around after some 65 ms.
+    let mut job_timer = dp.TIM11.counter::<10_000>(&clocks);
+    job_timer.listen(Event::Update);
+    job_timer.start(400.millis()).unwrap();
+    *JOB_TIMER.lock() = Some(job_timer);
+
+    unsafe {
+        cp.NVIC.set_priority(
+            stm32f4xx_hal::pac::interrupt::TIM1_TRG_COM_TIM11,
+            IRQ_NORMAL_PRIORITY,
+        );
+        cortex_m::peripheral::NVIC::unmask(stm32f4xx_hal::pac::interrupt::TIM1_TRG_COM_TIM11);
+    }
+
+    // Spawn the pool of workers taking the jobs. Task names must be static
+    // strings, hence the array.
+    for name in ["worker0", "worker1", "worker2"] {
+        task::build()
+            .set_name(name)
+            .set_stack_pool(0)
+            .set_entry(worker)
+            .spawn()
+            .unwrap();
+    }
+
+    fn worker() {
+        loop {
+            // Block until a job is posted.
+            JOBS.down();
+            log::debug!("job started, {} pending", JOBS.count());
+            // A job takes longer than the period at which jobs are posted,
+            // so the workers end up running jobs concurrently.
+            time::sleep_ms(1000).unwrap();
+            log::debug!("job done");
+        }
+    }
+
+    // ################################
+    // # Part 15: Reader-Writer Locks #
+    // ################################
+    //
+    // A `Mutex` admits one task at a time, even if all of them only read the
+    // data. Hopter provides no reader-writer lock, so the `rwlock` module of
+    // this quick start builds one from a kernel `CondVar`. Any number of
+    // readers hold it at the same time, while a writer holds it alone.
+    //
+    // Below, the blink periods are kept in `BLINK_CONFIG`. The two `reader`
+    // tasks hold the read lock for a while each time they consult it, so they
+    // often overlap. The `tuner` task takes the write lock every few seconds
+    // to pick other periods. Enter `rwlock` in the shell to see the lock
+    // statistics. A peak of two readers shows that they proceeded
+    // concurrently.
+
+    struct BlinkConfig {
+        on_ms: u32,
+        off_ms: u32,
+    }
+
+    static BLINK_CONFIG: RwLock<BlinkConfig> = RwLock::new(BlinkConfig {
+        on_ms: 100,
+        off_ms: 400,
+    });
+
+    for name in ["reader0", "reader1"] {
+        task::build()
+            .set_name(name)
+            .set_stack_pool(0)
+            .set_entry(reader)
+            .spawn()
+            .unwrap();
+    }
+
+    task::build()
+        .set_name("tuner")
+        .set_stack_pool(0)
+        .set_entry(tuner)
+        .spawn()
+        .unwrap();
+
+    shell::register("rwlock", "show the blink config lock statistics", |_| {
+        let stats = BLINK_CONFIG.stats();
+        console::println!(
+            "reads {} ({} contended), writes {} ({} contended), peak readers {}",
+            stats.reads,
+            stats.contended_reads,
+            stats.writes,
+            stats.contended_writes,
+            stats.peak_readers
+        );
+    });
+
+    fn reader() {
+        loop {
+            let period_ms = {
+                let config = BLINK_CONFIG.read();
+                // Stand for a slow read, e.g., of a larger configuration.
+                time::sleep_ms(50).unwrap();
+                config.on_ms + config.off_ms
+            };
+            log::debug!("blink period {} ms", period_ms);
+            time::sleep_ms(period_ms).unwrap();
+        }
+    }
+
+    fn tuner() {
+        for (on_ms, off_ms) in [(250, 250), (50, 950), (100, 400)].into_iter().cycle() {
+            time::sleep_ms(5000).unwrap();
+            let mut config = BLINK_CONFIG.write();
+            config.on_ms = on_ms;
+            config.off_ms = off_ms;
+        }
+    }
+
+    // ###########################
+    // # Part 16: Timed Blocking #
+    // ###########################
+    //
+    // A task blocked on an IRQ waits forever if the IRQ stops arriving, e.g.,
+    // because the peripheral hung or the IRQ got masked by mistake. Blocking
+    // with a timeout lets the task notice and react instead.
+    //
+    // The `blink_blue` task in Part 5A waits on the mailbox with
+    // `wait_until_timeout`, which returns `false` if no notification arrives
+    // in time. TIM2 fires every 500 ms, so the task gives up after three
+    // missed periods and falls back to a degraded pattern, a double flash,
+    // until the IRQ resumes. Enter `tim2 off` in the shell to mask the IRQ
+    // and watch the fallback, and `tim2 on` to restore it.
+
+    const TIM2_TIMEOUT_MS: u32 = 1500;
+
+    fn blink_degraded(blue_led: &mut BlueLed) {
+        for _ in 0..2 {
+            blue_led.set_high();
+            time::sleep_ms(50).unwrap();
+            blue_led.set_low();
+            time::sleep_ms(100).unwrap();
+        }
+    }
+
+    shell::register("tim2", "tim2 on|off: unmask or mask the TIM2 IRQ", |line| {
+        use stm32f4xx_hal::pac::interrupt::TIM2;
+        match line.args().next() {
+            Some("on") => unsafe { cortex_m::peripheral::NVIC::unmask(TIM2) },
+            Some("off") => cortex_m::peripheral::NVIC::mask(TIM2),
+            _ => console::println!("usage: tim2 on|off"),
+        }
+    });
+
+    // #############################
+    // # Part 17A: GPIO Interrupts #
+    // #############################
+    //
+    // See Part 17B for more descriptions.
+
+    // Raise EXTI0 when the user button on PA0 is pressed. The button drives
+    // the pin high, and the board pulls it down externally.
+    let mut syscfg = dp.SYSCFG.constrain();
+    let mut exti = dp.EXTI;
+    BUTTON.init(gpioa.pa0.into_floating_input(), &mut syscfg, &mut exti);
+
+    unsafe {
+        cp.NVIC
+            .set_priority(stm32f4xx_hal::pac::interrupt::EXTI0, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(stm32f4xx_hal::pac::interrupt::EXTI0);
+    }
+
+    task::build()
+        .set_name("button")
+        .set_stack_pool(0)
+        .set_entry(cycle_blue_modes)
+        .spawn()
+        .unwrap();
+
+    fn cycle_blue_modes() {
+        // The TIM2 periods, i.e., the blue LED half periods. `None` stops the
+        // timer, so `blink_blue` falls back to the pattern of Part 16.
+        const MODES: [Option<u32>; 4] = [Some(500), Some(250), Some(100), None];
+        let mut mode = 0;
+
+        loop {
+            BUTTON.wait_for_press();
+            // Also tell the `select` task of Part 43.
+            SELECT_EVENTS.signal_allow_isr(BUTTON_EVENT);
+
+            mode = (mode + 1) % MODES.len();
+            let mut timer = TIMER.lock();
+            let timer = timer.as_mut().unwrap();
+            match MODES[mode] {
+                Some(ms) => {
+                    timer.start(ms.millis()).unwrap();
+                    log::info!("blue LED toggles every {} ms", ms);
+                }
+                None => {
+                    // Cancelling a running timer cannot fail.
+                    timer.cancel().unwrap();
+                    log::info!("blue LED timer stopped");
+                }
+            }
+        }
+    }
+
+    // ##############################
+    // # Part 18: Periodic Sampling #
+    // ##############################
+    //
+    // ADC1 can sample two internal signals, the chip temperature sensor and
+    // the internal reference voltage VREFINT. The `temperature` module of this
+    // quick start samples both once per second and converts them to degrees
+    // Celsius and to the supply voltage, using the calibration values written
+    // at the factory.
+    //
+    // A task sampling periodically spends nearly all of its time waiting. The
+    // module builds it as a breathing task, see Part 4, so that its stack is
+    // released between samples. Enter `temp` in the shell for the latest
+    // reading.
+    //
+    // The supply monitor of Part 38 samples ADC1 as well. The `shared_adc`
+    // module of this quick start keeps the ADC behind a mutex, which each
+    // task takes for its conversions. See `src/shared_adc.rs`.
+
+    shared_adc::init(dp.ADC1);
+    temperature::spawn();
+
+    shell::register(
+        "temp",
+        "show the chip temperature and VDDA",
+        |_| match temperature::latest() {
+            Some(reading) => console::println!(
+                "{} C, VDDA {} mV, at tick {}",
+                temperature::Celsius(reading.centi_celsius),
+                reading.vdda_mv,
+                reading.tick
+            ),
+            None => console::println!("no sample yet"),
+        },
+    );
+
+    // ##########################
+    // # Part 19A: Audio Output #
+    // ##########################
+    //
+    // See Part 19B for more descriptions.
+
+    // Reach the registers of the codec through I2C1 on PB6 and PB9. Its reset
+    // pin is PD4.
+    let gpiob = dp.GPIOB.split();
+    let gpioc = dp.GPIOC.split();
+    let i2c = dp.I2C1.i2c((gpiob.pb6, gpiob.pb9), 100.kHz(), &clocks);
+    let codec_reset = gpiod.pd4.into_push_pull_output();
+
+    // Stream the samples through SPI3 in I2S mode, on PA4 (WS), PC10 (CK),
+    // PC7 (MCK), and PC12 (SD). The I2S clock is set up in Part 1.
+    let i2s = I2s::new(
+        dp.SPI3,
+        (gpioa.pa4, gpioc.pc10, gpioc.pc7, gpioc.pc12),
+        &clocks,
+    );
+    let i2s = I2sOut::new(i2s, AUDIO_SAMPLE_RATE_HZ);
+    AUDIO_SAMPLE_RATE.store(i2s.sample_rate(), Ordering::SeqCst);
+
+    // Let DMA1 stream 5 alternate between the two buffers, raising the IRQ
+    // whenever it switches.
+    let dma_config = DmaConfig::default()
+        .memory_increment(true)
+        .double_buffer(true)
+        .transfer_complete_interrupt(true);
+    let dma1 = StreamsTuple::new(dp.DMA1);
+    let mut transfer = Transfer::init_memory_to_peripheral(
+        dma1.5,
+        i2s,
+        AudioBuffer::take(0).unwrap(),
+        Some(AudioBuffer::take(1).unwrap()),
+        dma_config,
+    );
+    transfer.start(|i2s| i2s.enable());
+    *AUDIO_TRANSFER.lock() = Some(transfer);
+
+    unsafe {
+        cp.NVIC.set_priority(
+            stm32f4xx_hal::pac::interrupt::DMA1_STREAM5,
+            IRQ_NORMAL_PRIORITY,
+        );
+        cortex_m::peripheral::NVIC::unmask(stm32f4xx_hal::pac::interrupt::DMA1_STREAM5);
+    }
+
+    // Refill the buffer just played while the DMA plays the other one. The
+    // refill has a deadline, so it runs above the default priority.
+    task::build_breathing()
+        .set_name_and_priority("audio", config::DEFAULT_TASK_PRIORITY - 1)
+        .set_stack_pool(0)
+        .set_init(Tone::new)
+        .set_wait(breathing_group::in_group("default", |_: &mut Tone| {
+            AUDIO_REFILL.wait()
+        }))
+        .set_work(breathing_group::grouped(|tone: &mut Tone, _| {
+            refill_audio(tone)
+        }))
+        .spawn()
+        .unwrap();
+
+    // Power up the codec now that its master clock runs. It plays silence
+    // until the first refill.
+    let mut codec = Cs43l22::new(i2c, codec_reset);
+    match codec.init(AUDIO_VOLUME).and_then(|()| codec.play()) {
+        Ok(()) => log::info!("playing a {} Hz tone", TONE_HZ.load(Ordering::SeqCst)),
+        Err(err) => log::warn!("audio codec: {}", err),
+    }
+
+    // The codec keeps playing without its driver, which hands I2C1 over to
+    // Part 21.
+    let (i2c, _) = codec.release();
+
+    shell::register(
+        "tone",
+        "tone <hz>|off: change the tone",
+        |line| match line.args().next() {
+            Some("off") => TONE_HZ.store(0, Ordering::SeqCst),
+            Some(arg) => match arg.parse::<u32>() {
+                Ok(hz) if hz > 0 && hz < AUDIO_SAMPLE_RATE.load(Ordering::SeqCst) / 2 => {
+                    TONE_HZ.store(hz, Ordering::SeqCst)
+                }
+                _ => console::println!("frequency out of range"),
+            },
+            None => console::println!("usage: tone <hz>|off"),
+        },
+    );
+
+    // ##########################
+    // # Part 20: Audio Capture #
+    // ##########################
+    //
+    // The MEMS microphone on the board outputs a one-bit stream at about
+    // 1 MHz, which must be filtered down to PCM samples before use. The
+    // `drivers::mp45dt02` module of this quick start receives the bits
+    // through I2S2 and DMA, and decimates them in a `pdm` task, the same
+    // double buffering as the audio output of Part 19B. It hands the samples
+    // out in frames of 10 ms through a `Channel`, whose consuming end `init`
+    // returns, so that any task can process the audio as it arrives.
+    //
+    // Here the `mic` task measures the level of each frame. Enter `mic` in
+    // the shell to watch it change as you speak or tap the board.
+
+    let frames = mp45dt02::init(
+        dp.SPI2,
+        gpiob.pb12,
+        gpiob.pb10,
+        gpioc.pc3,
+        dma1.3,
+        &clocks,
+        &mut cp.NVIC,
+    );
+
+    // The peak and the mean absolute value of the last frame.
+    static MIC_PEAK: AtomicU32 = AtomicU32::new(0);
+    static MIC_MEAN: AtomicU32 = AtomicU32::new(0);
+
+    task::build()
+        .set_name("mic")
+        .set_stack_pool(0)
+        .set_entry(move || loop {
+            let frame = frames.consume();
+            let peak = frame.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+            let sum: u32 = frame.iter().map(|s| u32::from(s.unsigned_abs())).sum();
+            MIC_PEAK.store(u32::from(peak), Ordering::SeqCst);
+            MIC_MEAN.store(sum / frame.len() as u32, Ordering::SeqCst);
+        })
+        .spawn()
+        .unwrap();
+
+    shell::register("mic", "show the microphone level", |_| {
+        console::println!(
+            "peak {}, mean {}, {} overruns, {} frames dropped",
+            MIC_PEAK.load(Ordering::SeqCst),
+            MIC_MEAN.load(Ordering::SeqCst),
+            mp45dt02::overruns(),
+            mp45dt02::dropped()
+        )
+    });
+
+    // ############################
+    // # Part 21: I2C Bus Scanner #
+    // ############################
+    //
+    // External sensors are often wired to I2C1, the bus of the codec in Part
+    // 19A, on PB6 (SCL) and PB9 (SDA). The `i2c_scan` module of this quick
+    // start lists the devices answering on the bus when `i2c` is entered in
+    // the shell, the first thing to check when a new sensor stays silent. The
+    // `i2c` task serving the command gives up on a bus stuck low after a
+    // timeout, resets the peripheral, and keeps serving later commands.
+    //
+    // The bus is shared by the tasks talking to the devices on it, behind a
+    // `Mutex`. Each task holds the lock for one transaction or a few, and
+    // never while it sleeps.
+
+    let i2c = share!(Mutex<I2c<I2C1>>, Mutex::new(i2c));
+    i2c_scan::spawn(i2c.clone());
+
+    // #############################
+    // # Part 22: External Sensors #
+    // #############################
+    //
+    // A BME280 breakout wired to I2C1 measures the temperature, the humidity,
+    // and the pressure. The `drivers::bme280` module of this quick start
+    // talks to it, taking the bus only for the duration of each call.
+    //
+    // The `bme280` breathing task, see Part 4, starts a measurement every
+    // `ENV_PERIOD_MS`, sleeps until it completes with the bus lock released,
+    // then reads it and produces it into a `Channel`. The task looks for the
+    // sensor at both of its addresses until one answers, and again after an
+    // error, so the sensor can be plugged in at any time. The `env` task
+    // consumes the readings, and keeps the latest for the `env` command of
+    // the shell. Other consumers, e.g., a task sending them over a radio,
+    // would take the readings from the channel the same way.
+
+    const ENV_PERIOD_MS: u32 = 2000;
+
+    // The tick of a reading and the measurement.
+    type EnvReading = (u32, Measurement);
+
+    struct EnvCtxt {
+        sensor: Option<Bme280>,
+        barrier: IntervalBarrier,
+        readings: Producer<EnvReading, 2>,
+    }
+
+    let (readings, consumer) = sync::create_channel();
+
+    task::build_breathing()
+        .set_name("bme280")
+        .set_stack_pool(0)
+        .set_init(move || EnvCtxt {
+            sensor: None,
+            barrier: IntervalBarrier::new(ENV_PERIOD_MS).unwrap(),
+            readings,
+        })
+        .set_wait(breathing_group::in_group(
+            "default",
+            |ctxt: &mut EnvCtxt| ctxt.barrier.wait(),
+        ))
+        .set_work(breathing_group::grouped(move |ctxt: &mut EnvCtxt, _| {
+            sample_env(ctxt, &i2c)
+        }))
+        .spawn()
+        .unwrap();
+
+    fn sample_env(ctxt: &mut EnvCtxt, i2c: &Mutex<I2c<I2C1>>) {
+        let sensor = match &ctxt.sensor {
+            Some(sensor) => sensor,
+            None => {
+                let mut bus = i2c.lock();
+                let found = [bme280::ADDRESS_PRIMARY, bme280::ADDRESS_SECONDARY]
+                    .into_iter()
+                    .find_map(|address| Bme280::new(&mut bus, address).ok());
+                match found {
+                    Some(sensor) => {
+                        log::info!("BME280 found");
+                        ctxt.sensor.insert(sensor)
+                    }
+                    None => return,
+                }
+            }
+        };
+
+        // Each statement holds the bus lock only until its end, so the lock
+        // is released while the task sleeps.
+        let started = sensor.start(&mut i2c.lock());
+        let result = started.and_then(|()| {
+            time::sleep_ms(bme280::MEASUREMENT_TIME_MS).unwrap();
+            sensor.read(&mut i2c.lock())
+        });
+        match result {
+            Ok(measurement) => {
+                // Drop the reading if the consumer falls behind.
+                let _ = ctxt
+                    .readings
+                    .try_produce_allow_isr((time::get_tick(), measurement));
+            }
+            Err(err) => {
+                log::warn!("BME280: {}", err);
+                ctxt.sensor = None;
+            }
+        }
+    }
+
+    static ENV_LATEST: SpinSchedSafe<Option<EnvReading>> = SpinSchedSafe::new(None);
+
+    task::build()
+        .set_name("env")
+        .set_stack_pool(0)
+        .set_entry(move || loop {
+            let reading = consumer.consume();
+            *ENV_LATEST.lock() = Some(reading);
+        })
+        .spawn()
+        .unwrap();
+
+    shell::register("env", "show the BME280 reading", |_| {
+        match *ENV_LATEST.lock() {
+            Some((tick, m)) => console::println!(
+                "{} C, {}.{:02} %RH, {}.{:02} hPa, at tick {}",
+                temperature::Celsius(m.centi_celsius),
+                m.centi_percent_rh / 100,
+                m.centi_percent_rh % 100,
+                m.pascals / 100,
+                m.pascals % 100,
+                tick
+            ),
+            None => console::println!("no reading yet"),
+        }
+    });
+
+    // #########################
+    // # Part 23: SPI Display #
+    // #########################
+    //
+    // A cheap 240x240 TFT module with an ST7789 controller, wired to SPI1 as
+    // described in `drivers::st7789`, shows the state of the system. The
+    // `display` task redraws it every second with the uptime, the named
+    // tasks, the stacklets of Part 6, the chip temperature of Part 18, the
+    // reading of Part 22, and the microphone level of Part 20.
+    //
+    // The driver pushes the pixels to the display with DMA, band by band,
+    // while the task renders the next band. The text itself is kept in a
+    // framebuffer of one bit per pixel, provided by the `text_screen` module
+    // of this quick start. The framebuffer is allocated from the CCM when it
+    // is configured as the second heap region, see Part 7, which leaves the
+    // SRAM to the rest of the application. The DMA controllers cannot reach
+    // the CCM, so the driver expands the rows into its own band buffers in
+    // the SRAM before they are sent.
+    //
+    // The motion sensor on the board also sits on SPI1. Its chip select on
+    // PE3 is driven high to keep it off the bus.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    // Stream 5 of DMA2 goes to Part 24.
+    let dma2 = StreamsTuple::new(dp.DMA2);
+
+    #[cfg(not(feature = "static-alloc"))]
+    {
+        let gpioe = dp.GPIOE.split();
+        let _accel_cs = gpioe.pe3.into_push_pull_output_in_state(PinState::High);
+
+        let spi = Spi::new_bidi(
+            dp.SPI1,
+            (gpioa.pa5, gpioa.pa7),
+            st7789::SPI_MODE,
+            21.MHz(),
+            &clocks,
+        );
+        let mut display = St7789::new(
+            spi,
+            dma2.3,
+            gpioe.pe7.into_push_pull_output(),
+            gpioe.pe8.into_push_pull_output(),
+            gpioe.pe9.into_push_pull_output(),
+            &mut cp.NVIC,
+        );
+
+        let bits = match RegionHeap::get(1) {
+            Some(heap) => {
+                let mut bits = Vec::with_capacity_in(text_screen::BYTES, heap);
+                bits.resize(text_screen::BYTES, 0);
+                bits.leak()
+            }
+            None => alloc::vec![0; text_screen::BYTES].leak(),
+        };
+        let mut screen = TextScreen::new(bits);
+
+        task::build()
+            .set_name("display")
+            .set_stack_pool(0)
+            .set_entry(move || {
+                if let Err(err) = display.init() {
+                    log::warn!("display: {}", err);
+                    return;
+                }
+                let mut barrier = IntervalBarrier::new(1000).unwrap();
+                loop {
+                    barrier.wait();
+                    show_status(&mut screen);
+                    let result = display
+                        .draw(|row, out| screen.expand_row(row, out, DISPLAY_FG, DISPLAY_BG));
+                    if let Err(err) = result {
+                        log::warn!("display: {}", err);
+                    }
+                }
+            })
+            .spawn()
+            .unwrap();
+    }
+
+    // White on dark blue, in RGB565.
+    #[cfg(not(feature = "static-alloc"))]
+    const DISPLAY_FG: u16 = 0xffff;
+    #[cfg(not(feature = "static-alloc"))]
+    const DISPLAY_BG: u16 = 0x000f;
+
+    // Writing into a line of the screen never fails.
+    #[cfg(not(feature = "static-alloc"))]
+    fn show_status(screen: &mut TextScreen) {
+        let secs = time::get_tick() / 1000;
+        let _ = write!(
+            screen.line(0),
+            "UP {}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        let _ = write!(screen.line(1), "{} TASKS", task_name::names().count());
+        let _ = write!(
+            screen.line(2),
+            "{} STACKLETS",
+            segmented_stack::get_active_stacklet_count()
+        );
+        let _ = write!(
+            screen.line(3),
+            "{} EXTENSIONS",
+            segmented_stack::get_stack_extend_count()
+        );
+
+        let _ = match temperature::latest() {
+            Some(r) => write!(
+                screen.line(5),
+                "CHIP {} C",
+                temperature::Celsius(r.centi_celsius)
+            ),
+            None => write!(screen.line(5), "CHIP --"),
+        };
+
+        match *ENV_LATEST.lock() {
+            Some((_, m)) => {
+                let _ = write!(
+                    screen.line(7),
+                    "{} C",
+                    temperature::Celsius(m.centi_celsius)
+                );
+                let _ = write!(
+                    screen.line(8),
+                    "{}.{:02} %RH",
+                    m.centi_percent_rh / 100,
+                    m.centi_percent_rh % 100
+                );
+                let _ = write!(
+                    screen.line(9),
+                    "{}.{:02} HPA",
+                    m.pascals / 100,
+                    m.pascals % 100
+                );
+            }
+            None => {
+                let _ = write!(screen.line(7), "NO BME280");
+                screen.line(8);
+                screen.line(9);
+            }
+        }
+
+        let _ = write!(
+            screen.line(11),
+            "MIC PEAK {}",
+            MIC_PEAK.load(Ordering::SeqCst)
+        );
+    }
+    // ############################
+    // # Part 24: Addressable LEDs #
+    // ############################
+    //
+    // A strip of WS2812 LEDs takes its colors as pulses whose widths must be
+    // kept within a fraction of a microsecond, far below the tick of the
+    // kernel. A task toggling a pin could not keep that timing, since the
+    // scheduler preempts it at any time, IRQs interrupt it, and the SVCs
+    // allocating stacklets to it, see Part 6, delay it. The
+    // `drivers::ws2812` module of this quick start leaves the timing to
+    // hardware instead, TIM1 generating the pulses on PA8 and DMA feeding it
+    // their widths, so all of the above keep going during a write.
+    //
+    // Any task can write the strip through a copy of the handle returned by
+    // `init`. Here the `strip` task draws a rainbow moving along
+    // `STRIP_LEN` LEDs.
+
+    const STRIP_LEN: usize = 8;
+
+    let strip = ws2812::init(dp.TIM1, gpioa.pa8, dma2.5, &clocks, &mut cp.NVIC);
+
+    task::build()
+        .set_name("strip")
+        .set_stack_pool(0)
+        .set_entry(move || {
+            let mut barrier = IntervalBarrier::new(20).unwrap();
+            let mut colors = [Rgb::default(); STRIP_LEN];
+            for offset in (0..=u8::MAX).cycle() {
+                barrier.wait();
+                for (i, color) in colors.iter_mut().enumerate() {
+                    *color = rainbow(offset.wrapping_add((i * 256 / STRIP_LEN) as u8));
+                }
+                strip.write(&colors);
+            }
+        })
+        .spawn()
+        .unwrap();
+
+    // Return the color at the position along a rainbow, dimmed to spare the
+    // eyes and the supply.
+    fn rainbow(position: u8) -> Rgb {
+        let third = position % 85;
+        let (up, down) = (third * 3 / 8, (84 - third) * 3 / 8);
+        match position / 85 {
+            0 => Rgb::new(down, up, 0),
+            1 => Rgb::new(0, down, up),
+            _ => Rgb::new(up, 0, down),
+        }
+    }
+
+    // #####################
+    // # Part 25: CAN Bus #
+    // #####################
+    //
+    // CAN links the nodes of cars and machines over a shared pair of wires.
+    // The `can_node` module of this quick start drives CAN1 on PD0 (RX) and
+    // PD1 (TX) at 500 kbit/s and exchanges the typed messages of its
+    // `Message` enum. The IRQ of the receive FIFO decodes the frames and
+    // pushes the messages into a channel, like the bytes received by the
+    // console in Part 11, so a task consumes them without touching the
+    // controller.
+    //
+    // In loopback mode, the controller receives its own frames without
+    // driving the pins, so the part runs on a bare board. To join a bus,
+    // wire a 3.3 V transceiver to PD0 and PD1, give each board its own
+    // `CAN_NODE_ID`, and clear `CAN_LOOPBACK`. `init` then waits until the
+    // bus is idle. The `can` task sends a heartbeat and the chip temperature
+    // of Part 18 every second, then consumes the messages received since.
+    // Enter `can` in the shell for the traffic seen so far.
+
+    const CAN_NODE_ID: u8 = 1;
+    const CAN_LOOPBACK: bool = true;
+
+    static CAN_SENT: AtomicU32 = AtomicU32::new(0);
+    static CAN_RECEIVED: AtomicU32 = AtomicU32::new(0);
+    // The node and the count of the last heartbeat received.
+    static CAN_LAST_HEARTBEAT: SpinSchedSafe<Option<(u8, u32)>> = SpinSchedSafe::new(None);
+
+    let can_rx = can_node::init(
+        dp.CAN1,
+        gpiod.pd1,
+        gpiod.pd0,
+        CAN_LOOPBACK,
+        &clocks,
+        &mut cp.NVIC,
+    );
+
+    task::build()
+        .set_name("can")
+        .set_stack_pool(0)
+        .set_entry(move || {
+            let mut barrier = IntervalBarrier::new(1000).unwrap();
+            for count in 0.. {
+                barrier.wait();
+
+                can_node::send(Message::Heartbeat {
+                    node: CAN_NODE_ID,
+                    count,
+                });
+                CAN_SENT.fetch_add(1, Ordering::SeqCst);
+                if let Some(reading) = temperature::latest() {
+                    can_node::send(Message::Temperature {
+                        node: CAN_NODE_ID,
+                        centi_celsius: reading.centi_celsius,
+                    });
+                    CAN_SENT.fetch_add(1, Ordering::SeqCst);
+                }
+
+                // Non-blocking consumption is also allowed in task context.
+                while let Some(message) = can_rx.try_consume_allow_isr() {
+                    CAN_RECEIVED.fetch_add(1, Ordering::SeqCst);
+                    match message {
+                        Message::Heartbeat { node, count } => {
+                            *CAN_LAST_HEARTBEAT.lock() = Some((node, count));
+                        }
+                        Message::Temperature {
+                            node,
+                            centi_celsius,
+                        } => log::debug!(
+                            "node {} at {} C",
+                            node,
+                            temperature::Celsius(centi_celsius)
+                        ),
+                    }
+                }
+            }
+        })
+        .spawn()
+        .unwrap();
+
+    shell::register("can", "show the CAN traffic", |_| {
+        console::println!(
+            "sent {}, received {}, dropped {}, overruns {}",
+            CAN_SENT.load(Ordering::SeqCst),
+            CAN_RECEIVED.load(Ordering::SeqCst),
+            can_node::dropped(),
+            can_node::overruns()
+        );
+        if let Some((node, count)) = *CAN_LAST_HEARTBEAT.lock() {
+            console::println!("last heartbeat: node {} #{}", node, count);
+        }
+    });
+
+    // ################################
+    // # Part 26: Persistent Storage #
+    // ################################
+    //
+    // The RAM forgets everything upon a reset, and the backup SRAM of the
+    // panic record, see Part 3, upon a power loss. The `storage` module of
+    // this quick start keeps values by key in the last sector of the
+    // internal flash instead, which `memory.x` takes away from the program.
+    //
+    // Erasing or programming the flash stalls the CPU, so the module does
+    // both with every IRQ masked but SVC. Hopter grows the stacks through
+    // SVC, see Part 6, so masking every interrupt with the usual
+    // `cortex_m::interrupt::free` would fault as soon as the stack needs to
+    // grow. See `src/storage.rs` for details.
+    //
+    // Here the blue LED half period set in Part 5A survives resets. Enter
+    // `period 200` in the shell, reset the board, and the blue LED keeps
+    // toggling every 200 ms. Enter `period` alone to see the stored period and
+    // how much of the sector the records take.
+
+    const BLUE_PERIOD_KEY: u16 = 1;
+    const BLUE_PERIODS_MS: core::ops::RangeInclusive<u32> = 10..=5000;
+
+    storage::init(dp.FLASH);
+
+    if let Some(ms) = storage::get(BLUE_PERIOD_KEY) {
+        TIMER.lock().as_mut().unwrap().start(ms.millis()).unwrap();
+        log::info!("blue LED toggles every {} ms, as stored", ms);
+    }
+
+    shell::register(
+        "period",
+        "period [ms]: set and store the blue LED period",
+        |line| match line.args().next().map(str::parse::<u32>) {
+            Some(Ok(ms)) if BLUE_PERIODS_MS.contains(&ms) => {
+                TIMER.lock().as_mut().unwrap().start(ms.millis()).unwrap();
+                if let Err(err) = storage::set(BLUE_PERIOD_KEY, ms) {
+                    console::println!("cannot store the period: {}", err);
+                }
+            }
+            Some(_) => console::println!("usage: period [10..5000]"),
+            None => {
+                let (used, len) = storage::usage();
+                match storage::get(BLUE_PERIOD_KEY) {
+                    Some(ms) => console::println!("stored period {} ms", ms),
+                    None => console::println!("no stored period"),
+                }
+                console::println!("storage: {} of {} bytes used", used, len);
+            }
+        },
+    );
+
+    // ############################
+    // # Part 27: Firmware Update #
+    // ############################
+    //
+    // A board in the field is updated over its console rather than through
+    // the debug probe. Enter `update` in the shell, then send the image made
+    // by `make-update.sh` with the XMODEM function of the terminal. The
+    // `updater` module of this quick start programs the image into a staging
+    // region of the flash as it arrives, checks it, and only then copies it
+    // over the running program and resets. The shell hands the console over
+    // to the command for the transfer. See `src/updater.rs` for details.
+
+    shell::register_console(
+        "update",
+        "receive and install a new image by XMODEM",
+        updater::update,
+    );
+
+    // ###########################
+    // # Part 28: Watchdog Timer #
+    // ###########################
+    //
+    // A panicked task is unwound and restarted, as in Part 3, but a task
+    // stuck in a loop or waiting for a notification that never comes goes
+    // unnoticed. The `watchdog` module of this quick start starts the
+    // independent watchdog, which resets the system unless it is fed in
+    // time, and feeds it only while every task registered with
+    // `watchdog::register` keeps calling `watchdog::checkin` within the
+    // interval it declared.
+    //
+    // Below, the `sentry` task checks in every 500 ms and declares an
+    // interval of 2 s. Enter `wedge` in the shell to make it wait on a
+    // `Mailbox` that is never notified. It then misses its check-in, the
+    // watchdog task logs it, and the board resets some seconds later. The
+    // reset is reported here after the reboot.
+    //
+    // The watchdog cannot be stopped once started, so code run later that
+    // keeps the IRQs masked for long, e.g., flash erases, must stay well
+    // within `watchdog::TIMEOUT_MS`.
+
+    if watchdog::start(dp.IWDG, &dp.DBGMCU) {
+        log::warn!("the last reset was caused by the watchdog");
+    }
+
+    static WEDGED: AtomicBool = AtomicBool::new(false);
+
+    shell::register(
+        "wedge",
+        "stall the sentry task to trigger the watchdog",
+        |_| {
+            WEDGED.store(true, Ordering::SeqCst);
+        },
+    );
+
+    task::build()
+        .set_name("sentry")
+        .set_stack_pool(0)
+        .set_entry(sentry)
+        .spawn()
+        .unwrap();
+
+    fn sentry() {
+        watchdog::register(2000);
+        loop {
+            if WEDGED.load(Ordering::SeqCst) {
+                // Wait for a notification that never comes.
+                Mailbox::new().wait();
+            }
+            watchdog::checkin();
+            time::sleep_ms(500).unwrap();
+        }
+    }
+
+    // ########################
+    // # Part 29: Crash Dumps #
+    // ########################
+    //
+    // The fault report of Part 1 goes by once on the console, if anyone
+    // watches it. The `crash_dump` module of this quick start also saves the
+    // registers and the whole RAM, the control blocks and the stacks of the
+    // tasks included, to the CRASH region of the flash, where the dump stays
+    // across resets until it is erased. Enter `fault` in the shell, then
+    // `crash` after the reset for a summary. Capture the output of `crash
+    // dump` to a file, or read the region with
+    // `st-flash read crash.bin 0x80c0000 0x20000`, then decode it on the host
+    // computer with the `crash-decoder` crate, which lists the tasks and the
+    // code addresses found on their stacks. Enter `crash erase` to make room
+    // for the next dump. See `src/crash_dump.rs` for details.
+
+    crash_dump::init();
+    if let Some(len) = crash_dump::present() {
+        log::warn!("a crash dump of {} bytes is kept, see `crash`", len);
+    }
+
+    shell::register(
+        "crash",
+        "crash [dump|erase]: show, print, or erase the crash dump",
+        crash_dump::command,
+    );
+
+    // ################################
+    // # Part 30: CPU and Stack Usage #
+    // ################################
+    //
+    // A board running warm, or a task reacting late, calls for knowing which
+    // task keeps the CPU busy. The `monitor` module of this quick start
+    // samples the running task with the TIM4 IRQ about a thousand times per
+    // second, and counts the context switches by hooking PendSV, through
+    // which Hopter switches tasks. Every second, its task sums the counts up.
+    // Enter `top` in the shell for the share of the CPU and the number of
+    // times each task was switched in over the last second, or `top on` to
+    // print it every second. The idle task takes the time no task needs.
+    //
+    // The same hook, also placed on SVC, through which a task allocates and
+    // frees its stacklets, records the stacklets of the running task. Enter
+    // `ps` for the number of stacklets each task holds, the bytes they take
+    // from the heap, and the peak of those bytes, e.g., to tune
+    // `STACKLET_ADDITION_ALLOC_SIZE` or the stack limits. The table also
+    // shows the priority of each task, given with `set_name_and_priority`,
+    // whether it is ready, waiting, or ended, as told by the SVCs it makes,
+    // and how many times it panicked. Any task can print the table with
+    // `monitor::print_tasks`. See `src/monitor.rs` for details.
+    //
+    // The hooks read data private to Hopter, and are checked against hopter
+    // 0.2.3 only, so they are left out unless the `expert` feature is
+    // enabled. Without them, `top` shows the CPU usage alone, and `ps` the
+    // priorities alone.
+
+    monitor::start(dp.TIM4, &clocks, &mut cp.NVIC);
+
+    shell::register(
+        "top",
+        "top [on|off]: show the CPU usage of the tasks, or print it every second",
+        monitor::command,
+    );
+
+    // #######################
+    // # Part 31: Heap Usage #
+    // #######################
+    //
+    // Stacklets, `Box`, and `Arc` all come from the same heap, so the heap is
+    // the resource the tasks share the most. The `heap_stats` module of this
+    // quick start walks the chunks of the heap for the bytes used and free,
+    // and the largest free chunk, which bounds the largest block that can be
+    // allocated. Through the SVC hook of Part 30, it also counts the
+    // allocations and frees since boot, those of stacklets apart. Enter
+    // `free` in the shell for the figures, or `free on` to print them every
+    // ten seconds. A count of allocations growing faster than that of frees
+    // hints at a leak. The counts need the `expert` feature, as the hook
+    // does. See `src/heap_stats.rs` for details.
+
+    shell::register(
+        "free",
+        "free [on|off]: show the heap usage, or print it periodically",
+        heap_stats::command,
+    );
+
+    // ###############################
+    // # Part 32: Priority Inversion #
+    // ###############################
+    //
+    // Part 8 shows priority inheritance at work, but not the inversion it
+    // prevents. The `inversion` module of this quick start stages it with
+    // three tasks: a low priority one holding a lock, a high priority one
+    // waiting for it, and a medium priority one spinning in between. Enter
+    // `inversion` in the shell to run them twice. With a `Semaphore` used as
+    // the lock, which has no owner to inherit a priority, the high priority
+    // task waits for the medium one to finish spinning. With a `Mutex`, it
+    // waits only for the low priority one to finish its work. The waiting
+    // times are printed. See `src/inversion.rs` for details.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    shell::register(
+        "inversion",
+        "measure a priority inversion with a semaphore and with a mutex",
+        inversion::command,
+    );
+
+    // ############################
+    // # Part 33: Software Timers #
+    // ############################
+    //
+    // A small periodic chore hardly deserves a task of its own, with the
+    // stack and the task slot it takes. The `soft_timer` module of this
+    // quick start runs the callbacks of one-shot and periodic timers in a
+    // single `timers` task, which sleeps until the earliest deadline. A
+    // timer returns a handle, which cancels it. The callbacks share the
+    // stack of the task, so they should return quickly. See
+    // `src/soft_timer.rs` for details.
+    //
+    // Below, a periodic timer logs the uptime once a minute. Enter
+    // `heartbeat off` in the shell to cancel it, and `heartbeat on` to create
+    // it again. `heartbeat once` logs it a second later with a one-shot timer.
+
+    const HEARTBEAT_PERIOD_MS: u32 = 60_000;
+
+    static HEARTBEAT: SpinSchedSafe<Option<soft_timer::TimerHandle>> = SpinSchedSafe::new(None);
+
+    soft_timer::spawn();
+    *HEARTBEAT.lock() = soft_timer::periodic(HEARTBEAT_PERIOD_MS, heartbeat);
+
+    shell::register(
+        "heartbeat",
+        "heartbeat on|off|once: log the uptime every minute, stop, or log it once",
+        |line| match line.args().next() {
+            Some("on") => {
+                let mut handle = HEARTBEAT.lock();
+                if handle.is_none() {
+                    *handle = soft_timer::periodic(HEARTBEAT_PERIOD_MS, heartbeat);
+                }
+            }
+            Some("off") => {
+                if let Some(timer) = HEARTBEAT.lock().take() {
+                    timer.cancel();
+                }
+            }
+            Some("once") => {
+                if soft_timer::one_shot(1000, heartbeat).is_none() {
+                    console::println!("no timer free");
+                }
+            }
+            _ => console::println!("usage: heartbeat on|off|once"),
+        },
+    );
+
+    fn heartbeat() {
+        log::info!("up {} s", time::get_tick() / 1000);
+    }
+
+    // ##############################
+    // # Part 34: Microsecond Clock #
+    // ##############################
+    //
+    // The tick counts milliseconds, too coarse to time a short operation,
+    // and the cycle counter of the DWT wraps around within a minute. The
+    // `clock` module of this quick start counts microseconds on TIM5 into 64
+    // bits, which do not wrap around in the lifetime of the board.
+    // `clock::Instant::now` reads it from a task or an IRQ handler, and two
+    // readings give a `core::time::Duration`. See `src/clock.rs` for details.
+    //
+    // Enter `clock` in the shell for the reading, and for how long a
+    // one-millisecond sleep really takes, which depends on where in the tick
+    // period the sleep starts.
+
+    clock::init(dp.TIM5, &clocks, &mut cp.NVIC);
+
+    shell::register(
+        "clock",
+        "show the microsecond clock and time a 1 ms sleep",
+        |_| {
+            let start = clock::Instant::now();
+            time::sleep_ms(1).unwrap();
+            let slept = start.elapsed();
+            console::println!(
+                "clock {} us, sleep_ms(1) took {} us",
+                start.as_micros(),
+                slept.as_micros()
+            );
+        },
+    );
+
+    // ##########################################
+    // # Part 35: Deferred Interrupt Processing #
+    // ##########################################
+    //
+    // An IRQ handler holds up every task, and every handler of lower
+    // priority, for as long as it runs. The `bottom_half` module of this
+    // quick start lets a handler defer its work to the `bottom_half` task,
+    // which runs above the other tasks. The handler queues a function with a
+    // word of argument and returns, and the task calls the function. The
+    // queue is bounded and does not allocate. Entries arriving while it is
+    // full are dropped and counted. See `src/bottom_half.rs` for details.
+    //
+    // The TIM2 IRQ handler in Part 5B only acknowledges the IRQ there, and
+    // defers notifying the `blink_blue` task, checking the stack canary, and
+    // printing the nesting depth. Its task is started in Part 5A before the
+    // IRQ is unmasked. Enter `bottom_half` in the shell for the queue usage.
+
+    shell::register(
+        "bottom_half",
+        "show the work deferred by IRQ handlers and not yet run",
+        |_| {
+            console::println!(
+                "pending {}, peak {} of {}, dropped {}",
+                bottom_half::pending(),
+                bottom_half::peak(),
+                bottom_half::QUEUE_LEN,
+                bottom_half::dropped()
+            );
+        },
+    );
+
+    // ###############################
+    // # Part 36: Quadrature Encoder #
+    // ###############################
+    //
+    // A timer is more than a source of periodic IRQs. In encoder mode, it
+    // counts the edges of the two signals of a quadrature encoder, e.g., a
+    // rotary knob, up or down depending on which signal leads, with no help
+    // from the CPU. TIM3 takes the A and B signals on PB4 and PB5, pulled
+    // up, so that a mechanical encoder with its common pin grounded needs no
+    // other parts. Both edges of both signals are counted, i.e., four counts
+    // per step of the signals. The job timer of Part 14A was moved to TIM11
+    // to leave TIM3 to the encoder.
+    //
+    // The `encoder` task reads the counter every `ENCODER_PERIOD_MS`. The
+    // counter wraps around at 16 bits, so the change since the last reading
+    // is taken modulo 2^16, which holds as long as the encoder moves by less
+    // than 32768 counts in between. The task produces the change, scaled to
+    // counts per second, into a `Channel`. The `blink_speed` task consumes
+    // the velocities and blinks an LED on PB8, faster as the encoder turns
+    // faster, and not at all when it stands still. Enter `encoder` in the
+    // shell for the position and the velocity.
+
+    const ENCODER_PERIOD_MS: u32 = 50;
+
+    // Blink every `BLINK_SPEED_SCALE / speed` ms when turning at `speed`
+    // counts per second, i.e., every 250 ms at 96 counts per second, one turn
+    // of a knob with 24 detents. A single count in a period already reads as
+    // 20 counts per second.
+    const BLINK_SPEED_SCALE: u32 = 24_000;
+
+    // The position in counts and the velocity in counts per second.
+    static ENCODER_POSITION: AtomicI32 = AtomicI32::new(0);
+    static ENCODER_VELOCITY: AtomicI32 = AtomicI32::new(0);
+
+    let qei = dp.TIM3.qei((
+        gpiob.pb4.into_alternate().internal_pull_up(true),
+        gpiob.pb5.into_alternate().internal_pull_up(true),
+    ));
+    let speed_led = gpiob.pb8.into_push_pull_output();
+    let (velocities, consumer) = sync::create_channel();
+
+    task::build()
+        .set_name("encoder")
+        .set_stack_pool(0)
+        .set_entry(move || sample_encoder(qei, velocities))
+        .spawn()
+        .unwrap();
+
+    fn sample_encoder(qei: Qei<TIM3>, velocities: Producer<i32, 2>) {
+        let mut barrier = IntervalBarrier::new(ENCODER_PERIOD_MS).unwrap();
+        let mut last = qei.count();
+
+        loop {
+            barrier.wait();
+            let count = qei.count();
+            let delta = i32::from(count.wrapping_sub(last) as i16);
+            last = count;
+
+            let velocity = delta * (1000 / ENCODER_PERIOD_MS) as i32;
+            ENCODER_POSITION.fetch_add(delta, Ordering::SeqCst);
+            ENCODER_VELOCITY.store(velocity, Ordering::SeqCst);
+            // Drop the velocity if the consumer falls behind.
+            let _ = velocities.try_produce_allow_isr(velocity);
+        }
+    }
+
+    task::build()
+        .set_name("blink_speed")
+        .set_stack_pool(0)
+        .set_entry(move || blink_speed(speed_led, &consumer))
+        .spawn()
+        .unwrap();
+
+    fn blink_speed(mut speed_led: SpeedLed, velocities: &Consumer<i32, 2>) {
+        // The time since the last toggle.
+        let mut elapsed = 0;
+
+        loop {
+            // A velocity arrives every period, which paces the task.
+            let speed = velocities.consume().unsigned_abs();
+            elapsed += ENCODER_PERIOD_MS;
+            if speed == 0 {
+                speed_led.set_low();
+                elapsed = 0;
+            } else if elapsed >= BLINK_SPEED_SCALE / speed {
+                speed_led.toggle();
+                elapsed = 0;
+            }
+        }
+    }
+
+    shell::register("encoder", "show the encoder position and velocity", |_| {
+        console::println!(
+            "position {} counts, velocity {} counts/s",
+            ENCODER_POSITION.load(Ordering::SeqCst),
+            ENCODER_VELOCITY.load(Ordering::SeqCst)
+        );
+    });
+
+    // ######################
+    // # Part 37: Idle Time #
+    // ######################
+    //
+    // Whenever no task is ready, Hopter runs its idle task, which sleeps in
+    // `wfe` until an IRQ. How much of the time the board spends there sets
+    // how much current it draws, so it is worth seeing. The `idle_probe`
+    // module of this quick start is called back by the kernel whenever the
+    // idle task is switched in or out, and drives PB13 high while it runs.
+    // Probe PB13 with a logic analyzer for the sleep ratio, or use it to
+    // trigger a power analyzer and compare the current drawn while idle with
+    // that drawn while running. The module also counts the ticks spent idle.
+    // Enter `idle` in the shell for the share of the time spent idle since
+    // the command was last entered, and how many times the idle task was
+    // switched in meanwhile. See `src/idle_probe.rs` for details.
+
+    idle_probe::init(gpiob.pb13.into_push_pull_output().erase());
+
+    shell::register(
+        "idle",
+        "show the share of the time spent in the idle task",
+        idle_probe::command,
+    );
+
+    // ##############################
+    // # Part 38: Supply Monitoring #
+    // ##############################
+    //
+    // A board running from a battery, or from a weak regulator, sees its
+    // supply voltage sag long before it browns out, and a backup battery on
+    // VBAT runs down unnoticed until the RTC loses its time. The `supply`
+    // module of this quick start samples VREFINT, which gives VDD, and VBAT
+    // every 30 seconds, taking turns with the `temperature` task of Part 18
+    // on ADC1. It logs a warning when VDD falls below a threshold, and a
+    // notice when it recovers.
+    //
+    // The task is a breathing task, see Part 4, and a sampling period this
+    // long is where breathing pays off most: the task holds no stack for
+    // nearly all of its life. Enter `supply` in the shell for the latest
+    // reading and the sags seen. See `src/supply.rs` for details.
+
+    supply::spawn();
+
+    shell::register(
+        "supply",
+        "show VDD, VBAT, and the supply sags",
+        supply::command,
+    );
+
+    // ##########################
+    // # Part 39: Joining Tasks #
+    // ##########################
+    //
+    // A task handing work over to another one often needs the result back,
+    // and needs to know if the other task panicked instead. Hopter has no
+    // primitive to wait for a task to end. The `join` module of this quick
+    // start builds one from a `Mailbox` and a cell set once: it wraps a
+    // closure returning a value into the entry closure of a task, and returns
+    // a handle along with it. Joining the handle blocks until the task returns
+    // its value, or until the task is unwound after a panic, which the handle
+    // reports as an error. See `src/join.rs` for details.
+    //
+    // Below, the `worker` task computes the Adler-32 checksum of a buffer
+    // moved into it, and the main task waits at most a second for the sum.
+    // The `doomed` task panics midway, and the main task is told so rather
+    // than waiting forever.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    {
+        let data: Vec<u8> = (0..16 * 1024).map(|i| (i % 251) as u8).collect();
+        let (entry, worker) = join::joinable(move || adler32(&data));
+        task::build()
+            .set_name("worker")
+            .set_stack_pool(0)
+            .set_entry(entry)
+            .spawn()
+            .unwrap();
+        match worker.join_timeout(1000) {
+            Ok(Ok(sum)) => console::println!("worker: Adler-32 {:#010x}", sum),
+            Ok(Err(join::Panicked)) => console::println!("worker panicked"),
+            Err(_) => console::println!("worker still running after 1 s"),
+        }
+
+        let (entry, doomed) = join::joinable(|| -> u32 { panic!("doomed worker gave up") });
+        task::build()
+            .set_name("doomed")
+            .set_stack_pool(0)
+            .set_entry(entry)
+            .spawn()
+            .unwrap();
+        match doomed.join() {
+            Ok(sum) => console::println!("doomed: returned {}", sum),
+            Err(join::Panicked) => console::println!("doomed: panicked, as expected"),
+        }
+    }
+
+    // ##########################
+    // # Part 40: Killing Tasks #
+    // ##########################
+    //
+    // Parts 3 and 6 show tasks ended by a panic and by a stack overflow, both
+    // from within the task. A task may also need to be ended from outside,
+    // e.g., an operator stopping a job gone astray. Hopter has no call to end
+    // another task, as a task ended at an arbitrary point would leave its
+    // locks held and its heap allocations leaked. The `kill` module of this
+    // quick start lets a task be ended at the points it chooses instead: the
+    // task blocks and sleeps through a kill switch, and a request to end it
+    // wakes it up and makes it panic. Hopter unwinds it, which drops its
+    // locals, frees its heap allocations, and frees its stacklets. See
+    // `src/kill.rs` for details.
+    //
+    // Below, the `idler` task waits for jobs on its mailbox that never come,
+    // holding a 4 KiB buffer, and the `cruncher` task grows a 4 KiB buffer
+    // step by step, resting a second between steps. Enter `kill` in the shell
+    // for the killable tasks, and `kill idler` to end one of them. Entering
+    // `ps` then shows the task ended with one panic, its stacklets freed, and
+    // `free` shows its buffer given back to the heap.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    {
+        static IDLER: kill::KillSwitch = kill::KillSwitch::new();
+        static CRUNCHER: kill::KillSwitch = kill::KillSwitch::new();
+
+        kill::register("idler", &IDLER);
+        task::build()
+            .set_name("idler")
+            .set_stack_pool(0)
+            .set_entry(|| {
+                let buffer = alloc::vec![0u8; 4096];
+                loop {
+                    IDLER.wait();
+                    console::println!("idler: job on {} bytes", buffer.len());
+                }
+            })
+            .spawn()
+            .unwrap();
+
+        kill::register("cruncher", &CRUNCHER);
+        task::build()
+            .set_name("cruncher")
+            .set_stack_pool(0)
+            .set_entry(|| {
+                let mut buffer = Vec::with_capacity(4096);
+                for i in 0.. {
+                    if buffer.len() == buffer.capacity() {
+                        buffer.clear();
+                    }
+                    buffer.extend((0..64).map(|j| (i + j) as u8));
+                    CRUNCHER.sleep_ms(1000);
+                }
+            })
+            .spawn()
+            .unwrap();
+    }
+
+    #[cfg(not(feature = "static-alloc"))]
+    shell::register("kill", "kill [task]: end a killable task", kill::command);
+
+    // #############################
+    // # Part 41: Orderly Shutdown #
+    // #############################
+    //
+    // A reset throws away the state that tasks keep in the RAM. The `reboot`
+    // shell command, and the firmware update of Part 27, thus shut the tasks
+    // down before resetting. The `shutdown` module of this quick start tells
+    // every registered task of the shutdown, and waits up to two seconds for
+    // each of them to save its state and report back. A task that fails to
+    // report back is logged, and the reset goes ahead anyway. See
+    // `src/shutdown.rs` for details.
+    //
+    // Below, the `odometer` task keeps the run time of the board summed over
+    // its boots. Writing it to the storage of Part 26 every second would wear
+    // the flash out, so the task only writes it upon a shutdown. Enter
+    // `odometer` in the shell, then `reboot`, and the run time before the
+    // reboot is carried over. A reset by the button or the watchdog loses
+    // the run time since boot.
+
+    const ODOMETER_KEY: u16 = 2;
+    static ODOMETER: shutdown::Notice = shutdown::Notice::new();
+
+    shutdown::register("odometer", &ODOMETER);
+    task::build()
+        .set_name("odometer")
+        .set_stack_pool(0)
+        .set_entry(|| {
+            ODOMETER.wait();
+            let secs = storage::get(ODOMETER_KEY).unwrap_or(0) + time::get_tick() / 1000;
+            if let Err(err) = storage::set(ODOMETER_KEY, secs) {
+                log::error!("cannot store the run time: {}", err);
+            }
+            ODOMETER.done();
+        })
+        .spawn()
+        .unwrap();
+
+    shell::register("odometer", "show the run time summed over boots", |_| {
+        let secs = storage::get(ODOMETER_KEY).unwrap_or(0) + time::get_tick() / 1000;
+        console::println!(
+            "run {}:{:02}:{:02} over boots",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+    });
+
+    // ######################################
+    // # Part 42A: Spawning Tasks from IRQs #
+    // ######################################
+    //
+    // See Part 42B for more descriptions.
+    //
+    // The part is left out under the `static-alloc` feature.
+
+    #[cfg(not(feature = "static-alloc"))]
+    {
+        isr_spawn::spawn();
+
+        unsafe {
+            cp.NVIC
+                .set_priority(stm32f4xx_hal::pac::interrupt::EXTI1, IRQ_NORMAL_PRIORITY);
+            cortex_m::peripheral::NVIC::unmask(stm32f4xx_hal::pac::interrupt::EXTI1);
+        }
+
+        shell::register(
+            "raise",
+            "raise [n]: raise the stand-in error IRQ n times",
+            |line| {
+                let times = line.args().next().map_or(Ok(1), str::parse::<u32>);
+                let Ok(times @ 1..=100) = times else {
+                    console::println!("usage: raise [1..100]");
+                    return;
+                };
+                for _ in 0..times {
+                    cortex_m::peripheral::NVIC::pend(stm32f4xx_hal::pac::interrupt::EXTI1);
+                    time::sleep_ms(10).unwrap();
+                }
+                console::println!(
+                    "spawned {}, merged {}, dropped {}",
+                    isr_spawn::spawned(),
+                    isr_spawn::merged(),
+                    isr_spawn::dropped()
+                );
+            },
+        );
+    }
+
+    // ######################################
+    // # Part 43: Waiting on Several Events #
+    // ######################################
+    //
+    // A task often has to react to whichever of several things happens
+    // first, e.g., a button press, a timer, or a message. A task blocks on
+    // one primitive at a time, though, and Hopter has no call waiting on
+    // several. The `event_mux` module of this quick start multiplexes them:
+    // each source sets a bit of a shared set of events and notifies a single
+    // mailbox, on which the task waits. The task is handed all the events
+    // set since it last looked. See `src/event_mux.rs` for details.
+    //
+    // Below, the `select` task reacts to the presses of the user button,
+    // signalled by the `button` task of Part 17A, to a software timer firing
+    // every 15 seconds, see Part 33, and to numbers posted through a channel
+    // by the `post` shell command. It also notes when nothing happened for a
+    // minute. Enter `post 42` in the shell, or press the button, and watch
+    // the log.
+
+    const SELECT_TIMER_PERIOD_MS: u32 = 15_000;
+    const SELECT_IDLE_MS: u32 = 60_000;
+    const BUTTON_EVENT: u32 = 1 << 0;
+    const TIMER_EVENT: u32 = 1 << 1;
+    const POST_EVENT: u32 = 1 << 2;
+
+    static SELECT_EVENTS: event_mux::EventMux = event_mux::EventMux::new();
+    static POSTS: SpinSchedSafe<Option<Producer<u32, 4>>> = SpinSchedSafe::new(None);
+
+    let (producer, posts) = sync::create_channel();
+    *POSTS.lock() = Some(producer);
+
+    if soft_timer::periodic(SELECT_TIMER_PERIOD_MS, || {
+        SELECT_EVENTS.signal_allow_isr(TIMER_EVENT)
+    })
+    .is_none()
+    {
+        log::warn!("no timer free for the select task");
+    }
+
+    task::build()
+        .set_name("select")
+        .set_stack_pool(0)
+        .set_entry(move || select(&posts))
+        .spawn()
+        .unwrap();
+
+    shell::register("post", "post n: send a number to the select task", |line| {
+        let Some(Ok(n)) = line.args().next().map(str::parse::<u32>) else {
+            console::println!("usage: post n");
+            return;
+        };
+        let posted = POSTS
+            .lock()
+            .as_ref()
+            .unwrap()
+            .try_produce_allow_isr(n)
+            .is_ok();
+        if posted {
+            SELECT_EVENTS.signal_allow_isr(POST_EVENT);
+        } else {
+            console::println!("the select task is behind, {} dropped", n);
+        }
+    });
+
+    fn select(posts: &Consumer<u32, 4>) {
+        loop {
+            let events = SELECT_EVENTS.wait_until_timeout(SELECT_IDLE_MS);
+            if events == 0 {
+                log::info!("select: nothing for {} s", SELECT_IDLE_MS / 1000);
+            }
+            if events & BUTTON_EVENT != 0 {
+                log::info!("select: button pressed");
+            }
+            if events & TIMER_EVENT != 0 {
+                log::info!("select: timer fired");
+            }
+            if events & POST_EVENT != 0 {
+                // The event stands for every number posted since the last
+                // wake-up, so drain the channel.
+                while let Some(n) = posts.try_consume_allow_isr() {
+                    log::info!("select: {} posted", n);
+                }
+            }
+        }
+    }
+
+    // ##################################
+    // # Part 44: Allocation-Free Tasks #
+    // ##################################
+    //
+    // Some systems must show that they never touch the heap once running, so
+    // that no allocation can fail or fragment the heap after hours of
+    // operation. Spawning a task in Hopter always takes from the heap: the
+    // task itself, its stack, and its boxed entry closure. Hopter offers no
+    // way to place them in static storage instead. What can be guaranteed is
+    // that a task takes nothing more from the heap after it is spawned:
+    //
+    // - The task is spawned during initialization, and never ends.
+    // - Its state lives in statics with const initializers rather than
+    //   behind an `Arc`, so the entry closure captures nothing. A closure
+    //   capturing nothing has no size, and boxing it takes no heap.
+    // - Its stack is allocated in full when it is spawned. Under the
+    //   `static-alloc` feature, `set_stack_pool` does that. Otherwise, the
+    //   initial stacklet is made large enough never to be extended, see Part
+    //   30 on stacklets.
+    // - It blocks and sleeps only on Hopter primitives, which link waiting
+    //   tasks through intrusive lists and take no heap. Creating a channel
+    //   does allocate, so channels are created before the task is spawned.
+    //
+    // Below, the `sampler` task wakes up every 100 ms, records the time since
+    // its previous wake-up, and notifies the `averager` task, which keeps the
+    // mean and the longest interval. Each task has its heap operations
+    // counted apart by the `heap_stats` module of Part 31, through `watch`.
+    // With the `expert` feature, enter `steady` in the shell for the counts,
+    // which stay at zero.
+
+    const SAMPLE_PERIOD_MS: u32 = 100;
+    const STEADY_STACK_BYTES: usize = 1024;
+
+    static LAST_INTERVAL: AtomicU32 = AtomicU32::new(0);
+    static SAMPLED: Mailbox = Mailbox::new();
+    static INTERVAL_SUM: AtomicU32 = AtomicU32::new(0);
+    static INTERVAL_COUNT: AtomicU32 = AtomicU32::new(0);
+    static INTERVAL_MAX: AtomicU32 = AtomicU32::new(0);
+
+    task::build()
+        .set_name("sampler")
+        .set_stack_init_size(STEADY_STACK_BYTES)
+        .set_stack_pool(0)
+        .set_entry(sampler)
+        .spawn()
+        .unwrap();
+    task::build()
+        .set_name("averager")
+        .set_stack_init_size(STEADY_STACK_BYTES)
+        .set_stack_pool(0)
+        .set_entry(averager)
+        .spawn()
+        .unwrap();
+
+    shell::register(
+        "steady",
+        "steady: show the heap operations of the allocation-free tasks",
+        |_| {
+            for (id, name) in task_name::names() {
+                if let Some(operations) = heap_stats::operations_of(id) {
+                    console::println!("{}: {} heap operations", name, operations);
+                }
+            }
+            let count = INTERVAL_COUNT.load(Ordering::SeqCst).max(1);
+            console::println!(
+                "sampling interval: {} ms mean, {} ms longest",
+                INTERVAL_SUM.load(Ordering::SeqCst) / count,
+                INTERVAL_MAX.load(Ordering::SeqCst)
+            );
+        },
+    );
+
+    fn sampler() {
+        heap_stats::watch();
+        let mut barrier = IntervalBarrier::new(SAMPLE_PERIOD_MS).unwrap();
+        let mut last = time::get_tick();
+        loop {
+            barrier.wait();
+            let now = time::get_tick();
+            LAST_INTERVAL.store(now.wrapping_sub(last), Ordering::SeqCst);
+            last = now;
+            SAMPLED.notify_allow_isr();
+        }
+    }
+
+    fn averager() {
+        heap_stats::watch();
+        loop {
+            SAMPLED.wait();
+            let interval = LAST_INTERVAL.load(Ordering::SeqCst);
+            INTERVAL_SUM.fetch_add(interval, Ordering::SeqCst);
+            INTERVAL_COUNT.fetch_add(1, Ordering::SeqCst);
+            INTERVAL_MAX.fetch_max(interval, Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(not(feature = "static-alloc"))]
+    fn adler32(data: &[u8]) -> u32 {
+        const MOD: u32 = 65521;
+        let (a, b) = data.iter().fold((1, 0), |(a, b), &byte| {
+            let a = (a + u32::from(byte)) % MOD;
+            (a, (b + a) % MOD)
+        });
+        b << 16 | a
+    }
+}
+
+// ################################################
+// # Part 5B: IRQs and Synchronization Primitives #
+// ################################################
+//
+// Hopter provides synchronization primitives under `hopter::sync`, which can
+// be used for synchronization among tasks. A subset of them can be used also
+// for synchronization between IRQs and tasks, including `Mailbox`, `Semaphore`,
+// and `Channel`. The methods that allow calling from IRQ handler context are
+// those with name ending in `allow_isr`.
+//
+// Some lock types with their names ending in `IrqSafe` are designed to use
+// with IRQ handlers. When these locks are acquired, the corresponding IRQ will
+// also be masked to avoid deadlock. The `irq!` macro generates types to
+// represent IRQs, which can be passed to the `IrqSafe` locks.
+//
+// The handler of a specific IRQ is marked with `#[handler(IRQ_NAME)]`.
+// Panicking inside a handler will not cause the system to hang, either. The
+// handler will be forced to return with resources reclaimed.
+
+// Generate the `Tim2Irq` type that represents the TIM2 IRQ.
+irq!(Tim2Irq, stm32f4xx_hal::pac::interrupt::TIM2);
+
+// The global `TIMER` variable is protected by the spin lock. TIM2 IRQ will be
+// masked when the lock is acquired.
+static TIMER: SpinIrqSafe<Option<CounterUs<TIM2>>, Tim2Irq> = SpinIrqSafe::new(None);
+
+// Provide synchronization between the IRQ handler and the task.
+static MAILBOX: Mailbox = Mailbox::new();
+
+// The handler below does only what cannot wait, and defers the rest to the
+// `bottom_half` task. See Part 35.
+
+#[handler(TIM2)]
+fn tim2_handler() {
+    // Count the handler as nested above the active ones. It panics if IRQs
+    // nest deeper than `MAX_IRQ_NESTING_DEPTH` in
+    // `hopter-conf-params/src/lib.rs`.
+    let nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    TIMER.lock().as_mut().unwrap().wait().unwrap();
+
+    // Pass the nesting depth if it is a new peak, and zero otherwise.
+    let peak = if nesting.is_new_peak() {
+        nesting.depth() as u32
+    } else {
+        0
+    };
+    bottom_half::defer(tim2_bottom_half, peak);
+}
+
+// Run by the `bottom_half` task upon each TIM2 IRQ.
+fn tim2_bottom_half(peak: u32) {
+    // Notify the `blink_blue` task.
+    MAILBOX.notify_allow_isr();
+
+    // Detect an overflow of the contiguous stack, which IRQ handlers run on.
+    stack_guard::check();
+
+    // Report the nesting depth whenever it reaches a new peak, if a debugger
+    // is attached to print through semihosting.
+    if peak > 0 && DCB::is_debugger_attached() {
+        dbg_println!(
+            "TIM2: IRQ nesting depth {} of at most {}",
+            peak,
+            MAX_IRQ_NESTING_DEPTH
+        );
+    }
+}
+
+// #################################
+// # Part 14B: Counting Semaphores #
+// #################################
+//
+// A `Semaphore` holds a count bounded by a maximum. `down` blocks the calling
+// task while the count is zero and then decrements it, and `up` increments it,
+// blocking while it is at the maximum. Their non-blocking counterparts
+// `try_down_allow_isr` and `try_up_allow_isr` can be called from IRQ handlers.
+//
+// Below, the TIM11 IRQ handler posts jobs by incrementing the `JOBS` count, and
+// each of the three workers from Part 14A takes a job by decrementing it. The
+// count is the number of jobs posted but not yet taken. When it is at the
+// maximum, i.e., the workers fall behind, the handler drops the job instead of
+// blocking. The workers log through the `log` crate at the debug level, so
+// pass `log::LevelFilter::Debug` in Part 13 to watch them.
+
+// TIM11 shares its IRQ with the trigger and commutation events of TIM1,
+// which are not enabled.
+irq!(Tim11Irq, stm32f4xx_hal::pac::interrupt::TIM1_TRG_COM_TIM11);
+
+static JOB_TIMER: SpinIrqSafe<Option<Counter<TIM11, 10_000>>, Tim11Irq> = SpinIrqSafe::new(None);
+
+// At most three jobs pending, initially none.
+static JOBS: Semaphore = Semaphore::new(3, 0);
+
+#[handler(TIM1_TRG_COM_TIM11)]
+fn tim11_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Post a job, or drop it if too many are pending.
+    if JOBS.try_up_allow_isr().is_err() {
+        log::warn!("job dropped");
+    }
+
+    // Acknowledge the IRQ.
+    JOB_TIMER.lock().as_mut().unwrap().wait().unwrap();
+}
+
+// #############################
+// # Part 17B: GPIO Interrupts #
+// #############################
+//
+// Each EXTI line raises an IRQ upon an edge on a GPIO pin. Line 0 serves pin
+// 0 of the port selected through SYSCFG, here PA0 wired to the user button.
+// Like TIM2 in Part 5B, the handler only acknowledges the IRQ and leaves the
+// rest to a task. A bouncing contact raises the IRQ several times per press,
+// so the `button` task from Part 17A filters the edges in task context, where
+// it can sleep, before cycling the blue LED through its modes.
+//
+// The glue is provided by the `DebouncedButton` type of `drivers::button` in
+// this quick start, which also offers `wait_for_long_press`. It keeps the pin
+// in a `SpinIrqSafe` lock on EXTI0. Each such lock masks only its own IRQ, so
+// the `button` task holding `TIMER` to change the blue LED mode masks TIM2
+// but not EXTI0.
+
+irq!(Exti0Irq, stm32f4xx_hal::pac::interrupt::EXTI0);
+
+// Pressed when high. The edges within 200 ms after a press are bounces.
+static BUTTON: DebouncedButton<PA0<Input>, Exti0Irq> = DebouncedButton::new(true, 200);
+
+#[handler(EXTI0)]
+fn exti0_handler() {
+    let _nesting = irq_nesting::enter();
+    BUTTON.handle_irq();
+}
+
+// ##########################
+// # Part 19B: Audio Output #
+// ##########################
+//
+// The CS43L22 codec on the board turns a stream of samples into sound on the
+// headphone jack. The `drivers::cs43l22` module of this quick start configures
+// the codec through I2C and sets up SPI3 in I2S mode to stream the samples.
+// The stream must never run dry, while the CPU has better things to do than
+// feeding SPI3 every 10 us, so a DMA stream moves the samples instead.
+//
+// In double buffer mode, the DMA stream alternates between two buffers. Once
+// it has played one, it switches to the other without a gap and raises the
+// transfer-complete IRQ. The handler notifies the `audio` task, which refills
+// the buffer just played while the other one plays. The refill must finish
+// within the playing time of a buffer, otherwise the same samples play
+// again and the tone is heard with a glitch.
+//
+// The task waits most of the time, so it is a breathing task, see Part 4. Its
+// stack is released between refills.
+//
+// The buffers are written by the task and read by the DMA behind the back of
+// the compiler, so they are never handed out as Rust references. The DMA gets
+// each of them once as an `AudioBuffer`, and the task writes through raw
+// pointers to the one the DMA is not playing.
+
+// The sample rate requested from the I2S clock.
+const AUDIO_SAMPLE_RATE_HZ: u32 = 48_000;
+
+// The master volume of the codec in percent.
+const AUDIO_VOLUME: u8 = 70;
+
+// The number of stereo frames in each buffer, 10 ms at 48 kHz.
+const AUDIO_BUFFER_FRAMES: usize = 480;
+
+// A frame holds a 16-bit sample for the left channel then for the right one.
+static AUDIO_BUFFERS: [AudioStorage; 2] = [AudioStorage::new(), AudioStorage::new()];
+
+struct AudioStorage {
+    // Set once the buffer has been handed to the DMA.
+    taken: AtomicBool,
+    samples: UnsafeCell<[u16; 2 * AUDIO_BUFFER_FRAMES]>,
+}
+
+// Safety: The samples are only accessed through raw pointers, by the DMA and
+// by the `audio` task.
+unsafe impl Sync for AudioStorage {}
+
+impl AudioStorage {
+    const fn new() -> Self {
+        Self {
+            taken: AtomicBool::new(false),
+            samples: UnsafeCell::new([0; 2 * AUDIO_BUFFER_FRAMES]),
+        }
+    }
+}
+
+// The right of the DMA to read one of `AUDIO_BUFFERS`.
+struct AudioBuffer(usize);
+
+impl AudioBuffer {
+    // Return the right to read the buffer at the index, or `None` if it was
+    // already taken.
+    fn take(index: usize) -> Option<Self> {
+        if AUDIO_BUFFERS[index].taken.swap(true, Ordering::SeqCst) {
+            None
+        } else {
+            Some(Self(index))
+        }
+    }
+}
+
+// Safety: The buffers are statics, so their location is stable.
+unsafe impl ReadBuffer for AudioBuffer {
+    type Word = u16;
+
+    unsafe fn read_buffer(&self) -> (*const u16, usize) {
+        (
+            AUDIO_BUFFERS[self.0].samples.get().cast(),
+            2 * AUDIO_BUFFER_FRAMES,
+        )
+    }
+}
+
+type AudioTransfer = Transfer<Stream5<DMA1>, 0, I2sOut, MemoryToPeripheral, AudioBuffer>;
+
+irq!(Dma1Stream5Irq, stm32f4xx_hal::pac::interrupt::DMA1_STREAM5);
+
+// The running transfer. The DMA IRQ is masked when the lock is held.
+static AUDIO_TRANSFER: SpinIrqSafe<Option<AudioTransfer>, Dma1Stream5Irq> = SpinIrqSafe::new(None);
+
+// Notified by the handler whenever a buffer has been played.
+static AUDIO_REFILL: Mailbox = Mailbox::new();
+
+// The number of buffers played, and of those refilled by the `audio` task.
+static AUDIO_PLAYED: AtomicU32 = AtomicU32::new(0);
+static AUDIO_REFILLED: AtomicU32 = AtomicU32::new(0);
+
+// The sample rate actually achieved by the I2S clock.
+static AUDIO_SAMPLE_RATE: AtomicU32 = AtomicU32::new(AUDIO_SAMPLE_RATE_HZ);
+
+// The tone frequency, or 0 for silence. Changed by the `tone` shell command.
+static TONE_HZ: AtomicU32 = AtomicU32::new(440);
+
+// One period of a sine wave at full scale.
+const SINE: [i16; 64] = [
+    0, 3211, 6392, 9511, 12539, 15446, 18204, 20787, 23169, 25329, 27244, 28897, 30272, 31356,
+    32137, 32609, 32767, 32609, 32137, 31356, 30272, 28897, 27244, 25329, 23169, 20787, 18204,
+    15446, 12539, 9511, 6392, 3211, 0, -3211, -6392, -9511, -12539, -15446, -18204, -20787, -23169,
+    -25329, -27244, -28897, -30272, -31356, -32137, -32609, -32767, -32609, -32137, -31356, -30272,
+    -28897, -27244, -25329, -23169, -20787, -18204, -15446, -12539, -9511, -6392, -3211,
+];
+
+// A sine tone generator. The phase is a fraction of a period in 1/2^32 units,
+// so it wraps around at the end of each period.
+struct Tone {
+    phase: u32,
+}
+
+impl Tone {
+    fn new() -> Self {
+        Self { phase: 0 }
+    }
+
+    // Return the next sample of a tone at the given frequency, at an eighth
+    // of the full scale to spare the ears.
+    fn next(&mut self, hz: u32, sample_rate: u32) -> i16 {
+        let step = ((u64::from(hz) << 32) / u64::from(sample_rate)) as u32;
+        let sample = SINE[(self.phase >> 26) as usize] >> 3;
+        self.phase = self.phase.wrapping_add(step);
+        sample
+    }
+}
+
+fn refill_audio(tone: &mut Tone) {
+    // The buffer not being played is the one just played.
+    let index = {
+        let mut transfer = AUDIO_TRANSFER.lock();
+        // Safety: The stream is only read.
+        let stream = unsafe { transfer.as_mut().unwrap().stream() };
+        match stream.current_buffer() {
+            CurrentBuffer::FirstBuffer => 1,
+            CurrentBuffer::SecondBuffer => 0,
+        }
+    };
+
+    // A buffer played while the task was late was played again.
+    let played = AUDIO_PLAYED.load(Ordering::SeqCst);
+    let refilled = AUDIO_REFILLED.fetch_add(1, Ordering::SeqCst) + 1;
+    if played > refilled {
+        AUDIO_REFILLED.store(played, Ordering::SeqCst);
+        log::warn!("audio underrun, {} buffers replayed", played - refilled);
+    }
+
+    let hz = TONE_HZ.load(Ordering::SeqCst);
+    let sample_rate = AUDIO_SAMPLE_RATE.load(Ordering::SeqCst);
+    let samples = AUDIO_BUFFERS[index].samples.get().cast::<u16>();
+    for frame in 0..AUDIO_BUFFER_FRAMES {
+        let sample = if hz == 0 {
+            0
+        } else {
+            tone.next(hz, sample_rate)
+        };
+        // Safety: The index is within the buffer, which the DMA does not read
+        // until it switches to it. The write is volatile because the DMA
+        // reads the buffer.
+        unsafe {
+            ptr::write_volatile(samples.add(2 * frame), sample as u16);
+            ptr::write_volatile(samples.add(2 * frame + 1), sample as u16);
+        }
+    }
+}
+
+#[handler(DMA1_STREAM5)]
+fn dma1_stream5_handler() {
+    let _nesting = irq_nesting::enter();
+
+    // Acknowledge the IRQ.
+    AUDIO_TRANSFER
+        .lock()
+        .as_mut()
+        .unwrap()
+        .clear_flags(DmaFlag::TransferComplete);
+
+    AUDIO_PLAYED.fetch_add(1, Ordering::SeqCst);
+    AUDIO_REFILL.notify_allow_isr();
+}
+
+// ######################################
+// # Part 42B: Spawning Tasks from IRQs #
+// ######################################
+//
+// Some events are rare but call for heavy work, e.g., an error from which a
+// peripheral must be recovered by resetting and configuring it again. The
+// work is too long for the handler, and even for the `bottom_half` task of
+// Part 35, which must keep up with every IRQ. Nor does it belong to any of
+// the existing tasks. It rather belongs in a task of its own, spawned when
+// the event happens, which runs at a low priority, may block, and frees its
+// stacklets once it ends.
+//
+// Hopter spawns tasks through SVC, which an IRQ handler cannot issue. The
+// `isr_spawn` module of this quick start thus queues the request from the
+// handler, and its `spawner` task spawns the task. While the task of a job is
+// pending or running, further requests for the job are merged into it, so
+// that an error storm spawns a single task. See `src/isr_spawn.rs` for
+// details.
+//
+// Here EXTI1, wired to no pin, stands in for the error IRQ of a peripheral.
+// Enter `raise` in the shell to pend it, and `ps` to see the `recovery` task
+// before it ends. Enter `raise 20` to see the requests merged.
+//
+// The task of each job takes a stack of its own, which a stack pool would
+// never give back, so the part is left out under the `static-alloc` feature.
+
+// The errors raised since boot.
+#[cfg(not(feature = "static-alloc"))]
+static ERRORS: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(not(feature = "static-alloc"))]
+static RECOVERY: isr_spawn::Job =
+    isr_spawn::Job::new("recovery", config::DEFAULT_TASK_PRIORITY + 1, recover);
+
+#[cfg(not(feature = "static-alloc"))]
+#[handler(EXTI1)]
+fn exti1_handler() {
+    let _nesting = irq_nesting::enter();
+    let error = ERRORS.fetch_add(1, Ordering::SeqCst) + 1;
+    isr_spawn::spawn_allow_isr(&RECOVERY, error);
+}
+
+// Recover the peripheral from the error numbered `error`. Here the steps of
+// the recovery only take time.
+#[cfg(not(feature = "static-alloc"))]
+fn recover(error: u32) {
+    log::info!("recovering from error {}", error);
+    for step in 1..=3 {
+        time::sleep_ms(200).unwrap();
+        log::info!("recovery step {} of 3 done", step);
+    }
 }
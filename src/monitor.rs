@@ -0,0 +1,643 @@
+//! Per-task CPU usage, context switch counts, stack usage, and states.
+//!
+//! Hopter measures only the time spent in the idle task, see
+//! `hopter::debug::cpu_load`. This module also tells which task takes the
+//! rest. TIM4 fires at [`SAMPLE_HZ`] and its IRQ handler counts a sample for
+//! the task it interrupted. The rate is prime, so that the samples drift
+//! across the tick period rather than land at the same point of it, where
+//! the tasks woken by the tick would always, or never, be seen running.
+//!
+//! The rest of the module is only built with the `expert` feature, since it
+//! reads data private to the kernel: the context of the running task, the
+//! panic handler, and the headers of the heap chunks. It is checked against
+//! hopter 0.2.3 only, and may break with any other version. Without the
+//! feature, `top` shows the CPU usage alone, and `ps` shows no more than the
+//! names and priorities of the tasks.
+//!
+//! The kernel switches tasks only in PendSV. [`init`] routes the PendSV
+//! entry of the vector table in the RAM, see the `fault` module, through a
+//! trampoline that counts a switch for the running task whenever it is not
+//! the one seen by the previous PendSV, i.e., whenever the previous PendSV
+//! switched it in. Before the handler runs, the trampoline sets the stacklet
+//! boundary of the contiguous stack, as the IRQ entry of Hopter does.
+//!
+//! The sampling IRQ has the priority of the other IRQs, so it does not
+//! interrupt them. Their time is counted for the task they interrupted. A
+//! sample that interrupts the kernel, i.e., PendSV or SVC, is counted apart
+//! without looking up the task, because the kernel may be switching it.
+//!
+//! The SVC entry goes through the same trampoline. Upon both exceptions, the
+//! stacklets of the running task are counted, along with the bytes they take
+//! from the heap, read from the headers of the heap chunks. A task frees a
+//! stacklet only through SVC, so the peak is seen just before it. The
+//! hot-split prevention cache of a task, which sizes the stacklets allocated
+//! at the sites that split often, is private to Hopter and cannot be shown.
+//! A task also enters SVC for every heap operation, so the SVC numbers are
+//! passed on to the `heap_stats` module, which counts them, and whose
+//! periodic printing the reporting task does.
+//!
+//! The kernel keeps the state of a task to itself, so the hooks infer it. A
+//! task blocks, or yields, through the `TaskYield` SVC, after which PendSV
+//! switches it out, while PendSV entered otherwise preempts the task. A task
+//! ends with the `TaskDestroy` SVC. A panic starts with a `TaskUnwindPrepare`
+//! SVC made on behalf of the panic handler, `rust_begin_unwind`, which tells
+//! it apart from the same SVC made at each landing pad later on. The
+//! priority of a task is recorded by the `task_name` module when the task is
+//! spawned. [`print_tasks`] lists all of them, and the `ps` shell command
+//! calls it.
+//!
+//! The hooks are set by [`init`] early upon boot, so that the counts start
+//! there. The sampling starts later, with [`start`].
+//!
+//! A task counts [`REPORT_PERIOD_MS`] of samples into a report, which the
+//! `top` shell command prints. Tasks are told apart by their ID, so the
+//! unnamed tasks, which share the default ID, are reported together.
+
+use crate::{
+    console::{print, println},
+    heap_stats,
+    shell::Line,
+    stack_pool::SetStackPool,
+    task_name::{self, SetName},
+};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU8, Ordering};
+use cortex_m::peripheral::{NVIC, SCB};
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::{SpinIrqSafe, SpinSchedSafe},
+    task,
+    time::IntervalBarrier,
+};
+use hopter_conf_params::{
+    DEFAULT_TASK_ID, IDLE_TASK_ID, IDLE_TASK_PRIORITY, IRQ_NORMAL_PRIORITY, MAIN_TASK_ID,
+    MAIN_TASK_PRIORITY,
+};
+use stm32f4xx_hal::{
+    pac::{self, TIM4},
+    prelude::*,
+    rcc::Clocks,
+    timer::{CounterHz, Event},
+};
+
+/// The rate at which the running task is sampled.
+pub const SAMPLE_HZ: u32 = 997;
+
+/// The period covered by a report.
+pub const REPORT_PERIOD_MS: u32 = 1000;
+
+/// The number of tasks told apart: the named ones, the idle and the main
+/// task, and the unnamed ones together.
+const SLOTS: usize = config::MAX_TASK_NUMBER + 3;
+
+/// The ID of a free slot, which no task can have.
+const FREE: u16 = u16::MAX;
+
+irq!(Tim4Irq, pac::interrupt::TIM4);
+
+/// The counts of a task since the last report, and its stack usage.
+struct Slot {
+    /// The task ID, or [`FREE`].
+    id: AtomicU16,
+    samples: AtomicU32,
+    switches: AtomicU32,
+    /// The stacklets upon the last SVC or PendSV, and their bytes.
+    stacklets: AtomicU32,
+    stack_bytes: AtomicU32,
+    /// The most bytes seen since the task was first seen.
+    peak_bytes: AtomicU32,
+    /// A [`State`] other than running, as a `u8`.
+    state: AtomicU8,
+    panics: AtomicU32,
+}
+
+static COUNTS: [Slot; SLOTS] = [const {
+    Slot {
+        id: AtomicU16::new(FREE),
+        samples: AtomicU32::new(0),
+        switches: AtomicU32::new(0),
+        stacklets: AtomicU32::new(0),
+        stack_bytes: AtomicU32::new(0),
+        peak_bytes: AtomicU32::new(0),
+        state: AtomicU8::new(State::Ready as u8),
+        panics: AtomicU32::new(0),
+    }
+}; SLOTS];
+
+/// The samples that interrupted the kernel since the last report.
+static KERNEL_SAMPLES: AtomicU32 = AtomicU32::new(0);
+
+/// The samples of tasks with no free slot left since the last report.
+static OTHER_SAMPLES: AtomicU32 = AtomicU32::new(0);
+
+/// The sampling timer. TIM4 IRQ is masked when the lock is held.
+static SAMPLER: SpinIrqSafe<Option<CounterHz<TIM4>>, Tim4Irq> = SpinIrqSafe::new(None);
+
+/// The latest report, `None` until the first period ends.
+static LATEST: SpinSchedSafe<Option<Report>> = SpinSchedSafe::new(None);
+
+/// Whether every report is printed as it is made.
+static PRINTING: AtomicBool = AtomicBool::new(false);
+
+/// The state of a task, as last seen.
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum State {
+    /// Calling [`task_info`].
+    Running,
+    /// Preempted, or not yet switched out.
+    Ready,
+    /// Blocked, sleeping, or yielding.
+    Waiting,
+    Ended,
+}
+
+impl State {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            1 => Self::Ready,
+            2 => Self::Waiting,
+            3 => Self::Ended,
+            _ => Self::Running,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Running => "running",
+            Self::Ready => "ready",
+            Self::Waiting => "waiting",
+            Self::Ended => "ended",
+        }
+    }
+}
+
+/// What is known of a task.
+pub struct TaskInfo {
+    pub state: State,
+    /// The stacklets held upon the last SVC or context switch.
+    pub stacklets: u32,
+    /// The bytes the stacklets take from the heap, with their overhead.
+    pub bytes: u32,
+    /// The most bytes seen since [`init`].
+    pub peak: u32,
+    /// The panics since [`init`]. A restartable task is restarted after
+    /// each.
+    pub panics: u32,
+}
+
+/// The counts of a task over a report period.
+#[derive(Clone, Copy)]
+struct Usage {
+    id: u8,
+    samples: u32,
+    switches: u32,
+}
+
+/// The counts of all tasks over a report period.
+#[derive(Clone, Copy)]
+struct Report {
+    tasks: [Option<Usage>; SLOTS],
+    kernel: u32,
+    other: u32,
+}
+
+/// Hook SVC and PendSV if the `expert` feature is enabled. Must be called
+/// after `fault::init`.
+pub fn init() {
+    #[cfg(feature = "expert")]
+    hooks::init();
+}
+
+/// Start sampling with TIM4 and spawn the reporting task.
+pub fn start(tim4: TIM4, clocks: &Clocks, nvic: &mut NVIC) {
+    let mut timer = tim4.counter_hz(clocks);
+    timer.listen(Event::Update);
+    timer.start(SAMPLE_HZ.Hz()).unwrap();
+    *SAMPLER.lock() = Some(timer);
+
+    unsafe {
+        nvic.set_priority(pac::interrupt::TIM4, IRQ_NORMAL_PRIORITY);
+        NVIC::unmask(pac::interrupt::TIM4);
+    }
+
+    task::build()
+        .set_name("monitor")
+        .set_stack_pool(0)
+        .set_entry(report_loop)
+        .spawn()
+        .unwrap();
+}
+
+/// The `top` shell command: print the latest report, or turn the printing
+/// of every report on or off.
+pub fn command(line: &Line) {
+    match line.args().next() {
+        None => match *LATEST.lock() {
+            Some(report) => print(&report),
+            None => println!("no report yet"),
+        },
+        Some("on") => PRINTING.store(true, Ordering::SeqCst),
+        Some("off") => PRINTING.store(false, Ordering::SeqCst),
+        Some(_) => println!("usage: top [on|off]"),
+    }
+}
+
+/// Return what is known of the tasks with the ID, or `None` if none of them
+/// has run since [`init`], or if the `expert` feature is disabled. The tasks
+/// sharing an ID are seen as one, in the state of the one seen last.
+pub fn task_info(id: u8) -> Option<TaskInfo> {
+    // Only the hooks see the tasks.
+    if !cfg!(feature = "expert") {
+        return None;
+    }
+    let slot = COUNTS
+        .iter()
+        .find(|slot| slot.id.load(Ordering::Relaxed) == u16::from(id))?;
+    let state = match State::from_u8(slot.state.load(Ordering::Relaxed)) {
+        State::Ended => State::Ended,
+        _ if task::get_current_id() == id => State::Running,
+        state => state,
+    };
+    Some(TaskInfo {
+        state,
+        stacklets: slot.stacklets.load(Ordering::Relaxed),
+        bytes: slot.stack_bytes.load(Ordering::Relaxed),
+        peak: slot.peak_bytes.load(Ordering::Relaxed),
+        panics: slot.panics.load(Ordering::Relaxed),
+    })
+}
+
+/// Print a table of the tasks: the named ones, then the idle, the main, and
+/// the unnamed tasks if they have run. Can be called from any task.
+pub fn print_tasks() {
+    println!(
+        "{:>3} {:<16} {:>4} {:<7} {:>9} {:>6} {:>6} {:>6}",
+        "ID", "NAME", "PRIO", "STATE", "STACKLETS", "BYTES", "PEAK", "PANICS"
+    );
+    let row = |id: u8, name: &str, prio: Option<u8>, info: Option<TaskInfo>| {
+        print!("{:>3} {:<16} ", id, name);
+        match prio {
+            Some(prio) => print!("{:>4} ", prio),
+            None => print!("{:>4} ", "-"),
+        }
+        match info {
+            Some(info) => println!(
+                "{:<7} {:>9} {:>6} {:>6} {:>6}",
+                info.state.name(),
+                info.stacklets,
+                info.bytes,
+                info.peak,
+                info.panics
+            ),
+            None => println!("{:<7}", "-"),
+        }
+    };
+
+    for (id, name) in task_name::names() {
+        row(id, name, task_name::priority_of(id), task_info(id));
+    }
+    let others = [
+        (IDLE_TASK_ID, "(idle)", Some(IDLE_TASK_PRIORITY)),
+        (MAIN_TASK_ID, "(main)", Some(MAIN_TASK_PRIORITY)),
+        (DEFAULT_TASK_ID, "(unnamed)", None),
+    ];
+    for (id, name, prio) in others {
+        if let Some(info) = task_info(id) {
+            row(id, name, prio, Some(info));
+        }
+    }
+}
+
+/// Collect the counts into a report every period.
+fn report_loop() {
+    let mut barrier = IntervalBarrier::new(REPORT_PERIOD_MS).unwrap();
+    loop {
+        barrier.wait();
+
+        let mut report = Report {
+            tasks: [None; SLOTS],
+            kernel: KERNEL_SAMPLES.swap(0, Ordering::Relaxed),
+            other: OTHER_SAMPLES.swap(0, Ordering::Relaxed),
+        };
+        for (usage, slot) in report.tasks.iter_mut().zip(&COUNTS) {
+            let id = slot.id.load(Ordering::Relaxed);
+            if id != FREE {
+                *usage = Some(Usage {
+                    id: id as u8,
+                    samples: slot.samples.swap(0, Ordering::Relaxed),
+                    switches: slot.switches.swap(0, Ordering::Relaxed),
+                });
+            }
+        }
+
+        *LATEST.lock() = Some(report);
+        if PRINTING.load(Ordering::SeqCst) {
+            print(&report);
+        }
+        heap_stats::poll();
+    }
+}
+
+/// Print the share of the samples and the switches of every task seen over
+/// the period.
+fn print(report: &Report) {
+    let total = report
+        .tasks
+        .iter()
+        .flatten()
+        .map(|usage| usage.samples)
+        .sum::<u32>()
+        + report.kernel
+        + report.other;
+    let permille = |samples: u32| samples * 1000 / total.max(1);
+
+    // The switches are only counted by the hooks.
+    let switches = cfg!(feature = "expert");
+    print!("{:>3} {:<14} {:>6}", "ID", "NAME", "CPU");
+    if switches {
+        print!(" {:>9}", "SWITCHES");
+    }
+    println!();
+    for usage in report.tasks.iter().flatten() {
+        if usage.samples == 0 && usage.switches == 0 {
+            continue;
+        }
+        let name = match usage.id {
+            IDLE_TASK_ID => "(idle)",
+            MAIN_TASK_ID => "(main)",
+            DEFAULT_TASK_ID => "(unnamed)",
+            id => task_name::name_of(id).unwrap_or("?"),
+        };
+        let permille = permille(usage.samples);
+        print!(
+            "{:>3} {:<14} {:>3}.{}%",
+            usage.id,
+            name,
+            permille / 10,
+            permille % 10
+        );
+        if switches {
+            print!(" {:>9}", usage.switches);
+        }
+        println!();
+    }
+    for (name, samples) in [("(kernel)", report.kernel), ("(others)", report.other)] {
+        if samples != 0 {
+            let permille = permille(samples);
+            println!("    {:<14} {:>3}.{}%", name, permille / 10, permille % 10);
+        }
+    }
+    println!("{} samples over {} ms", total, REPORT_PERIOD_MS);
+}
+
+/// Return the index of the slot of the task, taking a free one if the task
+/// has none. Return `None` if no slot is left.
+fn slot(id: u8) -> Option<usize> {
+    // Slots are taken in order, so a slot taken concurrently for the same
+    // task is met before any free one.
+    COUNTS.iter().position(|slot| {
+        match slot
+            .id
+            .compare_exchange(FREE, id.into(), Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => true,
+            Err(taken) => taken == u16::from(id),
+        }
+    })
+}
+
+#[handler(TIM4)]
+fn tim4_handler() {
+    // Safety: The register is only read.
+    let icsr = unsafe { (*SCB::PTR).icsr.read() };
+
+    // RETTOBASE is set if no other exception is active, i.e., a task was
+    // interrupted.
+    if icsr & 1 << 11 != 0 {
+        match slot(task::get_current_id()) {
+            Some(index) => COUNTS[index].samples.fetch_add(1, Ordering::Relaxed),
+            None => OTHER_SAMPLES.fetch_add(1, Ordering::Relaxed),
+        };
+    } else {
+        KERNEL_SAMPLES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Acknowledge the IRQ.
+    SAMPLER.lock().as_mut().unwrap().wait().unwrap();
+}
+
+/// The hooks on SVC and PendSV. They read the private data of the kernel,
+/// and are only checked against hopter 0.2.3.
+#[cfg(feature = "expert")]
+mod hooks {
+    use super::{slot, State, COUNTS};
+    use crate::{fault, heap_stats};
+    use core::{
+        arch::asm,
+        ptr,
+        sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    };
+    use cortex_m::peripheral::{
+        scb::{Exception, VectActive},
+        SCB,
+    };
+    use hopter::task;
+    use hopter_conf_params::{svc, __CONTIGUOUS_STACK_BOUNDARY, __TLS_MEM_ADDR};
+
+    /// The indices of the SVC and PendSV entries in the vector table.
+    const SVCALL_VECTOR: usize = 11;
+    const PENDSV_VECTOR: usize = 14;
+
+    /// The number of stacklets counted per task at most, in case the chain is
+    /// damaged.
+    const MAX_STACKLETS: usize = 64;
+
+    /// The distance from the panic handler to the return address of its call to
+    /// the unwinder, at most.
+    const PANIC_HANDLER_CALL_LEN: u32 = 16;
+
+    extern "C" {
+        // Defined by Hopter. The address of the context of the running task,
+        // updated only by PendSV.
+        static CUR_TASK_CTXT_PTR: u32;
+
+        // The SVC and PendSV handlers of Hopter.
+        fn SVCall();
+        fn PendSV();
+
+        // The panic handler of Hopter, which starts unwinding the task.
+        fn rust_begin_unwind();
+    }
+
+    /// The task context seen upon the previous SVC or PendSV, and the index of
+    /// the slot of the task, `usize::MAX` if it got none.
+    static LAST_CTXT: AtomicU32 = AtomicU32::new(0);
+    static LAST_SLOT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    /// Whether the last SVC was `TaskYield`, i.e., whether the next PendSV
+    /// switches out a task that gave up the CPU.
+    static YIELDING: AtomicBool = AtomicBool::new(false);
+
+    /// Route SVC and PendSV through the trampolines.
+    pub fn init() {
+        fault::replace_vector(SVCALL_VECTOR, svc_trampoline);
+        fault::replace_vector(PENDSV_VECTOR, pendsv_trampoline);
+    }
+
+    /// Count a switch for the running task if it is not the task seen last,
+    /// infer its state, record the stacklets it holds, and pass the number of
+    /// an SVC on to `heap_stats`. `stklet_bound` is the boundary of the newest
+    /// stacklet of the task. Called upon PendSV and SVC, which never preempt
+    /// each other, before the kernel handles them.
+    extern "C" fn observe(stklet_bound: u32) {
+        // Safety: The word is written by PendSV only, which is not running yet.
+        let ctxt = unsafe { ptr::read_volatile(ptr::addr_of!(CUR_TASK_CTXT_PTR)) };
+        if LAST_CTXT.load(Ordering::Relaxed) != ctxt {
+            LAST_CTXT.store(ctxt, Ordering::Relaxed);
+            // The kernel is not switching tasks, so the lookup is safe.
+            let slot = slot(task::get_current_id());
+            if let Some(index) = slot {
+                COUNTS[index].switches.fetch_add(1, Ordering::Relaxed);
+                COUNTS[index]
+                    .state
+                    .store(State::Ready as u8, Ordering::Relaxed);
+            }
+            LAST_SLOT.store(slot.unwrap_or(usize::MAX), Ordering::Relaxed);
+        }
+        let slot = COUNTS.get(LAST_SLOT.load(Ordering::Relaxed));
+
+        if SCB::vect_active() == VectActive::Exception(Exception::SVCall) {
+            let (number, lr) = svc_number_and_lr();
+            heap_stats::count(number);
+            YIELDING.store(number == svc::TASK_YIELD, Ordering::Relaxed);
+            if let Some(slot) = slot {
+                match number {
+                    svc::TASK_DESTROY => slot.state.store(State::Ended as u8, Ordering::Relaxed),
+                    svc::TASK_UNWIND_PREPARE if called_by_panic_handler(lr) => {
+                        slot.panics.fetch_add(1, Ordering::Relaxed);
+                    }
+                    _ => {}
+                }
+            }
+        } else if let Some(slot) = slot {
+            // The task is being switched out, unless it ended.
+            let state = if YIELDING.swap(false, Ordering::Relaxed) {
+                State::Waiting
+            } else {
+                State::Ready
+            };
+            let _ = slot
+                .state
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+                    (old != State::Ended as u8).then_some(state as u8)
+                });
+        }
+
+        let Some(slot) = slot else {
+            return;
+        };
+        let (stacklets, bytes) = stacklets(stklet_bound);
+        slot.stacklets.store(stacklets, Ordering::Relaxed);
+        slot.stack_bytes.store(bytes, Ordering::Relaxed);
+        slot.peak_bytes.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    /// Return the number of the SVC being entered, read from the instruction
+    /// before the return address in the trap frame, as the kernel does, and lr
+    /// of the task.
+    fn svc_number_and_lr() -> (u8, u32) {
+        let psp = cortex_m::register::psp::read() as *const u32;
+        // Safety: The trap frame was just stacked by the processor, and the
+        // return address follows an SVC instruction in the flash.
+        unsafe {
+            let lr = ptr::read_volatile(psp.add(5));
+            let pc = ptr::read_volatile(psp.add(6));
+            (ptr::read_volatile((pc - 2) as *const u8), lr)
+        }
+    }
+
+    /// Return whether the return address lies in the panic handler, i.e.,
+    /// whether the unwinder was just called to start a panic.
+    fn called_by_panic_handler(lr: u32) -> bool {
+        let handler = rust_begin_unwind as usize as u32 & !1;
+        (lr & !1).wrapping_sub(handler) < PANIC_HANDLER_CALL_LEN
+    }
+
+    /// Return the number of stacklets in the chain from the boundary, and the
+    /// bytes they take from the heap, read from the headers of the heap chunks
+    /// holding them.
+    fn stacklets(mut bound: u32) -> (u32, u32) {
+        let (mut count, mut bytes) = (0, 0);
+        for _ in 0..MAX_STACKLETS {
+            // The stacklet starts with its metadata, right after the header of
+            // the chunk.
+            let header = bound.wrapping_sub(fault::STACKLET_META_OFFSET + 4);
+            if !fault::in_ram(header, 8) {
+                break;
+            }
+            // Safety: The words lie in the RAM, as checked above.
+            let (header, prev_bound) = unsafe {
+                let header = header as *const u32;
+                (
+                    ptr::read_volatile(header),
+                    ptr::read_volatile(header.add(1)),
+                )
+            };
+            // Bit 1 is set for an allocated chunk.
+            if header & 0x2 == 0 {
+                break;
+            }
+            count += 1;
+            bytes += header & !0x3;
+            if prev_bound == 0 {
+                break;
+            }
+            bound = prev_bound;
+        }
+        (count, bytes)
+    }
+
+    /// Define the entry of an exception that runs [`observe`] with the stacklet
+    /// boundary of the contiguous stack, restores the task local storage, then
+    /// goes on to the handler of Hopter as if it had been entered directly. The
+    /// registers clobbered were stacked by the processor, and the others are
+    /// preserved by [`observe`].
+    macro_rules! trampoline {
+        ($name:ident, $handler:ident) => {
+            #[naked]
+            unsafe extern "C" fn $name() {
+                asm!(
+                    // Preserve the task local storage and the exception return
+                    // value.
+                    "ldr   r12, ={tls_mem_addr}",
+                    "ldmia r12, {{r1-r3}}",
+                    "push  {{r1-r3, lr}}",
+                    // Pass the stacklet boundary of the task.
+                    "mov   r0, r1",
+                    // Set the kernel stacklet boundary and clear the other
+                    // fields.
+                    "ldr   r1, ={kern_stk_boundary}",
+                    "mov   r2, #0",
+                    "mov   r3, #0",
+                    "stmia r12, {{r1-r3}}",
+                    "bl    {observe}",
+                    // Restore them, leaving the kernel stack as it was upon
+                    // entry.
+                    "pop   {{r1-r3, lr}}",
+                    "ldr   r12, ={tls_mem_addr}",
+                    "stmia r12, {{r1-r3}}",
+                    "b     {handler}",
+                    tls_mem_addr = const __TLS_MEM_ADDR,
+                    kern_stk_boundary = const __CONTIGUOUS_STACK_BOUNDARY,
+                    observe = sym observe,
+                    handler = sym $handler,
+                    options(noreturn)
+                )
+            }
+        };
+    }
+
+    trampoline!(pendsv_trampoline, PendSV);
+    trampoline!(svc_trampoline, SVCall);
+}
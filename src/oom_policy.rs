@@ -0,0 +1,24 @@
+//! Enforcement of `OOM_POLICY`.
+//!
+//! The kernel treats a failed infallible allocation, e.g., by `Arc::new` or
+//! `Vec::push`, as fatal and stops the whole system. The fallible
+//! constructors of `alloc` types, e.g., `Arc::try_new` or `Vec::try_reserve`,
+//! return an error instead, and [`allocate`] applies the configured policy to
+//! the error.
+//!
+//! The kernel cannot kill a task other than the current one, so
+//! `OomPolicy::KillLowestPriority` is treated as `OomPolicy::PanicTask`.
+
+use hopter_conf_params::{OomPolicy, OOM_POLICY};
+
+/// Run the fallible allocation `f`, and apply `OOM_POLICY` if it fails.
+/// Return `None` only under `OomPolicy::ReturnNull`.
+pub fn allocate<T, E>(f: impl FnOnce() -> Result<T, E>) -> Option<T> {
+    match f() {
+        Ok(value) => Some(value),
+        Err(_) => match OOM_POLICY {
+            OomPolicy::ReturnNull => None,
+            OomPolicy::PanicTask | OomPolicy::KillLowestPriority => panic!("out of memory"),
+        },
+    }
+}
@@ -0,0 +1,167 @@
+//! Persisting the record of the last panic in the backup SRAM.
+//!
+//! The backup SRAM keeps its content across resets. When code guarded by the
+//! `panic_policy` module panics, the ID and the name of the panicked task and
+//! the tick of the panic are written there before the panic policy is
+//! applied. After a reset, [`take`] returns the record, so that the cause of,
+//! e.g., a reset requested by `PanicPolicy::ResetSystem` can be reported.
+//!
+//! The kernel owns the panic handler and discards the panic message, so the
+//! message cannot be recorded. The record instead counts the panics since the
+//! backup SRAM lost its content, which tells a task panicking once in a while
+//! apart from one that panics again upon each reset.
+//!
+//! A record is recognized by a magic word, which the garbage left in the
+//! backup SRAM after a power loss is unlikely to match.
+
+use crate::task_name;
+use core::ptr;
+use hopter::time;
+use hopter_conf_params::{
+    BACKUP_SRAM_ADDR, BACKUP_SRAM_LEN, ENABLE_PANIC_PERSIST, MAX_TASK_NAME_LEN,
+};
+use stm32f4xx_hal::pac;
+
+/// Marks a valid record.
+const MAGIC: u32 = 0x5041_4e43;
+
+/// The layout of the record in the backup SRAM.
+#[repr(C)]
+struct RawRecord {
+    magic: u32,
+    task_id: u32,
+    tick: u32,
+    name_len: u32,
+    name: [u8; MAX_TASK_NAME_LEN],
+    /// The number of panics, kept when the record is taken.
+    count: u32,
+    /// The complement of `count`, telling a valid count from garbage.
+    count_check: u32,
+}
+
+const _: () = assert!(
+    !ENABLE_PANIC_PERSIST || core::mem::size_of::<RawRecord>() <= BACKUP_SRAM_LEN as usize,
+    "the panic record does not fit in the backup SRAM"
+);
+
+/// The location of the record.
+const RECORD: *mut RawRecord = BACKUP_SRAM_ADDR as *mut RawRecord;
+
+/// The record of a panic that happened before the last reset.
+pub struct PanicRecord {
+    /// The ID of the panicked task.
+    pub task_id: u8,
+    /// The tick when the task panicked.
+    pub tick: u32,
+    /// The number of panics recorded since the backup SRAM lost its content,
+    /// this one included.
+    pub count: u32,
+    name: [u8; MAX_TASK_NAME_LEN],
+    name_len: usize,
+}
+
+impl PanicRecord {
+    /// Return the name of the panicked task, or `None` if it was unnamed.
+    pub fn name(&self) -> Option<&str> {
+        match self.name_len {
+            0 => None,
+            len => core::str::from_utf8(&self.name[..len]).ok(),
+        }
+    }
+}
+
+/// Enable the access to the backup SRAM. Must be called before the other
+/// functions of this module. Do nothing if `ENABLE_PANIC_PERSIST` is false.
+pub fn init() {
+    if !ENABLE_PANIC_PERSIST {
+        return;
+    }
+
+    // Safety: Only the bits concerning the backup domain are modified, which
+    // no other code touches.
+    unsafe {
+        let rcc = &*pac::RCC::ptr();
+        let pwr = &*pac::PWR::ptr();
+
+        // Clock the power controller and the backup SRAM. The BKPSRAMEN bit
+        // is missing from the register description of parts without a backup
+        // SRAM, so it is set by its position.
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        rcc.ahb1enr.modify(|r, w| w.bits(r.bits() | 1 << 18));
+
+        // Allow writing to the backup domain.
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+        // Turn on the backup regulator, i.e., set BRE, so that the content is
+        // kept also in standby mode and on battery power. Wait for BRR.
+        pwr.csr.modify(|r, w| w.bits(r.bits() | 1 << 9));
+        while pwr.csr.read().bits() & (1 << 3) == 0 {}
+    }
+}
+
+/// Write the record of a panic of the given task, replacing the previous
+/// one. Do nothing if `ENABLE_PANIC_PERSIST` is false.
+pub fn record(task_id: u8) {
+    if !ENABLE_PANIC_PERSIST {
+        return;
+    }
+
+    let task_name = task_name::name_of(task_id).unwrap_or("");
+    let mut name = [0; MAX_TASK_NAME_LEN];
+    name[..task_name.len()].copy_from_slice(task_name.as_bytes());
+
+    // Safety: Same as below.
+    let (count, count_check) = unsafe {
+        (
+            ptr::read_volatile(ptr::addr_of!((*RECORD).count)),
+            ptr::read_volatile(ptr::addr_of!((*RECORD).count_check)),
+        )
+    };
+    let count = if count_check == !count {
+        count.saturating_add(1)
+    } else {
+        1
+    };
+
+    let record = RawRecord {
+        magic: 0,
+        task_id: task_id.into(),
+        tick: time::get_tick(),
+        name_len: task_name.len() as u32,
+        name,
+        count,
+        count_check: !count,
+    };
+
+    // Safety: The record lies in the backup SRAM, which is used by nothing
+    // else. The magic word is written last, so that a reset in the middle
+    // does not leave a torn record behind.
+    unsafe {
+        ptr::write_volatile(RECORD, record);
+        ptr::write_volatile(ptr::addr_of_mut!((*RECORD).magic), MAGIC);
+    }
+}
+
+/// Return the record of the panic that happened before the last reset, and
+/// clear it. Return `None` if there is no record or if
+/// `ENABLE_PANIC_PERSIST` is false.
+pub fn take() -> Option<PanicRecord> {
+    if !ENABLE_PANIC_PERSIST {
+        return None;
+    }
+
+    // Safety: Same as in `record`.
+    let record = unsafe { ptr::read_volatile(RECORD) };
+    if record.magic != MAGIC {
+        return None;
+    }
+    unsafe { ptr::write_volatile(ptr::addr_of_mut!((*RECORD).magic), 0) };
+
+    Some(PanicRecord {
+        task_id: record.task_id as u8,
+        tick: record.tick,
+        count: record.count,
+        name: record.name,
+        name_len: (record.name_len as usize).min(MAX_TASK_NAME_LEN),
+    })
+}
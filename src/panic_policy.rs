@@ -0,0 +1,62 @@
+//! Enforcement of `PANIC_POLICY`.
+//!
+//! Hopter reacts to a panic by unwinding the panicked task, and the kernel
+//! owns the panic handler. This module applies the configured policy to the
+//! code run by [`guarded`]. When the code panics, a guard is dropped during
+//! unwinding, and the guard carries out the policy.
+
+use crate::panic_persist;
+use core::{
+    mem,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use hopter::task;
+use hopter_conf_params::{PanicPolicy, PANIC_POLICY};
+
+/// The IDs of the tasks killed under `PanicPolicy::KillTaskOnly`, one bit
+/// per ID.
+static KILLED: [AtomicU32; 8] = [const { AtomicU32::new(0) }; 8];
+
+/// Run `f`, and apply `PANIC_POLICY` if it panics. Under
+/// `PanicPolicy::KillTaskOnly`, a restarted instance of a task killed before
+/// returns immediately, so the task effectively ends.
+pub fn guarded(f: impl FnOnce()) {
+    let id = task::get_current_id();
+
+    if PANIC_POLICY == PanicPolicy::KillTaskOnly
+        && KILLED[id as usize / 32].load(Ordering::SeqCst) & (1 << (id % 32)) != 0
+    {
+        return;
+    }
+
+    let guard = Guard { id };
+    f();
+    mem::forget(guard);
+}
+
+/// Dropped only when the guarded code panics.
+struct Guard {
+    /// The ID of the task running the guarded code.
+    id: u8,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        // Keep a record of the panic in case the policy resets the system.
+        panic_persist::record(self.id);
+
+        match PANIC_POLICY {
+            PanicPolicy::UnwindAndRestart => {}
+            PanicPolicy::KillTaskOnly => {
+                KILLED[self.id as usize / 32].fetch_or(1 << (self.id % 32), Ordering::SeqCst);
+            }
+            PanicPolicy::ResetSystem => cortex_m::peripheral::SCB::sys_reset(),
+            PanicPolicy::Halt => {
+                cortex_m::interrupt::disable();
+                loop {
+                    cortex_m::asm::nop();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Allocation from the secondary heap regions.
+//!
+//! The kernel allocator serves `Box`, `Arc`, and the other `alloc` types from
+//! the primary heap region only, i.e., `HEAP_REGIONS[0]`. Each secondary
+//! region listed in `HEAP_REGIONS` is managed by an allocator of this module
+//! instead. The allocators implement the `Allocator` trait, so they can be
+//! passed to the `*_in` constructors, e.g., `Box::new_in` and
+//! `Vec::with_capacity_in`.
+
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+use hopter::sync::SpinSchedSafe;
+use hopter_conf_params::HEAP_REGIONS;
+use linked_list_allocator::Heap;
+
+/// The free lists of the secondary regions. The heap at index `i` manages
+/// `HEAP_REGIONS[i + 1]`. A heap is initialized upon the first allocation
+/// from it, so that external memory can be set up beforehand.
+static HEAPS: [SpinSchedSafe<Heap>; HEAP_REGIONS.len() - 1] =
+    [const { SpinSchedSafe::new(Heap::empty()) }; HEAP_REGIONS.len() - 1];
+
+/// A handle to the allocator of a secondary heap region.
+#[derive(Clone, Copy)]
+pub struct RegionHeap {
+    /// The index of the region in `HEAP_REGIONS`.
+    idx: usize,
+}
+
+impl RegionHeap {
+    /// Return the allocator of `HEAP_REGIONS[idx]`. Return `None` if the
+    /// region does not exist or if it is the primary region.
+    pub fn get(idx: usize) -> Option<Self> {
+        if idx == 0 || idx >= HEAP_REGIONS.len() {
+            return None;
+        }
+        Some(Self { idx })
+    }
+}
+
+unsafe impl Allocator for RegionHeap {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut heap = HEAPS[self.idx - 1].lock();
+
+        if heap.size() == 0 {
+            let region = &HEAP_REGIONS[self.idx];
+            // Safety: The region is reserved for this allocator and is not
+            // touched by the linker or the kernel.
+            unsafe {
+                heap.init(
+                    region.start as *mut u8,
+                    (region.end - region.start) as usize,
+                )
+            };
+        }
+
+        heap.allocate_first_fit(layout)
+            .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
+            .map_err(|_| AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        HEAPS[self.idx - 1].lock().deallocate(ptr, layout)
+    }
+}
@@ -0,0 +1,183 @@
+//! A reader-writer lock for tasks.
+//!
+//! Hopter provides no reader-writer lock, so this module builds one on the
+//! kernel's `CondVar`. Any number of readers hold the lock at the same time,
+//! while a writer holds it alone. Tasks block while the lock is unavailable.
+//! The lock must not be taken in IRQ handlers.
+//!
+//! A waiting writer does not hold back new readers, so a writer may wait as
+//! long as readers keep overlapping. Unlike Hopter's `Mutex`, the lock does
+//! not apply priority inheritance.
+//!
+//! Each lock counts its acquisitions, those that had to wait, and the largest
+//! number of readers observed at the same time. See [`RwLock::stats`].
+
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use hopter::sync::CondVar;
+
+/// The bit of `state` set while a writer holds the lock. The other bits
+/// count the readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    /// Notified when the lock is released or a reader is admitted.
+    released: CondVar,
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+    contended_reads: AtomicUsize,
+    contended_writes: AtomicUsize,
+    peak_readers: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+// Safety: Readers share `&T` across tasks, and a writer gets `&mut T`.
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+/// The statistics of a [`RwLock`].
+#[derive(Clone, Copy)]
+pub struct RwLockStats {
+    /// The number of read acquisitions.
+    pub reads: usize,
+    /// The number of write acquisitions.
+    pub writes: usize,
+    /// The number of read acquisitions that had to wait for a writer.
+    pub contended_reads: usize,
+    /// The number of write acquisitions that had to wait.
+    pub contended_writes: usize,
+    /// The largest number of readers that held the lock at the same time.
+    pub peak_readers: usize,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            released: CondVar::new(),
+            reads: AtomicUsize::new(0),
+            writes: AtomicUsize::new(0),
+            contended_reads: AtomicUsize::new(0),
+            contended_writes: AtomicUsize::new(0),
+            peak_readers: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Acquire the lock for reading, blocking while a writer holds it.
+    pub fn read(&self) -> ReadGuard<T> {
+        let mut contended = false;
+        let readers = loop {
+            let state = self.state.load(Ordering::SeqCst);
+            if state & WRITER == 0 {
+                if self
+                    .state
+                    .compare_exchange(state, state + 1, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    break state + 1;
+                }
+                continue;
+            }
+            contended = true;
+            self.released
+                .wait_without_lock_until(|| self.state.load(Ordering::SeqCst) & WRITER == 0);
+        };
+
+        // A single notification wakes up a single task, so pass it on to
+        // the next waiting reader, if any.
+        if contended {
+            self.released.notify_one_allow_isr();
+            self.contended_reads.fetch_add(1, Ordering::SeqCst);
+        }
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.peak_readers.fetch_max(readers, Ordering::SeqCst);
+
+        ReadGuard { lock: self }
+    }
+
+    /// Acquire the lock for writing, blocking while a reader or a writer
+    /// holds it.
+    pub fn write(&self) -> WriteGuard<T> {
+        let mut contended = false;
+        while self
+            .state
+            .compare_exchange(0, WRITER, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            contended = true;
+            self.released
+                .wait_without_lock_until(|| self.state.load(Ordering::SeqCst) == 0);
+        }
+
+        if contended {
+            self.contended_writes.fetch_add(1, Ordering::SeqCst);
+        }
+        self.writes.fetch_add(1, Ordering::SeqCst);
+
+        WriteGuard { lock: self }
+    }
+
+    /// Return the statistics of the lock.
+    pub fn stats(&self) -> RwLockStats {
+        RwLockStats {
+            reads: self.reads.load(Ordering::SeqCst),
+            writes: self.writes.load(Ordering::SeqCst),
+            contended_reads: self.contended_reads.load(Ordering::SeqCst),
+            contended_writes: self.contended_writes.load(Ordering::SeqCst),
+            peak_readers: self.peak_readers.load(Ordering::SeqCst),
+        }
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: No writer holds the lock while a reader does.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Only a waiting writer can be admitted by the last reader leaving.
+        if self.lock.state.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.lock.released.notify_one_allow_isr();
+        }
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: The writer holds the lock alone.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The writer holds the lock alone.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::SeqCst);
+        self.lock.released.notify_one_allow_isr();
+    }
+}
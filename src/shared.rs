@@ -0,0 +1,96 @@
+//! Sharing values among tasks with or without the heap.
+//!
+//! By default, a value shared among tasks is put behind an `Arc`. Under the
+//! `static-alloc` feature, the application must not allocate from the heap,
+//! so the value is moved into a static cell instead, and tasks share a
+//! `&'static` reference to it. Either way, [`share!`] returns a [`Shared`]
+//! handle, which dereferences to the value and is `Clone`.
+
+#[cfg(feature = "static-alloc")]
+use core::ops::Deref;
+
+#[cfg(not(feature = "static-alloc"))]
+pub type Shared<T> = alloc::sync::Arc<T>;
+
+/// A reference to a value in a static cell.
+#[cfg(feature = "static-alloc")]
+pub struct Shared<T: 'static>(&'static T);
+
+#[cfg(feature = "static-alloc")]
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0)
+    }
+}
+
+#[cfg(feature = "static-alloc")]
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.0
+    }
+}
+
+/// Share a value of the given type among tasks. Under the `static-alloc`
+/// feature, each use of the macro owns a static cell, so it must run only
+/// once, or it panics.
+macro_rules! share {
+    ($ty:ty, $value:expr) => {{
+        #[cfg(not(feature = "static-alloc"))]
+        let shared: $crate::shared::Shared<$ty> = alloc::sync::Arc::new($value);
+
+        #[cfg(feature = "static-alloc")]
+        let shared: $crate::shared::Shared<$ty> = {
+            static CELL: $crate::shared::StaticCell<$ty> = $crate::shared::StaticCell::new();
+            CELL.init($value)
+        };
+
+        shared
+    }};
+}
+
+pub(crate) use share;
+
+#[cfg(feature = "static-alloc")]
+pub use static_cell::StaticCell;
+
+#[cfg(feature = "static-alloc")]
+mod static_cell {
+    use super::Shared;
+    use core::{
+        cell::UnsafeCell,
+        mem::MaybeUninit,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    /// A static memory slot that can be initialized once at runtime.
+    pub struct StaticCell<T> {
+        taken: AtomicBool,
+        value: UnsafeCell<MaybeUninit<T>>,
+    }
+
+    // Safety: The value is written only once, guarded by `taken`, and is
+    // only shared by reference afterwards.
+    unsafe impl<T: Sync> Sync for StaticCell<T> {}
+
+    impl<T> StaticCell<T> {
+        pub const fn new() -> Self {
+            Self {
+                taken: AtomicBool::new(false),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }
+
+        /// Move the value into the cell and return a handle to it. Panic if
+        /// the cell is already initialized.
+        pub fn init(&'static self, value: T) -> Shared<T> {
+            assert!(
+                !self.taken.swap(true, Ordering::SeqCst),
+                "static cell initialized twice"
+            );
+            // Safety: The `taken` flag grants exclusive access to the slot.
+            Shared(unsafe { (*self.value.get()).write(value) })
+        }
+    }
+}
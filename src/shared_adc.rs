@@ -0,0 +1,45 @@
+//! ADC1, shared among the tasks sampling its internal channels.
+//!
+//! ADC1 converts one channel at a time, and the internal channels are
+//! switched on and off in the common control register of the ADCs. VBAT is
+//! connected through a bridge that drains the battery, so it should only be
+//! switched on while it is converted, and on some parts it takes the channel
+//! of the temperature sensor meanwhile. The tasks thus take turns with the
+//! whole ADC, through [`with`], and a task switching a channel on switches it
+//! off again before returning. The temperature sensor and VREFINT are left
+//! on, as every task needs VREFINT anyway.
+//!
+//! The ADC is behind a mutex rather than a spin lock, as a conversion of an
+//! internal channel takes over 10 us. The mutex applies priority inheritance,
+//! so a task of low priority holding it delays one of high priority by one
+//! turn at most.
+
+use hopter::sync::Mutex;
+use stm32f4xx_hal::{
+    adc::{config::AdcConfig, Adc},
+    pac::ADC1,
+    signature::VrefCal,
+};
+
+/// The VDDA at which the calibration values were taken.
+pub const CALIBRATION_VDDA_MV: u32 = 3300;
+
+/// The ADC, `None` before [`init`].
+static ADC: Mutex<Option<Adc<ADC1>>> = Mutex::new(None);
+
+/// Enable ADC1, with the temperature sensor and VREFINT switched on.
+pub fn init(adc1: ADC1) {
+    let mut adc = Adc::adc1(adc1, true, AdcConfig::default());
+    adc.enable_temperature_and_vref();
+    *ADC.lock() = Some(adc);
+}
+
+/// Run `f` with the ADC to itself. Panics before [`init`].
+pub fn with<R>(f: impl FnOnce(&mut Adc<ADC1>) -> R) -> R {
+    f(ADC.lock().as_mut().expect("shared_adc::init not called"))
+}
+
+/// Return the supply voltage VDDA in millivolts, given a sample of VREFINT.
+pub fn vdda_mv(vref: u16) -> u32 {
+    CALIBRATION_VDDA_MV * u32::from(VrefCal::get().read()) / u32::from(vref.max(1))
+}
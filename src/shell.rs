@@ -0,0 +1,239 @@
+//! A line-oriented command shell over the serial console.
+//!
+//! The shell runs as a task consuming the bytes received by the `console`
+//! module. It echoes them back, collects them into a line, and runs the
+//! command named by the first word of the line upon Enter. The remaining
+//! words are the arguments.
+//!
+//! Commands are looked up in a registry, to which other modules add their own.
+//! A command registered with [`register`] runs as a function in the shell
+//! task. A command registered with [`register_task`] is served by another
+//! task instead: the shell sends the line through a channel, and the task
+//! receives it from the returned [`Requests`]. The latter suits commands that
+//! act on resources owned by a task, e.g., an LED. A command registered with
+//! [`register_console`] runs in the shell task too, but also takes the bytes
+//! received by the console until it returns, e.g., to transfer a file.
+//!
+//! The built-in commands are `help`, `ps`, `uptime`, and `reboot`, which
+//! shuts down through the `shutdown` module before resetting.
+
+use crate::{
+    console::{self, print, println},
+    monitor, shutdown,
+    stack_pool::SetStackPool,
+    task_name::SetName,
+};
+use core::str::SplitWhitespace;
+use hopter::{
+    sync::{self, Consumer, Producer, SpinSchedSafe},
+    task, time,
+};
+
+/// The maximum length in bytes of a command line. Further bytes are ignored.
+pub const LINE_LEN: usize = 64;
+
+/// The maximum number of registered commands, including the built-in ones.
+pub const MAX_COMMANDS: usize = 40;
+
+/// The number of lines queued for a task serving a command before the shell
+/// blocks.
+pub const REQUEST_QUEUE_LEN: usize = 4;
+
+/// The lines sent to a task serving a command.
+pub type Requests = Consumer<Line, REQUEST_QUEUE_LEN>;
+
+/// A command line.
+#[derive(Clone, Copy)]
+pub struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    /// Return the text of the line.
+    pub fn as_str(&self) -> &str {
+        // Only ASCII bytes are collected.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+
+    /// Return the arguments, i.e., the words following the command name.
+    pub fn args(&self) -> SplitWhitespace {
+        let mut words = self.as_str().split_whitespace();
+        words.next();
+        words
+    }
+}
+
+/// How a command is run.
+#[derive(Clone)]
+enum Handler {
+    /// Call the function in the shell task.
+    Function(fn(&Line)),
+    /// Send the line to the task serving the command.
+    Task(Producer<Line, REQUEST_QUEUE_LEN>),
+    /// Call the function in the shell task, handing it the console.
+    Console(fn(&Line, &console::RxConsumer)),
+}
+
+#[derive(Clone)]
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    handler: Handler,
+}
+
+/// The registered commands.
+static COMMANDS: SpinSchedSafe<[Option<Command>; MAX_COMMANDS]> =
+    SpinSchedSafe::new([const { None }; MAX_COMMANDS]);
+
+/// Add a command to the registry. Panic if the registry is full.
+fn add(name: &'static str, help: &'static str, handler: Handler) {
+    let mut commands = COMMANDS.lock();
+    let slot = commands
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many shell commands");
+    *slot = Some(Command {
+        name,
+        help,
+        handler,
+    });
+}
+
+/// Register a command run as the given function in the shell task.
+pub fn register(name: &'static str, help: &'static str, f: fn(&Line)) {
+    add(name, help, Handler::Function(f));
+}
+
+/// Register a command run as the given function in the shell task, which
+/// consumes the bytes received by the console in place of the shell until it
+/// returns.
+pub fn register_console(
+    name: &'static str,
+    help: &'static str,
+    f: fn(&Line, &console::RxConsumer),
+) {
+    add(name, help, Handler::Console(f));
+}
+
+/// Register a command served by another task. Return the receiving end of
+/// the lines invoking the command.
+pub fn register_task(name: &'static str, help: &'static str) -> Requests {
+    let (producer, consumer) = sync::create_channel();
+    add(name, help, Handler::Task(producer));
+    consumer
+}
+
+/// Register the built-in commands and spawn the shell task consuming the
+/// bytes received by the console.
+pub fn spawn(rx: console::RxConsumer) {
+    register("help", "list the commands", help);
+    register(
+        "ps",
+        "list the tasks, their states, and their stack usage",
+        ps,
+    );
+    register("uptime", "show the time since boot", uptime);
+    register("reboot", "reset the system", reboot);
+
+    task::build()
+        .set_name("shell")
+        .set_stack_pool(1)
+        .set_entry(move || run(rx))
+        .spawn()
+        .unwrap();
+}
+
+fn run(rx: console::RxConsumer) {
+    let mut line = Line::new();
+    let mut dropped = 0;
+    print!("> ");
+
+    loop {
+        match rx.consume() {
+            // Terminals send CR upon the Enter key.
+            b'\r' => {
+                println!();
+                // Report the bytes dropped by the console since the last
+                // line, which may have been lost from this one.
+                let total = console::dropped();
+                if total != dropped {
+                    println!("({} bytes dropped)", total - dropped);
+                    dropped = total;
+                }
+                execute(&line, &rx);
+                line = Line::new();
+                print!("> ");
+            }
+            // Backspace or delete.
+            0x08 | 0x7f if line.len > 0 => {
+                line.len -= 1;
+                print!("\x08 \x08");
+            }
+            byte @ b' '..=b'~' if line.len < LINE_LEN => {
+                line.buf[line.len] = byte;
+                line.len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn execute(line: &Line, rx: &console::RxConsumer) {
+    let Some(name) = line.as_str().split_whitespace().next() else {
+        return;
+    };
+
+    // Release the registry before running the command, which may block.
+    let handler = COMMANDS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|command| command.name == name)
+        .map(|command| command.handler.clone());
+
+    match handler {
+        Some(Handler::Function(f)) => f(line),
+        Some(Handler::Task(producer)) => producer.produce(*line),
+        Some(Handler::Console(f)) => f(line, rx),
+        None => println!("unknown command: {}", name),
+    }
+}
+
+fn help(_: &Line) {
+    let commands = COMMANDS.lock().clone();
+    for command in commands.iter().flatten() {
+        println!("{:<8} {}", command.name, command.help);
+    }
+}
+
+/// The kernel keeps no list of tasks that an application can walk, so the
+/// `monitor` module lists the tasks named through the `task_name` module,
+/// then the other tasks it has seen.
+fn ps(_: &Line) {
+    monitor::print_tasks();
+}
+
+fn uptime(_: &Line) {
+    let ms = time::get_tick();
+    let secs = ms / 1000;
+    println!(
+        "up {}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ms % 1000
+    );
+}
+
+fn reboot(_: &Line) {
+    shutdown::reboot();
+}
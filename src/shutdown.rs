@@ -0,0 +1,131 @@
+//! Orderly shutdown before a reset.
+//!
+//! A reset throws away whatever a task holds in the RAM and has yet to save,
+//! e.g., a value kept in the RAM to spare the flash a write at every change.
+//! A task with such state registers a [`Notice`] under its name with
+//! [`register`]. The notice holds a `Mailbox`, which the task blocks on
+//! through [`Notice::wait`], or sleeps on through [`Notice::sleep_ms`], and
+//! a flag telling whether it has saved its state.
+//!
+//! [`reboot`] broadcasts the shutdown by notifying the mailbox of every
+//! registered notice, then waits for the tasks to save their state and call
+//! [`Notice::done`], each of which notifies a mailbox the rebooting task
+//! waits on. Once every task is done, or [`TIMEOUT_MS`] elapsed, the tasks
+//! not done are logged and the system is reset. A task stuck elsewhere thus
+//! delays the reset but never prevents it. The shell `reboot` command reboots
+//! this way, and the updater goes through the same steps before installing a
+//! new image, by calling [`prepare`].
+//!
+//! A task done saving should not change its state any further, as the reset
+//! follows at any time. It may keep running otherwise.
+
+use crate::console::println;
+use core::sync::atomic::{AtomicBool, Ordering};
+use hopter::{
+    sync::{Mailbox, SpinSchedSafe},
+    time,
+};
+
+/// The most tasks registered at once.
+const MAX_REGISTERED: usize = 8;
+
+/// The longest time given to the tasks to save their state.
+pub const TIMEOUT_MS: u32 = 2000;
+
+/// The means of telling a task of the shutdown.
+pub struct Notice {
+    /// Notified upon the shutdown.
+    mailbox: Mailbox,
+    done: AtomicBool,
+}
+
+/// The registered notices, with the names of their tasks.
+static NOTICES: SpinSchedSafe<[Option<(&'static str, &'static Notice)>; MAX_REGISTERED]> =
+    SpinSchedSafe::new([None; MAX_REGISTERED]);
+
+/// Set once the shutdown is broadcast.
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Notified by [`Notice::done`].
+static DONE: Mailbox = Mailbox::new();
+
+impl Notice {
+    pub const fn new() -> Self {
+        Self {
+            mailbox: Mailbox::new(),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Block until the shutdown.
+    pub fn wait(&self) {
+        while !SHUTTING_DOWN.load(Ordering::SeqCst) {
+            self.mailbox.wait();
+        }
+    }
+
+    /// Block until the shutdown or `ms` elapse. Return whether the system is
+    /// shutting down.
+    pub fn sleep_ms(&self, ms: u32) -> bool {
+        self.mailbox.wait_until_timeout(ms);
+        SHUTTING_DOWN.load(Ordering::SeqCst)
+    }
+
+    /// Tell that the task saved its state.
+    pub fn done(&self) {
+        if !self.done.swap(true, Ordering::SeqCst) {
+            DONE.notify_allow_isr();
+        }
+    }
+}
+
+/// Register the notice of the task with the name. Panics if too many tasks
+/// are registered.
+pub fn register(name: &'static str, notice: &'static Notice) {
+    let mut notices = NOTICES.lock();
+    let slot = notices
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many tasks registered for shutdown");
+    *slot = Some((name, notice));
+}
+
+/// Broadcast the shutdown and wait for the registered tasks to save their
+/// state, at most [`TIMEOUT_MS`]. If the shutdown is already under way,
+/// block for good, as the reset follows.
+pub fn prepare() {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        loop {
+            time::sleep_ms(1000).unwrap();
+        }
+    }
+
+    // Registering after the broadcast is not supported, so copy the notices
+    // out rather than hold the lock while waiting.
+    let notices = *NOTICES.lock();
+    let notices = notices.iter().flatten();
+    log::info!("shutting down {} tasks", notices.clone().count());
+    for (_, notice) in notices.clone() {
+        notice.mailbox.notify_allow_isr();
+    }
+
+    let start = time::get_tick();
+    for _ in notices.clone() {
+        let elapsed = time::get_tick().wrapping_sub(start);
+        if elapsed >= TIMEOUT_MS || !DONE.wait_until_timeout(TIMEOUT_MS - elapsed) {
+            break;
+        }
+    }
+    for (name, _) in notices.filter(|(_, notice)| !notice.done.load(Ordering::SeqCst)) {
+        log::warn!("{} did not shut down within {} ms", name, TIMEOUT_MS);
+    }
+}
+
+/// Shut down and reset the system.
+pub fn reboot() -> ! {
+    prepare();
+    println!("rebooting");
+    // Let the message go out, over USB in particular.
+    time::sleep_ms(100).unwrap();
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,182 @@
+//! One-shot and periodic software timers, whose callbacks run in a single
+//! timer service task.
+//!
+//! A small periodic chore, e.g., logging a figure once a minute, hardly
+//! deserves a task of its own, with the stack and the task slot it takes.
+//! A timer instead runs its callback in the `timers` task, which waits on a
+//! mailbox with a timeout until the earliest deadline. Creating or
+//! cancelling a timer notifies the mailbox, so that the task recomputes the
+//! deadline. Call [`spawn`] to start the task. Timers may be created before.
+//!
+//! The timers are kept in a table of [`MAX_TIMERS`] entries, and callbacks
+//! are plain functions, so no memory is allocated after initialization. A
+//! [`TimerHandle`] cancels its timer. It holds the generation of the entry,
+//! so that the handle of a fired one-shot timer does not cancel another
+//! timer later created in the same entry.
+//!
+//! The callbacks run one after another on the stack of the task, so a
+//! callback should return quickly and must not block for long. The task is
+//! restartable. A panicking callback restarts it, and the other timers are
+//! kept. A periodic timer keeps its rate. If a callback is late by more than
+//! a period, the missed periods are skipped.
+
+use crate::{stack_pool::SetStackPool, task_name::SetName};
+use hopter::{
+    sync::{Mailbox, SpinSchedSafe},
+    task, time,
+};
+use hopter_conf_params::MAX_SLEEP_MS;
+
+/// The number of timers that can exist at the same time.
+pub const MAX_TIMERS: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    callback: Option<fn()>,
+    /// The tick at which the callback runs next.
+    deadline: u32,
+    /// The period of a periodic timer, zero for a one-shot timer.
+    period: u32,
+    /// Incremented each time the entry is freed.
+    generation: u32,
+}
+
+static TIMERS: SpinSchedSafe<[Entry; MAX_TIMERS]> = SpinSchedSafe::new(
+    [Entry {
+        callback: None,
+        deadline: 0,
+        period: 0,
+        generation: 0,
+    }; MAX_TIMERS],
+);
+
+/// Notified when a timer is created or cancelled.
+static CHANGED: Mailbox = Mailbox::new();
+
+/// A timer that can be cancelled.
+#[derive(Clone, Copy)]
+pub struct TimerHandle {
+    index: usize,
+    generation: u32,
+}
+
+impl TimerHandle {
+    /// Cancel the timer. Return false if it had already fired, for a one-shot
+    /// timer, or been cancelled.
+    pub fn cancel(&self) -> bool {
+        let cancelled = {
+            let mut timers = TIMERS.lock();
+            let entry = &mut timers[self.index];
+            if entry.callback.is_some() && entry.generation == self.generation {
+                free(entry);
+                true
+            } else {
+                false
+            }
+        };
+        if cancelled {
+            CHANGED.notify_allow_isr();
+        }
+        cancelled
+    }
+}
+
+/// Run `callback` once, `delay_ms` from now. Return `None` if all timers are
+/// in use. Panic if `delay_ms` is over `MAX_SLEEP_MS`.
+pub fn one_shot(delay_ms: u32, callback: fn()) -> Option<TimerHandle> {
+    add(delay_ms, 0, callback)
+}
+
+/// Run `callback` every `period_ms`, starting one period from now. Return
+/// `None` if all timers are in use. Panic if `period_ms` is over
+/// `MAX_SLEEP_MS`.
+pub fn periodic(period_ms: u32, callback: fn()) -> Option<TimerHandle> {
+    assert!(period_ms > 0, "zero timer period");
+    add(period_ms, period_ms, callback)
+}
+
+fn add(delay_ms: u32, period: u32, callback: fn()) -> Option<TimerHandle> {
+    // Deadlines are compared by the sign of their difference to the tick.
+    assert!(delay_ms <= MAX_SLEEP_MS, "timer delay too long");
+    let handle = {
+        let mut timers = TIMERS.lock();
+        let index = timers.iter().position(|entry| entry.callback.is_none())?;
+        let entry = &mut timers[index];
+        entry.callback = Some(callback);
+        entry.deadline = time::get_tick().wrapping_add(delay_ms);
+        entry.period = period;
+        TimerHandle {
+            index,
+            generation: entry.generation,
+        }
+    };
+    CHANGED.notify_allow_isr();
+    Some(handle)
+}
+
+fn free(entry: &mut Entry) {
+    entry.callback = None;
+    entry.generation = entry.generation.wrapping_add(1);
+}
+
+/// Spawn the timer service task.
+pub fn spawn() {
+    task::build()
+        .set_name("timers")
+        .set_stack_pool(1)
+        .set_entry(run)
+        .spawn_restartable()
+        .unwrap();
+}
+
+fn run() {
+    loop {
+        for index in 0..MAX_TIMERS {
+            // Run the callback without the lock, which suspends the
+            // scheduler.
+            if let Some(callback) = take_due(index) {
+                callback();
+            }
+        }
+
+        match next_delay() {
+            Some(delay) => {
+                CHANGED.wait_until_timeout(delay);
+            }
+            None => CHANGED.wait(),
+        }
+    }
+}
+
+/// Return the callback of the timer at the index if it is due, and rearm or
+/// free the timer.
+fn take_due(index: usize) -> Option<fn()> {
+    let now = time::get_tick();
+    let mut timers = TIMERS.lock();
+    let entry = &mut timers[index];
+    let callback = entry.callback?;
+    if (now.wrapping_sub(entry.deadline) as i32) < 0 {
+        return None;
+    }
+    if entry.period == 0 {
+        free(entry);
+    } else {
+        entry.deadline = entry.deadline.wrapping_add(entry.period);
+        if (now.wrapping_sub(entry.deadline) as i32) >= 0 {
+            entry.deadline = now.wrapping_add(entry.period);
+        }
+    }
+    Some(callback)
+}
+
+/// Return the time until the earliest deadline, or `None` if there is no
+/// timer.
+fn next_delay() -> Option<u32> {
+    let now = time::get_tick();
+    TIMERS
+        .lock()
+        .iter()
+        .filter(|entry| entry.callback.is_some())
+        .map(|entry| (entry.deadline.wrapping_sub(now) as i32).max(0) as u32)
+        .min()
+}
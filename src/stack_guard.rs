@@ -0,0 +1,54 @@
+//! Canary protection of the contiguous stack.
+//!
+//! The boot code, the kernel, and the IRQ handlers all run on the contiguous
+//! stack. Segmented stacks of tasks are protected by stack limits, but an
+//! overflow of the contiguous stack can go unnoticed and silently corrupt the
+//! task local storage below it. This module fills the guard region beyond
+//! the stack boundary with a canary value and checks it later.
+//!
+//! A damaged canary is reported by panicking, which kills and unwinds the
+//! caller the same way as hitting a stack limit does. When called inside an
+//! IRQ handler, the handler is forced to return.
+
+use hopter_conf_params::{
+    __CONTIGUOUS_STACK_BOUNDARY, CONTIGUOUS_STACK_GUARD_SIZE, ENABLE_STACK_CANARY,
+    STACK_CANARY_VALUE,
+};
+
+/// The number of canary words in the guard region.
+const CANARY_WORDS: usize = CONTIGUOUS_STACK_GUARD_SIZE as usize / 4;
+
+/// The lowest address of the guard region.
+const GUARD_START: *mut u32 = (__CONTIGUOUS_STACK_BOUNDARY - CONTIGUOUS_STACK_GUARD_SIZE) as _;
+
+/// Fill the guard region with the canary value. Do nothing if
+/// `ENABLE_STACK_CANARY` is false.
+pub fn arm() {
+    if !ENABLE_STACK_CANARY {
+        return;
+    }
+
+    for i in 0..CANARY_WORDS {
+        // Safety: The guard region is reserved by the configuration and is
+        // not used by anything else.
+        unsafe { GUARD_START.add(i).write_volatile(STACK_CANARY_VALUE) };
+    }
+}
+
+/// Panic if the canary has been overwritten. The canary is restored before
+/// panicking, so that each overflow is reported only once. Do nothing if
+/// `ENABLE_STACK_CANARY` is false.
+pub fn check() {
+    if !ENABLE_STACK_CANARY {
+        return;
+    }
+
+    // Safety: Same as in `arm()`.
+    let intact = (0..CANARY_WORDS)
+        .all(|i| unsafe { GUARD_START.add(i).read_volatile() } == STACK_CANARY_VALUE);
+
+    if !intact {
+        arm();
+        panic!("contiguous stack overflow");
+    }
+}
@@ -0,0 +1,61 @@
+//! Fixed-size task stacks taken from `STACK_POOLS`.
+//!
+//! When `ALLOW_DYNAMIC_STACK` is false, e.g., under the `static-alloc`
+//! feature, a task runs on a single stack allocated in full when the task is
+//! spawned, and overflowing it brings down the whole system. A task builder
+//! picks a pool with `set_stack_pool`, which takes a stack from the pool and
+//! sizes the task's stack accordingly. Stacks are never returned to a pool.
+//!
+//! When `ALLOW_DYNAMIC_STACK` is true, `set_stack_pool` does nothing, and the
+//! stack grows and shrinks on demand.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use hopter::task::{BreathingTaskBuilder, TaskBuilder};
+use hopter_conf_params::{StackPool, ALLOW_DYNAMIC_STACK, STACK_POOLS};
+
+/// The number of stacks taken from each pool.
+static TAKEN: [AtomicUsize; STACK_POOLS.len()] = [const { AtomicUsize::new(0) }; STACK_POOLS.len()];
+
+/// Take a stack from the pool at the given index in `STACK_POOLS`, and return
+/// its size. Panic if the pool does not exist or is exhausted.
+fn take(pool: usize) -> usize {
+    let StackPool { size, count } = STACK_POOLS[pool];
+    let taken = TAKEN[pool].fetch_add(1, Ordering::SeqCst);
+    assert!(taken < count, "stack pool {} exhausted", pool);
+    size
+}
+
+/// Extend the task builders with the ability to take a stack from a pool.
+pub trait SetStackPool {
+    /// Run the task on a stack taken from the pool at the given index in
+    /// `STACK_POOLS`. Do nothing if dynamic stack extension is allowed.
+    fn set_stack_pool(self, pool: usize) -> Self;
+}
+
+impl<F> SetStackPool for TaskBuilder<F>
+where
+    F: FnOnce() + Send + 'static,
+{
+    fn set_stack_pool(self, pool: usize) -> Self {
+        if ALLOW_DYNAMIC_STACK {
+            return self;
+        }
+        self.disable_dynamic_stack().set_stack_limit(take(pool))
+    }
+}
+
+impl<F, G, H, S, I> SetStackPool for BreathingTaskBuilder<F, G, H, S, I>
+where
+    F: FnOnce() -> S + Send + Sync + 'static,
+    G: Fn(&mut S) -> I + Send + Sync + 'static,
+    H: Fn(&mut S, I) + Send + Sync + 'static,
+{
+    fn set_stack_pool(self, pool: usize) -> Self {
+        if ALLOW_DYNAMIC_STACK {
+            return self;
+        }
+        // Breathing tasks always have dynamic stacks. A large enough initial
+        // stacklet is never extended.
+        self.set_stack_init_size(take(pool))
+    }
+}
@@ -0,0 +1,250 @@
+//! Key-value storage in the last sector of the internal flash.
+//!
+//! Flash bits are erased to 1 a whole sector at a time, and programmed to 0
+//! a byte at a time. A sector endures some 10k erases. So rather than
+//! overwriting a value in place, [`set`] appends a record of the key and the
+//! new value after the previous records, and [`get`] returns the value of
+//! the last record of the key. The sector is erased only when no room is
+//! left, after which the latest value of each key is written back. The
+//! erases are thus spread over all the records the sector holds, some 16k of
+//! them.
+//!
+//! A record is the value followed by a header of the key and its
+//! complement. The header is programmed last, so a record torn by a reset
+//! lacks a valid header and is skipped. A reset while the values are written
+//! back after an erase loses them, however.
+//!
+//! The controller stalls every fetch from the flash while it erases or
+//! programs, and the flash holds all of the code, the vector table included.
+//! The operations are done holding [`FLASH`], a `SpinIrqSafe` lock masking
+//! every IRQ but SVC, so that no IRQ preempts the task midway and stalls
+//! there, or, worse, leaves the controller unlocked to a task switched in.
+//! SVC must stay unmasked, because Hopter allocates stacklets through SVC,
+//! see Part 6 of `main.rs`. Masking every interrupt with `cpsid i` instead
+//! would turn the allocation of a stacklet under the lock into a HardFault.
+//! Erasing the 128 KiB sector a byte at a time, as the HAL does, takes 2 to
+//! 4 s, during which the IRQs and the ticks of the kernel wait.
+//!
+//! The sector is reserved by the STORAGE region of `memory.x`, which keeps
+//! the program out of it.
+
+use core::{fmt, ptr};
+use hopter::{
+    interrupt::mask::AllIrqExceptSvc,
+    sync::{Mutex, SpinIrqSafe},
+};
+use stm32f4xx_hal::{
+    flash::{self, FlashExt, LockedFlash},
+    pac,
+};
+
+/// The maximum number of distinct keys. Only as many latest values are kept
+/// while the sector is erased.
+pub const MAX_KEYS: usize = 16;
+
+/// The size of a record: a 4-byte value and a 4-byte header.
+const RECORD_LEN: usize = 8;
+
+/// The value of erased flash bytes.
+const ERASED: u8 = 0xff;
+
+extern "C" {
+    // The bounds of the STORAGE region, defined in `memory.x`.
+    static _storage_start: u8;
+    static _storage_end: u8;
+}
+
+/// An error of [`set`].
+#[derive(Debug)]
+pub enum Error {
+    /// The key would exceed [`MAX_KEYS`].
+    TooManyKeys,
+    /// The flash controller reported an error.
+    Flash(flash::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooManyKeys => write!(f, "more than {} keys", MAX_KEYS),
+            Self::Flash(err) => write!(f, "flash error {:?}", err),
+        }
+    }
+}
+
+impl From<flash::Error> for Error {
+    fn from(err: flash::Error) -> Self {
+        Self::Flash(err)
+    }
+}
+
+/// The flash controller. Holding the lock masks every IRQ but SVC, see the
+/// module documentation.
+static FLASH: SpinIrqSafe<Option<LockedFlash>, AllIrqExceptSvc> = SpinIrqSafe::new(None);
+
+/// The offset of the first free record in the sector. Held by a task for the
+/// whole of a lookup or an update.
+static NEXT: Mutex<Option<usize>> = Mutex::new(None);
+
+/// Take over the flash controller and find the end of the records. Panic if
+/// called twice, or if the STORAGE region is not exactly one sector.
+pub fn init(flash: pac::FLASH) {
+    let flash = LockedFlash::new(flash);
+    let sector = flash
+        .sector(start() - flash.address())
+        .expect("the STORAGE region lies outside the flash");
+    assert!(sector.offset == start() - flash.address() && sector.size == len());
+    {
+        let mut slot = FLASH.lock();
+        assert!(slot.is_none());
+        *slot = Some(flash);
+    }
+
+    // The free records start after the last programmed byte, torn records
+    // included.
+    let next = (0..len() / RECORD_LEN)
+        .rev()
+        .find(|&index| (0..RECORD_LEN).any(|i| read_byte(index * RECORD_LEN + i) != ERASED))
+        .map_or(0, |index| (index + 1) * RECORD_LEN);
+    *NEXT.lock() = Some(next);
+}
+
+/// Return the value of the key, or `None` if it was never set. Panic if
+/// called before [`init`].
+pub fn get(key: u16) -> Option<u32> {
+    // Hold the lock until the scan ends, so no erase happens meanwhile.
+    let next = NEXT.lock();
+    records(next.unwrap())
+        .filter(|&(k, _)| k == key)
+        .last()
+        .map(|(_, value)| value)
+}
+
+/// Set the value of the key, erasing the sector if it is full. Panic if
+/// called before [`init`], or if the key is `0xffff`, which reads as erased
+/// flash.
+pub fn set(key: u16, value: u32) -> Result<(), Error> {
+    assert_ne!(key, u16::MAX);
+
+    let mut next = NEXT.lock();
+    let offset = next.as_mut().unwrap();
+    let mut latest = latest_values(*offset);
+    // The keys fill the entries from the first one.
+    let entry = latest
+        .iter_mut()
+        .find(|entry| entry.is_none() || entry.is_some_and(|(k, _)| k == key))
+        .ok_or(Error::TooManyKeys)?;
+    if *entry == Some((key, value)) {
+        return Ok(());
+    }
+    *entry = Some((key, value));
+
+    if *offset + RECORD_LEN > len() {
+        erase()?;
+        *offset = 0;
+        for (key, value) in latest.into_iter().flatten() {
+            program(*offset, key, value)?;
+            *offset += RECORD_LEN;
+        }
+        return Ok(());
+    }
+
+    // Move past the record even if programming it failed, since some of its
+    // bytes may be programmed already.
+    let result = program(*offset, key, value);
+    *offset += RECORD_LEN;
+    Ok(result?)
+}
+
+/// Return the number of bytes taken by the records, and the size of the
+/// sector. Panic if called before [`init`].
+pub fn usage() -> (usize, usize) {
+    (NEXT.lock().unwrap(), len())
+}
+
+/// Return the latest value of each key among the records before `next`.
+fn latest_values(next: usize) -> [Option<(u16, u32)>; MAX_KEYS] {
+    let mut latest = [None; MAX_KEYS];
+    for (key, value) in records(next) {
+        let entry = latest
+            .iter_mut()
+            .find(|entry| entry.is_none() || entry.is_some_and(|(k, _)| k == key));
+        // Only `set` adds keys, up to `MAX_KEYS` of them.
+        if let Some(entry) = entry {
+            *entry = Some((key, value));
+        }
+    }
+    latest
+}
+
+/// Iterate over the keys and the values of the valid records before `next`,
+/// oldest first.
+fn records(next: usize) -> impl Iterator<Item = (u16, u32)> {
+    (0..next).step_by(RECORD_LEN).filter_map(|offset| {
+        let word = |at: usize| u32::from_le_bytes(core::array::from_fn(|i| read_byte(at + i)));
+        let value = word(offset);
+        let header = word(offset + 4);
+        let key = header as u16;
+        (header >> 16 == u32::from(!key) && key != u16::MAX).then_some((key, value))
+    })
+}
+
+/// Run `f` on the flash controller holding the lock that masks every IRQ
+/// but SVC, then drop the flash contents cached before. Lets other modules,
+/// e.g., `updater`, erase and program the flash outside of the sector. Panic
+/// if called before [`init`].
+pub fn with_flash<R>(f: impl FnOnce(&mut LockedFlash) -> R) -> R {
+    let mut flash = FLASH.lock();
+    let result = f(flash.as_mut().unwrap());
+    reset_data_cache();
+    result
+}
+
+/// Erase the sector.
+fn erase() -> Result<(), flash::Error> {
+    with_flash(|flash| {
+        let sector = flash.sector(start() - flash.address()).unwrap().number;
+        flash.unlocked().erase(sector)
+    })
+}
+
+/// Program a record at the offset in the sector, the header last.
+fn program(offset: usize, key: u16, value: u32) -> Result<(), flash::Error> {
+    let header = u32::from(key) | u32::from(!key) << 16;
+    with_flash(|flash| {
+        let at = start() - flash.address() + offset;
+        let mut unlocked = flash.unlocked();
+        unlocked
+            .program(at, value.to_le_bytes().iter())
+            .and_then(|()| unlocked.program(at + 4, header.to_le_bytes().iter()))
+    })
+}
+
+/// Drop the flash contents cached before an erase or a program, which the
+/// data cache does not notice.
+fn reset_data_cache() {
+    // Safety: Only the data cache bits are modified, by the holder of the
+    // `FLASH` lock.
+    let acr = unsafe { &(*pac::FLASH::ptr()).acr };
+    acr.modify(|_, w| w.dcen().clear_bit());
+    acr.modify(|_, w| w.dcrst().set_bit());
+    acr.modify(|_, w| w.dcrst().clear_bit());
+    acr.modify(|_, w| w.dcen().set_bit());
+}
+
+/// Read a byte at the offset in the sector.
+fn read_byte(offset: usize) -> u8 {
+    // Safety: The offset is within the sector, which the program never
+    // writes but through the controller.
+    unsafe { ptr::read_volatile((start() + offset) as *const u8) }
+}
+
+/// Return the address of the sector.
+fn start() -> usize {
+    ptr::addr_of!(_storage_start) as usize
+}
+
+/// Return the size of the sector.
+fn len() -> usize {
+    ptr::addr_of!(_storage_end) as usize - start()
+}
@@ -0,0 +1,98 @@
+//! An escalation ladder for the restarts of a restartable task.
+//!
+//! Hopter restarts a panicking restartable task right away, and as often as
+//! it panics. A task failing upon every start thus burns the CPU restarting,
+//! and a fault it cannot clear on its own, e.g., a peripheral stuck in a bad
+//! state, is never cleared. [`supervised`] runs the work of a task under a
+//! [`Ladder`] instead. The first failures in a row restart the task right
+//! away. Further ones restart it after a delay, doubled at every failure up
+//! to a maximum, which leaves the CPU to the other tasks and gives a
+//! transient fault time to go away. Once the failures reach
+//! [`Ladder::reset_after`], the system is rebooted through the `shutdown`
+//! module, as a reset is the last remedy left.
+//!
+//! The failures are counted in the last task-local storage slot of the task,
+//! which a restarted instance inherits, see the `task_local` module. A guard
+//! counts a failure when dropped by the unwinding of the task. An instance
+//! running for [`Ladder::healthy_ms`] before failing starts the count anew,
+//! so that rare failures are never escalated. The panic record of the
+//! `panic_persist` module tells the task behind the reset after the reboot,
+//! if the work is run through `panic_policy::guarded`.
+
+use crate::{shutdown, task_local, task_name};
+use core::mem;
+use hopter::{task, time};
+use hopter_conf_params::TLS_SLOT_COUNT;
+
+/// The task-local storage slot counting the failures in a row.
+const SLOT: usize = TLS_SLOT_COUNT - 1;
+
+/// The reactions to the failures in a row of a task.
+pub struct Ladder {
+    /// The failures after which the task is restarted right away.
+    pub immediate: u32,
+    /// The delay before the first delayed restart.
+    pub delay_ms: u32,
+    /// The longest delay before a restart.
+    pub max_delay_ms: u32,
+    /// The failures after which the system is rebooted.
+    pub reset_after: u32,
+    /// The time an instance must run before failing for the count of
+    /// failures to start anew.
+    pub healthy_ms: u32,
+}
+
+impl Ladder {
+    /// Return the delay before restarting after the failures, if any.
+    fn delay_ms(&self, failures: u32) -> Option<u32> {
+        let delayed = failures.checked_sub(self.immediate + 1)?;
+        let delay = self.delay_ms.saturating_mul(1 << delayed.min(16));
+        Some(delay.min(self.max_delay_ms))
+    }
+}
+
+/// Run `f` under `ladder`, delaying the start or rebooting the system first
+/// if the task failed in a row before. Must be called from the entry
+/// closure of a restartable task.
+pub fn supervised(ladder: &Ladder, f: impl FnOnce()) {
+    let failures = task_local::with(SLOT, |slot| slot[0]);
+    let name = task_name::name_of(task::get_current_id()).unwrap_or("task");
+
+    if failures >= ladder.reset_after {
+        log::error!("{} failed {} times in a row, rebooting", name, failures);
+        shutdown::reboot();
+    }
+    if let Some(delay) = ladder.delay_ms(failures) {
+        log::warn!(
+            "{} failed {} times in a row, restarting in {} ms",
+            name,
+            failures,
+            delay
+        );
+        time::sleep_ms(delay).unwrap();
+    }
+
+    let guard = Guard {
+        start: time::get_tick(),
+        healthy_ms: ladder.healthy_ms,
+    };
+    f();
+    mem::forget(guard);
+    task_local::with(SLOT, |slot| slot[0] = 0);
+}
+
+/// Counts a failure when dropped by the unwinding of the task.
+struct Guard {
+    /// The tick at which the instance started its work.
+    start: u32,
+    healthy_ms: u32,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let healthy = time::get_tick().wrapping_sub(self.start) >= self.healthy_ms;
+        task_local::with(SLOT, |slot| {
+            slot[0] = if healthy { 1 } else { slot[0] + 1 };
+        });
+    }
+}
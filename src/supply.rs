@@ -0,0 +1,146 @@
+//! Monitoring of the supply voltage VDD and of the backup battery VBAT.
+//!
+//! A breathing task samples VREFINT and VBAT through ADC1 every
+//! [`SAMPLE_PERIOD_MS`], taking turns with the `temperature` module, see
+//! `src/shared_adc.rs`. VREFINT gives VDDA, which is VDD on boards tying the
+//! two together, as the discovery boards do. VBAT is switched on only for its
+//! conversion, as its bridge drains the battery, and is read through a
+//! divider by [`VBAT_DIVIDER`], so that it stays below VDDA. On the discovery
+//! boards, VBAT is tied to VDD as well.
+//!
+//! The supply of a board sags slowly, e.g., as a battery runs down, or as a
+//! load is added to a weak regulator. The task waits a long time between
+//! samples, with its stack released, so watching costs neither CPU time nor
+//! stack memory. A dip shorter than the period goes unseen. Dips down to the
+//! brownout level are caught by the brownout reset of the MCU instead.
+//!
+//! When VDD falls below [`SAG_THRESHOLD_MV`], a warning is logged, and a
+//! notice when it recovers above the threshold by [`HYSTERESIS_MV`], so that
+//! a supply hovering around the threshold is not reported at every sample.
+//! The `supply` shell command prints the latest reading and the sags seen.
+
+use crate::{
+    breathing_group, console::println, shared_adc, shell::Line, stack_pool::SetStackPool,
+    task_name::SetName,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+use hopter::{
+    sync::SpinSchedSafe,
+    task,
+    time::{self, IntervalBarrier},
+};
+use stm32f4xx_hal::adc::{config::SampleTime, Vbat, Vref};
+
+/// The period at which the supply is sampled.
+pub const SAMPLE_PERIOD_MS: u32 = 30_000;
+
+/// The VDD below which the supply is considered sagging. The discovery boards
+/// supply 3 V.
+pub const SAG_THRESHOLD_MV: u32 = 2800;
+
+/// How far above the threshold VDD must rise before the sag is over.
+pub const HYSTERESIS_MV: u32 = 50;
+
+/// The ratio by which VBAT is divided before it is converted, 2 on
+/// STM32F405/407, and 4 on later parts.
+const VBAT_DIVIDER: u32 = 2;
+
+/// The full scale of a 12-bit sample.
+const FULL_SCALE: u32 = 4095;
+
+/// A reading of the supply.
+#[derive(Clone, Copy)]
+pub struct Reading {
+    /// The tick at which the reading was taken.
+    pub tick: u32,
+    /// The supply voltage VDD in millivolts.
+    pub vdd_mv: u32,
+    /// The battery voltage VBAT in millivolts.
+    pub vbat_mv: u32,
+}
+
+/// The latest reading. It is `None` until the first sample is taken.
+static LATEST: SpinSchedSafe<Option<Reading>> = SpinSchedSafe::new(None);
+
+/// The sags seen since boot, and the lowest VDD seen.
+static SAGS: AtomicU32 = AtomicU32::new(0);
+static LOWEST_MV: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// Spawn the breathing task sampling the supply through ADC1. Must be called
+/// after `shared_adc::init`.
+pub fn spawn() {
+    struct Ctxt {
+        barrier: IntervalBarrier,
+        sagging: bool,
+    }
+
+    task::build_breathing()
+        .set_name("supply")
+        .set_stack_pool(0)
+        .set_init(|| Ctxt {
+            barrier: IntervalBarrier::new(SAMPLE_PERIOD_MS).unwrap(),
+            sagging: false,
+        })
+        .set_wait(breathing_group::in_group("default", |ctxt: &mut Ctxt| {
+            ctxt.barrier.wait()
+        }))
+        .set_work(breathing_group::grouped(|ctxt: &mut Ctxt, _| {
+            let reading = sample();
+            *LATEST.lock() = Some(reading);
+            LOWEST_MV.fetch_min(reading.vdd_mv, Ordering::SeqCst);
+
+            if !ctxt.sagging && reading.vdd_mv < SAG_THRESHOLD_MV {
+                ctxt.sagging = true;
+                SAGS.fetch_add(1, Ordering::SeqCst);
+                log::warn!(
+                    "VDD sagged to {} mV, below {} mV",
+                    reading.vdd_mv,
+                    SAG_THRESHOLD_MV
+                );
+            } else if ctxt.sagging && reading.vdd_mv >= SAG_THRESHOLD_MV + HYSTERESIS_MV {
+                ctxt.sagging = false;
+                log::info!("VDD recovered to {} mV", reading.vdd_mv);
+            }
+            log::debug!("VDD {} mV, VBAT {} mV", reading.vdd_mv, reading.vbat_mv);
+        }))
+        .spawn()
+        .unwrap();
+}
+
+/// Convert VREFINT and VBAT.
+fn sample() -> Reading {
+    // Both signals need a sampling time above 10 us, so take the longest one.
+    let (vref, vbat) = shared_adc::with(|adc| {
+        let vref = adc.convert(&Vref, SampleTime::Cycles_480);
+        adc.enable_vbat();
+        let vbat = adc.convert(&Vbat, SampleTime::Cycles_480);
+        adc.disable_vbat();
+        (vref, vbat)
+    });
+
+    let vdd_mv = shared_adc::vdda_mv(vref);
+    Reading {
+        tick: time::get_tick(),
+        vdd_mv,
+        vbat_mv: u32::from(vbat) * vdd_mv * VBAT_DIVIDER / FULL_SCALE,
+    }
+}
+
+/// The `supply` shell command: print the latest reading and the sags seen.
+pub fn command(_: &Line) {
+    let latest = *LATEST.lock();
+    let Some(reading) = latest else {
+        println!("no sample yet");
+        return;
+    };
+    println!(
+        "VDD {} mV, VBAT {} mV, at tick {}",
+        reading.vdd_mv, reading.vbat_mv, reading.tick
+    );
+    println!(
+        "{} sags below {} mV, lowest VDD {} mV",
+        SAGS.load(Ordering::SeqCst),
+        SAG_THRESHOLD_MV,
+        LOWEST_MV.load(Ordering::SeqCst)
+    );
+}
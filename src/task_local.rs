@@ -0,0 +1,59 @@
+//! Task-local storage slots for the application.
+//!
+//! The kernel keeps its own task-local fields at `__TLS_MEM_ADDR` and leaves
+//! no room there for the application. This module provides each task with
+//! `TLS_SLOT_COUNT` slots of `TLS_SLOT_SIZE` bytes instead, viewed as arrays
+//! of 32-bit words.
+//!
+//! Slots are looked up by task ID. A restarted instance of a restartable
+//! task keeps the ID, and hence also the slots, of the panicked instance.
+//! Tasks sharing an ID share their slots, so tasks needing slots of their
+//! own should be given distinct names. See the `task_name` module. Slots are
+//! not reclaimed when a task ends.
+
+use hopter::{config, sync::SpinSchedSafe, task};
+use hopter_conf_params::{TLS_SLOT_COUNT, TLS_SLOT_SIZE};
+
+/// The number of words in a slot.
+const SLOT_WORDS: usize = TLS_SLOT_SIZE / 4;
+
+/// All slots of a task.
+type Slots = [[u32; SLOT_WORDS]; TLS_SLOT_COUNT];
+
+/// The slots of each task ID seen so far.
+static TABLE: SpinSchedSafe<[Option<(u8, Slots)>; config::MAX_TASK_NUMBER]> =
+    SpinSchedSafe::new([None; config::MAX_TASK_NUMBER]);
+
+/// Run `f` with the given slot of the current task. All slots are initially
+/// zeroed. The closure runs with the scheduler suspended, so it should be
+/// short.
+///
+/// Panic if `slot` is out of range, or if more task IDs ask for slots than
+/// `MAX_TASK_NUMBER`.
+pub fn with<R>(slot: usize, f: impl FnOnce(&mut [u32; SLOT_WORDS]) -> R) -> R {
+    assert!(
+        slot < TLS_SLOT_COUNT,
+        "task-local storage slot out of range"
+    );
+
+    let id = task::get_current_id();
+    let mut table = TABLE.lock();
+
+    let idx = match table
+        .iter()
+        .position(|entry| matches!(entry, Some((owner, _)) if *owner == id))
+    {
+        Some(idx) => idx,
+        None => {
+            let idx = table
+                .iter()
+                .position(Option::is_none)
+                .expect("out of task-local storage");
+            table[idx] = Some((id, [[0; SLOT_WORDS]; TLS_SLOT_COUNT]));
+            idx
+        }
+    };
+
+    let (_, slots) = table[idx].as_mut().unwrap();
+    f(&mut slots[slot])
+}
@@ -0,0 +1,192 @@
+//! Human-readable task names.
+//!
+//! Hopter tags each task only with a numerical ID, which need not be unique.
+//! This module lets a task be given a name when it is built. Every distinct
+//! name is bound to its own ID, so the name of a task can later be recovered
+//! from its ID. A restarted instance of a restartable task keeps the ID, and
+//! hence also the name, of the panicked instance.
+//!
+//! With the `ENABLE_UNIQUE_TASK_IDS` configuration parameter set, tasks
+//! named alike but spawned at different places in the code get IDs of their
+//! own. A task spawned again at the same place, e.g., by a shell command run
+//! repeatedly, gets the ID bound on the first spawn, so that the IDs do not
+//! run out. Tasks spawned in a loop should thus be named apart, as the
+//! workers of Part 14A are.
+//!
+//! A task named with [`SetName::set_name_and_priority`] also has its priority
+//! recorded along with the name, for the `ps` shell command. The kernel keeps
+//! the priority of a task to itself.
+//!
+//! Naming can be turned off with the `ENABLE_TASK_NAMES` configuration
+//! parameter, in which case tasks keep the default ID.
+
+use core::{
+    panic::Location,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use hopter::{
+    config,
+    sync::SpinSchedSafe,
+    task::{BreathingTaskBuilder, TaskBuilder},
+};
+use hopter_conf_params::{
+    DEFAULT_TASK_PRIORITY, ENABLE_TASK_NAMES, ENABLE_UNIQUE_TASK_IDS, FIRST_APP_TASK_ID,
+    MAX_TASK_NAME_LEN,
+};
+
+// Every name must get an ID distinct from the default one.
+const _: () = assert!(
+    FIRST_APP_TASK_ID as usize + config::MAX_TASK_NUMBER <= config::DEFAULT_TASK_ID as usize
+);
+
+/// The registered names. The name at index `i` is bound to the ID
+/// `FIRST_APP_TASK_ID + i`. Names registered beyond the capacity are ignored.
+static NAMES: SpinSchedSafe<[Option<Binding>; config::MAX_TASK_NUMBER]> =
+    SpinSchedSafe::new([None; config::MAX_TASK_NUMBER]);
+
+/// A name along with the place in the code where it was first given.
+#[derive(Clone, Copy)]
+struct Binding {
+    name: &'static str,
+    site: &'static Location<'static>,
+}
+
+/// The priorities recorded along with the names, at the same indices.
+static PRIORITIES: [AtomicU8; config::MAX_TASK_NUMBER] =
+    [const { AtomicU8::new(DEFAULT_TASK_PRIORITY) }; config::MAX_TASK_NUMBER];
+
+/// Return the ID bound to the given name, registering the name if it is seen
+/// for the first time. If unique IDs are enabled, the name is registered anew
+/// for each place in the code it is given at. Return `None` if task naming is
+/// disabled or if no more name can be registered.
+#[track_caller]
+fn bind(name: &'static str) -> Option<u8> {
+    if !ENABLE_TASK_NAMES {
+        return None;
+    }
+
+    let name = truncate(name);
+    let site = Location::caller();
+    let mut names = NAMES.lock();
+
+    for (idx, slot) in names.iter_mut().enumerate() {
+        match slot {
+            Some(bound)
+                if bound.name == name && (!ENABLE_UNIQUE_TASK_IDS || *bound.site == *site) =>
+            {
+                return Some(FIRST_APP_TASK_ID + idx as u8)
+            }
+            Some(_) => continue,
+            None => {
+                *slot = Some(Binding { name, site });
+                return Some(FIRST_APP_TASK_ID + idx as u8);
+            }
+        }
+    }
+
+    None
+}
+
+/// Return the name bound to the given ID, or `None` if no name is bound to
+/// it.
+pub fn name_of(id: u8) -> Option<&'static str> {
+    let idx = id.checked_sub(FIRST_APP_TASK_ID)? as usize;
+    Some(NAMES.lock().get(idx)?.as_ref()?.name)
+}
+
+/// Return the priority recorded for the given ID, or `None` if no name is
+/// bound to it. Tasks not named with [`SetName::set_name_and_priority`] have
+/// the default priority.
+pub fn priority_of(id: u8) -> Option<u8> {
+    name_of(id)?;
+    let idx = (id - FIRST_APP_TASK_ID) as usize;
+    Some(PRIORITIES[idx].load(Ordering::Relaxed))
+}
+
+/// Return the registered names with the IDs bound to them.
+pub fn names() -> impl Iterator<Item = (u8, &'static str)> {
+    // Copy the names out so that the lock is not held while iterating.
+    let names = *NAMES.lock();
+    names
+        .into_iter()
+        .enumerate()
+        .filter_map(|(idx, binding)| Some((FIRST_APP_TASK_ID + idx as u8, binding?.name)))
+}
+
+/// Truncate the name to at most `MAX_TASK_NAME_LEN` bytes without splitting
+/// a UTF-8 character.
+fn truncate(name: &str) -> &str {
+    let mut len = name.len().min(MAX_TASK_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    &name[..len]
+}
+
+/// Extend the task builders with the ability to name the task.
+pub trait SetName {
+    /// Give the task a human-readable name. The task's ID is set to the one
+    /// bound to the name.
+    fn set_name(self, name: &'static str) -> Self;
+
+    /// Give the task a name, as [`set_name`](Self::set_name), and set its
+    /// priority, which is recorded along with the name.
+    fn set_name_and_priority(self, name: &'static str, prio: u8) -> Self;
+}
+
+/// Record the priority for the ID bound to a name.
+fn record_priority(id: u8, prio: u8) {
+    PRIORITIES[(id - FIRST_APP_TASK_ID) as usize].store(prio, Ordering::Relaxed);
+}
+
+impl<F> SetName for TaskBuilder<F>
+where
+    F: FnOnce() + Send + 'static,
+{
+    #[track_caller]
+    fn set_name(self, name: &'static str) -> Self {
+        match bind(name) {
+            Some(id) => self.set_id(id),
+            None => self,
+        }
+    }
+
+    #[track_caller]
+    fn set_name_and_priority(self, name: &'static str, prio: u8) -> Self {
+        let builder = self.set_priority(prio);
+        match bind(name) {
+            Some(id) => {
+                record_priority(id, prio);
+                builder.set_id(id)
+            }
+            None => builder,
+        }
+    }
+}
+
+impl<F, G, H, S, I> SetName for BreathingTaskBuilder<F, G, H, S, I>
+where
+    F: FnOnce() -> S + Send + Sync + 'static,
+    G: Fn(&mut S) -> I + Send + Sync + 'static,
+    H: Fn(&mut S, I) + Send + Sync + 'static,
+{
+    #[track_caller]
+    fn set_name(self, name: &'static str) -> Self {
+        match bind(name) {
+            Some(id) => self.set_id(id),
+            None => self,
+        }
+    }
+
+    #[track_caller]
+    fn set_name_and_priority(self, name: &'static str, prio: u8) -> Self {
+        let builder = self.set_priority(prio);
+        match bind(name) {
+            Some(id) => {
+                record_priority(id, prio);
+                builder.set_id(id)
+            }
+            None => builder,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Sampling of the internal temperature sensor and VREFINT through ADC1.
+//!
+//! A breathing task samples both channels every [`SAMPLE_PERIOD_MS`] and
+//! publishes the reading, which other tasks fetch with [`latest`]. Between
+//! samples, the task waits with its stack released, so the periodic sampling
+//! costs hardly any stack memory. See Part 4 of `main.rs`. The task shares
+//! ADC1 with the `supply` module, see `src/shared_adc.rs`.
+//!
+//! The conversions rely on the factory calibration values stored in the
+//! system memory. VREFINT gives the supply voltage VDDA, which scales every
+//! ADC sample. The temperature sensor is calibrated at 30 and 110 degrees
+//! Celsius with VDDA at 3.3 V, so its sample is first rescaled to 3.3 V and
+//! then interpolated between the two points.
+
+use crate::{
+    breathing_group,
+    shared_adc::{self, CALIBRATION_VDDA_MV},
+    stack_pool::SetStackPool,
+    task_name::SetName,
+};
+use core::fmt;
+use hopter::{
+    sync::SpinSchedSafe,
+    task,
+    time::{self, IntervalBarrier},
+};
+use stm32f4xx_hal::{
+    adc::{config::SampleTime, Temperature, Vref},
+    signature::{VtempCal110, VtempCal30},
+};
+
+/// The period at which the sensors are sampled.
+pub const SAMPLE_PERIOD_MS: u32 = 1000;
+
+/// A reading of the sensors.
+#[derive(Clone, Copy)]
+pub struct Reading {
+    /// The tick at which the reading was taken.
+    pub tick: u32,
+    /// The supply voltage VDDA in millivolts.
+    pub vdda_mv: u32,
+    /// The chip temperature in hundredths of a degree Celsius.
+    pub centi_celsius: i32,
+}
+
+/// Display hundredths of a degree Celsius in degrees with two decimals.
+pub struct Celsius(pub i32);
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, abs / 100, abs % 100)
+    }
+}
+
+/// The latest reading. It is `None` until the first sample is taken.
+static LATEST: SpinSchedSafe<Option<Reading>> = SpinSchedSafe::new(None);
+
+/// Return the latest reading, or `None` if no sample has been taken yet.
+pub fn latest() -> Option<Reading> {
+    *LATEST.lock()
+}
+
+/// Spawn the breathing task sampling the sensors through ADC1. Must be
+/// called after `shared_adc::init`.
+pub fn spawn() {
+    task::build_breathing()
+        .set_name("temperature")
+        .set_stack_pool(0)
+        .set_init(|| IntervalBarrier::new(SAMPLE_PERIOD_MS).unwrap())
+        .set_wait(breathing_group::in_group(
+            "default",
+            |barrier: &mut IntervalBarrier| barrier.wait(),
+        ))
+        .set_work(breathing_group::grouped(|_: &mut IntervalBarrier, _| {
+            // Both signals need a sampling time above 10 us, so take the
+            // longest one.
+            let (vref, temp) = shared_adc::with(|adc| {
+                (
+                    adc.convert(&Vref, SampleTime::Cycles_480),
+                    adc.convert(&Temperature, SampleTime::Cycles_480),
+                )
+            });
+            let reading = convert(vref, temp);
+            *LATEST.lock() = Some(reading);
+            log::debug!(
+                "VDDA {} mV, {} C",
+                reading.vdda_mv,
+                Celsius(reading.centi_celsius)
+            );
+        }))
+        .spawn()
+        .unwrap();
+}
+
+/// Convert the raw samples of VREFINT and of the temperature sensor.
+fn convert(vref: u16, temp: u16) -> Reading {
+    let vdda_mv = shared_adc::vdda_mv(vref);
+
+    let temp = (u32::from(temp) * vdda_mv / CALIBRATION_VDDA_MV) as i32;
+    let cal30 = i32::from(VtempCal30::get().read());
+    let cal110 = i32::from(VtempCal110::get().read());
+    let centi_celsius = 3000 + (temp - cal30) * (11000 - 3000) / (cal110 - cal30).max(1);
+
+    Reading {
+        tick: time::get_tick(),
+        vdda_mv,
+        centi_celsius,
+    }
+}
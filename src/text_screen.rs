@@ -0,0 +1,164 @@
+//! A screen of text lines for the display of `drivers::st7789`.
+//!
+//! The text is drawn into a framebuffer with one bit per pixel, set for the
+//! foreground. A frame in the RGB565 format of the display would take
+//! 115200 bytes, more than the CCM of STM32F407 or the free SRAM can spare,
+//! whereas the bits take [`BYTES`]. The rows are expanded to RGB565 only as
+//! the display driver asks for them, see [`TextScreen::expand_row`].
+//!
+//! The characters are drawn with a 5x7 font scaled up twice, in cells of
+//! 12x16 pixels, giving [`LINES`] lines of [`COLUMNS`] characters. The font
+//! covers the digits, the letters, drawn in upper case, and a few symbols.
+//! Other characters are drawn as `?`.
+
+use core::fmt;
+
+use crate::drivers::st7789::{HEIGHT, ROW_BYTES, WIDTH};
+
+/// The number of bytes in the framebuffer.
+pub const BYTES: usize = WIDTH * HEIGHT / 8;
+
+/// The number of characters in a line.
+pub const COLUMNS: usize = WIDTH / CELL_WIDTH;
+
+/// The number of lines on the screen.
+pub const LINES: usize = HEIGHT / CELL_HEIGHT;
+
+/// The number of times each dot of the font is repeated in both directions.
+const SCALE: usize = 2;
+
+const CELL_WIDTH: usize = 12;
+const CELL_HEIGHT: usize = 16;
+
+/// The number of bytes in a row of the framebuffer.
+const STRIDE: usize = WIDTH / 8;
+
+/// The screen.
+pub struct TextScreen {
+    bits: &'static mut [u8],
+}
+
+impl TextScreen {
+    /// Take the framebuffer of [`BYTES`] bytes, which can live in any memory
+    /// the CPU can write, and clear it.
+    pub fn new(bits: &'static mut [u8]) -> Self {
+        assert_eq!(bits.len(), BYTES);
+        bits.fill(0);
+        Self { bits }
+    }
+
+    /// Clear the line at the index and return a writer drawing text into it
+    /// from the left. The characters beyond [`COLUMNS`] are dropped.
+    pub fn line(&mut self, index: usize) -> Line<'_> {
+        assert!(index < LINES);
+        let start = index * CELL_HEIGHT * STRIDE;
+        self.bits[start..start + CELL_HEIGHT * STRIDE].fill(0);
+        Line {
+            screen: self,
+            index,
+            column: 0,
+        }
+    }
+
+    /// Fill the [`ROW_BYTES`] bytes with the pixels of the row in RGB565,
+    /// `fg` where the text is and `bg` elsewhere.
+    pub fn expand_row(&self, row: usize, out: &mut [u8], fg: u16, bg: u16) {
+        let bits = &self.bits[row * STRIDE..(row + 1) * STRIDE];
+        for (x, pixel) in out[..ROW_BYTES].chunks_exact_mut(2).enumerate() {
+            let set = bits[x / 8] & (0x80 >> (x % 8)) != 0;
+            pixel.copy_from_slice(&if set { fg } else { bg }.to_be_bytes());
+        }
+    }
+
+    /// Draw the character in the cell at the line and the column.
+    fn draw_char(&mut self, index: usize, column: usize, c: char) {
+        // Leave a blank pixel above and left of the glyph.
+        let x0 = column * CELL_WIDTH + 1;
+        let y0 = index * CELL_HEIGHT + 1;
+        for (gx, dots) in glyph(c).into_iter().enumerate() {
+            for gy in (0..7).filter(|gy| dots & (1 << gy) != 0) {
+                for y in y0 + gy * SCALE..y0 + (gy + 1) * SCALE {
+                    for x in x0 + gx * SCALE..x0 + (gx + 1) * SCALE {
+                        self.bits[y * STRIDE + x / 8] |= 0x80 >> (x % 8);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A writer drawing text into a line, see [`TextScreen::line`].
+pub struct Line<'a> {
+    screen: &'a mut TextScreen,
+    index: usize,
+    column: usize,
+}
+
+impl fmt::Write for Line<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.column == COLUMNS {
+                break;
+            }
+            self.screen.draw_char(self.index, self.column, c);
+            self.column += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Return the columns of the glyph, left to right, with the top row in the
+/// least significant bit.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        '(' => [0x00, 0x1c, 0x22, 0x41, 0x00],
+        ')' => [0x00, 0x41, 0x22, 0x1c, 0x00],
+        '+' => [0x08, 0x08, 0x3e, 0x08, 0x08],
+        ',' => [0x00, 0x50, 0x30, 0x00, 0x00],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '/' => [0x20, 0x10, 0x08, 0x04, 0x02],
+        '0' => [0x3e, 0x51, 0x49, 0x45, 0x3e],
+        '1' => [0x00, 0x42, 0x7f, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4b, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7f, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3c, 0x4a, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1e],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        '=' => [0x14, 0x14, 0x14, 0x14, 0x14],
+        'A' => [0x7e, 0x11, 0x11, 0x11, 0x7e],
+        'B' => [0x7f, 0x49, 0x49, 0x49, 0x36],
+        'C' => [0x3e, 0x41, 0x41, 0x41, 0x22],
+        'D' => [0x7f, 0x41, 0x41, 0x22, 0x1c],
+        'E' => [0x7f, 0x49, 0x49, 0x49, 0x41],
+        'F' => [0x7f, 0x09, 0x09, 0x09, 0x01],
+        'G' => [0x3e, 0x41, 0x49, 0x49, 0x7a],
+        'H' => [0x7f, 0x08, 0x08, 0x08, 0x7f],
+        'I' => [0x00, 0x41, 0x7f, 0x41, 0x00],
+        'J' => [0x20, 0x40, 0x41, 0x3f, 0x01],
+        'K' => [0x7f, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7f, 0x40, 0x40, 0x40, 0x40],
+        'M' => [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+        'N' => [0x7f, 0x04, 0x08, 0x10, 0x7f],
+        'O' => [0x3e, 0x41, 0x41, 0x41, 0x3e],
+        'P' => [0x7f, 0x09, 0x09, 0x09, 0x06],
+        'Q' => [0x3e, 0x41, 0x51, 0x21, 0x5e],
+        'R' => [0x7f, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'T' => [0x01, 0x01, 0x7f, 0x01, 0x01],
+        'U' => [0x3f, 0x40, 0x40, 0x40, 0x3f],
+        'V' => [0x1f, 0x20, 0x40, 0x20, 0x1f],
+        'W' => [0x3f, 0x40, 0x38, 0x40, 0x3f],
+        'X' => [0x63, 0x14, 0x08, 0x14, 0x63],
+        'Y' => [0x07, 0x08, 0x70, 0x08, 0x07],
+        'Z' => [0x61, 0x51, 0x49, 0x45, 0x43],
+        '_' => [0x40, 0x40, 0x40, 0x40, 0x40],
+        _ => [0x02, 0x01, 0x51, 0x09, 0x06],
+    }
+}
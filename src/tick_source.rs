@@ -0,0 +1,68 @@
+//! Driving the kernel tick from TIM7 instead of SysTick.
+//!
+//! The kernel advances its tick in the SysTick exception handler. When
+//! `TICK_SOURCE` selects a timer, TIM7 is configured to fire its update IRQ
+//! every millisecond, and the IRQ handler pends the SysTick exception so
+//! that the kernel handler runs. The SysTick counter is stopped and becomes
+//! free for other uses, e.g., a third-party middleware polling it as a
+//! free-running counter.
+
+use hopter::{
+    config,
+    interrupt::declare::{handler, irq},
+    sync::SpinIrqSafe,
+};
+use hopter_conf_params::{TickSource, TICK_SOURCE};
+use stm32f4xx_hal::{
+    pac::{self, TIM7},
+    prelude::*,
+    rcc::Clocks,
+    timer::{CounterHz, Event},
+};
+
+// The wiring below is specific to TIM7.
+const _: () = assert!(
+    match TICK_SOURCE {
+        TickSource::SysTick => true,
+        TickSource::Tim { tim, irq } => tim == 7 && irq == pac::Interrupt::TIM7 as u16,
+    },
+    "only TIM7 is wired as the alternative tick source"
+);
+
+irq!(Tim7Irq, pac::interrupt::TIM7);
+
+/// The timer producing the tick. TIM7 IRQ is masked when the lock is held.
+static TICK_TIMER: SpinIrqSafe<Option<CounterHz<TIM7>>, Tim7Irq> = SpinIrqSafe::new(None);
+
+/// Move the kernel tick from SysTick to TIM7 if `TICK_SOURCE` says so. Do
+/// nothing otherwise.
+pub fn init(tim7: TIM7, clocks: &Clocks, cp: &mut cortex_m::Peripherals) {
+    if TICK_SOURCE == TickSource::SysTick {
+        return;
+    }
+
+    let mut timer = tim7.counter_hz(clocks);
+    timer.listen(Event::Update);
+    timer.start(1.kHz()).unwrap();
+    *TICK_TIMER.lock() = Some(timer);
+
+    // The exception is pended by TIM7 from now on.
+    cp.SYST.disable_interrupt();
+    cp.SYST.disable_counter();
+
+    // The tick should be as urgent as it is on SysTick.
+    unsafe {
+        cp.NVIC
+            .set_priority(pac::interrupt::TIM7, config::SYSTICK_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::TIM7);
+    }
+}
+
+#[handler(TIM7)]
+fn tim7_handler() {
+    // Let the kernel advance the tick.
+    cortex_m::peripheral::SCB::set_pendst();
+
+    // Acknowledge the IRQ.
+    TICK_TIMER.lock().as_mut().unwrap().wait().unwrap();
+}
@@ -0,0 +1,428 @@
+//! Firmware update over the console by XMODEM.
+//!
+//! The `update` shell command erases the STAGING region of `memory.x` and
+//! receives a new application image into it by XMODEM, the CRC variant with
+//! either 128-byte or 1 KiB blocks, as sent by most terminals, e.g., `sx` of
+//! lrzsz, Tera Term, or minicom. The blocks are programmed as they arrive, so
+//! the image never needs to fit in the RAM. The log output is muted
+//! meanwhile, since it would corrupt the transfer.
+//!
+//! The image must end with a trailer holding its length, its CRC-32, and
+//! [`TRAILER_MAGIC`], which `make-update.sh` appends to the binary built by
+//! `cargo build --release`. Once the transfer ends, the updater checks the
+//! trailer and the CRC, and that the image starts with a vector table of this
+//! board. Only then are the tasks shut down, see `src/shutdown.rs`, and the
+//! image copied over the running program and the system reset into it. A
+//! transfer that fails midway leaves the program untouched.
+//!
+//! The copy cannot run from the flash that it erases, so [`copy_and_reset`]
+//! is placed in `.data`, which the boot code copies into the RAM. It holds
+//! the flash lock of the `storage` module, which masks every IRQ but SVC,
+//! and calls nothing, so no code is fetched from the flash once it starts
+//! erasing. A reset during the copy leaves no bootable program, which
+//! `flash-board.sh` then has to restore.
+
+use crate::{
+    console::{print, println, RxConsumer},
+    shell::Line,
+    shutdown, storage,
+};
+use core::{arch::asm, fmt, ptr, slice};
+use hopter::time;
+use hopter_conf_params::SRAM_END_ADDR;
+use log::LevelFilter;
+use stm32f4xx_hal::flash::{self, FlashExt};
+
+/// The last bytes of an image before the XMODEM padding.
+pub const TRAILER_MAGIC: [u8; 4] = *b"HQS1";
+
+/// The size of the trailer: the length, the CRC-32, and the magic.
+const TRAILER_LEN: usize = 12;
+
+/// The XMODEM control bytes.
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+/// Pads the last block.
+const SUB: u8 = 0x1a;
+
+/// The number of times `C` is sent, once per second, before giving up on the
+/// sender.
+const START_TRIES: u32 = 60;
+
+/// The time to wait for the next byte before the sender is deemed gone.
+const BYTE_TIMEOUT_MS: u32 = 1000;
+const BLOCK_TIMEOUT_MS: u32 = 10_000;
+
+/// The number of corrupted blocks in a row before the transfer is cancelled.
+const MAX_RETRIES: u32 = 10;
+
+extern "C" {
+    // The bounds of the STAGING region, defined in `memory.x`.
+    static _staging_start: u8;
+    static _staging_end: u8;
+}
+
+/// An error of an update.
+#[derive(Debug)]
+pub enum Error {
+    /// The sender did not start, or stopped midway.
+    Timeout,
+    /// The sender cancelled the transfer, or too many blocks were corrupted.
+    Cancelled,
+    /// The image does not fit in the staging region.
+    TooLarge,
+    /// The trailer is missing or does not match the image.
+    BadTrailer,
+    /// The image does not start with a vector table of this board.
+    NotAnImage,
+    /// The flash controller reported an error.
+    Flash(flash::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out"),
+            Self::Cancelled => write!(f, "transfer cancelled"),
+            Self::TooLarge => write!(f, "image too large"),
+            Self::BadTrailer => write!(f, "bad trailer or CRC"),
+            Self::NotAnImage => write!(f, "not an image of this board"),
+            Self::Flash(err) => write!(f, "flash error {:?}", err),
+        }
+    }
+}
+
+impl From<flash::Error> for Error {
+    fn from(err: flash::Error) -> Self {
+        Self::Flash(err)
+    }
+}
+
+/// Receive an image, and install it if it checks out. Only returns upon a
+/// failure. The `storage` module must be initialized.
+pub fn update(_: &Line, rx: &RxConsumer) {
+    println!("erasing the staging area");
+    if let Err(err) = erase_staging() {
+        println!("update failed: {}", Error::from(err));
+        return;
+    }
+
+    println!("send the image by XMODEM");
+    let level = log::max_level();
+    log::set_max_level(LevelFilter::Off);
+    let received = receive(rx);
+    log::set_max_level(level);
+
+    // Let the terminal leave its transfer mode before printing.
+    time::sleep_ms(500).unwrap();
+    while rx.try_consume_allow_isr().is_some() {}
+
+    match received.and_then(verify) {
+        Ok(len) => {
+            shutdown::prepare();
+            println!("installing {} bytes", len);
+            // Let the message go out, over USB in particular.
+            time::sleep_ms(100).unwrap();
+            install(len);
+        }
+        Err(err) => println!("update failed: {}", err),
+    }
+}
+
+/// Erase the sectors of the staging region.
+fn erase_staging() -> Result<(), flash::Error> {
+    let mut offset = staging_start() - flash_start();
+    while offset < staging_start() - flash_start() + staging_len() {
+        // One sector at a time, so that the IRQs are served in between.
+        offset += storage::with_flash(|flash| {
+            let sector = flash.sector(offset).unwrap();
+            flash.unlocked().erase(sector.number).map(|()| sector.size)
+        })?;
+    }
+    Ok(())
+}
+
+/// Receive an image into the staging region. Return the number of bytes
+/// received, the padding of the last block included.
+fn receive(rx: &RxConsumer) -> Result<usize, Error> {
+    let mut byte = (0..START_TRIES)
+        .find_map(|_| {
+            send(CRC_MODE);
+            read(rx, BYTE_TIMEOUT_MS)
+        })
+        .ok_or(Error::Timeout)?;
+
+    let mut block = [0; 1024];
+    let mut expected: u8 = 1;
+    let mut len = 0;
+    let mut retries = 0;
+    loop {
+        let result = match byte {
+            SOH => receive_block(rx, &mut block[..128], expected),
+            STX => receive_block(rx, &mut block, expected),
+            EOT => {
+                send(ACK);
+                return Ok(len);
+            }
+            CAN => return Err(Error::Cancelled),
+            _ => Ok(Block::Corrupted),
+        };
+
+        match result {
+            Ok(Block::Next(data_len)) => {
+                if len + data_len > staging_len() {
+                    cancel();
+                    return Err(Error::TooLarge);
+                }
+                let offset = staging_start() - flash_start() + len;
+                if let Err(err) = storage::with_flash(|flash| {
+                    flash.unlocked().program(offset, block[..data_len].iter())
+                }) {
+                    cancel();
+                    return Err(err.into());
+                }
+                len += data_len;
+                expected = expected.wrapping_add(1);
+                retries = 0;
+                send(ACK);
+            }
+            // Our ACK of the previous block was lost.
+            Ok(Block::Repeated) => send(ACK),
+            Ok(Block::Corrupted) if retries < MAX_RETRIES => {
+                retries += 1;
+                purge(rx);
+                send(NAK);
+            }
+            Ok(Block::Corrupted) | Err(_) => {
+                cancel();
+                return Err(result.err().unwrap_or(Error::Cancelled));
+            }
+        }
+
+        byte = read(rx, BLOCK_TIMEOUT_MS).ok_or(Error::Timeout)?;
+    }
+}
+
+/// The outcome of receiving a block.
+enum Block {
+    /// The expected block, of the given length.
+    Next(usize),
+    /// The block before the expected one.
+    Repeated,
+    /// A damaged block.
+    Corrupted,
+}
+
+/// Receive the rest of a block after its first byte into `data`, whose
+/// length is the size of the block.
+fn receive_block(rx: &RxConsumer, data: &mut [u8], expected: u8) -> Result<Block, Error> {
+    let number = read(rx, BYTE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+    let complement = read(rx, BYTE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+    for byte in data.iter_mut() {
+        *byte = read(rx, BYTE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+    }
+    let high = read(rx, BYTE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+    let low = read(rx, BYTE_TIMEOUT_MS).ok_or(Error::Timeout)?;
+
+    if number != !complement || crc16(data) != u16::from_be_bytes([high, low]) {
+        Ok(Block::Corrupted)
+    } else if number == expected {
+        Ok(Block::Next(data.len()))
+    } else if number == expected.wrapping_sub(1) {
+        Ok(Block::Repeated)
+    } else {
+        // The sender and the receiver lost track of each other.
+        Err(Error::Cancelled)
+    }
+}
+
+/// Check the received image. Return its length without the trailer.
+fn verify(received: usize) -> Result<usize, Error> {
+    // Safety: The staging region is only written by this module, through
+    // the flash controller.
+    let staged = unsafe { slice::from_raw_parts(staging_start() as *const u8, received) };
+    let end = staged
+        .iter()
+        .rposition(|&byte| byte != SUB)
+        .map_or(0, |i| i + 1);
+    if end < TRAILER_LEN || staged[end - 4..end] != TRAILER_MAGIC {
+        return Err(Error::BadTrailer);
+    }
+    let word = |at: usize| u32::from_le_bytes(staged[at..at + 4].try_into().unwrap());
+    let len = end - TRAILER_LEN;
+    if word(len) as usize != len || word(len + 4) != crc32(&staged[..len]) {
+        return Err(Error::BadTrailer);
+    }
+
+    // The initial stack pointer must be in the RAM, and the reset vector in
+    // the image, with the Thumb bit set.
+    if len < 8 || len > staging_start() - flash_start() {
+        return Err(Error::NotAnImage);
+    }
+    let (sp, reset) = (word(0), word(4) as usize);
+    let image = flash_start()..flash_start() + len;
+    if !(0x2000_0000..=SRAM_END_ADDR).contains(&sp) || !image.contains(&reset) || reset & 1 == 0 {
+        return Err(Error::NotAnImage);
+    }
+    Ok(len)
+}
+
+/// Copy the image of the given length from the staging region over the
+/// program, and reset.
+fn install(len: usize) -> ! {
+    storage::with_flash(|flash| {
+        let sectors = flash.sector(len - 1).unwrap().number + 1;
+        // The controller must stay unlocked during the copy.
+        core::mem::forget(flash.unlocked());
+        // Safety: The image checked out, and no other code runs until the
+        // reset, see the module documentation.
+        unsafe { copy_and_reset(staging_start(), len, sectors) }
+    })
+}
+
+/// Erase the first `sectors` sectors, copy `len` bytes from `src` to the
+/// start of the flash, and reset. Must be called with the flash controller
+/// unlocked and the IRQs masked.
+///
+/// The function runs from the RAM. It may allocate a stacklet upon entry,
+/// which runs kernel code in the flash, but not after. Hence the loops
+/// below rather than iterators, and the inline assembly rather than
+/// `ptr::read_volatile`, any of which may be left as a call into the flash in
+/// debug builds.
+#[link_section = ".data.copy_and_reset"]
+#[inline(never)]
+unsafe fn copy_and_reset(src: usize, len: usize, sectors: u8) -> ! {
+    const FLASH_SR: usize = 0x4002_3c0c;
+    const FLASH_CR: usize = 0x4002_3c10;
+    const SCB_AIRCR: usize = 0xe000_ed0c;
+    const IWDG_KR: usize = 0x4000_3000;
+    const BSY: u32 = 1 << 16;
+    const PG: u32 = 1 << 0;
+    const SER: u32 = 1 << 1;
+    const STRT: u32 = 1 << 16;
+    const PSIZE_X32: u32 = 0b10 << 8;
+
+    // Clear the error flags left by earlier operations, which would block
+    // the next ones.
+    store(FLASH_SR, 0xf3);
+
+    let mut sector = 0;
+    while sector < sectors {
+        store(FLASH_CR, PSIZE_X32 | u32::from(sector) << 3 | SER);
+        store(FLASH_CR, PSIZE_X32 | u32::from(sector) << 3 | SER | STRT);
+        while load(FLASH_SR) & BSY != 0 {}
+        // Feed the watchdog, if started, after each erase.
+        store(IWDG_KR, 0xaaaa);
+        sector += 1;
+    }
+
+    store(FLASH_CR, PSIZE_X32 | PG);
+    let mut offset = 0;
+    while offset < len {
+        store(0x0800_0000 + offset, load(src + offset));
+        while load(FLASH_SR) & BSY != 0 {}
+        store(IWDG_KR, 0xaaaa);
+        offset += 4;
+    }
+    store(FLASH_CR, 0);
+
+    // Request a system reset.
+    asm!("dsb");
+    store(SCB_AIRCR, 0x05fa_0004);
+    loop {
+        // Wait for the reset.
+        asm!("nop");
+    }
+}
+
+/// Load a word from the address.
+#[inline(always)]
+unsafe fn load(addr: usize) -> u32 {
+    let value;
+    asm!("ldr {}, [{}]", out(reg) value, in(reg) addr, options(nostack, readonly));
+    value
+}
+
+/// Store a word at the address.
+#[inline(always)]
+unsafe fn store(addr: usize, value: u32) {
+    asm!("str {}, [{}]", in(reg) value, in(reg) addr, options(nostack));
+}
+
+/// Return the next byte received within the timeout, or `None`.
+fn read(rx: &RxConsumer, timeout_ms: u32) -> Option<u8> {
+    let start = time::get_tick();
+    loop {
+        if let Some(byte) = rx.try_consume_allow_isr() {
+            return Some(byte);
+        }
+        if time::get_tick().wrapping_sub(start) >= timeout_ms {
+            return None;
+        }
+        // The console buffers a few milliseconds of bytes.
+        time::sleep_ms(1).unwrap();
+    }
+}
+
+/// Drop the bytes received until the line stays quiet for a second.
+fn purge(rx: &RxConsumer) {
+    while read(rx, BYTE_TIMEOUT_MS).is_some() {}
+}
+
+/// Send a control byte.
+fn send(byte: u8) {
+    print!("{}", byte as char);
+}
+
+/// Tell the sender to stop.
+fn cancel() {
+    for _ in 0..3 {
+        send(CAN);
+    }
+}
+
+/// Return the CRC-16/XMODEM of the data.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ u16::from(byte) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Return the CRC-32 of the data, as computed by `zlib.crc32` in Python.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Return the address of the flash.
+fn flash_start() -> usize {
+    0x0800_0000
+}
+
+/// Return the address of the staging region.
+fn staging_start() -> usize {
+    ptr::addr_of!(_staging_start) as usize
+}
+
+/// Return the size of the staging region.
+fn staging_len() -> usize {
+    ptr::addr_of!(_staging_end) as usize - staging_start()
+}
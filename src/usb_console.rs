@@ -0,0 +1,300 @@
+//! Serial console over a USB virtual COM port, selected by the `usb-console`
+//! feature in place of the USART2 console of `src/console.rs`.
+//!
+//! The OTG_FS port enumerates as a CDC-ACM device, which hosts drive without
+//! a custom driver, e.g., as `/dev/ttyACM0` on Linux or a COM port on
+//! Windows. The data lines are PA11 (DM) and PA12 (DP), wired to the micro
+//! USB connector of the OTG_FS port on the Discovery boards. Any terminal
+//! settings work, since the baud rate of a virtual port is meaningless.
+//!
+//! The module offers the same interface as the USART2 console, so the shell
+//! and the logger run unchanged over USB. Tasks print with the [`print!`] and
+//! [`println!`] macros, which push the bytes into a channel. The OTG_FS IRQ
+//! handler drains the channel into the USB endpoint, and pushes the received
+//! bytes into another channel, whose consuming end [`init`] returns. A task
+//! printing faster than the host reads blocks when the channel fills up.
+//! Until a terminal opens the port, i.e., raises DTR, printed text is dropped
+//! rather than blocking the tasks.
+//!
+//! OTG_FS runs on the 48 MHz output of the main PLL, see `PLL_Q` in
+//! `hopter-conf-params/src/lib.rs`, which the PLL can produce alongside the
+//! 168 MHz system clock of STM32F407. The 100 MHz of STM32F411 and STM32F412
+//! cannot share the PLL with it. Lower the system clock to 96 MHz on those
+//! boards, with `PLL_N` at 192 and `PLL_Q` at 4.
+//!
+//! The USB stack masks interrupts with `cpsid i` in places, which must not
+//! happen while a task may allocate a stacklet, see Part 1 of `src/main.rs`.
+//! The device is thus built and served only in the IRQ handler, which runs on
+//! the contiguous kernel stack.
+
+use core::{
+    cell::UnsafeCell,
+    fmt::{self, Write},
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use hopter::{
+    interrupt::declare::{handler, irq},
+    sync::{self, Consumer, Mutex, Producer, SpinIrqSafe},
+};
+use hopter_conf_params::IRQ_NORMAL_PRIORITY;
+use stm32f4xx_hal::{
+    gpio::{PA11, PA12},
+    otg_fs::{UsbBus, UsbBusType, USB},
+    pac::{self, OTG_FS_DEVICE, OTG_FS_GLOBAL, OTG_FS_PWRCLK},
+    rcc::Clocks,
+};
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbDeviceState, UsbVidPid},
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+use crate::irq_nesting;
+
+/// The number of received bytes buffered before task context consumes them.
+pub const RX_BUFFER_LEN: usize = 64;
+
+/// The number of bytes buffered before the IRQ handler sends them.
+pub const TX_BUFFER_LEN: usize = 256;
+
+/// The consuming end of the channel carrying the received bytes.
+pub type RxConsumer = Consumer<u8, RX_BUFFER_LEN>;
+
+/// The VID and PID shared by the CDC-ACM devices of the pid.codes test range.
+const VID_PID: UsbVidPid = UsbVidPid(0x16c0, 0x27dd);
+
+/// The size of the packet memory of OTG_FS in words.
+const EP_MEMORY_WORDS: usize = 320;
+
+/// The bytes moved between the channels and the port in one go.
+const CHUNK_LEN: usize = 64;
+
+irq!(OtgFsIrq, pac::interrupt::OTG_FS);
+
+/// The endpoint memory and the allocator of the USB bus, which the device
+/// borrows for the rest of the program.
+struct BusStorage {
+    /// Set once the storage has been handed out.
+    taken: AtomicBool,
+    ep_memory: UnsafeCell<[u32; EP_MEMORY_WORDS]>,
+    allocator: UnsafeCell<MaybeUninit<UsbBusAllocator<UsbBusType>>>,
+}
+
+// Safety: The storage is handed out once, see `BusStorage::take`.
+unsafe impl Sync for BusStorage {}
+
+impl BusStorage {
+    /// Return the storage, or `None` if it was already taken.
+    fn take(
+        &'static self,
+    ) -> Option<(
+        &'static mut [u32; EP_MEMORY_WORDS],
+        &'static mut MaybeUninit<UsbBusAllocator<UsbBusType>>,
+    )> {
+        if self.taken.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        // Safety: The flag guarantees that the references are unique.
+        unsafe { Some((&mut *self.ep_memory.get(), &mut *self.allocator.get())) }
+    }
+}
+
+static BUS: BusStorage = BusStorage {
+    taken: AtomicBool::new(false),
+    ep_memory: UnsafeCell::new([0; EP_MEMORY_WORDS]),
+    allocator: UnsafeCell::new(MaybeUninit::uninit()),
+};
+
+/// The state of the port, owned by the IRQ handler.
+struct Port {
+    bus: &'static UsbBusAllocator<UsbBusType>,
+    /// The device and its serial class. They are `None` until the handler
+    /// first runs.
+    usb: Option<(
+        UsbDevice<'static, UsbBusType>,
+        SerialPort<'static, UsbBusType>,
+    )>,
+    rx: Producer<u8, RX_BUFFER_LEN>,
+    tx: Consumer<u8, TX_BUFFER_LEN>,
+    /// Bytes taken from `tx` but not yet accepted by the serial class.
+    pending: [u8; CHUNK_LEN],
+    pending_len: usize,
+}
+
+/// The port. OTG_FS IRQ is masked when the lock is held.
+static PORT: SpinIrqSafe<Option<Port>, OtgFsIrq> = SpinIrqSafe::new(None);
+
+/// The producing end of the channel carrying the bytes to send. It is `None`
+/// until [`init`] is called.
+static TX: Mutex<Option<Producer<u8, TX_BUFFER_LEN>>> = Mutex::new(None);
+
+/// Set while a terminal has the port open.
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// The number of received bytes dropped because the channel was full.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Attach OTG_FS to the bus and unmask its IRQ. Return the consuming end of
+/// the channel carrying the received bytes. The 48 MHz clock of OTG_FS must
+/// be enabled. Panic if called twice.
+pub fn init(
+    usb: (OTG_FS_GLOBAL, OTG_FS_DEVICE, OTG_FS_PWRCLK),
+    dm: PA11,
+    dp: PA12,
+    clocks: &Clocks,
+    nvic: &mut cortex_m::peripheral::NVIC,
+) -> RxConsumer {
+    assert!(
+        clocks.is_pll48clk_valid(),
+        "the clock of OTG_FS is not 48 MHz"
+    );
+    let (ep_memory, allocator) = BUS.take().unwrap();
+    let bus = allocator.write(UsbBus::new(USB::new(usb, (dm, dp), clocks), ep_memory));
+
+    let (rx_producer, rx_consumer) = sync::create_channel();
+    let (tx_producer, tx_consumer) = sync::create_channel();
+    *TX.lock() = Some(tx_producer);
+    *PORT.lock() = Some(Port {
+        bus,
+        usb: None,
+        rx: rx_producer,
+        tx: tx_consumer,
+        pending: [0; CHUNK_LEN],
+        pending_len: 0,
+    });
+
+    unsafe {
+        nvic.set_priority(pac::interrupt::OTG_FS, IRQ_NORMAL_PRIORITY);
+        cortex_m::peripheral::NVIC::unmask(pac::interrupt::OTG_FS);
+    }
+    // Let the handler build the device.
+    cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+
+    rx_consumer
+}
+
+/// Return the number of received bytes dropped so far.
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::SeqCst)
+}
+
+/// Write the formatted text to the console. Do nothing before [`init`] is
+/// called. Used by the [`print!`] and [`println!`] macros.
+pub fn write_fmt(args: fmt::Arguments) {
+    if let Some(tx) = TX.lock().as_mut() {
+        // Writing blocks until the bytes are buffered, so it never fails.
+        let _ = TxWriter(tx).write_fmt(args);
+        // Let the handler send the bytes.
+        cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+    }
+}
+
+/// Pushes the text into the channel of the bytes to send.
+struct TxWriter<'a>(&'a mut Producer<u8, TX_BUFFER_LEN>);
+
+impl Write for TxWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            // Nobody reads the text while the port is closed.
+            if !CONNECTED.load(Ordering::SeqCst) {
+                break;
+            }
+            if let Err(byte) = self.0.try_produce_allow_isr(byte) {
+                // Let the handler drain the channel, and wait for room.
+                cortex_m::peripheral::NVIC::pend(pac::interrupt::OTG_FS);
+                self.0.produce(byte);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Print to the console.
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::write_fmt(format_args!($($arg)*))
+    };
+}
+
+/// Print to the console, with a CR LF line ending.
+macro_rules! println {
+    () => {
+        $crate::console::print!("\r\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::console::write_fmt(format_args!("{}\r\n", format_args!($($arg)*)))
+    };
+}
+
+pub(crate) use {print, println};
+
+impl Port {
+    /// Serve the device, then move the bytes received and those to send.
+    fn serve(&mut self) {
+        let bus = self.bus;
+        let (device, serial) = self.usb.get_or_insert_with(|| {
+            let serial = SerialPort::new(bus);
+            let strings = StringDescriptors::default()
+                .manufacturer("Hopter")
+                .product("Hopter quick start console")
+                .serial_number("0");
+            let device = UsbDeviceBuilder::new(bus, VID_PID)
+                .strings(&[strings])
+                .unwrap()
+                .device_class(USB_CLASS_CDC)
+                .build();
+            (device, serial)
+        });
+
+        if device.poll(&mut [serial]) {
+            let mut received = [0; CHUNK_LEN];
+            if let Ok(len) = serial.read(&mut received) {
+                for &byte in &received[..len] {
+                    if self.rx.try_produce_allow_isr(byte).is_err() {
+                        DROPPED.fetch_add(1, Ordering::SeqCst);
+                    }
+                }
+            }
+        }
+
+        let connected = device.state() == UsbDeviceState::Configured && serial.dtr();
+        CONNECTED.store(connected, Ordering::SeqCst);
+        if !connected {
+            // Discard the text, waking any task waiting for room.
+            while self.tx.try_consume_allow_isr().is_some() {}
+            self.pending_len = 0;
+            return;
+        }
+
+        loop {
+            while self.pending_len < CHUNK_LEN {
+                match self.tx.try_consume_allow_isr() {
+                    Some(byte) => {
+                        self.pending[self.pending_len] = byte;
+                        self.pending_len += 1;
+                    }
+                    None => break,
+                }
+            }
+            // The serial class buffers the bytes and sends them as the host
+            // polls, each completion raising the IRQ again.
+            match serial.write(&self.pending[..self.pending_len]) {
+                Ok(len) if len > 0 => {
+                    self.pending.copy_within(len..self.pending_len, 0);
+                    self.pending_len -= len;
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+#[handler(OTG_FS)]
+fn otg_fs_handler() {
+    let _nesting = irq_nesting::enter();
+
+    if let Some(port) = PORT.lock().as_mut() {
+        port.serve();
+    }
+}
@@ -0,0 +1,150 @@
+//! A supervisor feeding the independent watchdog on behalf of the tasks.
+//!
+//! The IWDG resets the system unless it is fed within [`TIMEOUT_MS`]. It runs
+//! from its own oscillator, so it fires even if the clocks, the IRQs, or the
+//! kernel are stuck. Feeding it from a single task would only prove that this
+//! task runs. Instead, each task to be supervised calls [`register`] with an
+//! interval, then [`checkin`] at least once per interval. The `watchdog` task
+//! feeds the IWDG only while every registered task is on time. A task that is
+//! wedged, e.g., spinning or waiting forever, rather than panicked, thus
+//! causes a reset once it misses its interval.
+//!
+//! Tasks are told apart by ID, like in the `task_local` module. A restarted
+//! instance of a restartable task keeps the ID, and hence the registration,
+//! of the panicked instance. A task stays registered until the reset, even
+//! if killed by a panic, so only tasks meant to run forever should register.
+//!
+//! The timeout leaves room for the erase of a 128 KiB flash sector by the
+//! `storage` and `updater` modules, during which the IRQs are masked and no
+//! task runs for up to 4 s. The IWDG is frozen while the core is halted by a
+//! debugger.
+
+use crate::{
+    stack_pool::SetStackPool,
+    task_name::{self, SetName},
+};
+use hopter::{config, sync::SpinSchedSafe, task, time};
+use stm32f4xx_hal::{
+    pac::{DBGMCU, IWDG, RCC},
+    prelude::*,
+    watchdog::IndependentWatchdog,
+};
+
+/// The time after the last feed at which the IWDG resets the system.
+pub const TIMEOUT_MS: u32 = 8000;
+
+/// The period at which the `watchdog` task checks the registered tasks.
+const PERIOD_MS: u32 = 100;
+
+/// The priority of the `watchdog` task, above every task but `main`, so that
+/// a task busy at a high priority but still checking in does not starve it.
+const PRIORITY: u8 = 1;
+
+/// A registered task.
+#[derive(Clone, Copy)]
+struct Entry {
+    id: u8,
+    interval_ms: u32,
+    /// The tick of the last check-in.
+    last: u32,
+}
+
+/// The registered tasks.
+static ENTRIES: SpinSchedSafe<[Option<Entry>; config::MAX_TASK_NUMBER]> =
+    SpinSchedSafe::new([None; config::MAX_TASK_NUMBER]);
+
+/// Start the IWDG and spawn the `watchdog` task feeding it. Return whether
+/// the last reset was caused by the IWDG.
+pub fn start(iwdg: IWDG, dbgmcu: &DBGMCU) -> bool {
+    // Safety: Only the reset flags are read and then cleared, which no other
+    // code uses.
+    let rcc = unsafe { &*RCC::ptr() };
+    // IWDGRSTF, which the PACs of some parts name differently.
+    let bitten = rcc.csr.read().bits() & 1 << 29 != 0;
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    let mut iwdg = IndependentWatchdog::new(iwdg);
+    iwdg.stop_on_debug(dbgmcu, true);
+    iwdg.start(TIMEOUT_MS.millis());
+
+    task::build()
+        .set_name_and_priority("watchdog", PRIORITY)
+        .set_stack_pool(0)
+        .set_entry(move || supervise(iwdg))
+        .spawn()
+        .unwrap();
+
+    bitten
+}
+
+/// Register the current task, which must then check in at least every
+/// `interval_ms`. Registering again changes the interval. Panic if more
+/// tasks register than `MAX_TASK_NUMBER`.
+pub fn register(interval_ms: u32) {
+    let id = task::get_current_id();
+    let entry = Entry {
+        id,
+        interval_ms,
+        last: time::get_tick(),
+    };
+    let mut entries = ENTRIES.lock();
+    let slot = match entries
+        .iter()
+        .position(|slot| matches!(slot, Some(entry) if entry.id == id))
+    {
+        Some(idx) => &mut entries[idx],
+        None => entries
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("too many supervised tasks"),
+    };
+    *slot = Some(entry);
+}
+
+/// Tell the supervisor that the current task is alive. Do nothing if the
+/// task is not registered.
+pub fn checkin() {
+    let id = task::get_current_id();
+    let now = time::get_tick();
+    for entry in ENTRIES.lock().iter_mut().flatten() {
+        if entry.id == id {
+            entry.last = now;
+        }
+    }
+}
+
+/// Return the ID of a registered task that missed its interval, if any.
+fn overdue() -> Option<u8> {
+    let now = time::get_tick();
+    let entries = *ENTRIES.lock();
+    entries
+        .into_iter()
+        .flatten()
+        .find(|entry| now.wrapping_sub(entry.last) > entry.interval_ms)
+        .map(|entry| entry.id)
+}
+
+/// The entry of the `watchdog` task.
+fn supervise(mut iwdg: IndependentWatchdog) {
+    let mut reported = false;
+    loop {
+        match overdue() {
+            None => {
+                iwdg.feed();
+                reported = false;
+            }
+            // Stop feeding, and report the culprit once before the reset.
+            Some(id) if !reported => {
+                log::error!(
+                    "task {} ({}) missed its check-in, reset within {} ms",
+                    id,
+                    task_name::name_of(id).unwrap_or("unnamed"),
+                    TIMEOUT_MS
+                );
+                reported = true;
+            }
+            Some(_) => {}
+        }
+        time::sleep_ms(PERIOD_MS).unwrap();
+    }
+}